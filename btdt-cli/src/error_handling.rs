@@ -0,0 +1,231 @@
+use btdt::cache::{Cache, CacheEntry, CacheHit, SetOptions};
+use btdt::error::IoPathResult;
+use btdt::util::close::Close;
+use clap::ValueEnum;
+use std::fmt::Display;
+use std::io;
+use std::io::Write;
+
+/// How to handle errors when accessing the cache.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OnCacheError {
+    /// Fail the command.
+    #[default]
+    Fail,
+    /// Treat errors when retrieving entries as a miss, and only warn about errors when storing
+    /// entries.
+    Miss,
+}
+
+/// A cache applying an [OnCacheError] policy to errors of the wrapped cache.
+///
+/// Only errors from retrieving and storing entries are handled. Errors while reading the data of
+/// a retrieved entry are always returned, as the data might already have been partially used.
+pub struct ErrorHandlingCache<C: Cache> {
+    cache: C,
+    on_error: OnCacheError,
+}
+
+impl<C: Cache> ErrorHandlingCache<C> {
+    pub fn new(cache: C, on_error: OnCacheError) -> Self {
+        Self { cache, on_error }
+    }
+
+    fn handle_get_error<T>(&self, result: IoPathResult<Option<T>>) -> IoPathResult<Option<T>> {
+        match result {
+            Err(err) if self.on_error == OnCacheError::Miss => {
+                warn("Could not retrieve from cache, treating as miss", err);
+                Ok(None)
+            }
+            result => result,
+        }
+    }
+}
+
+impl<C: Cache> Cache for ErrorHandlingCache<C> {
+    type Reader = C::Reader;
+    type Writer = ErrorHandlingWriter<C::Writer>;
+
+    fn get<'a>(&self, keys: &[&'a str]) -> IoPathResult<Option<CacheHit<'a, Self::Reader>>> {
+        self.handle_get_error(self.cache.get(keys))
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefixes: &[&str],
+    ) -> IoPathResult<Option<CacheHit<'static, Self::Reader>>> {
+        self.handle_get_error(self.cache.get_by_prefix(prefixes))
+    }
+
+    fn set_with_options(&self, keys: &[&str], options: &SetOptions) -> IoPathResult<Self::Writer> {
+        let writer = match self.cache.set_with_options(keys, options) {
+            Ok(writer) => Some(writer),
+            Err(err) if self.on_error == OnCacheError::Miss => {
+                warn("Could not store in cache", err);
+                None
+            }
+            Err(err) => return Err(err),
+        };
+        Ok(ErrorHandlingWriter {
+            writer,
+            on_error: self.on_error,
+        })
+    }
+
+    fn list(&self, prefix: &str) -> IoPathResult<Vec<CacheEntry>> {
+        self.cache.list(prefix)
+    }
+
+    fn entry(&self, key: &str) -> IoPathResult<Option<CacheEntry>> {
+        self.handle_get_error(self.cache.entry(key))
+    }
+
+    fn delete(&self, keys: &[&str]) -> IoPathResult<()> {
+        self.cache.delete(keys)
+    }
+}
+
+/// Writer returned by the [ErrorHandlingCache].
+///
+/// With [OnCacheError::Miss], the wrapped writer is aborted after the first error and the
+/// remaining data is discarded.
+pub struct ErrorHandlingWriter<W: Write + Close> {
+    writer: Option<W>,
+    on_error: OnCacheError,
+}
+
+impl<W: Write + Close> ErrorHandlingWriter<W> {
+    fn handle_error<T>(&mut self, result: io::Result<T>, discarded: T) -> io::Result<T> {
+        match result {
+            Err(err) if self.on_error == OnCacheError::Miss => {
+                warn("Could not store in cache", err);
+                if let Some(writer) = self.writer.take() {
+                    writer.abort();
+                }
+                Ok(discarded)
+            }
+            result => result,
+        }
+    }
+}
+
+impl<W: Write + Close> Write for ErrorHandlingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.writer {
+            Some(writer) => {
+                let result = writer.write(buf);
+                self.handle_error(result, buf.len())
+            }
+            None => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.writer {
+            Some(writer) => {
+                let result = writer.flush();
+                self.handle_error(result, ())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl<W: Write + Close> Close for ErrorHandlingWriter<W> {
+    fn close(mut self) -> io::Result<()> {
+        match self.writer.take() {
+            Some(writer) => {
+                let result = writer.close();
+                self.handle_error(result, ())
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn abort(mut self) {
+        if let Some(writer) = self.writer.take() {
+            writer.abort();
+        }
+    }
+}
+
+fn warn(message: &str, err: impl Display) {
+    eprintln!("Warning: {message}: {err}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// A writer failing on every write, and panicking if dropped without being closed or aborted,
+    /// like the writers of the storages finalizing on drop.
+    struct FailingWriter {
+        aborted: Rc<Cell<bool>>,
+        done: bool,
+    }
+
+    impl Write for FailingWriter {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("write failed"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Close for FailingWriter {
+        fn close(mut self) -> io::Result<()> {
+            self.done = true;
+            Ok(())
+        }
+
+        fn abort(mut self) {
+            self.done = true;
+            self.aborted.set(true);
+        }
+    }
+
+    impl Drop for FailingWriter {
+        fn drop(&mut self) {
+            if !self.done {
+                panic!("finalized a failed writer on drop");
+            }
+        }
+    }
+
+    #[test]
+    fn test_writer_is_aborted_after_error_with_on_cache_error_miss() {
+        let aborted = Rc::new(Cell::new(false));
+        let mut writer = ErrorHandlingWriter {
+            writer: Some(FailingWriter {
+                aborted: aborted.clone(),
+                done: false,
+            }),
+            on_error: OnCacheError::Miss,
+        };
+
+        assert_eq!(writer.write(b"data").unwrap(), 4);
+        assert!(aborted.get());
+        assert_eq!(writer.write(b"more data").unwrap(), 9);
+        writer.close().unwrap();
+    }
+
+    #[test]
+    fn test_writer_returns_error_with_on_cache_error_fail() {
+        let aborted = Rc::new(Cell::new(false));
+        let mut writer = ErrorHandlingWriter {
+            writer: Some(FailingWriter {
+                aborted: aborted.clone(),
+                done: false,
+            }),
+            on_error: OnCacheError::Fail,
+        };
+
+        assert!(writer.write(b"data").is_err());
+        writer.abort();
+        assert!(aborted.get());
+    }
+}
@@ -1,21 +1,30 @@
+mod error_handling;
 mod hashing;
+mod templating;
 
+use crate::error_handling::{ErrorHandlingCache, OnCacheError};
 use crate::hashing::hash_paths;
 use anyhow::{Context, anyhow};
 use biscuit_auth::UnverifiedBiscuit;
 use btdt::cache::cache_dispatcher::CacheDispatcher;
+use btdt::cache::clean::{CleanOptions, CleanReport, EvictionPolicy, PrefixQuota};
 use btdt::cache::local::LocalCache;
-use btdt::cache::remote::RemoteCache;
-use btdt::cache::remote::http::HttpClient;
-use btdt::pipeline::Pipeline;
+use btdt::cache::remote::http::{HttpClient, ProxyConfig, Timeouts};
+use btdt::cache::remote::{RemoteCache, RetryPolicy};
+use btdt::cache::tiered::TieredCache;
+use btdt::cache::{Cache, CacheEntry};
+use btdt::pipeline::{Compression, Pipeline};
 use btdt::storage::filesystem::FilesystemStorage;
+use btdt::storage::s3::{S3Config, S3Storage};
 use btdt::util::humanbytes;
-use clap::{Args, Parser, Subcommand};
+use chrono::SecondsFormat;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use ignore::overrides::OverrideBuilder;
 use std::os::unix::fs::PermissionsExt;
-use std::path::PathBuf;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
-use std::{fs, io};
+use std::{fs, io, process};
 use url::Url;
 
 /// "been there, done that" - a tool for flexible CI caching
@@ -56,6 +65,127 @@ enum Commands {
         /// This doesn't account for metadata, thus the overall cache size may be a bit larger.
         #[arg(long, value_parser=humanbytes::parse_bytes_from_str)]
         max_size: Option<u64>,
+
+        /// Maximum size of the entries with keys starting with a prefix, given as PREFIX=SIZE.
+        ///
+        /// Entries of each prefix are deleted in the order given by --policy until the quota is
+        /// met, before applying --max-age and --max-size. May be given multiple times.
+        #[arg(long = "quota", value_name = "PREFIX=SIZE", value_parser = parse_prefix_quota)]
+        quotas: Vec<PrefixQuota>,
+
+        /// Order in which entries are deleted to meet --max-size and --quota.
+        #[arg(long, value_enum, default_value_t = EvictionPolicyOpt::Lru)]
+        policy: EvictionPolicyOpt,
+
+        /// Only report which entries would be deleted, without deleting anything.
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Print the report of a dry run as JSON.
+        #[arg(long, requires = "dry_run")]
+        json: bool,
+    },
+
+    /// Delete entries from the cache.
+    ///
+    /// Keys not found in the cache are ignored. The cached files are deleted once no other key
+    /// refers to them anymore.
+    Delete {
+        #[command(flatten)]
+        entries_ref: CacheEntriesRef,
+    },
+
+    /// Restore cached files, run a command if they could not be restored, and store the result.
+    ///
+    /// This combines `restore` and `store`: The files are restored to `--path`. If they could not
+    /// be restored, the command is run. If it succeeds, the files in `--path` are stored in the
+    /// cache under all specified keys.
+    ///
+    /// By default, the command is only run if none of the keys is found in the cache. Use
+    /// `--run-on-non-primary-key` to also run the command if the files were restored from a
+    /// non-primary key or a key matching a restore prefix.
+    ///
+    /// # Exit codes:
+    ///
+    /// - 0: Files were restored, or the command succeeded and the files were stored.
+    /// - 1: General error
+    /// - 2: Error in command invocation.
+    ///
+    /// If the command fails, its exit code is returned and nothing is stored.
+    Exec {
+        #[command(flatten)]
+        entries_ref: CacheEntriesRef,
+
+        /// Directory to restore the files to and to store in the cache after running the command.
+        #[arg(short, long)]
+        path: PathBuf,
+
+        /// Key prefixes to fall back to if none of the keys is found in the cache.
+        ///
+        /// Works like the option of the same name of the `restore` subcommand.
+        #[arg(long)]
+        restore_prefix: Vec<String>,
+
+        /// Also run the command if files were restored from a non-primary key or a key matching a
+        /// restore prefix.
+        #[arg(long, action)]
+        run_on_non_primary_key: bool,
+
+        /// Paths to exclude from the cached archive.
+        ///
+        /// Works like the option of the same name of the `store` subcommand.
+        #[arg(long)]
+        exclude: Vec<String>,
+
+        #[command(flatten)]
+        compression: CompressionOpts,
+
+        /// Duration after which the stored entry expires.
+        ///
+        /// Works like the option of the same name of the `store` subcommand.
+        #[arg(long)]
+        ttl: Option<humantime::Duration>,
+
+        /// Share the stored data with entries of identical content.
+        ///
+        /// Works like the option of the same name of the `store` subcommand.
+        #[arg(long)]
+        deduplicate: bool,
+
+        /// How to handle errors when accessing the cache, e.g. if a remote cache is unreachable.
+        ///
+        /// With `miss`, errors when looking up the keys are treated as if none of the keys was
+        /// found, and errors when storing the files are reported as a warning.
+        #[arg(long, value_enum, default_value_t)]
+        on_cache_error: OnCacheError,
+
+        /// Command to run if the files could not be restored.
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+    },
+
+    /// Check a local or S3 cache for inconsistencies.
+    ///
+    /// Reports meta files that cannot be decoded, keys referring to missing data, data not
+    /// referred to by any key, and data not matching the checksum recorded when it was stored.
+    ///
+    /// # Exit codes:
+    ///
+    /// - 0: No inconsistencies were found, or all were repaired.
+    /// - 1: General error
+    /// - 2: Error in command invocation.
+    /// - 3: Inconsistencies were found and not repaired.
+    Fsck {
+        #[command(flatten)]
+        cache_ref: CacheRef,
+
+        /// Repair the inconsistencies.
+        ///
+        /// Keys referring to missing or corrupted data are deleted, as well as data not referred
+        /// to by any key. Undecodable meta files and corrupted data are moved to the `quarantine`
+        /// directory of the cache. The cache should not be in use while repairing it.
+        #[arg(long, action)]
+        repair: bool,
     },
 
     /// Calculate the hash of a file.
@@ -64,16 +194,45 @@ enum Commands {
         path: Vec<PathBuf>,
     },
 
+    /// List entries in the cache.
+    ///
+    /// Lists the key, size of the cached data, and time of the latest access for each entry,
+    /// sorted by key. Listing does not count as an access.
+    List {
+        #[command(flatten)]
+        cache_ref: CacheRef,
+
+        /// Only list entries whose key starts with this prefix.
+        ///
+        /// The prefix supports the same expressions as keys.
+        #[arg(long)]
+        prefix: Option<String>,
+
+        /// Output format.
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
+
+    /// Pin keys to exempt the entries from being cleaned.
+    ///
+    /// Pinned entries are never deleted by `btdt clean`, but still count towards the maximum size.
+    /// Keys may be pinned before entries are stored under them.
+    Pin {
+        #[command(flatten)]
+        entries_ref: CacheEntriesRef,
+    },
+
     /// Restore cached files.
     ///
-    /// The first key that exists in the cache will be used.
+    /// The first key that exists in the cache will be used. If none of the keys exists, the most
+    /// recently accessed key starting with one of the restore prefixes will be used.
     ///
     /// # Exit codes:
     ///
     /// - 0: Files were restored from the primary (i.e. first listed) cache key.
     /// - 1: General error
     /// - 2: Error in command invocation.
-    /// - 3: Files were restored from a non-primary cache key.
+    /// - 3: Files were restored from a non-primary cache key or a key matching a restore prefix.
     /// - 4: No keys were found in the cache.
     Restore {
         #[command(flatten)]
@@ -82,12 +241,27 @@ enum Commands {
         /// Directory to restore the files to.
         destination_dir: PathBuf,
 
+        /// Key prefixes to fall back to if none of the keys is found in the cache.
+        ///
+        /// The prefixes are tried in order. For the first prefix matching any key in the cache,
+        /// the most recently accessed matching key is restored. Prefixes support the same
+        /// expressions as keys.
+        #[arg(long)]
+        restore_prefix: Vec<String>,
+
         /// Exit with success status code if any key is found in the cache.
         ///
         /// Usually, the success exit code is only returned if the primary key (i.e. first listed
         /// key) is found in the cache, and 3 is returned if another key was restored.
         #[arg(long, action)]
         success_rc_on_any_key: bool,
+
+        /// How to handle errors when accessing the cache, e.g. if a remote cache is unreachable.
+        ///
+        /// With `miss`, errors when looking up the keys are reported as a warning and treated as
+        /// if none of the keys was found.
+        #[arg(long, value_enum, default_value_t)]
+        on_cache_error: OnCacheError,
     },
 
     /// Store files in the cache.
@@ -113,14 +287,138 @@ enum Commands {
         /// with `--exclude !dir/subpath`.
         #[arg(long)]
         exclude: Vec<String>,
+
+        #[command(flatten)]
+        compression: CompressionOpts,
+
+        /// Duration after which the stored entry expires.
+        ///
+        /// Supports human-readable units like "1d" for one day. Expired entries are treated as if
+        /// they did not exist and are deleted by `btdt clean`. By default, entries do not expire.
+        #[arg(long)]
+        ttl: Option<humantime::Duration>,
+
+        /// Share the stored data with entries of identical content.
+        ///
+        /// The data is identified by its hash, so that storing identical archives under different
+        /// keys uses the space only once. Only applies to local and S3 caches; deduplication for
+        /// remote caches is configured on the server.
+        #[arg(long)]
+        deduplicate: bool,
+
+        /// How to handle errors when accessing the cache, e.g. if a remote cache is unreachable.
+        ///
+        /// With `miss`, errors when storing the files in the cache are reported as a warning
+        /// instead of failing the command.
+        #[arg(long, value_enum, default_value_t)]
+        on_cache_error: OnCacheError,
+
+        /// Do not store the files if the primary (i.e. first listed) key already exists.
+        #[arg(long, conflicts_with = "skip_if_unchanged")]
+        skip_if_exists: bool,
+
+        /// Do not store the files if the primary key holds identical files.
+        ///
+        /// A hash of the source directory (as computed by `btdt hash`) is recorded in the stored
+        /// entry and compared against the hash of the entry under the primary key. The hash covers
+        /// all files in the source directory, including excluded ones.
+        #[arg(long)]
+        skip_if_unchanged: bool,
+    },
+
+    /// Remove the pins of keys, so that their entries can be cleaned again.
+    Unpin {
+        #[command(flatten)]
+        entries_ref: CacheEntriesRef,
     },
 }
 
+/// Options controlling the compression of stored archives.
+#[derive(Args)]
+struct CompressionOpts {
+    /// Compression algorithm to use for the cached archive.
+    ///
+    /// Restoring detects the compression automatically. Note that older versions of btdt are not
+    /// able to restore compressed archives.
+    #[arg(long, value_enum, default_value_t = CompressionAlgorithm::None)]
+    compression: CompressionAlgorithm,
+
+    /// Compression level to use.
+    ///
+    /// For zstd, this ranges from 1 (fastest) to 22 (strongest compression).
+    #[arg(long, default_value_t = Compression::DEFAULT_ZSTD_LEVEL, allow_negative_numbers = true)]
+    compression_level: i32,
+
+    /// Number of threads to use for compression.
+    ///
+    /// 0 disables multithreading.
+    #[arg(long, default_value_t = 0)]
+    compression_threads: u32,
+}
+
+/// Compression algorithms supported for cached archives.
+#[derive(Clone, Copy, ValueEnum)]
+enum CompressionAlgorithm {
+    /// Do not compress.
+    None,
+    /// Compress with Zstandard.
+    Zstd,
+}
+
+impl CompressionOpts {
+    fn to_compression(&self) -> Compression {
+        match self.compression {
+            CompressionAlgorithm::None => Compression::None,
+            CompressionAlgorithm::Zstd => Compression::Zstd {
+                level: self.compression_level,
+                workers: self.compression_threads,
+            },
+        }
+    }
+}
+
+/// Eviction policies supported for cleaning the cache.
+#[derive(Clone, Copy, ValueEnum)]
+enum EvictionPolicyOpt {
+    /// Delete the least recently used entries first.
+    Lru,
+    /// Delete the least frequently used entries first.
+    Lfu,
+    /// Delete the entries with the largest product of size and time since the last access first.
+    SizeWeighted,
+}
+
+impl From<EvictionPolicyOpt> for EvictionPolicy {
+    fn from(policy: EvictionPolicyOpt) -> Self {
+        match policy {
+            EvictionPolicyOpt::Lru => EvictionPolicy::Lru,
+            EvictionPolicyOpt::Lfu => EvictionPolicy::Lfu,
+            EvictionPolicyOpt::SizeWeighted => EvictionPolicy::SizeWeighted,
+        }
+    }
+}
+
+/// Output formats for listing cache entries.
+#[derive(Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    /// Human-readable table.
+    Table,
+    /// JSON array of objects with `key`, `size`, and `latest_access` fields.
+    Json,
+}
+
 /// Reference to cache entries defining the cache to use and the keys in the cache to operate on.
 #[derive(Args)]
 struct CacheEntriesRef {
     /// Keys to operate on.
-    #[arg(short, long, required = true, value_delimiter = ',')]
+    ///
+    /// Keys may contain expressions of the form `${...}` that are expanded before use:
+    /// `${hashFiles('**/package-lock.json')}` expands to the hash of the files matching the glob
+    /// patterns, `${env.NAME}` to the value of the environment variable `NAME`, and `${'text'}` to
+    /// the literal text.
+    ///
+    /// Multiple keys can be separated by commas.
+    #[arg(short, long, required = true)]
     keys: Vec<String>,
 
     #[command(flatten)]
@@ -130,9 +428,14 @@ struct CacheEntriesRef {
 /// Reference to the cache to use.
 #[derive(Args)]
 struct CacheRef {
-    /// Path to the cache directory.
-    #[arg(short, long)]
-    cache: String,
+    /// Path to the cache directory, URL of a remote cache, or `s3://<bucket>/<prefix>` for an S3
+    /// bucket.
+    ///
+    /// Give a cache directory followed by the URL of a remote cache to use the directory as a
+    /// local tier in front of the remote cache. Entries are looked up in the local tier first and
+    /// stored there when retrieved from the remote cache. Stored entries are written to both.
+    #[arg(short, long, required = true)]
+    cache: Vec<String>,
 
     /// File with authentication token for remote caches.
     #[arg(short, long)]
@@ -142,25 +445,78 @@ struct CacheRef {
     /// certificates).
     #[arg(long)]
     root_cert: Vec<PathBuf>,
+
+    /// Split the cached data into content-defined chunks that are stored only once, so that
+    /// entries with similar data share most of the storage.
+    ///
+    /// A local or S3 cache must be used consistently with or without this flag. For remote caches,
+    /// chunking is configured on the server.
+    #[arg(long)]
+    chunked: bool,
+
+    /// Maximum number of retries of failed requests to remote caches.
+    #[arg(long, default_value_t = RetryPolicy::default().max_retries)]
+    retries: u32,
+
+    /// Delay before the first retry of a failed request to a remote cache. The delay doubles with
+    /// each further retry.
+    #[arg(long, default_value = "500ms")]
+    retry_backoff: humantime::Duration,
+
+    /// Timeout for establishing connections to remote and S3 caches. Zero disables the timeout.
+    #[arg(long, env = "BTDT_CONNECT_TIMEOUT", default_value = "30s")]
+    connect_timeout: humantime::Duration,
+
+    /// Timeout for receiving data from remote and S3 caches. Zero disables the timeout.
+    #[arg(long, env = "BTDT_READ_TIMEOUT", default_value = "60s")]
+    read_timeout: humantime::Duration,
+
+    /// Timeout for sending data to remote and S3 caches. Zero disables the timeout.
+    #[arg(long, env = "BTDT_WRITE_TIMEOUT", default_value = "60s")]
+    write_timeout: humantime::Duration,
+
+    /// Timeout for a whole request to remote and S3 caches, including the transfer of the data.
+    /// Zero disables the timeout.
+    #[arg(long, env = "BTDT_REQUEST_TIMEOUT", default_value = "0s")]
+    request_timeout: humantime::Duration,
 }
 
 impl CacheEntriesRef {
-    fn keys(&self) -> Vec<&str> {
-        self.keys
-            .iter()
-            .filter(|k| !k.is_empty())
-            .map(String::as_str)
-            .collect()
+    fn keys(&self) -> Result<Vec<String>, anyhow::Error> {
+        expand_templates(&self.keys)
     }
 
-    fn to_pipeline(&self) -> Result<Pipeline<CacheDispatcher>, anyhow::Error> {
-        Ok(Pipeline::new(self.cache_ref.to_cache()?))
+    fn to_pipeline(
+        &self,
+        on_cache_error: OnCacheError,
+    ) -> Result<Pipeline<ErrorHandlingCache<CacheDispatcher>>, anyhow::Error> {
+        Ok(Pipeline::new(ErrorHandlingCache::new(
+            self.cache_ref.to_cache()?,
+            on_cache_error,
+        )))
     }
 }
 
 impl CacheRef {
     fn to_cache(&self) -> Result<CacheDispatcher, anyhow::Error> {
-        if self.cache.starts_with("http://") || self.cache.starts_with("https://") {
+        match self.cache.as_slice() {
+            [cache] => self.to_single_cache(cache),
+            [local, remote] => {
+                match (self.to_single_cache(local)?, self.to_single_cache(remote)?) {
+                    (CacheDispatcher::Filesystem(local), CacheDispatcher::Remote(remote)) => Ok(
+                        CacheDispatcher::Tiered(Box::new(TieredCache::new(local, *remote))),
+                    ),
+                    _ => Err(anyhow!(
+                        "A tiered cache requires a cache directory followed by a remote cache URL, and does not support --chunked."
+                    )),
+                }
+            }
+            _ => Err(anyhow!("At most two caches can be given.")),
+        }
+    }
+
+    fn to_single_cache(&self, cache: &str) -> Result<CacheDispatcher, anyhow::Error> {
+        if cache.starts_with("http://") || cache.starts_with("https://") {
             if let Some(auth_token_file) = &self.auth_token_file {
                 let auth_private_key_meta = fs::metadata(auth_token_file)
                     .with_context(|| format!("stat on {}", auth_token_file.display()))?;
@@ -178,23 +534,35 @@ impl CacheRef {
                 })?;
                 let token = UnverifiedBiscuit::from_base64(token_bytes.trim_ascii())
                     .with_context(|| "Could not parse authentication token")?;
-                let http_client = if self.root_cert.is_empty() {
-                    HttpClient::default()
-                } else {
-                    HttpClient::with_tls_root_cert_paths(&self.root_cert)
-                }?;
-                Ok(CacheDispatcher::Remote(Box::new(RemoteCache::new(
-                    Url::parse(&self.cache)?,
-                    http_client,
-                    token,
-                )?)))
+                let retry_policy = RetryPolicy {
+                    max_retries: self.retries,
+                    initial_backoff: *self.retry_backoff.as_ref(),
+                    ..RetryPolicy::default()
+                };
+                Ok(CacheDispatcher::Remote(Box::new(
+                    RemoteCache::new(Url::parse(cache)?, self.http_client()?, token)?
+                        .with_retry_policy(retry_policy),
+                )))
             } else {
                 Err(anyhow!(
                     "Authentication token is required for remote cache.",
                 ))
             }
+        } else if let Some(bucket_and_prefix) = cache.strip_prefix("s3://") {
+            let (bucket, prefix) = bucket_and_prefix
+                .split_once('/')
+                .unwrap_or((bucket_and_prefix, ""));
+            if bucket.is_empty() {
+                return Err(anyhow!("Missing bucket name in S3 cache URL: {cache}"));
+            }
+            let config =
+                S3Config::from_env(bucket, prefix).with_context(|| "Invalid S3 configuration")?;
+            Ok(
+                CacheDispatcher::S3(LocalCache::new(S3Storage::new(self.http_client()?, config)))
+                    .with_chunking(self.chunked),
+            )
         } else {
-            let path = PathBuf::from(&self.cache)
+            let path = PathBuf::from(cache)
                 .canonicalize()
                 .and_then(|path| {
                     if !path.is_dir() {
@@ -205,11 +573,275 @@ impl CacheRef {
                     }
                     Ok(path)
                 })
-                .with_context(|| format!("Could not access cache: {}", &self.cache))?;
+                .with_context(|| format!("Could not access cache: {cache}"))?;
             let storage = FilesystemStorage::new(path);
-            Ok(CacheDispatcher::Filesystem(LocalCache::new(storage)))
+            Ok(CacheDispatcher::Filesystem(LocalCache::new(storage)).with_chunking(self.chunked))
         }
     }
+
+    /// Returns the given cache locations for use in messages.
+    fn display(&self) -> String {
+        self.cache.join(", ")
+    }
+
+    fn http_client(&self) -> Result<HttpClient, anyhow::Error> {
+        let client = if self.root_cert.is_empty() {
+            HttpClient::default()
+        } else {
+            HttpClient::with_tls_root_cert_paths(&self.root_cert)
+        }?;
+        let proxy_config = ProxyConfig::from_env().context("Invalid proxy configuration")?;
+        Ok(client
+            .with_proxy_config(proxy_config)
+            .with_timeouts(Timeouts {
+                connect: Some(*self.connect_timeout.as_ref()),
+                read: Some(*self.read_timeout.as_ref()),
+                write: Some(*self.write_timeout.as_ref()),
+                total: Some(*self.request_timeout.as_ref()),
+            }))
+    }
+}
+
+/// Splits the comma-separated templates and expands the expressions in the non-empty ones.
+fn expand_templates(templates: &[String]) -> Result<Vec<String>, anyhow::Error> {
+    templates
+        .iter()
+        .flat_map(|templates| templating::split(templates))
+        .filter(|template| !template.is_empty())
+        .map(templating::expand)
+        .collect()
+}
+
+/// Outcome of restoring files from the cache.
+enum RestoreOutcome {
+    /// Files were restored from the primary (i.e. first listed) key.
+    PrimaryKey,
+    /// Files were restored from a non-primary key or a key matching a restore prefix.
+    NonPrimaryKey,
+    /// None of the keys was found in the cache.
+    Miss,
+}
+
+/// Condition under which storing files is skipped.
+enum SkipStore {
+    /// Always store the files.
+    Never,
+    /// Skip if the primary key already exists.
+    IfExists,
+    /// Skip if the primary key holds an entry with the same tree hash.
+    IfUnchanged,
+}
+
+fn restore(
+    entries_ref: &CacheEntriesRef,
+    keys: &[String],
+    restore_prefixes: &[String],
+    destination_dir: &Path,
+    on_cache_error: OnCacheError,
+) -> Result<RestoreOutcome, anyhow::Error> {
+    let restored_key = entries_ref
+        .to_pipeline(on_cache_error)?
+        .restore_with_prefixes(
+            &keys.iter().map(String::as_str).collect::<Vec<_>>(),
+            &restore_prefixes
+                .iter()
+                .map(String::as_str)
+                .collect::<Vec<_>>(),
+            destination_dir,
+        )
+        .with_context(|| format!("Could not restore to: {}", destination_dir.display()))?;
+    Ok(match restored_key {
+        Some(restored_key) => {
+            println!("Restored key {restored_key}");
+            if Some(restored_key.as_ref()) == keys.first().map(String::as_str) {
+                RestoreOutcome::PrimaryKey
+            } else {
+                RestoreOutcome::NonPrimaryKey
+            }
+        }
+        None => {
+            eprintln!("Keys not found in cache.");
+            RestoreOutcome::Miss
+        }
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+fn store(
+    entries_ref: &CacheEntriesRef,
+    keys: &[String],
+    source_dir: &Path,
+    exclude: &[String],
+    compression: &CompressionOpts,
+    ttl: Option<humantime::Duration>,
+    deduplicate: bool,
+    skip: SkipStore,
+    on_cache_error: OnCacheError,
+) -> Result<(), anyhow::Error> {
+    let ttl = ttl
+        .map(|ttl| chrono::TimeDelta::from_std(*ttl.as_ref()))
+        .transpose()?;
+    let mut override_builder = OverrideBuilder::new(source_dir);
+    for exclude_glob in exclude {
+        if let Some(inverted) = exclude_glob.strip_prefix('!') {
+            override_builder
+                .add(inverted)
+                .with_context(|| "Invalid exclude glob")?;
+        } else {
+            override_builder
+                .add(&format!("!{exclude_glob}"))
+                .with_context(|| "Invalid exclude glob")?;
+        }
+    }
+
+    let cache = ErrorHandlingCache::new(
+        entries_ref
+            .cache_ref
+            .to_cache()?
+            .with_deduplication(deduplicate),
+        on_cache_error,
+    );
+    let primary_key = keys[0].as_str();
+    let tree_hash = match skip {
+        SkipStore::Never => None,
+        SkipStore::IfExists => {
+            if cache.entry(primary_key)?.is_some() {
+                println!("Key {primary_key} already exists, skipping store");
+                return Ok(());
+            }
+            None
+        }
+        SkipStore::IfUnchanged => Some(hash_paths(&[source_dir])?),
+    };
+    let mut pipeline = Pipeline::new(cache)
+        .with_compression(compression.to_compression())
+        .with_ttl(ttl)
+        .with_tree_hash(tree_hash);
+    if tree_hash.is_some() && pipeline.stored_tree_hash(primary_key)? == tree_hash {
+        println!("Key {primary_key} is unchanged, skipping store");
+        return Ok(());
+    }
+    pipeline
+        .store_with_overrides(
+            &keys.iter().map(String::as_str).collect::<Vec<_>>(),
+            source_dir,
+            override_builder
+                .build()
+                .with_context(|| "Invalid exclude globs")?,
+        )
+        .with_context(|| format!("Could not cache: {}", source_dir.display()))
+}
+
+fn parse_prefix_quota(input: &str) -> Result<PrefixQuota, String> {
+    let (prefix, size) = input
+        .rsplit_once('=')
+        .ok_or_else(|| "expected PREFIX=SIZE".to_string())?;
+    Ok(PrefixQuota {
+        prefix: prefix.to_string(),
+        max_blob_size_sum: humanbytes::parse_bytes_from_str(size).map_err(|err| err.to_string())?,
+    })
+}
+
+fn print_clean_report(report: &CleanReport, json: bool) -> Result<(), anyhow::Error> {
+    if json {
+        let evictions: Vec<_> = report
+            .evictions
+            .iter()
+            .map(|eviction| {
+                serde_json::json!({
+                    "keys": eviction.keys,
+                    "size": eviction.blob_size,
+                    "latest_access": eviction
+                        .latest_access
+                        .to_rfc3339_opts(SecondsFormat::AutoSi, true),
+                    "reason": eviction.reason.as_str(),
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&evictions)?);
+        return Ok(());
+    }
+
+    let rows: Vec<_> = report
+        .evictions
+        .iter()
+        .map(|eviction| {
+            (
+                if eviction.keys.is_empty() {
+                    "<no keys>".to_string()
+                } else {
+                    eviction.keys.join(",")
+                },
+                eviction.blob_size.to_string(),
+                eviction
+                    .latest_access
+                    .to_rfc3339_opts(SecondsFormat::Secs, true),
+                eviction.reason.as_str(),
+            )
+        })
+        .collect();
+    let keys_width = rows.iter().map(|row| row.0.len()).fold(4, usize::max);
+    let size_width = rows.iter().map(|row| row.1.len()).fold(4, usize::max);
+    let access_width = rows.iter().map(|row| row.2.len()).fold(13, usize::max);
+    println!(
+        "{:keys_width$}  {:>size_width$}  {:access_width$}  REASON",
+        "KEYS", "SIZE", "LATEST ACCESS"
+    );
+    for (keys, size, latest_access, reason) in rows {
+        println!(
+            "{keys:keys_width$}  {size:>size_width$}  {latest_access:access_width$}  {reason}"
+        );
+    }
+    println!(
+        "Would delete {} blobs ({} bytes).",
+        report.evictions.len(),
+        report.freed_size()
+    );
+    Ok(())
+}
+
+fn print_entries(entries: &[CacheEntry], format: OutputFormat) -> Result<(), anyhow::Error> {
+    match format {
+        OutputFormat::Table => {
+            let rows: Vec<_> = entries
+                .iter()
+                .map(|entry| {
+                    (
+                        entry.key.as_str(),
+                        entry.size.to_string(),
+                        entry
+                            .latest_access
+                            .to_rfc3339_opts(SecondsFormat::Secs, true),
+                    )
+                })
+                .collect();
+            let key_width = rows.iter().map(|row| row.0.len()).fold(3, usize::max);
+            let size_width = rows.iter().map(|row| row.1.len()).fold(4, usize::max);
+            println!(
+                "{:key_width$}  {:>size_width$}  LATEST ACCESS",
+                "KEY", "SIZE"
+            );
+            for (key, size, latest_access) in rows {
+                println!("{key:key_width$}  {size:>size_width$}  {latest_access}");
+            }
+        }
+        OutputFormat::Json => {
+            let entries: Vec<_> = entries
+                .iter()
+                .map(|entry| {
+                    serde_json::json!({
+                        "key": entry.key,
+                        "size": entry.size,
+                        "latest_access": entry
+                            .latest_access
+                            .to_rfc3339_opts(SecondsFormat::AutoSi, true),
+                    })
+                })
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&entries)?);
+        }
+    }
+    Ok(())
 }
 
 fn main() -> Result<ExitCode, anyhow::Error> {
@@ -219,67 +851,242 @@ fn main() -> Result<ExitCode, anyhow::Error> {
             cache_ref,
             max_age,
             max_size,
+            quotas,
+            policy,
+            dry_run,
+            json,
+        } => {
+            let options = CleanOptions {
+                max_unused_age: max_age
+                    .map(|max_age| chrono::TimeDelta::from_std(*max_age.as_ref()))
+                    .transpose()?,
+                max_blob_size_sum: max_size,
+                prefix_quotas: quotas,
+                policy: policy.into(),
+            };
+            if dry_run {
+                let report = match cache_ref.to_cache()? {
+                    CacheDispatcher::Filesystem(cache) => cache.clean_dry_run(&options)?,
+                    CacheDispatcher::S3(cache) => cache.clean_dry_run(&options)?,
+                    CacheDispatcher::ChunkedFilesystem(cache) => cache.clean_dry_run(&options)?,
+                    CacheDispatcher::ChunkedS3(cache) => cache.clean_dry_run(&options)?,
+                    CacheDispatcher::Tiered(cache) => cache.local().clean_dry_run(&options)?,
+                    _ => return Err(anyhow!("Only local and S3 caches can be cleaned.")),
+                };
+                print_clean_report(&report, json)?;
+                return Ok(ExitCode::SUCCESS);
+            }
+            match cache_ref.to_cache()? {
+                CacheDispatcher::Filesystem(mut cache) => {
+                    cache.clean(&options)?;
+                    cache
+                        .into_storage()
+                        .context("Cache storage is still in use")?
+                        .clean_leftover_tmp_files()?;
+                }
+                CacheDispatcher::S3(mut cache) => {
+                    cache.clean(&options)?;
+                }
+                CacheDispatcher::ChunkedFilesystem(mut cache) => {
+                    cache.clean(&options)?;
+                    cache
+                        .into_inner()
+                        .into_storage()
+                        .context("Cache storage is still in use")?
+                        .clean_leftover_tmp_files()?;
+                }
+                CacheDispatcher::ChunkedS3(mut cache) => {
+                    cache.clean(&options)?;
+                }
+                CacheDispatcher::Tiered(cache) => {
+                    let (mut cache, _) = cache.into_inner();
+                    cache.clean(&options)?;
+                    cache
+                        .into_storage()
+                        .context("Cache storage is still in use")?
+                        .clean_leftover_tmp_files()?;
+                }
+                _ => return Err(anyhow!("Only local and S3 caches can be cleaned.")),
+            }
+        }
+        Commands::Delete { entries_ref } => {
+            entries_ref
+                .cache_ref
+                .to_cache()?
+                .delete(
+                    &entries_ref
+                        .keys()?
+                        .iter()
+                        .map(String::as_str)
+                        .collect::<Vec<_>>(),
+                )
+                .with_context(|| {
+                    format!(
+                        "Could not delete from cache: {}",
+                        entries_ref.cache_ref.display()
+                    )
+                })?;
+        }
+        Commands::Exec {
+            entries_ref,
+            path,
+            restore_prefix,
+            run_on_non_primary_key,
+            exclude,
+            compression,
+            ttl,
+            deduplicate,
+            on_cache_error,
+            command,
         } => {
-            if let CacheDispatcher::Filesystem(mut cache) = cache_ref.to_cache()? {
-                cache.clean(
-                    max_age
-                        .map(|max_age| chrono::TimeDelta::from_std(*max_age.as_ref()))
-                        .transpose()?,
-                    max_size,
-                )?;
-                cache.into_storage().clean_leftover_tmp_files()?;
+            let keys = entries_ref.keys()?;
+            match restore(
+                &entries_ref,
+                &keys,
+                &expand_templates(&restore_prefix)?,
+                &path,
+                on_cache_error,
+            )? {
+                RestoreOutcome::PrimaryKey => return Ok(ExitCode::SUCCESS),
+                RestoreOutcome::NonPrimaryKey if !run_on_non_primary_key => {
+                    return Ok(ExitCode::SUCCESS);
+                }
+                RestoreOutcome::NonPrimaryKey | RestoreOutcome::Miss => {}
+            }
+
+            let status = process::Command::new(&command[0])
+                .args(&command[1..])
+                .status()
+                .with_context(|| format!("Could not run command: {}", command[0]))?;
+            if !status.success() {
+                return Ok(match status.code() {
+                    Some(code) => ExitCode::from(code as u8),
+                    None => ExitCode::from(128 + status.signal().unwrap_or(0) as u8),
+                });
+            }
+            store(
+                &entries_ref,
+                &keys,
+                &path,
+                &exclude,
+                &compression,
+                ttl,
+                deduplicate,
+                SkipStore::Never,
+                on_cache_error,
+            )?;
+        }
+        Commands::Fsck { cache_ref, repair } => {
+            let inconsistencies = match cache_ref.to_cache()? {
+                CacheDispatcher::Filesystem(cache) => cache.fsck(repair)?,
+                CacheDispatcher::S3(cache) => cache.fsck(repair)?,
+                CacheDispatcher::ChunkedFilesystem(cache) => cache.fsck(repair)?,
+                CacheDispatcher::ChunkedS3(cache) => cache.fsck(repair)?,
+                _ => return Err(anyhow!("Only local and S3 caches can be checked.")),
+            };
+            for inconsistency in &inconsistencies {
+                println!("{inconsistency}");
+            }
+            if inconsistencies.is_empty() {
+                println!("No inconsistencies found.");
+            } else if repair {
+                println!("Repaired {} inconsistencies.", inconsistencies.len());
+            } else {
+                println!(
+                    "Found {} inconsistencies. Run with --repair to repair them.",
+                    inconsistencies.len()
+                );
+                return Ok(ExitCode::from(3));
             }
         }
         Commands::Hash { path } => {
             println!("{}", hash_paths(&path)?.to_hex());
         }
+        Commands::List {
+            cache_ref,
+            prefix,
+            format,
+        } => {
+            let prefix = prefix
+                .as_deref()
+                .map(templating::expand)
+                .transpose()?
+                .unwrap_or_default();
+            let entries = cache_ref.to_cache()?.list(&prefix).with_context(|| {
+                format!("Could not list entries of cache: {}", cache_ref.display())
+            })?;
+            print_entries(&entries, format)?;
+        }
+        Commands::Pin { entries_ref } => {
+            let keys = entries_ref.keys()?;
+            let keys: Vec<_> = keys.iter().map(String::as_str).collect();
+            match entries_ref.cache_ref.to_cache()? {
+                CacheDispatcher::Filesystem(cache) => cache.pin(&keys)?,
+                CacheDispatcher::S3(cache) => cache.pin(&keys)?,
+                CacheDispatcher::ChunkedFilesystem(cache) => cache.pin(&keys)?,
+                CacheDispatcher::ChunkedS3(cache) => cache.pin(&keys)?,
+                _ => return Err(anyhow!("Only local and S3 caches support pinning.")),
+            }
+        }
         Commands::Store {
             entries_ref,
             source_dir,
             exclude,
+            compression,
+            ttl,
+            deduplicate,
+            on_cache_error,
+            skip_if_exists,
+            skip_if_unchanged,
         } => {
-            let mut override_builder = OverrideBuilder::new(&source_dir);
-            for exclude_glob in exclude {
-                if let Some(inverted) = exclude_glob.strip_prefix('!') {
-                    override_builder
-                        .add(inverted)
-                        .with_context(|| "Invalid exclude glob")?;
-                } else {
-                    override_builder
-                        .add(&format!("!{exclude_glob}"))
-                        .with_context(|| "Invalid exclude glob")?;
-                }
-            }
-
-            entries_ref
-                .to_pipeline()?
-                .store_with_overrides(
-                    &entries_ref.keys(),
-                    &source_dir,
-                    override_builder
-                        .build()
-                        .with_context(|| "Invalid exclude globs")?,
-                )
-                .with_context(|| format!("Could not cache: {}", source_dir.display()))?;
+            let skip = if skip_if_exists {
+                SkipStore::IfExists
+            } else if skip_if_unchanged {
+                SkipStore::IfUnchanged
+            } else {
+                SkipStore::Never
+            };
+            store(
+                &entries_ref,
+                &entries_ref.keys()?,
+                &source_dir,
+                &exclude,
+                &compression,
+                ttl,
+                deduplicate,
+                skip,
+                on_cache_error,
+            )?;
         }
         Commands::Restore {
             entries_ref,
             destination_dir,
+            restore_prefix,
             success_rc_on_any_key,
+            on_cache_error,
         } => {
-            if let Some(restored_key) = entries_ref
-                .to_pipeline()?
-                .restore(&entries_ref.keys(), &destination_dir)
-                .with_context(|| format!("Could not restore to: {}", destination_dir.display()))?
-            {
-                println!("Restored key {restored_key}");
-                let primary_key = entries_ref.keys.first().map(String::as_str);
-                if !success_rc_on_any_key && Some(restored_key) != primary_key {
-                    return Ok(ExitCode::from(3));
-                }
-            } else {
-                eprintln!("Keys not found in cache.");
-                return Ok(ExitCode::from(4));
+            match restore(
+                &entries_ref,
+                &entries_ref.keys()?,
+                &expand_templates(&restore_prefix)?,
+                &destination_dir,
+                on_cache_error,
+            )? {
+                RestoreOutcome::PrimaryKey => {}
+                RestoreOutcome::NonPrimaryKey if success_rc_on_any_key => {}
+                RestoreOutcome::NonPrimaryKey => return Ok(ExitCode::from(3)),
+                RestoreOutcome::Miss => return Ok(ExitCode::from(4)),
+            }
+        }
+        Commands::Unpin { entries_ref } => {
+            let keys = entries_ref.keys()?;
+            let keys: Vec<_> = keys.iter().map(String::as_str).collect();
+            match entries_ref.cache_ref.to_cache()? {
+                CacheDispatcher::Filesystem(cache) => cache.unpin(&keys)?,
+                CacheDispatcher::S3(cache) => cache.unpin(&keys)?,
+                CacheDispatcher::ChunkedFilesystem(cache) => cache.unpin(&keys)?,
+                CacheDispatcher::ChunkedS3(cache) => cache.unpin(&keys)?,
+                _ => return Err(anyhow!("Only local and S3 caches support pinning.")),
             }
         }
     }
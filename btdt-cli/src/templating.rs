@@ -0,0 +1,384 @@
+//! Expansion of `${...}` expressions in cache keys.
+//!
+//! Supported expressions are:
+//!
+//! - `'literal'` or `"literal"`: A string literal. The quote character can be included by
+//!   doubling it.
+//! - `env.NAME`: The value of the environment variable `NAME`.
+//! - `hashFiles('pattern', ...)`: The hash of all files matching any of the glob patterns
+//!   (relative to the working directory). Patterns prefixed with `!` exclude matching files.
+
+use crate::hashing::hash_paths;
+use anyhow::{Context, anyhow, bail};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// Splits a comma-separated list of templates, ignoring commas within expressions.
+pub fn split(templates: &str) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut part_start = 0;
+    let mut in_expression = false;
+    let mut quote = None;
+    let mut chars = templates.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match (in_expression, quote, c) {
+            (false, _, '$') if chars.peek().is_some_and(|&(_, next)| next == '{') => {
+                chars.next();
+                in_expression = true;
+            }
+            (false, _, ',') => {
+                parts.push(&templates[part_start..i]);
+                part_start = i + 1;
+            }
+            (true, None, '\'' | '"') => quote = Some(c),
+            (true, None, '}') => in_expression = false,
+            (true, Some(q), _) if q == c => quote = None,
+            _ => {}
+        }
+    }
+    parts.push(&templates[part_start..]);
+    parts
+}
+
+/// Expands all expressions in the template using the current working directory and environment.
+pub fn expand(template: &str) -> anyhow::Result<String> {
+    let working_dir = env::current_dir().with_context(|| "Could not get working directory")?;
+    expand_with(template, &working_dir, |name| env::var(name).ok())
+}
+
+/// Expands all expressions in the template, resolving file patterns relative to `working_dir` and
+/// looking up environment variables with `env_var`.
+pub fn expand_with(
+    template: &str,
+    working_dir: &Path,
+    env_var: impl Fn(&str) -> Option<String>,
+) -> anyhow::Result<String> {
+    let mut parser = Parser {
+        input: template,
+        pos: 0,
+        working_dir,
+        env_var: &env_var,
+    };
+    parser
+        .template()
+        .with_context(|| format!("Could not expand key template: {template}"))
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+    working_dir: &'a Path,
+    env_var: &'a dyn Fn(&str) -> Option<String>,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &str {
+        &self.input[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn expect(&mut self, expected: char) -> anyhow::Result<()> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.pos += c.len_utf8();
+                Ok(())
+            }
+            Some(c) => bail!(
+                "Expected `{expected}` at position {}, found `{c}`",
+                self.pos
+            ),
+            None => bail!("Expected `{expected}` at position {}", self.pos),
+        }
+    }
+
+    fn template(&mut self) -> anyhow::Result<String> {
+        let mut output = String::with_capacity(self.input.len());
+        while let Some(start) = self.rest().find("${") {
+            output.push_str(&self.rest()[..start]);
+            self.pos += start + 2;
+            output.push_str(&self.expression()?);
+            self.expect('}')?;
+        }
+        output.push_str(self.rest());
+        Ok(output)
+    }
+
+    fn expression(&mut self) -> anyhow::Result<String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(quote @ ('\'' | '"')) => self.string_literal(quote),
+            Some(_) => {
+                let start = self.pos;
+                let name = self.identifier()?;
+                match self.peek() {
+                    Some('.') if name == "env" => {
+                        self.pos += 1;
+                        let var = self.identifier()?;
+                        (self.env_var)(var)
+                            .ok_or_else(|| anyhow!("Environment variable `{var}` is not set"))
+                    }
+                    Some('.') => bail!("Unknown context `{name}` at position {start}"),
+                    _ => {
+                        self.skip_whitespace();
+                        if self.peek() != Some('(') {
+                            bail!("Unknown identifier `{name}` at position {start}");
+                        }
+                        let args = self.arguments()?;
+                        self.call(name, start, &args)
+                    }
+                }
+            }
+            None => bail!("Unterminated expression at position {}", self.pos),
+        }
+    }
+
+    fn identifier(&mut self) -> anyhow::Result<&'a str> {
+        let input = self.input;
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        if len == 0 {
+            bail!("Expected identifier at position {}", self.pos);
+        }
+        let start = self.pos;
+        self.pos += len;
+        Ok(&input[start..self.pos])
+    }
+
+    fn string_literal(&mut self, quote: char) -> anyhow::Result<String> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut value = String::new();
+        loop {
+            let Some(end) = self.rest().find(quote) else {
+                bail!("Unterminated string literal at position {start}");
+            };
+            value.push_str(&self.rest()[..end]);
+            self.pos += end + 1;
+            if self.peek() == Some(quote) {
+                value.push(quote);
+                self.pos += 1;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn arguments(&mut self) -> anyhow::Result<Vec<String>> {
+        self.expect('(')?;
+        let mut args = vec![];
+        self.skip_whitespace();
+        if self.peek() == Some(')') {
+            self.pos += 1;
+            return Ok(args);
+        }
+        loop {
+            args.push(self.expression()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                _ => {
+                    self.expect(')')?;
+                    return Ok(args);
+                }
+            }
+        }
+    }
+
+    fn call(&self, name: &str, start: usize, args: &[String]) -> anyhow::Result<String> {
+        match name {
+            "hashFiles" => hash_files(self.working_dir, args),
+            _ => bail!("Unknown function `{name}` at position {start}"),
+        }
+    }
+}
+
+fn hash_files(working_dir: &Path, patterns: &[String]) -> anyhow::Result<String> {
+    if patterns.is_empty() {
+        bail!("hashFiles requires at least one pattern");
+    }
+    let mut include = GlobSetBuilder::new();
+    let mut exclude = GlobSetBuilder::new();
+    for pattern in patterns {
+        match pattern.strip_prefix('!') {
+            Some(pattern) => exclude.add(glob(pattern)?),
+            None => include.add(glob(pattern)?),
+        };
+    }
+    let (include, exclude) = (include.build()?, exclude.build()?);
+
+    let mut paths = matching_files(working_dir, &include, &exclude)?;
+    if paths.is_empty() {
+        bail!("No files match the patterns: {}", patterns.join(", "));
+    }
+    paths.sort_unstable();
+    Ok(hash_paths(&paths)?.to_hex().to_string())
+}
+
+fn glob(pattern: &str) -> anyhow::Result<Glob> {
+    globset::GlobBuilder::new(pattern.trim_start_matches("./"))
+        .literal_separator(true)
+        .build()
+        .with_context(|| format!("Invalid glob pattern: {pattern}"))
+}
+
+fn matching_files(
+    working_dir: &Path,
+    include: &GlobSet,
+    exclude: &GlobSet,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let mut paths = vec![];
+    for entry in WalkBuilder::new(working_dir)
+        .standard_filters(false)
+        .build()
+    {
+        let entry = entry?;
+        if !entry
+            .file_type()
+            .is_some_and(|file_type| file_type.is_file())
+        {
+            continue;
+        }
+        let relative_path = entry.path().strip_prefix(working_dir)?;
+        if include.is_match(relative_path) && !exclude.is_match(relative_path) {
+            paths.push(entry.into_path());
+        }
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing::hash_path;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn expand_in(template: &str, working_dir: &Path) -> anyhow::Result<String> {
+        expand_with(template, working_dir, |name| match name {
+            "RUNNER_OS" => Some("Linux".to_string()),
+            _ => None,
+        })
+    }
+
+    fn expand_str(template: &str) -> anyhow::Result<String> {
+        expand_in(template, Path::new("."))
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(split("a,b,,c"), vec!["a", "b", "", "c"]);
+        assert_eq!(
+            split("a-${hashFiles('x', '}', ',')},b"),
+            vec!["a-${hashFiles('x', '}', ',')}", "b"]
+        );
+        assert_eq!(split("$,{a,b}"), vec!["$", "{a", "b}"]);
+    }
+
+    #[test]
+    fn test_returns_template_without_expressions_unchanged() {
+        assert_eq!(expand_str("cache-key").unwrap(), "cache-key");
+        assert_eq!(expand_str("$cache-{key}").unwrap(), "$cache-{key}");
+    }
+
+    #[test]
+    fn test_expands_literals() {
+        assert_eq!(expand_str("a-${'b'}-c").unwrap(), "a-b-c");
+        assert_eq!(expand_str(r#"${ "b" }"#).unwrap(), "b");
+        assert_eq!(expand_str("${'it''s'}").unwrap(), "it's");
+        assert_eq!(expand_str("${'${'}").unwrap(), "${");
+    }
+
+    #[test]
+    fn test_expands_env_vars() {
+        assert_eq!(expand_str("key-${env.RUNNER_OS}").unwrap(), "key-Linux");
+        assert_eq!(
+            expand_str("key-${env.UNSET}")
+                .unwrap_err()
+                .root_cause()
+                .to_string(),
+            "Environment variable `UNSET` is not set"
+        );
+    }
+
+    #[test]
+    fn test_expands_hash_files() {
+        let tempdir = tempdir().unwrap();
+        fs::create_dir_all(tempdir.path().join("sub/node_modules/dep")).unwrap();
+        fs::write(tempdir.path().join("package-lock.json"), "root").unwrap();
+        fs::write(tempdir.path().join("sub/package-lock.json"), "sub").unwrap();
+        fs::write(
+            tempdir
+                .path()
+                .join("sub/node_modules/dep/package-lock.json"),
+            "dep",
+        )
+        .unwrap();
+
+        assert_eq!(
+            expand_in("${hashFiles('package-lock.json')}", tempdir.path()).unwrap(),
+            hash_path(&tempdir.path().join("package-lock.json"))
+                .unwrap()
+                .to_hex()
+                .as_str()
+        );
+        assert_eq!(
+            expand_in(
+                "${hashFiles('**/package-lock.json', '!**/node_modules/**')}",
+                tempdir.path()
+            )
+            .unwrap(),
+            hash_paths(&[
+                tempdir.path().join("package-lock.json"),
+                tempdir.path().join("sub/package-lock.json"),
+            ])
+            .unwrap()
+            .to_hex()
+            .as_str()
+        );
+    }
+
+    #[test]
+    fn test_hash_files_fails_if_nothing_matches() {
+        let tempdir = tempdir().unwrap();
+        assert_eq!(
+            expand_in("${hashFiles('*.lock')}", tempdir.path())
+                .unwrap_err()
+                .root_cause()
+                .to_string(),
+            "No files match the patterns: *.lock"
+        );
+    }
+
+    #[test]
+    fn test_reports_errors() {
+        for (template, error) in [
+            ("${foo('x')}", "Unknown function `foo` at position 2"),
+            ("${github.sha}", "Unknown context `github` at position 2"),
+            ("${foo}", "Unknown identifier `foo` at position 2"),
+            ("${'x'", "Expected `}` at position 5"),
+            ("${'x", "Unterminated string literal at position 2"),
+            ("${", "Unterminated expression at position 2"),
+            ("${'a' 'b'}", "Expected `}` at position 6, found `'`"),
+            ("${hashFiles()}", "hashFiles requires at least one pattern"),
+        ] {
+            assert_eq!(
+                expand_str(template).unwrap_err().root_cause().to_string(),
+                error,
+                "template: {template}"
+            );
+        }
+    }
+}
@@ -28,14 +28,14 @@ impl CacheFixture {
             let mut file = File::create_new(tmp.path().join("a.txt"))?;
             file.write_all(b"lorem ipsum\n")?;
         }
-        cache_pipeline.store(&["cache-key-0", "cache-key-1"], &tmp.path())?;
+        cache_pipeline.store(&["cache-key-0", "cache-key-1"], tmp.path())?;
 
         let tmp = tempdir()?;
         {
             let mut file = File::create_new(tmp.path().join("b.txt"))?;
             file.write_all(b"wrong file restored\n")?;
         }
-        cache_pipeline.store(&["other-cache-key"], &tmp.path())?;
+        cache_pipeline.store(&["other-cache-key"], tmp.path())?;
 
         Ok(Self { cache_dir })
     }
@@ -30,7 +30,7 @@ pub fn bench_stream_adapter(c: &mut Criterion) {
     let mut harness = BenchHarness::default();
     let mut group = c.benchmark_group("StreamAdapter");
     #[allow(non_snake_case)]
-    for size_kB in [1, 10, 1 * 1024, 10 * 1024, 100 * 1024] {
+    for size_kB in [1, 10, 1024, 10 * 1024, 100 * 1024] {
         let size = size_kB * 1024;
         let input_path = harness.tempdir.path().join("input");
         File::create_filled(&input_path, size, &mut harness.rng).unwrap();
@@ -42,7 +42,7 @@ impl BtdtTestServer {
     /// Build the btdt-server binary in test profile.
     pub fn build() {
         let mut build_command = Command::new("cargo");
-        build_command.args(&[
+        build_command.args([
             "build",
             "--profile",
             "test",
@@ -153,7 +153,7 @@ impl BtdtTestServer {
     pub fn is_ready(&self) -> bool {
         self.get("/api/health")
             .send()
-            .map_or(false, |r| r.error_for_status().is_ok())
+            .is_ok_and(|r| r.error_for_status().is_ok())
     }
 
     /// Wait until the server is ready or timeout after 5 seconds.
@@ -1,22 +1,28 @@
+use crate::app::get_entry::GetEntryResponse;
 use crate::app::get_from_cache::GetFromCacheResponse;
+use crate::app::list_keys::ListKeysResponse;
 use biscuit_auth::builder_ext::AuthorizerExt;
 use biscuit_auth::macros::authorizer;
 use biscuit_auth::{Biscuit, KeyPair};
-use btdt::cache::Cache;
 use btdt::cache::cache_dispatcher::CacheDispatcher;
+use btdt::cache::{Cache, SetOptions};
+use btdt::error::IoPathError;
 use btdt::util::close::Close;
+use chrono::TimeDelta;
 use poem::Body;
 use poem::http::StatusCode;
 use poem_openapi::auth::Bearer;
 use poem_openapi::param::{Path, Query};
-use poem_openapi::payload::{PlainText, Response};
+use poem_openapi::payload::{Json, PlainText, Response};
 use poem_openapi::{OpenApi, OpenApiService, SecurityScheme};
 use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
 use tokio::task::spawn_blocking;
 use tokio_util::io::SyncIoBridge;
 
 pub struct Api {
-    caches: HashMap<String, CacheDispatcher>,
+    caches: HashMap<String, Arc<CacheDispatcher>>,
     auth_key_pair: KeyPair,
 }
 
@@ -26,7 +32,10 @@ pub fn create_openapi_service(
 ) -> OpenApiService<Api, ()> {
     OpenApiService::new(
         Api {
-            caches,
+            caches: caches
+                .into_iter()
+                .map(|(cache_id, cache)| (cache_id, Arc::new(cache)))
+                .collect(),
             auth_key_pair,
         },
         "btdt server API",
@@ -37,6 +46,8 @@ pub fn create_openapi_service(
 enum Operation {
     GetFromCache,
     PutIntoCache,
+    ListKeys,
+    DeleteFromCache,
 }
 
 impl Operation {
@@ -44,6 +55,8 @@ impl Operation {
         match self {
             Operation::GetFromCache => "get",
             Operation::PutIntoCache => "put",
+            Operation::ListKeys => "list",
+            Operation::DeleteFromCache => "delete",
         }
     }
 }
@@ -88,6 +101,21 @@ impl BiscuitBearerAuth {
     }
 }
 
+/// Runs a blocking cache operation on a thread where blocking is acceptable, to not stall the
+/// async runtime.
+async fn run_blocking<T, E>(
+    operation: impl FnOnce() -> Result<T, E> + Send + 'static,
+) -> Result<T, poem::Error>
+where
+    T: Send + 'static,
+    E: Error + Send + Sync + 'static,
+{
+    spawn_blocking(operation)
+        .await
+        .map_err(poem::error::InternalServerError)?
+        .map_err(poem::error::InternalServerError)
+}
+
 #[OpenApi]
 impl Api {
     /// Health check endpoint
@@ -99,43 +127,81 @@ impl Api {
     }
 
     /// Returns the data stored under the first given key found in the cache. If none
-    /// of the keys is found, 204 "no content" is returned.
+    /// of the keys is found, the data stored under the most recently accessed key starting with
+    /// the first matching prefix is returned. If neither a key nor a prefix matches, 204
+    /// "no content" is returned.
     #[oai(path = "/caches/:cache_id", method = "get")]
     async fn get_from_cache(
         &self,
         cache_id: Path<String>,
         key: Query<Vec<String>>,
+        prefix: Query<Vec<String>>,
         auth: BiscuitBearerAuth,
     ) -> Result<GetFromCacheResponse, poem::Error> {
         auth.authorize(Operation::GetFromCache, &cache_id.0, &self.auth_key_pair)?;
         Ok(match self.caches.get(&cache_id.0) {
             Some(cache) => {
-                match cache
-                    .get(&key.0.iter().map(String::as_ref).collect::<Vec<_>>())
-                    .map_err(poem::error::InternalServerError)?
-                {
-                    None => GetFromCacheResponse::CacheMiss,
-                    Some(cache_hit) => cache_hit.into(),
-                }
+                let cache = cache.clone();
+                run_blocking(move || {
+                    let cache_hit =
+                        match cache.get(&key.0.iter().map(String::as_ref).collect::<Vec<_>>())? {
+                            Some(cache_hit) => Some(cache_hit.into()),
+                            None => cache
+                                .get_by_prefix(
+                                    &prefix.0.iter().map(String::as_ref).collect::<Vec<_>>(),
+                                )?
+                                .map(Into::into),
+                        };
+                    Ok::<_, IoPathError>(cache_hit.unwrap_or(GetFromCacheResponse::CacheMiss))
+                })
+                .await?
             }
             None => GetFromCacheResponse::CacheNotFound,
         })
     }
 
     /// Stores the data under all the given keys in the cache.
+    ///
+    /// If a TTL in seconds is given, the entries expire after this duration. If a tree hash (hex
+    /// encoded BLAKE3 hash) is given, it is recorded in the entries and returned when listing
+    /// them.
     #[oai(path = "/caches/:cache_id", method = "put")]
     async fn put_into_cache(
         &self,
         cache_id: Path<String>,
         key: Query<Vec<String>>,
+        ttl: Query<Option<u64>>,
+        tree_hash: Query<Option<String>>,
         body: Body,
         auth: BiscuitBearerAuth,
     ) -> Result<Response<()>, poem::Error> {
         auth.authorize(Operation::PutIntoCache, &cache_id, &self.auth_key_pair)?;
+        let ttl = ttl
+            .0
+            .map(|ttl| {
+                i64::try_from(ttl)
+                    .ok()
+                    .and_then(TimeDelta::try_seconds)
+                    .ok_or_else(|| {
+                        poem::Error::from_string("TTL out of range", StatusCode::BAD_REQUEST)
+                    })
+            })
+            .transpose()?;
+        let tree_hash = tree_hash
+            .0
+            .map(|tree_hash| {
+                blake3::Hash::from_hex(tree_hash).map_err(|_| {
+                    poem::Error::from_string("Invalid tree hash", StatusCode::BAD_REQUEST)
+                })
+            })
+            .transpose()?;
         Ok(match self.caches.get(&cache_id.0) {
             Some(cache) => {
                 let mut writer = cache
-                    .set(&key.0.iter().map(String::as_ref).collect::<Vec<_>>())
+                    .set_with_options(
+                        &key.0.iter().map(String::as_ref).collect::<Vec<_>>(),
+                        &SetOptions { ttl, tree_hash },
+                    )
                     .map_err(poem::error::InternalServerError)?;
                 let mut sync_reader = SyncIoBridge::new(body.into_async_read());
                 spawn_blocking(move || {
@@ -150,6 +216,78 @@ impl Api {
             None => Response::new(()).status(StatusCode::NOT_FOUND),
         })
     }
+
+    /// Deletes the given keys from the cache.
+    ///
+    /// Keys not found in the cache are ignored. The data stored under a key is deleted once no
+    /// other key refers to it anymore.
+    #[oai(path = "/caches/:cache_id", method = "delete")]
+    async fn delete_from_cache(
+        &self,
+        cache_id: Path<String>,
+        key: Query<Vec<String>>,
+        auth: BiscuitBearerAuth,
+    ) -> Result<Response<()>, poem::Error> {
+        auth.authorize(Operation::DeleteFromCache, &cache_id.0, &self.auth_key_pair)?;
+        Ok(match self.caches.get(&cache_id.0) {
+            Some(cache) => {
+                let cache = cache.clone();
+                run_blocking(move || {
+                    cache.delete(&key.0.iter().map(String::as_ref).collect::<Vec<_>>())
+                })
+                .await?;
+                Response::new(()).status(StatusCode::NO_CONTENT)
+            }
+            None => Response::new(()).status(StatusCode::NOT_FOUND),
+        })
+    }
+
+    /// Returns the entries in the cache whose key starts with the given prefix, sorted by key.
+    ///
+    /// If no prefix is given, all entries are returned.
+    #[oai(path = "/caches/:cache_id/keys", method = "get")]
+    async fn list_keys(
+        &self,
+        cache_id: Path<String>,
+        prefix: Query<Option<String>>,
+        auth: BiscuitBearerAuth,
+    ) -> Result<ListKeysResponse, poem::Error> {
+        auth.authorize(Operation::ListKeys, &cache_id.0, &self.auth_key_pair)?;
+        Ok(match self.caches.get(&cache_id.0) {
+            Some(cache) => {
+                let cache = cache.clone();
+                let entries =
+                    run_blocking(move || cache.list(prefix.0.as_deref().unwrap_or_default()))
+                        .await?;
+                ListKeysResponse::Entries(Json(entries.into_iter().map(Into::into).collect()))
+            }
+            None => ListKeysResponse::CacheNotFound,
+        })
+    }
+
+    /// Returns the entry stored under the given key, without returning its data or updating its
+    /// latest access time. If the key is not found, 204 "no content" is returned.
+    ///
+    /// Requires the permission to get data from the cache.
+    #[oai(path = "/caches/:cache_id/entry", method = "get")]
+    async fn get_entry(
+        &self,
+        cache_id: Path<String>,
+        key: Query<String>,
+        auth: BiscuitBearerAuth,
+    ) -> Result<GetEntryResponse, poem::Error> {
+        auth.authorize(Operation::GetFromCache, &cache_id.0, &self.auth_key_pair)?;
+        Ok(match self.caches.get(&cache_id.0) {
+            Some(cache) => {
+                let cache = cache.clone();
+                match run_blocking(move || cache.entry(&key.0)).await? {
+                    Some(entry) => GetEntryResponse::Entry(Json(entry.into())),
+                    None => GetEntryResponse::EntryNotFound,
+                }
+            }
+            None => GetEntryResponse::CacheNotFound,
+        })
+    }
 }
 
 #[cfg(test)]
@@ -177,9 +315,11 @@ mod tests {
     impl Default for TestFixture {
         fn default() -> Self {
             let tempdir = tempdir().unwrap();
-            let caches: HashMap<String, CacheDispatcher> = HashMap::from([(
+            let caches = HashMap::from([(
                 "test-cache".to_string(),
-                CacheDispatcher::InMemory(LocalCache::new(InMemoryStorage::new())),
+                Arc::new(CacheDispatcher::InMemory(LocalCache::new(
+                    InMemoryStorage::new(),
+                ))),
             )]);
             let auth_key_pair = KeyPair::new();
             let auth_token = biscuit!("").build(&auth_key_pair).unwrap();
@@ -303,6 +443,45 @@ mod tests {
         resp.assert_status(StatusCode::NO_CONTENT);
     }
 
+    #[tokio::test]
+    async fn put_on_caches_endpoint_stores_entries_with_ttl() {
+        let fixture = TestFixture::default();
+        let put_resp = fixture
+            .client
+            .put("/caches/test-cache")
+            .query("key", &"expired-key")
+            .query("ttl", &0)
+            .typed_header(fixture.auth_token.to_header())
+            .body("test-value")
+            .send()
+            .await;
+        put_resp.assert_status(StatusCode::NO_CONTENT);
+
+        let get_resp = fixture
+            .client
+            .get("/caches/test-cache")
+            .query("key", &"expired-key")
+            .typed_header(fixture.auth_token.to_header())
+            .send()
+            .await;
+        get_resp.assert_status(StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn put_on_caches_endpoint_returns_400_for_ttl_out_of_range() {
+        let fixture = TestFixture::default();
+        let resp = fixture
+            .client
+            .put("/caches/test-cache")
+            .query("key", &"test-key")
+            .query("ttl", &u64::MAX)
+            .typed_header(fixture.auth_token.to_header())
+            .body("test-value")
+            .send()
+            .await;
+        resp.assert_status(StatusCode::BAD_REQUEST);
+    }
+
     #[tokio::test]
     async fn put_on_caches_endpoint_returns_401_without_authorization_token() {
         let fixture = TestFixture::default();
@@ -374,6 +553,289 @@ mod tests {
         get_resp.assert_text("test-value").await;
     }
 
+    #[tokio::test]
+    async fn get_on_caches_endpoint_falls_back_to_prefix() {
+        let fixture = TestFixture::default();
+        let put_resp = fixture
+            .client
+            .put("/caches/test-cache")
+            .query("key", &"prefix-key")
+            .typed_header(fixture.auth_token.to_header())
+            .body("test-value")
+            .send()
+            .await;
+        put_resp.assert_status(StatusCode::NO_CONTENT);
+
+        let get_resp = fixture
+            .client
+            .get("/caches/test-cache")
+            .query("key", &"non-existent")
+            .query("prefix", &"other-")
+            .query("prefix", &"prefix-")
+            .typed_header(fixture.auth_token.to_header())
+            .send()
+            .await;
+        get_resp.assert_status(StatusCode::OK);
+        get_resp.assert_header("Btdt-Cache-Key", "prefix-key");
+        get_resp.assert_text("test-value").await;
+
+        let get_resp = fixture
+            .client
+            .get("/caches/test-cache")
+            .query("prefix", &"other-")
+            .typed_header(fixture.auth_token.to_header())
+            .send()
+            .await;
+        get_resp.assert_status(StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn delete_on_caches_endpoint_deletes_keys() {
+        let fixture = TestFixture::default();
+        let put_resp = fixture
+            .client
+            .put("/caches/test-cache")
+            .query("key", &"key-a")
+            .query("key", &"key-b")
+            .typed_header(fixture.auth_token.to_header())
+            .body("test-value")
+            .send()
+            .await;
+        put_resp.assert_status(StatusCode::NO_CONTENT);
+
+        let resp = fixture
+            .client
+            .delete("/caches/test-cache")
+            .query("key", &"key-a")
+            .query("key", &"non-existent-key")
+            .typed_header(fixture.auth_token.to_header())
+            .send()
+            .await;
+        resp.assert_status(StatusCode::NO_CONTENT);
+
+        let resp = fixture
+            .client
+            .get("/caches/test-cache")
+            .query("key", &"key-a")
+            .typed_header(fixture.auth_token.to_header())
+            .send()
+            .await;
+        resp.assert_status(StatusCode::NO_CONTENT);
+
+        let resp = fixture
+            .client
+            .get("/caches/test-cache")
+            .query("key", &"key-b")
+            .typed_header(fixture.auth_token.to_header())
+            .send()
+            .await;
+        resp.assert_status(StatusCode::OK);
+        resp.assert_text("test-value").await;
+    }
+
+    #[tokio::test]
+    async fn delete_on_caches_endpoint_returns_404_for_non_existent_repository() {
+        let fixture = TestFixture::default();
+        let resp = fixture
+            .client
+            .delete("/caches/nonexistent")
+            .query("key", &"key")
+            .typed_header(fixture.auth_token.to_header())
+            .send()
+            .await;
+        resp.assert_status(StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn delete_on_caches_endpoint_returns_403_without_required_permission() {
+        let fixture = TestFixture::default();
+        let attenuated_token = fixture
+            .auth_token
+            .append(block!(
+                r#"check if operation({operation});"#,
+                operation = Operation::GetFromCache.as_str()
+            ))
+            .unwrap();
+        let resp = fixture
+            .client
+            .delete("/caches/test-cache")
+            .query("key", &"key")
+            .typed_header(attenuated_token.to_header())
+            .send()
+            .await;
+        resp.assert_status(StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn list_keys_endpoint_returns_entries_matching_prefix() {
+        let fixture = TestFixture::default();
+        let put_resp = fixture
+            .client
+            .put("/caches/test-cache")
+            .query("key", &"prefix-key")
+            .query("key", &"other-key")
+            .typed_header(fixture.auth_token.to_header())
+            .body("test-value")
+            .send()
+            .await;
+        put_resp.assert_status(StatusCode::NO_CONTENT);
+
+        let resp = fixture
+            .client
+            .get("/caches/test-cache/keys")
+            .query("prefix", &"prefix-")
+            .typed_header(fixture.auth_token.to_header())
+            .send()
+            .await;
+        resp.assert_status(StatusCode::OK);
+        let json = resp.json().await;
+        let entries = json.value().array();
+        entries.assert_len(1);
+        let entry = entries.get(0).object();
+        entry.get("key").assert_string("prefix-key");
+        entry.get("size").assert_i64(10);
+        assert!(!entry.get("latest_access").string().is_empty());
+
+        let resp = fixture
+            .client
+            .get("/caches/test-cache/keys")
+            .typed_header(fixture.auth_token.to_header())
+            .send()
+            .await;
+        resp.assert_status(StatusCode::OK);
+        resp.json().await.value().array().assert_len(2);
+    }
+
+    #[tokio::test]
+    async fn list_keys_endpoint_returns_404_for_non_existent_repository() {
+        let fixture = TestFixture::default();
+        let resp = fixture
+            .client
+            .get("/caches/nonexistent/keys")
+            .typed_header(fixture.auth_token.to_header())
+            .send()
+            .await;
+        resp.assert_status(StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn list_keys_endpoint_returns_403_without_required_permission() {
+        let fixture = TestFixture::default();
+        let attenuated_token = fixture
+            .auth_token
+            .append(block!(
+                r#"check if operation({operation});"#,
+                operation = Operation::GetFromCache.as_str()
+            ))
+            .unwrap();
+        let resp = fixture
+            .client
+            .get("/caches/test-cache/keys")
+            .typed_header(attenuated_token.to_header())
+            .send()
+            .await;
+        resp.assert_status(StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn get_entry_endpoint_returns_entry() {
+        let fixture = TestFixture::default();
+        let put_resp = fixture
+            .client
+            .put("/caches/test-cache")
+            .query("key", &"some-key")
+            .typed_header(fixture.auth_token.to_header())
+            .body("test-value")
+            .send()
+            .await;
+        put_resp.assert_status(StatusCode::NO_CONTENT);
+
+        let resp = fixture
+            .client
+            .get("/caches/test-cache/entry")
+            .query("key", &"some-key")
+            .typed_header(fixture.auth_token.to_header())
+            .send()
+            .await;
+        resp.assert_status(StatusCode::OK);
+        let json = resp.json().await;
+        let entry = json.value().object();
+        entry.get("key").assert_string("some-key");
+        entry.get("size").assert_i64(10);
+        assert!(!entry.get("latest_access").string().is_empty());
+    }
+
+    #[tokio::test]
+    async fn get_entry_endpoint_returns_tree_hash_given_when_storing() {
+        let fixture = TestFixture::default();
+        let tree_hash = blake3::hash(b"tree").to_hex().to_string();
+        let put_resp = fixture
+            .client
+            .put("/caches/test-cache")
+            .query("key", &"some-key")
+            .query("tree_hash", &tree_hash)
+            .typed_header(fixture.auth_token.to_header())
+            .body("test-value")
+            .send()
+            .await;
+        put_resp.assert_status(StatusCode::NO_CONTENT);
+
+        let resp = fixture
+            .client
+            .get("/caches/test-cache/entry")
+            .query("key", &"some-key")
+            .typed_header(fixture.auth_token.to_header())
+            .send()
+            .await;
+        resp.assert_status(StatusCode::OK);
+        let json = resp.json().await;
+        json.value()
+            .object()
+            .get("tree_hash")
+            .assert_string(&tree_hash);
+    }
+
+    #[tokio::test]
+    async fn put_on_caches_endpoint_returns_400_for_invalid_tree_hash() {
+        let fixture = TestFixture::default();
+        let resp = fixture
+            .client
+            .put("/caches/test-cache")
+            .query("key", &"test-key")
+            .query("tree_hash", &"invalid")
+            .typed_header(fixture.auth_token.to_header())
+            .body("test-value")
+            .send()
+            .await;
+        resp.assert_status(StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn get_entry_endpoint_returns_204_for_non_existent_key() {
+        let fixture = TestFixture::default();
+        let resp = fixture
+            .client
+            .get("/caches/test-cache/entry")
+            .query("key", &"non-existent")
+            .typed_header(fixture.auth_token.to_header())
+            .send()
+            .await;
+        resp.assert_status(StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn get_entry_endpoint_returns_404_for_non_existent_repository() {
+        let fixture = TestFixture::default();
+        let resp = fixture
+            .client
+            .get("/caches/nonexistent/entry")
+            .query("key", &"some-key")
+            .typed_header(fixture.auth_token.to_header())
+            .send()
+            .await;
+        resp.assert_status(StatusCode::NOT_FOUND);
+    }
+
     #[test]
     fn test_bearer_auth_all_operations_allowed_with_unattenuated_token() {
         let key_pair = KeyPair::new();
@@ -389,6 +851,14 @@ mod tests {
             auth.authorize(Operation::PutIntoCache, "some-cache", &key_pair)
                 .is_ok()
         );
+        assert!(
+            auth.authorize(Operation::ListKeys, "some-cache", &key_pair)
+                .is_ok()
+        );
+        assert!(
+            auth.authorize(Operation::DeleteFromCache, "some-cache", &key_pair)
+                .is_ok()
+        );
     }
 
     #[test]
@@ -0,0 +1,16 @@
+use crate::app::list_keys::CacheEntryObject;
+use poem_openapi::ApiResponse;
+use poem_openapi::payload::Json;
+
+#[derive(ApiResponse)]
+pub enum GetEntryResponse {
+    /// The key was not found in the cache.
+    #[oai(status = 204)]
+    EntryNotFound,
+    /// The cache with the given ID does not exist.
+    #[oai(status = 404)]
+    CacheNotFound,
+    /// The entry stored under the key.
+    #[oai(status = 200)]
+    Entry(Json<CacheEntryObject>),
+}
@@ -21,6 +21,12 @@ pub enum GetFromCacheResponse {
         /// The cache key that was used to retrieve the data.
         #[oai(header = "Btdt-Cache-Key")]
         String,
+        /// The exact size of the data in bytes to verify its integrity, if known.
+        #[oai(header = "Btdt-Content-Size")]
+        Option<u64>,
+        /// The BLAKE3 hash (hex encoded) of the data to verify its integrity, if known.
+        #[oai(header = "Btdt-Content-Blake3")]
+        Option<String>,
     ),
 }
 
@@ -35,6 +41,9 @@ where
                 hit.size_hint,
             ))),
             hit.key.to_string(),
+            hit.checksum.map(|checksum| checksum.size),
+            hit.checksum
+                .map(|checksum| checksum.hash.to_hex().to_string()),
         )
     }
 }
@@ -0,0 +1,43 @@
+use btdt::cache::CacheEntry;
+use chrono::SecondsFormat;
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, Object};
+
+#[derive(ApiResponse)]
+pub enum ListKeysResponse {
+    /// The cache with the given ID does not exist.
+    #[oai(status = 404)]
+    CacheNotFound,
+    /// The entries in the cache matching the prefix, sorted by key.
+    #[oai(status = 200)]
+    Entries(Json<Vec<CacheEntryObject>>),
+}
+
+/// An entry in a cache.
+#[derive(Object)]
+pub struct CacheEntryObject {
+    /// Key of the entry.
+    key: String,
+    /// Size of the cached data in bytes.
+    size: u64,
+    /// Time of the latest access of the entry via its key (RFC 3339).
+    latest_access: String,
+    /// The BLAKE3 hash (hex encoded) of the directory tree stored in the entry, if given when
+    /// storing it.
+    tree_hash: Option<String>,
+}
+
+impl From<CacheEntry> for CacheEntryObject {
+    fn from(entry: CacheEntry) -> Self {
+        CacheEntryObject {
+            key: entry.key,
+            size: entry.size,
+            latest_access: entry
+                .latest_access
+                .to_rfc3339_opts(SecondsFormat::AutoSi, true),
+            tree_hash: entry
+                .tree_hash
+                .map(|tree_hash| tree_hash.to_hex().to_string()),
+        }
+    }
+}
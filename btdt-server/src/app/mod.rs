@@ -4,7 +4,9 @@ use poem::Route;
 use std::collections::HashMap;
 
 mod api;
+mod get_entry;
 mod get_from_cache;
+mod list_keys;
 
 #[derive(Clone, Debug)]
 pub struct Options {
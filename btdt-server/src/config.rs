@@ -1,3 +1,4 @@
+use btdt::cache::clean::EvictionPolicy;
 use config::builder::DefaultState;
 use config::{Config, ConfigBuilder, ConfigError, Environment, File, Map, Source};
 use std::borrow::Cow;
@@ -22,6 +23,14 @@ pub struct CleanupConfig {
     pub interval: String,
     pub cache_expiration: String,
     pub max_cache_size: String,
+    #[serde(default)]
+    pub quotas: Vec<QuotaConfig>,
+}
+
+#[derive(Clone, Debug, serde::Deserialize, PartialEq, Eq)]
+pub struct QuotaConfig {
+    pub prefix: String,
+    pub max_size: String,
 }
 
 impl BtdtServerConfig {
@@ -30,11 +39,54 @@ impl BtdtServerConfig {
     }
 }
 
+#[derive(Clone, Debug, serde::Deserialize, PartialEq, Eq)]
+pub struct CacheConfig {
+    #[serde(flatten)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub eviction_policy: EvictionPolicyConfig,
+    #[serde(default)]
+    pub deduplicate: bool,
+    #[serde(default)]
+    pub chunked: bool,
+}
+
 #[derive(Clone, Debug, serde::Deserialize, PartialEq, Eq)]
 #[serde(tag = "type")]
-pub enum CacheConfig {
+pub enum StorageConfig {
     InMemory,
-    Filesystem { path: String },
+    Filesystem {
+        path: String,
+    },
+    S3 {
+        bucket: String,
+        #[serde(default)]
+        prefix: String,
+        region: Option<String>,
+        endpoint: Option<String>,
+        access_key_id: Option<String>,
+        secret_access_key: Option<String>,
+        session_token: Option<String>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Default, serde::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum EvictionPolicyConfig {
+    #[default]
+    Lru,
+    Lfu,
+    SizeWeighted,
+}
+
+impl From<EvictionPolicyConfig> for EvictionPolicy {
+    fn from(policy: EvictionPolicyConfig) -> Self {
+        match policy {
+            EvictionPolicyConfig::Lru => EvictionPolicy::Lru,
+            EvictionPolicyConfig::Lfu => EvictionPolicy::Lfu,
+            EvictionPolicyConfig::SizeWeighted => EvictionPolicy::SizeWeighted,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -136,6 +188,7 @@ mod tests {
                     interval: "10min".to_string(),
                     cache_expiration: "7days".to_string(),
                     max_cache_size: "50GiB".to_string(),
+                    quotas: vec![],
                 },
                 caches: HashMap::new(),
             }
@@ -156,9 +209,18 @@ mod tests {
             cache_expiration = '14days'
             max_cache_size = '100GiB'
 
+            [[cleanup.quotas]]
+            prefix = 'gradle-'
+            max_size = '20GiB'
+
+            [[cleanup.quotas]]
+            prefix = 'npm-'
+            max_size = '5GiB'
+
             [caches]
             in_memory = { type = 'InMemory' }
-            filesystem = { type = 'Filesystem', path = '/var/lib/btdt-server/cache' }
+            filesystem = { type = 'Filesystem', path = '/var/lib/btdt-server/cache', eviction_policy = 'size-weighted', deduplicate = true }
+            s3 = { type = 'S3', bucket = 'bucket', endpoint = 'http://localhost:9000', chunked = true }
         ";
         let file = File::from_str(config, FileFormat::Toml);
         let parsed_config = ConfigLoader::new().add_file_source(file).load().unwrap();
@@ -174,13 +236,53 @@ mod tests {
                     interval: "5min".to_string(),
                     cache_expiration: "14days".to_string(),
                     max_cache_size: "100GiB".to_string(),
+                    quotas: vec![
+                        QuotaConfig {
+                            prefix: "gradle-".to_string(),
+                            max_size: "20GiB".to_string(),
+                        },
+                        QuotaConfig {
+                            prefix: "npm-".to_string(),
+                            max_size: "5GiB".to_string(),
+                        },
+                    ],
                 },
                 caches: HashMap::from([
-                    ("in_memory".to_string(), CacheConfig::InMemory),
+                    (
+                        "in_memory".to_string(),
+                        CacheConfig {
+                            storage: StorageConfig::InMemory,
+                            eviction_policy: EvictionPolicyConfig::Lru,
+                            deduplicate: false,
+                            chunked: false,
+                        }
+                    ),
                     (
                         "filesystem".to_string(),
-                        CacheConfig::Filesystem {
-                            path: "/var/lib/btdt-server/cache".to_string()
+                        CacheConfig {
+                            storage: StorageConfig::Filesystem {
+                                path: "/var/lib/btdt-server/cache".to_string()
+                            },
+                            eviction_policy: EvictionPolicyConfig::SizeWeighted,
+                            deduplicate: true,
+                            chunked: false,
+                        }
+                    ),
+                    (
+                        "s3".to_string(),
+                        CacheConfig {
+                            storage: StorageConfig::S3 {
+                                bucket: "bucket".to_string(),
+                                prefix: "".to_string(),
+                                region: None,
+                                endpoint: Some("http://localhost:9000".to_string()),
+                                access_key_id: None,
+                                secret_access_key: None,
+                                session_token: None,
+                            },
+                            eviction_policy: EvictionPolicyConfig::Lru,
+                            deduplicate: false,
+                            chunked: true,
                         }
                     )
                 ])
@@ -234,6 +336,7 @@ mod tests {
                     interval: "5min".to_string(),
                     cache_expiration: "14days".to_string(),
                     max_cache_size: "100GiB".to_string(),
+                    quotas: vec![],
                 },
                 caches: HashMap::new(),
             }
@@ -3,8 +3,9 @@ use crate::config::{BtdtServerConfig, CleanupConfig};
 use crate::storage::StorageHandle;
 use biscuit_auth::KeyPair;
 use btdt::cache::cache_dispatcher::CacheDispatcher;
-use btdt::error::IoPathResult;
-use btdt::util::http::{HttpClient, Url};
+use btdt::cache::clean::{CleanOptions, CleanReport, EvictionPolicy, PrefixQuota};
+use btdt::error::{IoPathError, IoPathResult};
+use btdt::util::http::{HttpClient, ProxyConfig, Url};
 use btdt::util::humanbytes;
 use chrono::{Local, TimeDelta};
 use clap::{Parser, Subcommand};
@@ -19,14 +20,14 @@ use std::collections::{BTreeMap, HashMap};
 use std::convert::Infallible;
 use std::error::Error;
 use std::fs::{File, OpenOptions};
-use std::io::{Read, Write};
+use std::io::{ErrorKind, Read, Write};
 use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread::{JoinHandle, park_timeout};
 use std::time::{Duration, Instant};
-use std::{env, fs, thread};
+use std::{env, fs, io, thread};
 use tokio::select;
 use tokio::signal::unix::SignalKind;
 use zeroize::Zeroizing;
@@ -217,7 +218,8 @@ async fn main() -> Result<(), Box<dyn Error>> {
                         .flat_map(|cert| cert.map(PathBuf::from))
                         .collect::<Vec<_>>(),
                 )
-            }?;
+            }?
+            .with_proxy_config(ProxyConfig::from_env()?);
             let health_url = base_url.join("/api/health")?;
             let (status, resp) = client.get(&health_url)?.no_body()?.read_status()?;
             if status.is_success() {
@@ -274,18 +276,33 @@ async fn run_server() -> Result<(), Box<dyn Error>> {
     let storage_locations: BTreeMap<String, StorageHandle> = settings
         .caches
         .iter()
-        .map(|(key, cache_config)| (key.clone(), StorageHandle::from(cache_config)))
-        .collect();
+        .map(|(key, cache_config)| {
+            Ok((key.clone(), StorageHandle::try_from(&cache_config.storage)?))
+        })
+        .collect::<Result<_, Box<dyn Error>>>()?;
 
     let cleanup_caches = storage_locations
         .iter()
-        .map(|(key, storage_handle)| (key.clone(), storage_handle.to_cache()))
+        .map(|(key, storage_handle)| {
+            let cache_config = &settings.caches[key];
+            let cache = storage_handle
+                .to_cache()
+                .with_chunking(cache_config.chunked);
+            (key.clone(), (cache, cache_config.eviction_policy.into()))
+        })
         .collect();
     let cleanup_task = CleanupTask::new(cleanup_caches, &settings.cleanup)?.run();
 
     let caches: HashMap<String, CacheDispatcher> = storage_locations
         .into_iter()
-        .map(|(key, storage_handle)| (key, storage_handle.into_cache()))
+        .map(|(key, storage_handle)| {
+            let cache_config = &settings.caches[&key];
+            let cache = storage_handle
+                .into_cache()
+                .with_deduplication(cache_config.deduplicate)
+                .with_chunking(cache_config.chunked);
+            (key, cache)
+        })
         .collect();
 
     let mut sigint = tokio::signal::unix::signal(SignalKind::interrupt())?;
@@ -323,26 +340,38 @@ async fn run_server() -> Result<(), Box<dyn Error>> {
 }
 
 struct CleanupTask {
-    caches: HashMap<String, CacheDispatcher>,
+    caches: HashMap<String, (CacheDispatcher, EvictionPolicy)>,
     cleanup_interval: Duration,
     cache_expiration: TimeDelta,
     max_cache_size: u64,
+    quotas: Vec<PrefixQuota>,
 }
 
 impl CleanupTask {
     pub fn new(
-        caches: HashMap<String, CacheDispatcher>,
+        caches: HashMap<String, (CacheDispatcher, EvictionPolicy)>,
         settings: &CleanupConfig,
     ) -> Result<Self, Box<dyn Error>> {
         let cleanup_interval = humantime::parse_duration(&settings.interval)?;
         let cache_expiration =
             TimeDelta::from_std(humantime::parse_duration(&settings.cache_expiration)?)?;
         let max_cache_size = humanbytes::parse_bytes_from_str(&settings.max_cache_size)?;
+        let quotas = settings
+            .quotas
+            .iter()
+            .map(|quota| {
+                Ok(PrefixQuota {
+                    prefix: quota.prefix.clone(),
+                    max_blob_size_sum: humanbytes::parse_bytes_from_str(&quota.max_size)?,
+                })
+            })
+            .collect::<Result<_, Box<dyn Error>>>()?;
         Ok(Self {
             caches,
             cleanup_interval,
             cache_expiration,
             max_cache_size,
+            quotas,
         })
     }
 
@@ -365,11 +394,16 @@ impl CleanupTask {
                     if parked_since.elapsed() < self.cleanup_interval {
                         continue;
                     }
-                    for cache in self.caches.values_mut() {
-                        if let Err(e) =
-                            cache.clean_cache(self.cache_expiration, self.max_cache_size)
-                        {
-                            eprintln!("Error during periodic cleanup: {e}");
+                    for (cache_id, (cache, eviction_policy)) in self.caches.iter_mut() {
+                        let options = CleanOptions {
+                            max_unused_age: Some(self.cache_expiration),
+                            max_blob_size_sum: Some(self.max_cache_size),
+                            prefix_quotas: self.quotas.clone(),
+                            policy: *eviction_policy,
+                        };
+                        match cache.clean_cache(&options) {
+                            Ok(report) => log_clean_report(cache_id, &report),
+                            Err(e) => eprintln!("Error during periodic cleanup: {e}"),
                         }
                     }
                     parked_since = Instant::now();
@@ -383,25 +417,35 @@ impl CleanupTask {
     }
 }
 
+fn log_clean_report(cache_id: &str, report: &CleanReport) {
+    for eviction in &report.evictions {
+        println!("Cleanup of cache {cache_id} deleted {eviction}");
+    }
+    println!(
+        "Cleanup of cache {cache_id} deleted {} blobs ({} bytes)",
+        report.evictions.len(),
+        report.freed_size()
+    );
+}
+
 trait Clean {
-    fn clean_cache(&mut self, cache_expiration: TimeDelta, max_cache_size: u64)
-    -> IoPathResult<()>;
+    fn clean_cache(&mut self, options: &CleanOptions) -> IoPathResult<CleanReport>;
 }
 
 impl Clean for CacheDispatcher {
-    fn clean_cache(
-        &mut self,
-        cache_expiration: TimeDelta,
-        max_cache_size: u64,
-    ) -> IoPathResult<()> {
+    fn clean_cache(&mut self, options: &CleanOptions) -> IoPathResult<CleanReport> {
         match self {
-            CacheDispatcher::InMemory(cache) => {
-                cache.clean(Some(cache_expiration), Some(max_cache_size))
-            }
-            CacheDispatcher::Filesystem(cache) => {
-                cache.clean(Some(cache_expiration), Some(max_cache_size))
-            }
-            CacheDispatcher::Remote(_) => Ok(()),
+            CacheDispatcher::InMemory(cache) => cache.clean(options),
+            CacheDispatcher::Filesystem(cache) => cache.clean(options),
+            CacheDispatcher::S3(cache) => cache.clean(options),
+            CacheDispatcher::ChunkedInMemory(cache) => cache.clean(options),
+            CacheDispatcher::ChunkedFilesystem(cache) => cache.clean(options),
+            CacheDispatcher::ChunkedS3(cache) => cache.clean(options),
+            CacheDispatcher::Tiered(cache) => cache.local_mut().clean(options),
+            CacheDispatcher::Remote(_) => Err(IoPathError::new_no_path(io::Error::new(
+                ErrorKind::Unsupported,
+                "clean is not supported for remote caches",
+            ))),
         }
     }
 }
@@ -1,22 +1,53 @@
-use crate::config::CacheConfig;
+use crate::config::StorageConfig;
 use btdt::cache::cache_dispatcher::CacheDispatcher;
+use btdt::cache::remote::http::{HttpClient, ProxyConfig};
 use btdt::storage::filesystem::FilesystemStorage;
 use btdt::storage::in_memory::InMemoryStorage;
+use btdt::storage::s3::{S3Config, S3Storage};
+use std::error::Error;
 
 #[derive(Clone)]
 pub enum StorageHandle {
     InMemory(InMemoryStorage),
     Filesystem(FilesystemStorage),
+    S3(S3Storage),
 }
 
-impl From<&CacheConfig> for StorageHandle {
-    fn from(cache_config: &CacheConfig) -> Self {
-        match cache_config {
-            CacheConfig::InMemory => StorageHandle::InMemory(InMemoryStorage::new()),
-            CacheConfig::Filesystem { path } => {
+impl TryFrom<&StorageConfig> for StorageHandle {
+    type Error = Box<dyn Error>;
+
+    fn try_from(storage_config: &StorageConfig) -> Result<Self, Self::Error> {
+        Ok(match storage_config {
+            StorageConfig::InMemory => StorageHandle::InMemory(InMemoryStorage::new()),
+            StorageConfig::Filesystem { path } => {
                 StorageHandle::Filesystem(FilesystemStorage::new(path.into()))
             }
-        }
+            StorageConfig::S3 {
+                bucket,
+                prefix,
+                region,
+                endpoint,
+                access_key_id,
+                secret_access_key,
+                session_token,
+            } => {
+                // Explicitly configured values take precedence over the environment.
+                let config = S3Config::from_lookup(bucket, prefix, |name| {
+                    match name {
+                        "AWS_REGION" => region.clone(),
+                        "AWS_ENDPOINT_URL_S3" | "AWS_ENDPOINT_URL" => endpoint.clone(),
+                        "AWS_ACCESS_KEY_ID" => access_key_id.clone(),
+                        "AWS_SECRET_ACCESS_KEY" => secret_access_key.clone(),
+                        "AWS_SESSION_TOKEN" => session_token.clone(),
+                        _ => None,
+                    }
+                    .or_else(|| std::env::var(name).ok())
+                })?;
+                let http_client =
+                    HttpClient::default()?.with_proxy_config(ProxyConfig::from_env()?);
+                StorageHandle::S3(S3Storage::new(http_client, config))
+            }
+        })
     }
 }
 
@@ -29,6 +60,9 @@ impl StorageHandle {
             StorageHandle::Filesystem(storage) => {
                 CacheDispatcher::Filesystem(btdt::cache::local::LocalCache::new(storage))
             }
+            StorageHandle::S3(storage) => {
+                CacheDispatcher::S3(btdt::cache::local::LocalCache::new(storage))
+            }
         }
     }
 
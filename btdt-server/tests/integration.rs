@@ -42,7 +42,7 @@ impl BtdtTestServerWithAuthorizedClient {
             .write_all(key_pair.to_private_key_pem().unwrap().as_bytes())
             .unwrap();
         let token =
-            UnverifiedBiscuit::from(&biscuit!("").build(&key_pair).unwrap().to_vec().unwrap())
+            UnverifiedBiscuit::from(biscuit!("").build(&key_pair).unwrap().to_vec().unwrap())
                 .unwrap();
 
         env.insert(
@@ -9,6 +9,13 @@ pub const BLOB_ID_SIZE: usize = 16;
 /// A unique identifier for a blob in the cache.
 pub type BlobId = [u8; BLOB_ID_SIZE];
 
+/// Derives the ID of a content-addressed blob from the hash of its content.
+pub fn content_blob_id(hash: &blake3::Hash) -> BlobId {
+    let mut blob_id = [0; BLOB_ID_SIZE];
+    blob_id.copy_from_slice(&hash.as_bytes()[..BLOB_ID_SIZE]);
+    blob_id
+}
+
 /// Trait for providing random bytes.
 pub trait RngBytes {
     /// Fills the given buffer with random bytes.
@@ -1,11 +1,14 @@
 //! A cache dispatcher to dispatch to different cache implementations.
 
+use crate::cache::chunked::ChunkedCache;
 use crate::cache::local::LocalCache;
 use crate::cache::remote::RemoteCache;
-use crate::cache::{Cache, CacheHit};
+use crate::cache::tiered::TieredCache;
+use crate::cache::{Cache, CacheEntry, CacheHit, SetOptions};
 use crate::error::IoPathResult;
 use crate::storage::filesystem::FilesystemStorage;
 use crate::storage::in_memory::InMemoryStorage;
+use crate::storage::s3::S3Storage;
 use crate::util::close::Close;
 use std::io;
 use std::io::{Read, Write};
@@ -16,8 +19,58 @@ pub enum CacheDispatcher {
     InMemory(LocalCache<InMemoryStorage>),
     /// Dispatches to a local cache with filesystem storage.
     Filesystem(LocalCache<FilesystemStorage>),
+    /// Dispatches to a local cache with S3 storage.
+    S3(LocalCache<S3Storage>),
+    /// Dispatches to a chunked cache with in-memory storage.
+    ChunkedInMemory(ChunkedCache<InMemoryStorage>),
+    /// Dispatches to a chunked cache with filesystem storage.
+    ChunkedFilesystem(ChunkedCache<FilesystemStorage>),
+    /// Dispatches to a chunked cache with S3 storage.
+    ChunkedS3(ChunkedCache<S3Storage>),
     /// Dispatches to a remote cache.
     Remote(Box<RemoteCache>),
+    /// Dispatches to a tiered cache with a local cache with filesystem storage in front of a
+    /// remote cache.
+    Tiered(Box<TieredCache<LocalCache<FilesystemStorage>, RemoteCache>>),
+}
+
+impl CacheDispatcher {
+    /// Sets whether local caches deduplicate blobs with identical content.
+    ///
+    /// See [LocalCache::with_deduplication]. Remote caches are left unchanged, as deduplication is
+    /// configured on the server. For tiered caches, this applies to the local tier.
+    pub fn with_deduplication(self, deduplicate: bool) -> Self {
+        match self {
+            Self::InMemory(cache) => Self::InMemory(cache.with_deduplication(deduplicate)),
+            Self::Filesystem(cache) => Self::Filesystem(cache.with_deduplication(deduplicate)),
+            Self::S3(cache) => Self::S3(cache.with_deduplication(deduplicate)),
+            Self::Tiered(cache) => {
+                let (local, remote) = cache.into_inner();
+                Self::Tiered(Box::new(TieredCache::new(
+                    local.with_deduplication(deduplicate),
+                    remote,
+                )))
+            }
+            cache => cache,
+        }
+    }
+
+    /// Sets whether local caches split the data into chunks that are stored only once.
+    ///
+    /// See [ChunkedCache]. Remote caches are left unchanged, as chunking is configured on the
+    /// server. Chunked caches already deduplicate their data, so
+    /// [CacheDispatcher::with_deduplication] does not apply to them.
+    pub fn with_chunking(self, chunked: bool) -> Self {
+        if !chunked {
+            return self;
+        }
+        match self {
+            Self::InMemory(cache) => Self::ChunkedInMemory(ChunkedCache::new(cache)),
+            Self::Filesystem(cache) => Self::ChunkedFilesystem(ChunkedCache::new(cache)),
+            Self::S3(cache) => Self::ChunkedS3(ChunkedCache::new(cache)),
+            cache => cache,
+        }
+    }
 }
 
 impl Cache for CacheDispatcher {
@@ -26,56 +79,126 @@ impl Cache for CacheDispatcher {
 
     fn get<'a>(&self, keys: &[&'a str]) -> IoPathResult<Option<CacheHit<'a, Self::Reader>>> {
         Ok(match self {
-            Self::InMemory(cache) => cache.get(keys)?.map(
-                |CacheHit {
-                     key,
-                     reader,
-                     size_hint,
-                 }| CacheHit {
-                    key,
-                    reader: Box::new(reader) as Box<dyn Read + Send>,
-                    size_hint,
-                },
-            ),
-            Self::Filesystem(cache) => cache.get(keys)?.map(
-                |CacheHit {
-                     key,
-                     reader,
-                     size_hint,
-                 }| CacheHit {
-                    key,
-                    reader: Box::new(reader) as Box<dyn Read + Send>,
-                    size_hint,
-                },
-            ),
-            CacheDispatcher::Remote(cache) => cache.get(keys)?.map(
-                |CacheHit {
-                     key,
-                     reader,
-                     size_hint,
-                 }| CacheHit {
-                    key,
-                    reader: Box::new(reader) as Box<dyn Read + Send>,
-                    size_hint,
-                },
-            ),
+            Self::InMemory(cache) => cache.get(keys)?.map(box_reader),
+            Self::Filesystem(cache) => cache.get(keys)?.map(box_reader),
+            Self::S3(cache) => cache.get(keys)?.map(box_reader),
+            Self::ChunkedInMemory(cache) => cache.get(keys)?.map(box_reader),
+            Self::ChunkedFilesystem(cache) => cache.get(keys)?.map(box_reader),
+            Self::ChunkedS3(cache) => cache.get(keys)?.map(box_reader),
+            CacheDispatcher::Remote(cache) => cache.get(keys)?.map(box_reader),
+            CacheDispatcher::Tiered(cache) => cache.get(keys)?.map(box_reader),
+        })
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefixes: &[&str],
+    ) -> IoPathResult<Option<CacheHit<'static, Self::Reader>>> {
+        Ok(match self {
+            Self::InMemory(cache) => cache.get_by_prefix(prefixes)?.map(box_reader),
+            Self::Filesystem(cache) => cache.get_by_prefix(prefixes)?.map(box_reader),
+            Self::S3(cache) => cache.get_by_prefix(prefixes)?.map(box_reader),
+            Self::ChunkedInMemory(cache) => cache.get_by_prefix(prefixes)?.map(box_reader),
+            Self::ChunkedFilesystem(cache) => cache.get_by_prefix(prefixes)?.map(box_reader),
+            Self::ChunkedS3(cache) => cache.get_by_prefix(prefixes)?.map(box_reader),
+            CacheDispatcher::Remote(cache) => cache.get_by_prefix(prefixes)?.map(box_reader),
+            CacheDispatcher::Tiered(cache) => cache.get_by_prefix(prefixes)?.map(box_reader),
         })
     }
 
-    fn set(&self, keys: &[&str]) -> IoPathResult<Self::Writer> {
+    fn set_with_options(&self, keys: &[&str], options: &SetOptions) -> IoPathResult<Self::Writer> {
         match self {
-            Self::InMemory(cache) => cache.set(keys).map(CacheWriter::InMemory),
-            Self::Filesystem(cache) => cache.set(keys).map(CacheWriter::Filesystem),
-            CacheDispatcher::Remote(cache) => cache.set(keys).map(CacheWriter::Remote),
+            Self::InMemory(cache) => cache
+                .set_with_options(keys, options)
+                .map(CacheWriter::InMemory),
+            Self::Filesystem(cache) => cache
+                .set_with_options(keys, options)
+                .map(CacheWriter::Filesystem),
+            Self::S3(cache) => cache.set_with_options(keys, options).map(CacheWriter::S3),
+            Self::ChunkedInMemory(cache) => cache
+                .set_with_options(keys, options)
+                .map(CacheWriter::ChunkedInMemory),
+            Self::ChunkedFilesystem(cache) => cache
+                .set_with_options(keys, options)
+                .map(CacheWriter::ChunkedFilesystem),
+            Self::ChunkedS3(cache) => cache
+                .set_with_options(keys, options)
+                .map(CacheWriter::ChunkedS3),
+            CacheDispatcher::Remote(cache) => cache
+                .set_with_options(keys, options)
+                .map(CacheWriter::Remote),
+            CacheDispatcher::Tiered(cache) => cache
+                .set_with_options(keys, options)
+                .map(|writer| CacheWriter::Tiered(Box::new(writer))),
         }
     }
+
+    fn list(&self, prefix: &str) -> IoPathResult<Vec<CacheEntry>> {
+        match self {
+            Self::InMemory(cache) => cache.list(prefix),
+            Self::Filesystem(cache) => cache.list(prefix),
+            Self::S3(cache) => cache.list(prefix),
+            Self::ChunkedInMemory(cache) => cache.list(prefix),
+            Self::ChunkedFilesystem(cache) => cache.list(prefix),
+            Self::ChunkedS3(cache) => cache.list(prefix),
+            CacheDispatcher::Remote(cache) => cache.list(prefix),
+            CacheDispatcher::Tiered(cache) => cache.list(prefix),
+        }
+    }
+
+    fn entry(&self, key: &str) -> IoPathResult<Option<CacheEntry>> {
+        match self {
+            Self::InMemory(cache) => cache.entry(key),
+            Self::Filesystem(cache) => cache.entry(key),
+            Self::S3(cache) => cache.entry(key),
+            Self::ChunkedInMemory(cache) => cache.entry(key),
+            Self::ChunkedFilesystem(cache) => cache.entry(key),
+            Self::ChunkedS3(cache) => cache.entry(key),
+            CacheDispatcher::Remote(cache) => cache.entry(key),
+            CacheDispatcher::Tiered(cache) => cache.entry(key),
+        }
+    }
+
+    fn delete(&self, keys: &[&str]) -> IoPathResult<()> {
+        match self {
+            Self::InMemory(cache) => cache.delete(keys),
+            Self::Filesystem(cache) => cache.delete(keys),
+            Self::S3(cache) => cache.delete(keys),
+            Self::ChunkedInMemory(cache) => cache.delete(keys),
+            Self::ChunkedFilesystem(cache) => cache.delete(keys),
+            Self::ChunkedS3(cache) => cache.delete(keys),
+            CacheDispatcher::Remote(cache) => cache.delete(keys),
+            CacheDispatcher::Tiered(cache) => cache.delete(keys),
+        }
+    }
+}
+
+fn box_reader<R: Read + Send + 'static>(
+    CacheHit {
+        key,
+        reader,
+        size_hint,
+        checksum,
+    }: CacheHit<R>,
+) -> CacheHit<Box<dyn Read + Send>> {
+    CacheHit {
+        key,
+        reader: Box::new(reader),
+        size_hint,
+        checksum,
+    }
 }
 
 /// Writer returned by the [CacheDispatcher].
 pub enum CacheWriter {
     InMemory(<LocalCache<InMemoryStorage> as Cache>::Writer),
     Filesystem(<LocalCache<FilesystemStorage> as Cache>::Writer),
+    S3(<LocalCache<S3Storage> as Cache>::Writer),
+    ChunkedInMemory(<ChunkedCache<InMemoryStorage> as Cache>::Writer),
+    ChunkedFilesystem(<ChunkedCache<FilesystemStorage> as Cache>::Writer),
+    ChunkedS3(<ChunkedCache<S3Storage> as Cache>::Writer),
     Remote(<RemoteCache as Cache>::Writer),
+    Tiered(Box<<TieredCache<LocalCache<FilesystemStorage>, RemoteCache> as Cache>::Writer>),
 }
 
 impl Write for CacheWriter {
@@ -83,7 +206,12 @@ impl Write for CacheWriter {
         match self {
             Self::InMemory(writer) => writer.write(buf),
             Self::Filesystem(writer) => writer.write(buf),
+            Self::S3(writer) => writer.write(buf),
+            Self::ChunkedInMemory(writer) => writer.write(buf),
+            Self::ChunkedFilesystem(writer) => writer.write(buf),
+            Self::ChunkedS3(writer) => writer.write(buf),
             CacheWriter::Remote(writer) => writer.write(buf),
+            CacheWriter::Tiered(writer) => writer.write(buf),
         }
     }
 
@@ -91,7 +219,12 @@ impl Write for CacheWriter {
         match self {
             Self::InMemory(writer) => writer.flush(),
             Self::Filesystem(writer) => writer.flush(),
+            Self::S3(writer) => writer.flush(),
+            Self::ChunkedInMemory(writer) => writer.flush(),
+            Self::ChunkedFilesystem(writer) => writer.flush(),
+            Self::ChunkedS3(writer) => writer.flush(),
             CacheWriter::Remote(writer) => writer.flush(),
+            CacheWriter::Tiered(writer) => writer.flush(),
         }
     }
 }
@@ -101,7 +234,25 @@ impl Close for CacheWriter {
         match self {
             Self::InMemory(writer) => writer.close(),
             Self::Filesystem(writer) => writer.close(),
+            Self::S3(writer) => writer.close(),
+            Self::ChunkedInMemory(writer) => writer.close(),
+            Self::ChunkedFilesystem(writer) => writer.close(),
+            Self::ChunkedS3(writer) => writer.close(),
             CacheWriter::Remote(writer) => writer.close(),
+            CacheWriter::Tiered(writer) => writer.close(),
+        }
+    }
+
+    fn abort(self) {
+        match self {
+            Self::InMemory(writer) => writer.abort(),
+            Self::Filesystem(writer) => writer.abort(),
+            Self::S3(writer) => writer.abort(),
+            Self::ChunkedInMemory(writer) => writer.abort(),
+            Self::ChunkedFilesystem(writer) => writer.abort(),
+            Self::ChunkedS3(writer) => writer.abort(),
+            CacheWriter::Remote(writer) => writer.abort(),
+            CacheWriter::Tiered(writer) => writer.abort(),
         }
     }
 }
@@ -0,0 +1,183 @@
+//! Checksums to verify the integrity of cached data.
+
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+
+/// Size and BLAKE3 hash of the data stored in a cache entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checksum {
+    /// Exact size of the data in bytes.
+    pub size: u64,
+
+    /// BLAKE3 hash of the data.
+    pub hash: blake3::Hash,
+}
+
+/// Error returned when data does not match its [Checksum].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChecksumMismatch {
+    /// The expected checksum.
+    pub expected: Checksum,
+
+    /// The checksum of the actual data. If the data exceeded the expected size, reading is aborted
+    /// early and this is the checksum of the data read up to that point.
+    pub actual: Checksum,
+}
+
+impl Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.expected.size != self.actual.size {
+            write!(
+                f,
+                "cached data is corrupted: expected {} bytes, got {} bytes",
+                self.expected.size, self.actual.size
+            )
+        } else {
+            write!(
+                f,
+                "cached data is corrupted: expected hash {}, got {}",
+                self.expected.hash, self.actual.hash
+            )
+        }
+    }
+}
+
+impl std::error::Error for ChecksumMismatch {}
+
+/// A writer that calculates the [Checksum] of the data written through it.
+pub struct ChecksumWriter<W: Write> {
+    inner: W,
+    hasher: blake3::Hasher,
+    size: u64,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    /// Creates a new writer forwarding data to `inner`.
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: blake3::Hasher::new(),
+            size: 0,
+        }
+    }
+
+    /// Returns the inner writer and the checksum of the data written so far.
+    pub fn finalize(self) -> (W, Checksum) {
+        let checksum = Checksum {
+            size: self.size,
+            hash: self.hasher.finalize(),
+        };
+        (self.inner, checksum)
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A reader that verifies the data read through it against a [Checksum].
+///
+/// The verification happens when the end of the data is reached. On a mismatch, an error of kind
+/// [ErrorKind::InvalidData] wrapping a [ChecksumMismatch] is returned instead of signaling the end
+/// of the data. Thus, the data must be read completely to be verified.
+pub struct VerifyingReader<R: Read> {
+    inner: R,
+    expected: Checksum,
+    hasher: blake3::Hasher,
+    size: u64,
+}
+
+impl<R: Read> VerifyingReader<R> {
+    /// Creates a new reader verifying the data read from `inner` against `expected`.
+    pub fn new(inner: R, expected: Checksum) -> Self {
+        Self {
+            inner,
+            expected,
+            hasher: blake3::Hasher::new(),
+            size: 0,
+        }
+    }
+
+    fn mismatch(&self) -> io::Error {
+        io::Error::new(
+            ErrorKind::InvalidData,
+            ChecksumMismatch {
+                expected: self.expected,
+                actual: Checksum {
+                    size: self.size,
+                    hash: self.hasher.finalize(),
+                },
+            },
+        )
+    }
+}
+
+impl<R: Read> Read for VerifyingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        self.size += read as u64;
+        if self.size > self.expected.size
+            || (read == 0
+                && !buf.is_empty()
+                && (self.size != self.expected.size
+                    || self.hasher.finalize() != self.expected.hash))
+        {
+            return Err(self.mismatch());
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checksum_of(data: &[u8]) -> Checksum {
+        let mut writer = ChecksumWriter::new(io::sink());
+        writer.write_all(data).unwrap();
+        writer.finalize().1
+    }
+
+    #[test]
+    fn test_checksum_writer_calculates_checksum() {
+        assert_eq!(
+            checksum_of(b"Hello, world!"),
+            Checksum {
+                size: 13,
+                hash: blake3::hash(b"Hello, world!"),
+            }
+        );
+    }
+
+    #[test]
+    fn test_verifying_reader_accepts_matching_data() {
+        let mut reader = VerifyingReader::new(&b"Hello, world!"[..], checksum_of(b"Hello, world!"));
+        let mut buf = String::new();
+        reader.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "Hello, world!");
+    }
+
+    #[test]
+    fn test_verifying_reader_rejects_mismatching_data() {
+        for data in [&b"Hello, world"[..], b"Hello, world!!", b"Hello, World!"] {
+            let mut reader = VerifyingReader::new(data, checksum_of(b"Hello, world!"));
+            let err = io::copy(&mut reader, &mut io::sink()).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+            assert!(
+                err.get_ref()
+                    .is_some_and(|err| err.is::<ChecksumMismatch>())
+            );
+        }
+    }
+}
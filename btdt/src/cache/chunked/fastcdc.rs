@@ -0,0 +1,149 @@
+//! Content-defined chunking based on FastCDC.
+//!
+//! Chunk boundaries are determined by a rolling hash over the content, so that inserting or
+//! removing data only changes the chunks around the modification instead of shifting all following
+//! chunk boundaries. See Xia et al., "FastCDC: a Fast and Efficient Content-Defined Chunking
+//! Approach for Data Deduplication", USENIX ATC 2016.
+
+/// Minimum size of a chunk in bytes, unless it is the last chunk of the data.
+pub const MIN_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Targeted average size of a chunk in bytes.
+pub const AVG_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Maximum size of a chunk in bytes.
+pub const MAX_CHUNK_SIZE: usize = 256 * 1024;
+
+/// Mask applied to the hash below [AVG_CHUNK_SIZE]. It has more bits than the average chunk size
+/// requires, making boundaries less likely (normalized chunking).
+const MASK_SMALL: u64 = !0 << (64 - 18);
+
+/// Mask applied to the hash above [AVG_CHUNK_SIZE]. It has fewer bits than the average chunk size
+/// requires, making boundaries more likely.
+const MASK_LARGE: u64 = !0 << (64 - 14);
+
+/// Random values added to the rolling hash for each byte value.
+///
+/// The values must never change, as this would change the chunk boundaries of existing data.
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    // SplitMix64 with a fixed seed
+    let mut table = [0; 256];
+    let mut state: u64 = 0x6274_6474_6364_6321;
+    let mut i = 0;
+    while i < table.len() {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// Returns the length of the first chunk of `data`.
+///
+/// If `is_final` is `false`, more data may follow `data`. In that case, `None` is returned unless
+/// `data` contains at least [MAX_CHUNK_SIZE] bytes, as a boundary can only be determined with
+/// certainty if this many bytes are known. If `is_final` is `true`, `None` is only returned for
+/// empty data.
+pub fn chunk_len(data: &[u8], is_final: bool) -> Option<usize> {
+    if data.is_empty() || (!is_final && data.len() < MAX_CHUNK_SIZE) {
+        return None;
+    }
+    if data.len() <= MIN_CHUNK_SIZE {
+        return Some(data.len());
+    }
+
+    let limit = data.len().min(MAX_CHUNK_SIZE);
+    let normal = limit.min(AVG_CHUNK_SIZE);
+    let mut hash: u64 = 0;
+    for (i, &byte) in data.iter().enumerate().take(limit).skip(MIN_CHUNK_SIZE) {
+        hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+        let mask = if i < normal { MASK_SMALL } else { MASK_LARGE };
+        if hash & mask == 0 {
+            return Some(i + 1);
+        }
+    }
+    Some(limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::RngCore;
+    use rand::SeedableRng;
+    use rand_xoshiro::Xoshiro256PlusPlus;
+
+    fn random_data(len: usize) -> Vec<u8> {
+        let mut data = vec![0; len];
+        Xoshiro256PlusPlus::seed_from_u64(42).fill_bytes(&mut data);
+        data
+    }
+
+    fn chunk_lens(mut data: &[u8]) -> Vec<usize> {
+        let mut lens = vec![];
+        while let Some(len) = chunk_len(data, true) {
+            lens.push(len);
+            data = &data[len..];
+        }
+        lens
+    }
+
+    #[test]
+    fn test_chunk_len_returns_none_without_enough_data() {
+        assert_eq!(chunk_len(&[], true), None);
+        assert_eq!(chunk_len(&[], false), None);
+        assert_eq!(chunk_len(&random_data(MAX_CHUNK_SIZE - 1), false), None);
+    }
+
+    #[test]
+    fn test_chunk_len_returns_whole_data_if_short_and_final() {
+        assert_eq!(chunk_len(&[1, 2, 3], true), Some(3));
+        assert_eq!(
+            chunk_len(&random_data(MIN_CHUNK_SIZE), true),
+            Some(MIN_CHUNK_SIZE)
+        );
+    }
+
+    #[test]
+    fn test_chunks_are_within_size_limits() {
+        let data = random_data(4 * 1024 * 1024);
+        let lens = chunk_lens(&data);
+        assert_eq!(lens.iter().sum::<usize>(), data.len());
+        let (last, others) = lens.split_last().unwrap();
+        assert!(*last <= MAX_CHUNK_SIZE);
+        for len in others {
+            assert!((MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(len));
+        }
+        let avg = data.len() / lens.len();
+        assert!(
+            (AVG_CHUNK_SIZE / 2..AVG_CHUNK_SIZE * 2).contains(&avg),
+            "average chunk size {avg}"
+        );
+    }
+
+    #[test]
+    fn test_chunk_len_does_not_depend_on_following_data_once_max_size_is_known() {
+        let data = random_data(2 * MAX_CHUNK_SIZE);
+        assert_eq!(
+            chunk_len(&data[..MAX_CHUNK_SIZE], false),
+            chunk_len(&data, false)
+        );
+    }
+
+    #[test]
+    fn test_insertion_only_changes_chunks_around_it() {
+        let data = random_data(4 * 1024 * 1024);
+        let mut modified = data[..1000].to_vec();
+        modified.extend_from_slice(b"inserted");
+        modified.extend_from_slice(&data[1000..]);
+
+        let lens = chunk_lens(&data);
+        let modified_lens = chunk_lens(&modified);
+        assert_ne!(lens[0], modified_lens[0]);
+        assert_eq!(lens[2..], modified_lens[2..]);
+    }
+}
@@ -0,0 +1,903 @@
+//! Provides a cache that deduplicates data at the level of content-defined chunks.
+//!
+//! The data of an entry is split into chunks with [FastCDC](fastcdc). Each chunk is stored once
+//! in the storage under the hash of its content, and a manifest listing the chunks is stored as
+//! the blob of the entry in a [LocalCache]. Thus, entries that differ only in parts of their data
+//! (e.g. archives of dependency directories that differ in a single package) share most of their
+//! chunks.
+
+pub mod fastcdc;
+
+use super::blob_id::{BlobId, RngBytes, ThreadRng, content_blob_id};
+use super::checksum::{Checksum, VerifyingReader};
+use super::clean::{CleanOptions, CleanReport, Eviction, EvictionReason};
+use super::fsck::Inconsistency;
+use super::index::Index;
+use super::local::{self, LocalCache, ORPHAN_BLOB_GRACE_PERIOD};
+use super::{Cache, CacheEntry, CacheHit, SetOptions};
+use crate::error::{IoPathError, IoPathResult, WithPath};
+use crate::storage::Storage;
+use crate::util::clock::{Clock, SystemClock};
+use crate::util::close::Close;
+use crate::util::encoding::ICASE_NOPAD_ALPHANUMERIC_ENCODING;
+use chrono::{DateTime, Utc};
+use rkyv::rancor;
+use rkyv::util::AlignedVec;
+use rkyv::{Archive, Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::sync::Arc;
+
+const MANIFEST_VERSION: u16 = 1;
+
+/// A chunk of the data of a cache entry.
+#[derive(Archive, Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+struct ChunkRef {
+    id: BlobId,
+    size: u32,
+}
+
+/// The chunks making up the data of a cache entry.
+#[derive(Archive, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+struct Manifest {
+    version: u16,
+    size: u64,
+    hash: [u8; blake3::OUT_LEN],
+    chunks: Vec<ChunkRef>,
+}
+
+impl Manifest {
+    /// Deserializes a manifest. Returns `None` if the data is not a valid manifest of the current
+    /// version.
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut data = AlignedVec::<16>::with_capacity(bytes.len());
+        data.extend_from_slice(bytes);
+        rkyv::from_bytes::<Self, rancor::Error>(&data)
+            .ok()
+            .filter(|manifest| manifest.version == MANIFEST_VERSION)
+    }
+
+    fn to_bytes(&self) -> AlignedVec {
+        rkyv::to_bytes::<rancor::Error>(self).expect("failed to serialize manifest")
+    }
+
+    fn checksum(&self) -> Checksum {
+        Checksum {
+            size: self.size,
+            hash: blake3::Hash::from_bytes(self.hash),
+        }
+    }
+}
+
+/// A cache that splits the data of entries into content-defined chunks and stores each distinct
+/// chunk only once.
+///
+/// Keys, pins, and expiry are managed by the wrapped [LocalCache], which stores a manifest of the
+/// chunks for each entry. The chunks are stored in the `/chunk` directory of the same storage.
+/// Caches with and without chunking must not be mixed in the same storage, as the manifests are
+/// not readable as data of a plain [LocalCache] and vice versa.
+///
+/// # Examples
+///
+/// ```rust
+/// # use std::io;
+/// use std::io::{Read, Write};
+/// use btdt::cache::Cache;
+/// use btdt::cache::chunked::ChunkedCache;
+/// use btdt::cache::local::LocalCache;
+/// use btdt::storage::in_memory::InMemoryStorage;
+/// use btdt::util::close::Close;
+///
+/// # fn main() -> io::Result<()> {
+/// let cache = ChunkedCache::new(LocalCache::new(InMemoryStorage::new()));
+/// let mut writer = cache.set(&["cache-key"])?;
+/// writer.write_all(b"Hello, world!")?;
+/// writer.close()?;
+/// let mut buf = String::new();
+/// cache.get(&["cache-key"])?.unwrap().reader.read_to_string(&mut buf)?;
+/// assert_eq!(buf, "Hello, world!");
+/// # Ok(())
+/// # }
+/// ```
+pub struct ChunkedCache<S: Storage, C: Clock = SystemClock, R: RngBytes = ThreadRng> {
+    cache: LocalCache<S, C, R>,
+}
+
+impl<S: Storage, C: Clock, R: RngBytes> ChunkedCache<S, C, R> {
+    /// Creates a new chunked cache storing the manifests of the entries in the given cache, and
+    /// the chunks in its storage.
+    pub fn new(cache: LocalCache<S, C, R>) -> Self {
+        Self { cache }
+    }
+
+    /// Consumes the chunked cache and returns the wrapped cache storing the manifests.
+    pub fn into_inner(self) -> LocalCache<S, C, R> {
+        self.cache
+    }
+
+    /// Pins the given keys. See [LocalCache::pin].
+    pub fn pin(&self, keys: &[&str]) -> IoPathResult<()> {
+        self.cache.pin(keys)
+    }
+
+    /// Removes the pins of the given keys. See [LocalCache::unpin].
+    pub fn unpin(&self, keys: &[&str]) -> IoPathResult<()> {
+        self.cache.unpin(keys)
+    }
+
+    /// Checks the manifests of the cache for inconsistencies. See [LocalCache::fsck].
+    ///
+    /// The chunks are not checked, but the data of an entry is verified against its checksum when
+    /// it is read.
+    pub fn fsck(&self, repair: bool) -> IoPathResult<Vec<Inconsistency>> {
+        self.cache.fsck(repair)
+    }
+
+    /// Deletes cache entries like [LocalCache::clean], but applies the `max_blob_size_sum` and the
+    /// quotas of the `options` to the total size of the distinct chunks referred to by the entries.
+    ///
+    /// Chunks not referred to by any entry are deleted once they are older than
+    /// [ORPHAN_BLOB_GRACE_PERIOD]. The size of an eviction in the report is the size of the chunks
+    /// that were only referred to by the evicted entry. Unreferenced chunks not attributable to an
+    /// evicted entry are reported as a single orphan eviction.
+    pub fn clean(&mut self, options: &CleanOptions) -> IoPathResult<CleanReport> {
+        self.clean_impl(options, false)
+    }
+
+    /// Returns the report of what [ChunkedCache::clean] would delete, without deleting anything.
+    pub fn clean_dry_run(&self, options: &CleanOptions) -> IoPathResult<CleanReport> {
+        self.clean_impl(options, true)
+    }
+
+    fn clean_impl(&self, options: &CleanOptions, dry_run: bool) -> IoPathResult<CleanReport> {
+        let now = self.cache.clock().now();
+        let mut chunks = ChunkRefCounts::default();
+        let mut report = self.cache.clean_with(dry_run, |index, now, pinned_keys| {
+            let (evictions, planned_chunks) =
+                self.plan_evictions(index, now, pinned_keys, options)?;
+            chunks = planned_chunks;
+            Ok(evictions)
+        })?;
+
+        let storage = self.cache.storage();
+        let referenced: HashSet<String> = chunks.sizes.keys().map(chunk_path).collect();
+        let released: HashSet<String> = chunks.released.iter().map(chunk_path).collect();
+        let orphan_cutoff = now - ORPHAN_BLOB_GRACE_PERIOD;
+        let mut orphans: Option<Eviction> = None;
+        for chunk_file in LocalCache::<S, C, R>::iter_subdir_files_if_exists(storage, "/chunk")? {
+            let chunk_file = chunk_file?;
+            let Some(modified) = chunk_file
+                .modified
+                .filter(|&modified| modified < orphan_cutoff)
+            else {
+                continue;
+            };
+            if referenced.contains(&chunk_file.path) {
+                continue;
+            }
+            if !dry_run {
+                match storage.delete(&chunk_file.path) {
+                    Err(err) if err.io_error().kind() == ErrorKind::NotFound => {}
+                    result => result?,
+                }
+            }
+            if released.contains(&chunk_file.path) {
+                continue;
+            }
+            let orphans = orphans.get_or_insert_with(|| Eviction {
+                keys: vec![],
+                blob_size: 0,
+                latest_access: DateTime::<Utc>::MIN_UTC,
+                reason: EvictionReason::Orphan,
+            });
+            orphans.blob_size += chunk_file.size;
+            orphans.latest_access = orphans.latest_access.max(modified);
+        }
+        report.evictions.extend(orphans);
+        Ok(report)
+    }
+
+    /// Determines the manifests to evict like [LocalCache::clean], but applies the size limit and
+    /// the quotas to the distinct chunks referred to by the manifests.
+    ///
+    /// Returns the evictions along with the chunks still referred to afterwards.
+    fn plan_evictions(
+        &self,
+        index: &Index,
+        now: DateTime<Utc>,
+        pinned_keys: &HashSet<String>,
+        options: &CleanOptions,
+    ) -> IoPathResult<(Vec<(BlobId, Eviction)>, ChunkRefCounts)> {
+        let mut blob_keys: HashMap<BlobId, Vec<String>> = HashMap::new();
+        for (key, indexed_key) in &index.keys {
+            blob_keys
+                .entry(indexed_key.blob_id)
+                .or_default()
+                .push(key.clone());
+        }
+        let mut chunks = ChunkRefCounts::default();
+        for blob_id in blob_keys.keys() {
+            if let Some(manifest) = self.read_manifest(blob_id)? {
+                chunks.insert(*blob_id, &manifest.chunks);
+            }
+        }
+
+        // Expired, unused, and orphaned manifests are evicted as by the wrapped cache.
+        let mut evictions = LocalCache::<S, C, R>::plan_evictions(
+            index,
+            now,
+            pinned_keys,
+            &CleanOptions {
+                max_unused_age: options.max_unused_age,
+                policy: options.policy,
+                ..CleanOptions::default()
+            },
+        );
+        for (blob_id, eviction) in &mut evictions {
+            if chunks.contains(blob_id) {
+                eviction.blob_size = chunks.remove(blob_id);
+            }
+        }
+
+        // A size limit of zero yields all remaining evictable entries in the order of the policy.
+        let mut candidates: Vec<Option<(BlobId, Eviction)>> =
+            LocalCache::<S, C, R>::plan_evictions(
+                index,
+                now,
+                pinned_keys,
+                &CleanOptions {
+                    max_blob_size_sum: Some(0),
+                    policy: options.policy,
+                    ..CleanOptions::default()
+                },
+            )
+            .into_iter()
+            .filter(|(blob_id, eviction)| {
+                eviction.reason == EvictionReason::Size && chunks.contains(blob_id)
+            })
+            .map(Some)
+            .collect();
+        for quota in &options.prefix_quotas {
+            let in_namespace =
+                |keys: &[String]| keys.iter().any(|key| key.starts_with(&quota.prefix));
+            let mut namespace_chunks = ChunkRefCounts::default();
+            for (blob_id, keys) in &blob_keys {
+                if in_namespace(keys)
+                    && let Some(chunk_ids) = chunks.manifests.get(blob_id)
+                {
+                    namespace_chunks.insert_ids(*blob_id, chunk_ids, &chunks.sizes);
+                }
+            }
+            for slot in &mut candidates {
+                if namespace_chunks.size <= quota.max_blob_size_sum {
+                    break;
+                }
+                if let Some((blob_id, eviction)) =
+                    slot.take_if(|(_, eviction)| in_namespace(&eviction.keys))
+                {
+                    namespace_chunks.remove(&blob_id);
+                    let eviction = chunks.evict(blob_id, eviction, EvictionReason::Quota);
+                    evictions.push((blob_id, eviction));
+                }
+            }
+        }
+        if let Some(max_chunk_size_sum) = options.max_blob_size_sum {
+            for (blob_id, eviction) in candidates.into_iter().flatten() {
+                if chunks.size <= max_chunk_size_sum {
+                    break;
+                }
+                let eviction = chunks.evict(blob_id, eviction, EvictionReason::Size);
+                evictions.push((blob_id, eviction));
+            }
+        }
+        Ok((evictions, chunks))
+    }
+
+    /// Reads the manifest of the blob with the given ID.
+    ///
+    /// Returns `Ok(None)` if the blob does not exist or is not a manifest.
+    fn read_manifest(&self, blob_id: &BlobId) -> IoPathResult<Option<Manifest>> {
+        let mut reader = match self.cache.read_blob(blob_id) {
+            Ok(reader) => reader,
+            Err(err) if err.io_error().kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let mut data = vec![];
+        reader.read_to_end(&mut data).no_path()?;
+        Ok(Manifest::from_bytes(&data))
+    }
+
+    /// Replaces the reader of the manifest in a cache hit with a reader of the chunked data.
+    fn read_chunks<'a>(
+        &self,
+        cache_hit: CacheHit<'a, S::Reader>,
+    ) -> IoPathResult<CacheHit<'a, ChunkedReader<S>>> {
+        let mut data = vec![];
+        let mut reader = cache_hit.reader;
+        match cache_hit.checksum {
+            Some(checksum) => VerifyingReader::new(reader, checksum).read_to_end(&mut data),
+            None => reader.read_to_end(&mut data),
+        }
+        .no_path()?;
+        let manifest = Manifest::from_bytes(&data).ok_or_else(|| {
+            IoPathError::new_no_path(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("cache entry {} is not a chunk manifest", cache_hit.key),
+            ))
+        })?;
+        Ok(CacheHit {
+            key: cache_hit.key,
+            reader: ChunkedReader {
+                storage: self.cache.storage().clone(),
+                chunks: manifest.chunks.clone().into_iter(),
+                current: None,
+            },
+            size_hint: Some(manifest.size),
+            checksum: Some(manifest.checksum()),
+        })
+    }
+}
+
+impl<S: Storage, C: Clock, R: RngBytes> Cache for ChunkedCache<S, C, R> {
+    type Reader = ChunkedReader<S>;
+    type Writer = ChunkedWriter<S>;
+
+    fn get<'a>(&self, keys: &[&'a str]) -> IoPathResult<Option<CacheHit<'a, Self::Reader>>> {
+        self.cache
+            .get(keys)?
+            .map(|cache_hit| self.read_chunks(cache_hit))
+            .transpose()
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefixes: &[&str],
+    ) -> IoPathResult<Option<CacheHit<'static, Self::Reader>>> {
+        self.cache
+            .get_by_prefix(prefixes)?
+            .map(|cache_hit| self.read_chunks(cache_hit))
+            .transpose()
+    }
+
+    fn set_with_options(&self, keys: &[&str], options: &SetOptions) -> IoPathResult<Self::Writer> {
+        Ok(ChunkedWriter {
+            storage: self.cache.storage().clone(),
+            manifest_writer: self.cache.set_with_options(keys, options)?,
+            buf: Vec::with_capacity(fastcdc::MAX_CHUNK_SIZE),
+            chunks: vec![],
+            hasher: blake3::Hasher::new(),
+            size: 0,
+            renew_chunks_before: self.cache.clock().now() - ORPHAN_BLOB_GRACE_PERIOD / 2,
+        })
+    }
+
+    fn list(&self, prefix: &str) -> IoPathResult<Vec<CacheEntry>> {
+        let mut entries = vec![];
+        let mut sizes = HashMap::new();
+        for (mut entry, blob_id) in self.cache.list_with_blob_ids(prefix)? {
+            let size = match sizes.entry(blob_id) {
+                Entry::Occupied(size) => *size.get(),
+                Entry::Vacant(size) => {
+                    *size.insert(self.read_manifest(&blob_id)?.map(|manifest| manifest.size))
+                }
+            };
+            if let Some(size) = size {
+                entry.size = size;
+            }
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    fn entry(&self, key: &str) -> IoPathResult<Option<CacheEntry>> {
+        let Some((mut entry, blob_id)) = self.cache.entry_with_blob_id(key)? else {
+            return Ok(None);
+        };
+        if let Some(manifest) = self.read_manifest(&blob_id)? {
+            entry.size = manifest.size;
+        }
+        Ok(Some(entry))
+    }
+
+    fn delete(&self, keys: &[&str]) -> IoPathResult<()> {
+        // The chunks are deleted by ChunkedCache::clean once no manifest refers to them anymore.
+        self.cache.delete(keys)
+    }
+}
+
+fn chunk_path(chunk_id: &BlobId) -> String {
+    let chunk_id = ICASE_NOPAD_ALPHANUMERIC_ENCODING.encode(chunk_id.as_ref());
+    format!("/chunk/{}/{}", &chunk_id[..2], &chunk_id[2..])
+}
+
+/// Reference counts of the chunks referred to by a set of manifests.
+#[derive(Default)]
+struct ChunkRefCounts {
+    /// The distinct chunks of each manifest.
+    manifests: HashMap<BlobId, Vec<BlobId>>,
+    /// The sizes of the referenced chunks.
+    sizes: HashMap<BlobId, u64>,
+    counts: HashMap<BlobId, usize>,
+    /// Total size of the referenced chunks.
+    size: u64,
+    /// Chunks that are no longer referenced after removing manifests.
+    released: HashSet<BlobId>,
+}
+
+impl ChunkRefCounts {
+    fn insert(&mut self, blob_id: BlobId, chunks: &[ChunkRef]) {
+        let sizes: HashMap<_, _> = chunks
+            .iter()
+            .map(|chunk| (chunk.id, u64::from(chunk.size)))
+            .collect();
+        let chunk_ids: Vec<_> = sizes.keys().copied().collect();
+        self.insert_ids(blob_id, &chunk_ids, &sizes);
+    }
+
+    fn insert_ids(&mut self, blob_id: BlobId, chunk_ids: &[BlobId], sizes: &HashMap<BlobId, u64>) {
+        for chunk_id in chunk_ids {
+            let count = self.counts.entry(*chunk_id).or_default();
+            if *count == 0 {
+                self.sizes.insert(*chunk_id, sizes[chunk_id]);
+                self.size += sizes[chunk_id];
+            }
+            *count += 1;
+        }
+        self.manifests.insert(blob_id, chunk_ids.to_vec());
+    }
+
+    fn contains(&self, blob_id: &BlobId) -> bool {
+        self.manifests.contains_key(blob_id)
+    }
+
+    /// Removes a manifest and returns the total size of the chunks no longer referenced.
+    fn remove(&mut self, blob_id: &BlobId) -> u64 {
+        let mut released_size = 0;
+        for chunk_id in self.manifests.remove(blob_id).unwrap_or_default() {
+            let Some(count) = self.counts.get_mut(&chunk_id) else {
+                continue;
+            };
+            *count -= 1;
+            if *count == 0 {
+                self.counts.remove(&chunk_id);
+                let size = self.sizes.remove(&chunk_id).unwrap_or_default();
+                self.size -= size;
+                released_size += size;
+                self.released.insert(chunk_id);
+            }
+        }
+        released_size
+    }
+
+    /// Removes the manifest of an evicted entry and sets the size and reason of the eviction.
+    fn evict(
+        &mut self,
+        blob_id: BlobId,
+        mut eviction: Eviction,
+        reason: EvictionReason,
+    ) -> Eviction {
+        eviction.blob_size = self.remove(&blob_id);
+        eviction.reason = reason;
+        eviction
+    }
+}
+
+/// A reader for the data of a [ChunkedCache] entry, reading its chunks one after another.
+pub struct ChunkedReader<S: Storage> {
+    storage: Arc<S>,
+    chunks: std::vec::IntoIter<ChunkRef>,
+    current: Option<S::Reader>,
+}
+
+impl<S: Storage> Read for ChunkedReader<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if let Some(reader) = &mut self.current {
+                let bytes_read = reader.read(buf)?;
+                if bytes_read > 0 || buf.is_empty() {
+                    return Ok(bytes_read);
+                }
+                self.current = None;
+            }
+            let Some(chunk) = self.chunks.next() else {
+                return Ok(0);
+            };
+            let path = chunk_path(&chunk.id);
+            match self.storage.get(&path) {
+                Ok(file_handle) => self.current = Some(file_handle.reader),
+                Err(err) => return Err(io::Error::new(err.io_error().kind(), err)),
+            }
+        }
+    }
+}
+
+/// A writer for a [ChunkedCache] entry.
+///
+/// The chunks are stored while writing, the manifest once the writer is closed. Chunks reused from
+/// other entries are not deleted by [ChunkedCache::clean] within half of the
+/// [ORPHAN_BLOB_GRACE_PERIOD] after creating the writer, even if no manifest refers to them.
+pub struct ChunkedWriter<S: Storage> {
+    storage: Arc<S>,
+    manifest_writer: local::CacheWriter<S, AlignedVec>,
+    /// Data not yet assigned to a chunk.
+    buf: Vec<u8>,
+    chunks: Vec<ChunkRef>,
+    hasher: blake3::Hasher,
+    size: u64,
+    /// Existing chunks modified before this time are replaced when reused.
+    renew_chunks_before: DateTime<Utc>,
+}
+
+impl<S: Storage> ChunkedWriter<S> {
+    /// Stores the first `len` bytes of the buffer as a chunk.
+    ///
+    /// An existing chunk with the same content is kept, unless it was modified before
+    /// `renew_chunks_before`. Then, it is replaced to renew the modification time relevant for the
+    /// grace period of unreferenced chunks.
+    fn store_chunk(&mut self, len: usize) -> io::Result<()> {
+        let chunk = &self.buf[..len];
+        let id = content_blob_id(&blake3::hash(chunk));
+        let path = chunk_path(&id);
+        let is_recent = match self.storage.get(&path) {
+            Ok(file_handle) => file_handle
+                .modified
+                .is_some_and(|modified| modified >= self.renew_chunks_before),
+            Err(err) if err.io_error().kind() == ErrorKind::NotFound => false,
+            Err(err) => return Err(err.into()),
+        };
+        if !is_recent {
+            let mut writer = self.storage.put(&path)?;
+            writer.write_all(chunk)?;
+            writer.close()?;
+        }
+        self.chunks.push(ChunkRef {
+            id,
+            size: len as u32,
+        });
+        self.buf.drain(..len);
+        Ok(())
+    }
+}
+
+impl<S: Storage> Write for ChunkedWriter<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.hasher.update(buf);
+        self.size += buf.len() as u64;
+        self.buf.extend_from_slice(buf);
+        while let Some(len) = fastcdc::chunk_len(&self.buf, false) {
+            self.store_chunk(len)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<S: Storage> Close for ChunkedWriter<S> {
+    /// Aborts storing the manifest. Chunks already stored are deleted by [ChunkedCache::clean]
+    /// after the grace period.
+    fn abort(self) {
+        self.manifest_writer.abort();
+    }
+
+    fn close(mut self) -> io::Result<()> {
+        while let Some(len) = fastcdc::chunk_len(&self.buf, true) {
+            self.store_chunk(len)?;
+        }
+        let manifest = Manifest {
+            version: MANIFEST_VERSION,
+            size: self.size,
+            hash: *self.hasher.finalize().as_bytes(),
+            chunks: self.chunks,
+        };
+        self.manifest_writer.write_all(&manifest.to_bytes())?;
+        self.manifest_writer.close()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::clean::PrefixQuota;
+    use crate::error::IoPathResult;
+    use crate::storage::in_memory::InMemoryStorage;
+    use crate::util::clock::test_fakes::ControlledClock;
+    use chrono::TimeDelta;
+    use rand::{RngCore, SeedableRng};
+    use rand_xoshiro::Xoshiro256PlusPlus;
+    use std::thread;
+    use std::time::Duration;
+
+    fn random_data(seed: u64, len: usize) -> Vec<u8> {
+        let mut data = vec![0; len];
+        Xoshiro256PlusPlus::seed_from_u64(seed).fill_bytes(&mut data);
+        data
+    }
+
+    fn with_insertion(data: &[u8], at: usize, inserted: &[u8]) -> Vec<u8> {
+        let mut modified = data[..at].to_vec();
+        modified.extend_from_slice(inserted);
+        modified.extend_from_slice(&data[at..]);
+        modified
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let cache = ChunkedCache::new(LocalCache::new(InMemoryStorage::new()));
+        let data = random_data(0, 1024 * 1024);
+        cache_entry_with_content(&cache, &["key"], &data).unwrap();
+        assert_cache_entry_with_content(&cache, &["key"], &data);
+    }
+
+    #[test]
+    fn test_roundtrip_of_empty_data() {
+        let cache = ChunkedCache::new(LocalCache::new(InMemoryStorage::new()));
+        cache_entry_with_content(&cache, &["key"], &[]).unwrap();
+        assert_cache_entry_with_content(&cache, &["key"], &[]);
+    }
+
+    #[test]
+    fn test_get_by_prefix_returns_chunked_data() {
+        let cache = ChunkedCache::new(LocalCache::new(InMemoryStorage::new()));
+        let data = random_data(0, 100_000);
+        cache_entry_with_content(&cache, &["prefix-key"], &data).unwrap();
+
+        let mut buf = vec![];
+        let mut cache_hit = cache.get_by_prefix(&["prefix-"]).unwrap().unwrap();
+        cache_hit.reader.read_to_end(&mut buf).unwrap();
+        assert_eq!(cache_hit.key, "prefix-key");
+        assert_eq!(buf, data);
+    }
+
+    #[test]
+    fn test_entries_with_similar_data_share_chunks() {
+        let storage = InMemoryStorage::new();
+        let cache = ChunkedCache::new(LocalCache::new(storage.clone()));
+        let data = random_data(0, 1024 * 1024);
+        let modified = with_insertion(&data, 1000, b"inserted");
+
+        cache_entry_with_content(&cache, &["original"], &data).unwrap();
+        cache_entry_with_content(&cache, &["modified"], &modified).unwrap();
+
+        assert_cache_entry_with_content(&cache, &["original"], &data);
+        assert_cache_entry_with_content(&cache, &["modified"], &modified);
+        let stored_size = chunk_size_sum(&storage);
+        assert!(stored_size >= modified.len() as u64);
+        assert!(stored_size <= (modified.len() + 2 * fastcdc::MAX_CHUNK_SIZE) as u64);
+    }
+
+    #[test]
+    fn test_list_reports_size_of_data() {
+        let cache = ChunkedCache::new(LocalCache::new(InMemoryStorage::new()));
+        let data = random_data(0, 500_000);
+        cache_entry_with_content(&cache, &["key"], &data).unwrap();
+
+        let entries = cache.list("").unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].key, "key");
+        assert_eq!(entries[0].size, data.len() as u64);
+    }
+
+    #[test]
+    fn test_entry_reports_size_of_data() {
+        let cache = ChunkedCache::new(LocalCache::new(InMemoryStorage::new()));
+        let data = random_data(0, 100_000);
+        cache_entry_with_content(&cache, &["key"], &data).unwrap();
+
+        assert_eq!(cache.entry("key").unwrap().unwrap().size, data.len() as u64);
+        assert_eq!(cache.entry("non-existent").unwrap(), None);
+    }
+
+    #[test]
+    fn test_get_returns_error_for_entries_stored_without_chunking() {
+        let storage = InMemoryStorage::new();
+        let local_cache = LocalCache::new(storage.clone());
+        let mut writer = local_cache.set(&["key"]).unwrap();
+        writer.write_all(b"not a manifest").unwrap();
+        writer.close().unwrap();
+
+        let cache = ChunkedCache::new(LocalCache::new(storage));
+        let err = cache.get(&["key"]).err().unwrap();
+        assert_eq!(err.io_error().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_clean_applies_size_limit_to_distinct_chunks() {
+        let mut clock = ControlledClock::new(Utc::now());
+        let storage = InMemoryStorage::new();
+        let mut cache = ChunkedCache::new(LocalCache::with_clock(storage.clone(), clock.clone()));
+        let data = random_data(0, 1024 * 1024);
+        let modified = with_insertion(&data, 1000, b"inserted");
+
+        cache_entry_with_content(&cache, &["original"], &data).unwrap();
+        clock.advance_by(TimeDelta::minutes(1));
+        cache_entry_with_content(&cache, &["modified"], &modified).unwrap();
+
+        let limit = (modified.len() + 2 * fastcdc::MAX_CHUNK_SIZE) as u64;
+        let report = cache
+            .clean(&CleanOptions {
+                max_blob_size_sum: Some(limit),
+                ..CleanOptions::default()
+            })
+            .unwrap();
+        assert_eq!(report, CleanReport::default());
+
+        let limit = modified.len() as u64;
+        let report = cache
+            .clean_dry_run(&CleanOptions {
+                max_blob_size_sum: Some(limit),
+                ..CleanOptions::default()
+            })
+            .unwrap();
+        assert_eq!(report.evictions.len(), 1);
+        assert_eq!(report.evictions[0].keys, vec!["original".to_string()]);
+        assert_eq!(report.evictions[0].reason, EvictionReason::Size);
+        assert!(report.evictions[0].blob_size > 0);
+        assert!(report.evictions[0].blob_size <= 2 * fastcdc::MAX_CHUNK_SIZE as u64);
+        assert_eq!(cache.list("").unwrap().len(), 2);
+
+        let clean_report = cache
+            .clean(&CleanOptions {
+                max_blob_size_sum: Some(limit),
+                ..CleanOptions::default()
+            })
+            .unwrap();
+        assert_eq!(clean_report, report);
+        assert!(cache.get(&["original"]).unwrap().is_none());
+        assert_cache_entry_with_content(&cache, &["modified"], &modified);
+    }
+
+    #[test]
+    fn test_clean_applies_prefix_quotas_to_distinct_chunks() {
+        let mut clock = ControlledClock::new(Utc::now());
+        let storage = InMemoryStorage::new();
+        let mut cache = ChunkedCache::new(LocalCache::with_clock(storage.clone(), clock.clone()));
+        let data = random_data(0, 1024 * 1024);
+        let modified = with_insertion(&data, 1000, b"inserted");
+        let other = random_data(1, 1024 * 1024);
+
+        cache_entry_with_content(&cache, &["other"], &other).unwrap();
+        clock.advance_by(TimeDelta::minutes(1));
+        cache_entry_with_content(&cache, &["prefix-original"], &data).unwrap();
+        clock.advance_by(TimeDelta::minutes(1));
+        cache_entry_with_content(&cache, &["prefix-modified"], &modified).unwrap();
+
+        let quotas = vec![PrefixQuota {
+            prefix: "prefix-".to_string(),
+            max_blob_size_sum: modified.len() as u64,
+        }];
+        let report = cache
+            .clean(&CleanOptions {
+                prefix_quotas: quotas,
+                ..CleanOptions::default()
+            })
+            .unwrap();
+
+        assert_eq!(report.evictions.len(), 1);
+        assert_eq!(
+            report.evictions[0].keys,
+            vec!["prefix-original".to_string()]
+        );
+        assert_eq!(report.evictions[0].reason, EvictionReason::Quota);
+        assert_cache_entry_with_content(&cache, &["other"], &other);
+        assert_cache_entry_with_content(&cache, &["prefix-modified"], &modified);
+    }
+
+    #[test]
+    fn test_clean_deletes_unreferenced_chunks_after_grace_period() {
+        let mut clock = ControlledClock::new(Utc::now());
+        let storage = InMemoryStorage::new();
+        let mut cache = ChunkedCache::new(LocalCache::with_clock(storage.clone(), clock.clone()));
+        let data = random_data(0, 1024 * 1024);
+        cache_entry_with_content(&cache, &["deleted"], &data).unwrap();
+        let kept = random_data(1, 100_000);
+        cache_entry_with_content(&cache, &["kept"], &kept).unwrap();
+        cache.delete(&["deleted"]).unwrap();
+
+        let report = cache.clean(&CleanOptions::default()).unwrap();
+        assert_eq!(report, CleanReport::default());
+
+        clock.advance_by(ORPHAN_BLOB_GRACE_PERIOD + TimeDelta::minutes(1));
+        let report = cache.clean(&CleanOptions::default()).unwrap();
+
+        assert_eq!(report.evictions.len(), 1);
+        assert_eq!(report.evictions[0].reason, EvictionReason::Orphan);
+        assert_eq!(report.evictions[0].blob_size, data.len() as u64);
+        assert_eq!(chunk_size_sum(&storage), kept.len() as u64);
+        assert_cache_entry_with_content(&cache, &["kept"], &kept);
+    }
+
+    #[test]
+    fn test_clean_keeps_unreferenced_chunks_reused_by_pending_writer() {
+        let start = Utc::now();
+        let mut clock = ControlledClock::new(start);
+        let storage = InMemoryStorage::new();
+        let mut cache = ChunkedCache::new(LocalCache::with_clock(storage.clone(), clock.clone()));
+        let data = random_data(0, 1024 * 1024);
+        cache_entry_with_content(&cache, &["deleted"], &data).unwrap();
+        cache.delete(&["deleted"]).unwrap();
+        thread::sleep(Duration::from_millis(10));
+        let reused_at = Utc::now();
+
+        clock.advance_by(reused_at - start + ORPHAN_BLOB_GRACE_PERIOD / 2);
+        let mut writer = cache.set(&["key"]).unwrap();
+        writer.write_all(&data).unwrap();
+        clock.advance_by(ORPHAN_BLOB_GRACE_PERIOD / 2);
+        cache.clean(&CleanOptions::default()).unwrap();
+        writer.close().unwrap();
+
+        assert_cache_entry_with_content(&cache, &["key"], &data);
+    }
+
+    #[test]
+    fn test_set_keeps_recently_stored_chunks() {
+        let storage = InMemoryStorage::new();
+        let cache = ChunkedCache::new(LocalCache::new(storage.clone()));
+        let data = random_data(0, 1024 * 1024);
+        cache_entry_with_content(&cache, &["original"], &data).unwrap();
+        let chunks_modified = chunk_modification_times(&storage);
+        thread::sleep(Duration::from_millis(10));
+
+        cache_entry_with_content(&cache, &["copy"], &data).unwrap();
+
+        assert_eq!(chunk_modification_times(&storage), chunks_modified);
+        assert_cache_entry_with_content(&cache, &["copy"], &data);
+    }
+
+    fn cache_entry_with_content<C: Cache>(
+        cache: &C,
+        keys: &[&str],
+        content: &[u8],
+    ) -> IoPathResult<()> {
+        let mut writer = cache.set(keys)?;
+        let pseudo_path = keys.join(", ");
+        writer.write_all(content).with_path(&pseudo_path)?;
+        writer.close().with_path(&pseudo_path)
+    }
+
+    fn assert_cache_entry_with_content<C: Cache>(cache: &C, keys: &[&str], content: &[u8]) {
+        let CacheHit {
+            mut reader,
+            size_hint,
+            checksum,
+            ..
+        } = cache
+            .get(keys)
+            .expect("IO failure getting cache entry")
+            .expect("cache entry not found");
+        let mut buf = vec![];
+        reader
+            .read_to_end(&mut buf)
+            .expect("failed to read cache entry");
+        assert!(buf == content, "cache entry content mismatch");
+        assert_eq!(size_hint, Some(content.len() as u64));
+        assert_eq!(
+            checksum,
+            Some(Checksum {
+                size: content.len() as u64,
+                hash: blake3::hash(content),
+            })
+        );
+    }
+
+    fn chunk_size_sum<S: Storage>(storage: &S) -> u64 {
+        LocalCache::<S>::iter_subdir_files_if_exists(storage, "/chunk")
+            .unwrap()
+            .map(|chunk_file| chunk_file.unwrap().size)
+            .sum()
+    }
+
+    fn chunk_modification_times<S: Storage>(storage: &S) -> HashMap<String, Option<DateTime<Utc>>> {
+        LocalCache::<S>::iter_subdir_files_if_exists(storage, "/chunk")
+            .unwrap()
+            .map(|chunk_file| {
+                let chunk_file = chunk_file.unwrap();
+                (chunk_file.path, chunk_file.modified)
+            })
+            .collect()
+    }
+}
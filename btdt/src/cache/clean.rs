@@ -0,0 +1,131 @@
+//! Types describing the result of cleaning a [LocalCache](super::local::LocalCache).
+
+use chrono::{DateTime, SecondsFormat, TimeDelta, Utc};
+use std::fmt::{Display, Formatter};
+
+/// The order in which [LocalCache::clean](super::local::LocalCache::clean) evicts blobs to meet
+/// size limits.
+///
+/// Blobs not accessed within the maximum unused age are evicted independent of the policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EvictionPolicy {
+    /// Evict the least recently used blobs first.
+    #[default]
+    Lru,
+    /// Evict the least frequently used blobs first, i.e. those whose keys were accessed the least
+    /// number of times. Ties are broken by evicting the least recently used blob first.
+    Lfu,
+    /// Evict the blobs with the largest product of size and time since the latest access first,
+    /// i.e. large blobs that have not been used for a long time.
+    SizeWeighted,
+}
+
+/// The reason why a blob is evicted from the cache by
+/// [LocalCache::clean](super::local::LocalCache::clean).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionReason {
+    /// The blob was not accessed within the maximum unused age.
+    Age,
+    /// The blob was evicted according to the [EvictionPolicy] while the cache exceeded its maximum
+    /// size.
+    Size,
+    /// The blob was evicted according to the [EvictionPolicy] while the blobs of a key prefix
+    /// exceeded its [PrefixQuota].
+    Quota,
+    /// The blob was not referred to by any key.
+    Orphan,
+    /// The keys referring to the blob have expired.
+    Expired,
+}
+
+impl EvictionReason {
+    /// Returns a short, machine-readable name of the reason.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EvictionReason::Age => "age",
+            EvictionReason::Size => "size",
+            EvictionReason::Quota => "quota",
+            EvictionReason::Orphan => "orphan",
+            EvictionReason::Expired => "expired",
+        }
+    }
+}
+
+impl Display for EvictionReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The limits applied by [LocalCache::clean](super::local::LocalCache::clean).
+///
+/// The default options only delete expired entries and orphaned blobs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CleanOptions {
+    /// Entries not accessed within this duration are deleted.
+    pub max_unused_age: Option<TimeDelta>,
+    /// Maximum total size in bytes of the blobs in the cache.
+    pub max_blob_size_sum: Option<u64>,
+    /// Limits on the total size of the blobs referred to by keys starting with a prefix, applied
+    /// in the given order.
+    pub prefix_quotas: Vec<PrefixQuota>,
+    /// The order in which entries are deleted to meet the size limits.
+    pub policy: EvictionPolicy,
+}
+
+/// A limit on the total size of the blobs referred to by keys starting with a prefix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefixQuota {
+    /// The prefix of the keys the quota applies to.
+    pub prefix: String,
+    /// Maximum total size in bytes of the blobs referred to by any key starting with the prefix.
+    pub max_blob_size_sum: u64,
+}
+
+/// A blob evicted from the cache together with the keys referring to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Eviction {
+    /// Keys referring to the blob, sorted. Empty for orphaned blobs.
+    pub keys: Vec<String>,
+    /// Size of the blob in bytes.
+    pub blob_size: u64,
+    /// Latest access of any of the keys, or the modification time for orphaned blobs.
+    pub latest_access: DateTime<Utc>,
+    /// Why the blob is evicted.
+    pub reason: EvictionReason,
+}
+
+impl Display for Eviction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let keys = if self.keys.is_empty() {
+            "<no keys>".to_string()
+        } else {
+            self.keys.join(", ")
+        };
+        write!(
+            f,
+            "{keys} ({} bytes, latest access {}, reason: {})",
+            self.blob_size,
+            self.latest_access
+                .to_rfc3339_opts(SecondsFormat::Secs, true),
+            self.reason
+        )
+    }
+}
+
+/// The evictions performed (or, for a dry run, planned) by cleaning a cache.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CleanReport {
+    /// The evicted blobs in the order of eviction.
+    pub evictions: Vec<Eviction>,
+}
+
+impl CleanReport {
+    /// Returns the total size of the evicted blobs in bytes.
+    pub fn freed_size(&self) -> u64 {
+        self.evictions
+            .iter()
+            .map(|eviction| eviction.blob_size)
+            .sum()
+    }
+}
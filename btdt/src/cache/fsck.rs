@@ -0,0 +1,77 @@
+//! Types describing inconsistencies found when checking a [LocalCache](super::local::LocalCache).
+
+use std::fmt::{Display, Formatter};
+
+/// An inconsistency found in a cache by [LocalCache::fsck](super::local::LocalCache::fsck).
+///
+/// Paths are relative to the root of the cache storage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inconsistency {
+    /// A meta file that could not be decoded.
+    ///
+    /// When repairing, the meta file is moved to the `/quarantine` directory.
+    UndecodableMeta {
+        /// Path of the meta file.
+        path: String,
+    },
+
+    /// A key referring to a blob that does not exist.
+    ///
+    /// When repairing, the key is deleted.
+    DanglingKey {
+        /// The key.
+        key: String,
+        /// Path of the missing blob.
+        blob_path: String,
+    },
+
+    /// A blob not referred to by any key.
+    ///
+    /// When repairing, the blob is deleted.
+    OrphanBlob {
+        /// Path of the blob.
+        path: String,
+    },
+
+    /// A blob whose content does not match the checksum recorded when it was stored.
+    ///
+    /// When repairing, the blob is moved to the `/quarantine` directory and the keys referring to
+    /// it are deleted.
+    CorruptedBlob {
+        /// Path of the blob.
+        path: String,
+        /// Keys referring to the blob.
+        keys: Vec<String>,
+    },
+}
+
+impl Inconsistency {
+    /// Returns the path of the file that is inconsistent.
+    pub fn path(&self) -> &str {
+        match self {
+            Inconsistency::UndecodableMeta { path } => path,
+            Inconsistency::DanglingKey { blob_path, .. } => blob_path,
+            Inconsistency::OrphanBlob { path } => path,
+            Inconsistency::CorruptedBlob { path, .. } => path,
+        }
+    }
+}
+
+impl Display for Inconsistency {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Inconsistency::UndecodableMeta { path } => {
+                write!(f, "Undecodable meta file: {path}")
+            }
+            Inconsistency::DanglingKey { key, blob_path } => {
+                write!(f, "Key {key} refers to missing blob: {blob_path}")
+            }
+            Inconsistency::OrphanBlob { path } => {
+                write!(f, "Blob not referred to by any key: {path}")
+            }
+            Inconsistency::CorruptedBlob { path, keys } => {
+                write!(f, "Corrupted blob: {path} (keys: {})", keys.join(", "))
+            }
+        }
+    }
+}
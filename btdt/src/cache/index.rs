@@ -0,0 +1,408 @@
+//! A persistent index of the entries of a [LocalCache](super::local::LocalCache).
+//!
+//! Cleaning a cache needs the size and latest access of all blobs. Collecting this information
+//! by reading every meta file is slow for large caches, in particular on network file systems.
+//! Thus, the cache keeps a compacted [Index] of this information. Changes are recorded as
+//! [JournalRecord]s in separate, small files that are written atomically, so that no locking
+//! across processes is required. Cleaning the cache applies the journal to the index.
+//!
+//! The meta files remain the source of truth. The index may miss changes (e.g. made while it was
+//! being created), and needs to be rebuilt from the meta files in that case.
+
+use crate::cache::blob_id::BlobId;
+use chrono::{DateTime, Utc};
+use rkyv::rancor;
+use rkyv::util::AlignedVec;
+use rkyv::{Archive, Deserialize, Serialize};
+use std::collections::HashMap;
+
+const INDEX_VERSION: u16 = 2;
+
+/// A point in time, stored as seconds and nanoseconds since the Unix epoch.
+#[derive(Archive, Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Timestamp {
+    secs: i64,
+    nsecs: u32,
+}
+
+impl From<DateTime<Utc>> for Timestamp {
+    fn from(datetime: DateTime<Utc>) -> Self {
+        Self {
+            secs: datetime.timestamp(),
+            nsecs: datetime.timestamp_subsec_nanos(),
+        }
+    }
+}
+
+impl From<Timestamp> for DateTime<Utc> {
+    fn from(timestamp: Timestamp) -> Self {
+        DateTime::from_timestamp(timestamp.secs, timestamp.nsecs)
+            .unwrap_or(DateTime::<Utc>::MIN_UTC)
+    }
+}
+
+/// A blob known to the index.
+#[derive(Archive, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct IndexedBlob {
+    /// Size of the blob in bytes.
+    pub size: u64,
+    /// Time when the blob was stored, if known.
+    pub created: Option<Timestamp>,
+    /// Time when the keys referring to the blob expire, or `None` if any of them does not expire.
+    pub expires_at: Option<Timestamp>,
+    /// Keys that may refer to the blob, i.e. the keys it was stored under.
+    ///
+    /// This is `None` if the keys are unknown, i.e. for blobs not referred to by any key when the
+    /// index was rebuilt.
+    pub keys: Option<Vec<String>>,
+}
+
+impl IndexedBlob {
+    /// Extends the expiry of the blob to cover a key referring to it that expires at `expires_at`.
+    pub fn extend_expiry(&mut self, expires_at: Option<Timestamp>) {
+        self.expires_at = self.expires_at.zip(expires_at).map(|(a, b)| a.max(b));
+    }
+}
+
+/// A key known to the index.
+#[derive(Archive, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct IndexedKey {
+    /// The blob the key refers to.
+    pub blob_id: BlobId,
+    /// Latest access of the key.
+    pub latest_access: Timestamp,
+    /// Number of accesses of the key.
+    pub access_count: u64,
+}
+
+/// The compacted index of a cache.
+#[derive(Archive, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Index {
+    version: u16,
+    rebuilt_at: Timestamp,
+    /// The blobs in the cache.
+    pub blobs: HashMap<BlobId, IndexedBlob>,
+    /// The keys in the cache.
+    pub keys: HashMap<String, IndexedKey>,
+}
+
+impl Index {
+    /// Creates an empty index rebuilt at the given time.
+    pub fn new(rebuilt_at: DateTime<Utc>) -> Self {
+        Self {
+            version: INDEX_VERSION,
+            rebuilt_at: rebuilt_at.into(),
+            blobs: HashMap::new(),
+            keys: HashMap::new(),
+        }
+    }
+
+    /// Deserializes an index. Returns `None` if the data is not a valid index of the current
+    /// version.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut data = AlignedVec::<16>::with_capacity(bytes.len());
+        data.extend_from_slice(bytes);
+        rkyv::from_bytes::<Self, rancor::Error>(&data)
+            .ok()
+            .filter(|index| index.version == INDEX_VERSION)
+    }
+
+    /// Serializes the index.
+    pub fn to_bytes(&self) -> AlignedVec {
+        rkyv::to_bytes::<rancor::Error>(self).expect("failed to serialize index")
+    }
+
+    /// Returns the time when the index was last rebuilt from the meta files.
+    pub fn rebuilt_at(&self) -> DateTime<Utc> {
+        self.rebuilt_at.into()
+    }
+
+    /// Applies the given journal records in chronological order.
+    pub fn apply(&mut self, mut records: Vec<JournalRecord>) {
+        records.sort_by_key(JournalRecord::time);
+        for record in records {
+            self.apply_record(record);
+        }
+    }
+
+    fn apply_record(&mut self, record: JournalRecord) {
+        match record {
+            JournalRecord::Set {
+                time,
+                blob_id,
+                size,
+                expires_at,
+                keys,
+            } => {
+                for key in &keys {
+                    let is_outdated = self.keys.get(key).is_some_and(|indexed_key| {
+                        indexed_key.blob_id != blob_id
+                            && self.blobs.get(&indexed_key.blob_id).is_some_and(|blob| {
+                                blob.created.is_some_and(|created| created > time)
+                            })
+                    });
+                    if !is_outdated {
+                        self.access(key, blob_id, time, 0);
+                    }
+                }
+                // Content-addressed blobs are shared by all entries with the same content.
+                let blob = self.blobs.entry(blob_id).or_insert(IndexedBlob {
+                    size,
+                    created: Some(time),
+                    expires_at,
+                    keys: Some(vec![]),
+                });
+                blob.created = blob.created.max(Some(time));
+                blob.extend_expiry(expires_at);
+                if let Some(blob_keys) = &mut blob.keys {
+                    for key in keys {
+                        if !blob_keys.contains(&key) {
+                            blob_keys.push(key);
+                        }
+                    }
+                }
+            }
+            JournalRecord::Access {
+                time,
+                key,
+                blob_id,
+                access_count,
+            } => {
+                if self
+                    .keys
+                    .get(&key)
+                    .is_some_and(|indexed_key| indexed_key.blob_id == blob_id)
+                {
+                    self.access(&key, blob_id, time, access_count);
+                }
+            }
+            JournalRecord::Delete {
+                time,
+                keys,
+                blob_ids,
+            } => {
+                for key in keys {
+                    if self
+                        .keys
+                        .get(&key)
+                        .is_some_and(|indexed_key| indexed_key.latest_access <= time)
+                    {
+                        self.keys.remove(&key);
+                    }
+                }
+                for blob_id in blob_ids {
+                    self.blobs.remove(&blob_id);
+                }
+            }
+        }
+    }
+
+    fn access(&mut self, key: &str, blob_id: BlobId, time: Timestamp, access_count: u64) {
+        let indexed_key = self.keys.entry(key.to_string()).or_insert(IndexedKey {
+            blob_id,
+            latest_access: time,
+            access_count,
+        });
+        if indexed_key.blob_id != blob_id {
+            *indexed_key = IndexedKey {
+                blob_id,
+                latest_access: time,
+                access_count,
+            };
+        } else {
+            indexed_key.latest_access = indexed_key.latest_access.max(time);
+            indexed_key.access_count = indexed_key.access_count.max(access_count);
+        }
+    }
+
+    /// Removes a blob and the keys referring to it.
+    pub fn remove_blob(&mut self, blob_id: &BlobId) {
+        let Some(IndexedBlob {
+            keys: Some(keys), ..
+        }) = self.blobs.remove(blob_id)
+        else {
+            return;
+        };
+        for key in keys {
+            if self
+                .keys
+                .get(&key)
+                .is_some_and(|indexed_key| &indexed_key.blob_id == blob_id)
+            {
+                self.keys.remove(&key);
+            }
+        }
+    }
+}
+
+/// A change to the cache recorded in the journal.
+#[derive(Archive, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum JournalRecord {
+    /// A blob was stored under the given keys.
+    Set {
+        time: Timestamp,
+        blob_id: BlobId,
+        size: u64,
+        expires_at: Option<Timestamp>,
+        keys: Vec<String>,
+    },
+    /// A key was accessed.
+    Access {
+        time: Timestamp,
+        key: String,
+        blob_id: BlobId,
+        /// Number of accesses of the key, including this one.
+        access_count: u64,
+    },
+    /// Keys and blobs were deleted.
+    Delete {
+        time: Timestamp,
+        keys: Vec<String>,
+        blob_ids: Vec<BlobId>,
+    },
+}
+
+impl JournalRecord {
+    /// Deserializes a journal record. Returns `None` if the data is not a valid record.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut data = AlignedVec::<16>::with_capacity(bytes.len());
+        data.extend_from_slice(bytes);
+        rkyv::from_bytes::<Self, rancor::Error>(&data).ok()
+    }
+
+    /// Serializes the journal record.
+    pub fn to_bytes(&self) -> AlignedVec {
+        rkyv::to_bytes::<rancor::Error>(self).expect("failed to serialize journal record")
+    }
+
+    fn time(&self) -> Timestamp {
+        match self {
+            JournalRecord::Set { time, .. }
+            | JournalRecord::Access { time, .. }
+            | JournalRecord::Delete { time, .. } => *time,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn datetime(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    fn set_record(secs: i64, blob_id: u8, keys: &[&str]) -> JournalRecord {
+        JournalRecord::Set {
+            time: datetime(secs).into(),
+            blob_id: [blob_id; 16],
+            size: 42,
+            expires_at: None,
+            keys: keys.iter().map(|key| key.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_index_roundtrip() {
+        let mut index = Index::new(datetime(0));
+        index.apply(vec![set_record(1, 1, &["key-a", "key-b"])]);
+        let deserialized = Index::from_bytes(&index.to_bytes()).unwrap();
+        assert_eq!(deserialized.rebuilt_at(), datetime(0));
+        assert_eq!(deserialized, index);
+    }
+
+    #[test]
+    fn test_journal_record_roundtrip() {
+        let record = JournalRecord::Access {
+            time: datetime(1).into(),
+            key: "key".to_string(),
+            blob_id: [1; 16],
+            access_count: 1,
+        };
+        assert_eq!(JournalRecord::from_bytes(&record.to_bytes()), Some(record));
+    }
+
+    #[test]
+    fn test_invalid_data_is_rejected() {
+        assert_eq!(Index::from_bytes(b"invalid"), None);
+        assert_eq!(JournalRecord::from_bytes(b""), None);
+    }
+
+    #[test]
+    fn test_applies_records_in_chronological_order() {
+        let mut index = Index::new(datetime(0));
+        index.apply(vec![
+            JournalRecord::Access {
+                time: datetime(3).into(),
+                key: "key".to_string(),
+                blob_id: [1; 16],
+                access_count: 1,
+            },
+            set_record(2, 2, &["key"]),
+            set_record(1, 1, &["key"]),
+        ]);
+
+        assert_eq!(
+            index.keys["key"],
+            IndexedKey {
+                blob_id: [2; 16],
+                latest_access: datetime(2).into(),
+                access_count: 0,
+            }
+        );
+        assert_eq!(index.blobs.len(), 2);
+    }
+
+    #[test]
+    fn test_ignores_outdated_set_records() {
+        let mut index = Index::new(datetime(0));
+        index.apply(vec![set_record(2, 2, &["key"])]);
+        index.apply(vec![set_record(1, 1, &["key"])]);
+        assert_eq!(index.keys["key"].blob_id, [2; 16]);
+    }
+
+    #[test]
+    fn test_set_records_of_shared_blob_are_merged() {
+        let mut index = Index::new(datetime(0));
+        let mut expiring_record = set_record(2, 1, &["key-b"]);
+        if let JournalRecord::Set { expires_at, .. } = &mut expiring_record {
+            *expires_at = Some(datetime(3).into());
+        }
+        index.apply(vec![set_record(1, 1, &["key-a"]), expiring_record]);
+
+        let blob = &index.blobs[&[1; 16]];
+        assert_eq!(blob.created, Some(datetime(2).into()));
+        assert_eq!(blob.expires_at, None);
+        assert_eq!(
+            blob.keys,
+            Some(vec!["key-a".to_string(), "key-b".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_delete_removes_keys_and_blobs() {
+        let mut index = Index::new(datetime(0));
+        index.apply(vec![
+            set_record(1, 1, &["key-a", "key-b"]),
+            JournalRecord::Delete {
+                time: datetime(2).into(),
+                keys: vec!["key-a".to_string(), "key-b".to_string()],
+                blob_ids: vec![[1; 16]],
+            },
+        ]);
+        assert!(index.keys.is_empty());
+        assert!(index.blobs.is_empty());
+    }
+
+    #[test]
+    fn test_remove_blob_removes_referring_keys() {
+        let mut index = Index::new(datetime(0));
+        index.apply(vec![
+            set_record(1, 1, &["key-a", "key-b"]),
+            set_record(2, 2, &["key-b"]),
+        ]);
+        index.remove_blob(&[1; 16]);
+        assert_eq!(index.keys.keys().collect::<Vec<_>>(), vec!["key-b"]);
+        assert_eq!(index.blobs.len(), 1);
+    }
+}
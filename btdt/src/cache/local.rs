@@ -1,8 +1,12 @@
 //! Provides a local cache implementation that stores data in a storage backend.
 
-use super::blob_id::{BlobId, BlobIdFactory, RngBytes, ThreadRng};
+use super::blob_id::{BlobId, BlobIdFactory, RngBytes, ThreadRng, content_blob_id};
+use super::checksum::{Checksum, ChecksumMismatch, ChecksumWriter, VerifyingReader};
+use super::clean::{CleanOptions, CleanReport, Eviction, EvictionPolicy, EvictionReason};
+use super::fsck::Inconsistency;
+use super::index::{Index, IndexedBlob, IndexedKey, JournalRecord};
 use super::meta::{META_MAX_SIZE, Meta};
-use super::{Cache, CacheHit};
+use super::{Cache, CacheEntry, CacheHit, SetOptions};
 use crate::error::{IoPathError, IoPathResult, WithPath};
 use crate::storage::{EntryType, Storage};
 use crate::util::clock::{Clock, SystemClock};
@@ -10,12 +14,15 @@ use crate::util::close::Close;
 use crate::util::encoding::ICASE_NOPAD_ALPHANUMERIC_ENCODING;
 use chrono::{DateTime, TimeDelta, Utc};
 use rkyv::util::AlignedVec;
+use std::borrow::Cow;
 use std::cmp::Reverse;
-use std::collections::{BinaryHeap, HashMap};
+use std::collections::{HashMap, HashSet};
 use std::io;
 use std::io::{ErrorKind, Read, Write};
 use std::ops::Deref;
 use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 /// A local cache that stores data in a storage backend.
 ///
@@ -46,9 +53,16 @@ use std::pin::Pin;
 /// # }
 /// ```
 pub struct LocalCache<S: Storage, C: Clock = SystemClock, R: RngBytes = ThreadRng> {
-    storage: S,
+    /// Shared with the writers of the cache.
+    storage: Arc<S>,
     blob_id_factory: BlobIdFactory<R>,
     clock: C,
+    deduplicate: bool,
+    /// Whether the cache is known to have an index.
+    ///
+    /// Only a positive result is remembered, so that an index created by another instance or
+    /// process is noticed on the next change.
+    indexed: AtomicBool,
 }
 impl<S: Storage> LocalCache<S, SystemClock, ThreadRng> {
     /// Creates a new local cache that stores data in the given storage backend.
@@ -62,9 +76,11 @@ impl<S: Storage, R: RngBytes> LocalCache<S, SystemClock, R> {
     /// blob ID factory.
     pub fn with_blob_id_factory(storage: S, blob_id_factory: BlobIdFactory<R>) -> Self {
         Self {
-            storage,
+            storage: Arc::new(storage),
             blob_id_factory,
             clock: SystemClock,
+            deduplicate: false,
+            indexed: AtomicBool::new(false),
         }
     }
 }
@@ -74,17 +90,39 @@ impl<S: Storage, C: Clock> LocalCache<S, C, ThreadRng> {
     /// clock.
     pub(crate) fn with_clock(storage: S, clock: C) -> Self {
         Self {
-            storage,
+            storage: Arc::new(storage),
             blob_id_factory: BlobIdFactory::default(),
             clock,
+            deduplicate: false,
+            indexed: AtomicBool::new(false),
         }
     }
 }
 
 impl<S: Storage, C: Clock, R: RngBytes> LocalCache<S, C, R> {
+    /// Sets whether stored blobs are identified by the hash of their content.
+    ///
+    /// With deduplication, entries with identical content share a single blob. The blob is written
+    /// under a random ID first and moved to the ID derived from its content once it is complete.
+    /// Entries stored without deduplication keep their own blobs.
+    pub fn with_deduplication(mut self, deduplicate: bool) -> Self {
+        self.deduplicate = deduplicate;
+        self
+    }
+
     /// Consumes the cache and returns the underlying storage.
-    pub fn into_storage(self) -> S {
-        self.storage
+    ///
+    /// Returns `None` if a writer returned by the cache is still alive, as it shares the storage.
+    pub fn into_storage(self) -> Option<S> {
+        Arc::into_inner(self.storage)
+    }
+
+    pub(crate) fn storage(&self) -> &Arc<S> {
+        &self.storage
+    }
+
+    pub(crate) fn clock(&self) -> &C {
+        &self.clock
     }
 
     fn blob_path(blob_id: &BlobId) -> String {
@@ -93,10 +131,24 @@ impl<S: Storage, C: Clock, R: RngBytes> LocalCache<S, C, R> {
     }
 
     fn meta_path(key: &str) -> String {
+        Self::key_path("/meta", key)
+    }
+
+    fn pin_path(key: &str) -> String {
+        Self::key_path("/pin", key)
+    }
+
+    fn new_journal_path(&self) -> String {
+        let record_id =
+            ICASE_NOPAD_ALPHANUMERIC_ENCODING.encode(self.blob_id_factory.new_id().as_ref());
+        format!("{INDEX_JOURNAL_PATH}/{record_id}")
+    }
+
+    fn key_path(dir: &str, key: &str) -> String {
         // Use a hash of the key to avoid too many files in a single directory
         let hash =
             ICASE_NOPAD_ALPHANUMERIC_ENCODING.encode(&blake3::hash(key.as_bytes()).as_bytes()[..1]);
-        format!("/meta/{hash}/{key}")
+        format!("{dir}/{hash}/{key}")
     }
 }
 
@@ -106,142 +158,893 @@ impl<S: Storage, C: Clock, R: RngBytes> Cache for LocalCache<S, C, R> {
 
     fn get<'a>(&self, keys: &[&'a str]) -> IoPathResult<Option<CacheHit<'a, Self::Reader>>> {
         for key in keys {
-            let meta_path = Self::meta_path(key);
-            let meta = self.read_meta(&meta_path);
-            match meta {
-                Ok(mut meta) => {
-                    meta.set_latest_access(self.clock.now());
-                    let mut writer = self.storage.put(&meta_path)?;
-                    writer
-                        .write_all(meta.deref().as_ref())
-                        .with_path(&meta_path)?;
-                    writer.close().with_path(&meta_path)?;
-
-                    let blob_path = Self::blob_path(meta.blob_id());
-                    match self.storage.get(&blob_path) {
-                        Ok(file_handle) => {
-                            return Ok(Some(CacheHit {
-                                key,
-                                reader: file_handle.reader,
-                                size_hint: Some(file_handle.size_hint),
-                            }));
-                        }
-                        Err(err) => match err.io_error().kind() {
-                            ErrorKind::NotFound => continue,
-                            _ => return Err(err),
-                        },
-                    }
+            if let Some(cache_hit) = self.open_entry(Cow::Borrowed(key))? {
+                return Ok(Some(cache_hit));
+            }
+        }
+        Ok(None)
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefixes: &[&str],
+    ) -> IoPathResult<Option<CacheHit<'static, Self::Reader>>> {
+        if prefixes.is_empty() {
+            return Ok(None);
+        }
+
+        let key_files = match Self::iter_subdir_files(&self.storage, "/meta") {
+            Ok(key_files) => key_files,
+            Err(err) if err.io_error().kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let mut candidates = vec![vec![]; prefixes.len()];
+        for key_file in key_files {
+            let key_file = key_file?;
+            let Some(prefix_index) = prefixes
+                .iter()
+                .position(|prefix| key_file.name.starts_with(prefix))
+            else {
+                continue;
+            };
+            let latest_access = match self.read_meta(&key_file.path) {
+                Ok(meta) => meta.latest_access().map_err(|err| {
+                    IoPathError::new(
+                        io::Error::new(ErrorKind::InvalidData, format!("{err:?}")),
+                        &key_file.path,
+                    )
+                })?,
+                Err(err) if err.io_error().kind() == ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+            candidates[prefix_index].push((Reverse(latest_access), key_file.name));
+        }
+
+        for mut prefix_candidates in candidates {
+            prefix_candidates.sort_unstable();
+            for (_, key) in prefix_candidates {
+                if let Some(cache_hit) = self.open_entry(Cow::Owned(key))? {
+                    return Ok(Some(cache_hit));
                 }
-                Err(err) => match err.io_error().kind() {
-                    ErrorKind::NotFound => continue,
-                    _ => return Err(err),
-                },
             }
         }
         Ok(None)
     }
 
-    fn set(&self, keys: &[&str]) -> IoPathResult<Self::Writer> {
+    fn set_with_options(&self, keys: &[&str], options: &SetOptions) -> IoPathResult<Self::Writer> {
         let blob_id = self.blob_id_factory.new_id();
-        let meta = Meta::new(blob_id, self.clock.now());
+        let now = self.clock.now();
+        let mut meta = Meta::new(blob_id, now);
+        meta.set_expires_at(options.ttl.and_then(|ttl| now.checked_add_signed(ttl)));
+        meta.set_tree_hash(options.tree_hash);
         let blob_path = Self::blob_path(&blob_id);
         let blob_writer = self.storage.put(&blob_path)?;
-        let meta_writers = keys
-            .iter()
-            .map(|&key| Self::meta_path(key))
-            .map(|key| self.storage.put(&key))
-            .collect::<IoPathResult<Vec<_>>>()?;
-        Ok(CacheWriter::new(blob_writer, meta_writers, meta))
+        let meta_paths = keys.iter().map(|&key| Self::meta_path(key)).collect();
+        let journal = if self.is_indexed()? {
+            Some((
+                self.new_journal_path(),
+                keys.iter().map(|key| key.to_string()).collect(),
+            ))
+        } else {
+            None
+        };
+        let staged_blob_path = self.deduplicate.then_some(blob_path);
+        Ok(CacheWriter::new(
+            self.storage.clone(),
+            blob_writer,
+            staged_blob_path,
+            meta_paths,
+            journal,
+            meta,
+        ))
+    }
+
+    fn list(&self, prefix: &str) -> IoPathResult<Vec<CacheEntry>> {
+        Ok(self
+            .list_with_blob_ids(prefix)?
+            .into_iter()
+            .map(|(entry, _)| entry)
+            .collect())
+    }
+
+    fn entry(&self, key: &str) -> IoPathResult<Option<CacheEntry>> {
+        Ok(self.entry_with_blob_id(key)?.map(|(entry, _)| entry))
+    }
+
+    fn delete(&self, keys: &[&str]) -> IoPathResult<()> {
+        let mut deleted_keys = vec![];
+        let mut unreferenced_blob_ids = HashSet::new();
+        for key in keys {
+            let meta_path = Self::meta_path(key);
+            match self.read_meta(&meta_path) {
+                Ok(meta) => unreferenced_blob_ids.insert(*meta.blob_id()),
+                Err(err) if err.io_error().kind() == ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+            Self::ignore_not_found(self.storage.delete(&meta_path))?;
+            deleted_keys.push(key.to_string());
+        }
+        if unreferenced_blob_ids.is_empty() {
+            return Ok(());
+        }
+
+        for key_file in Self::iter_subdir_files(&self.storage, "/meta")? {
+            let key_file = key_file?;
+            match self.read_meta(&key_file.path) {
+                Ok(meta) => unreferenced_blob_ids.remove(meta.blob_id()),
+                Err(err) if err.io_error().kind() == ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+        }
+        for blob_id in &unreferenced_blob_ids {
+            Self::ignore_not_found(self.storage.delete(&Self::blob_path(blob_id)))?;
+        }
+        self.append_to_journal(JournalRecord::Delete {
+            time: self.clock.now().into(),
+            keys: deleted_keys,
+            blob_ids: unreferenced_blob_ids.into_iter().collect(),
+        })
     }
 }
 
+/// Minimum age of blobs not referred to by any key before they are deleted by
+/// [LocalCache::clean].
+///
+/// Blobs are written before the keys referring to them. The grace period prevents deleting the
+/// blobs of entries that are being stored concurrently.
+pub const ORPHAN_BLOB_GRACE_PERIOD: TimeDelta = TimeDelta::hours(1);
+
+/// Maximum age of the index used by [LocalCache::clean] before it is rebuilt from the meta files.
+///
+/// Rebuilding picks up changes not recorded in the index, e.g. entries stored while the index was
+/// created for the first time.
+pub const INDEX_REBUILD_INTERVAL: TimeDelta = TimeDelta::days(1);
+
+const INDEX_PATH: &str = "/index/index";
+const INDEX_JOURNAL_PATH: &str = "/index/journal";
+
 impl<S: Storage, C: Clock, R: RngBytes> LocalCache<S, C, R> {
-    pub fn clean(
-        &mut self,
-        max_unused_age: Option<TimeDelta>,
-        max_blob_size_sum: Option<u64>,
-    ) -> IoPathResult<()> {
-        if max_unused_age.is_none() && max_blob_size_sum.is_none() {
-            return Ok(());
+    /// Deletes cache entries not accessed within the `max_unused_age` of the `options` and, if the
+    /// total size of the blobs exceeds `max_blob_size_sum`, further entries in the order given by
+    /// the `policy` until the limit is met.
+    ///
+    /// Blobs not referred to by any key (e.g. because the key was overwritten) are deleted once
+    /// they are older than [ORPHAN_BLOB_GRACE_PERIOD]. Until then, they count towards
+    /// `max_blob_size_sum`.
+    ///
+    /// Entries that have expired according to the TTL given when storing them
+    /// ([Cache::set_with_ttl]) are deleted independent of the limits.
+    ///
+    /// Before applying `max_unused_age` and `max_blob_size_sum`, entries with a key starting with
+    /// the prefix of a [PrefixQuota](super::clean::PrefixQuota) are deleted in the order given by the `policy` until the quota
+    /// is met. The quotas are applied in the given order.
+    ///
+    /// Blobs referred to by a [pinned](LocalCache::pin) key are not deleted (unless expired), but
+    /// count towards `max_blob_size_sum` and the quotas.
+    ///
+    /// Returns a report of the deleted blobs. Use [LocalCache::clean_dry_run] to get the report
+    /// without deleting anything.
+    pub fn clean(&mut self, options: &CleanOptions) -> IoPathResult<CleanReport> {
+        self.clean_impl(options, false)
+    }
+
+    /// Returns the report of what [LocalCache::clean] would delete, without deleting anything.
+    pub fn clean_dry_run(&self, options: &CleanOptions) -> IoPathResult<CleanReport> {
+        self.clean_impl(options, true)
+    }
+
+    fn clean_impl(&self, options: &CleanOptions, dry_run: bool) -> IoPathResult<CleanReport> {
+        self.clean_with(dry_run, |index, now, pinned_keys| {
+            Ok(Self::plan_evictions(index, now, pinned_keys, options))
+        })
+    }
+
+    /// Deletes the blobs, and the keys referring to them, planned by `plan` from the index of the
+    /// cache, the current time, and the pinned keys.
+    ///
+    /// The plan is repeated with an index rebuilt from the meta files if the index turns out to be
+    /// outdated.
+    pub(crate) fn clean_with(
+        &self,
+        dry_run: bool,
+        mut plan: impl FnMut(
+            &Index,
+            DateTime<Utc>,
+            &HashSet<String>,
+        ) -> IoPathResult<Vec<(BlobId, Eviction)>>,
+    ) -> IoPathResult<CleanReport> {
+        let now = self.clock.now();
+        let pinned_keys = self.pinned_keys()?;
+
+        // The journal must be read before the index to not miss records compacted concurrently.
+        let journal = self.read_journal(now)?;
+        let mut indexed_evictions = None;
+        if journal.is_complete
+            && let Some(mut index) = self.read_index()?
+            && now - index.rebuilt_at() < INDEX_REBUILD_INTERVAL
+        {
+            index.apply(journal.records);
+            let evictions = plan(&index, now, &pinned_keys)?;
+            if self.verify_evictions(&index, &evictions)? {
+                indexed_evictions = Some((index, evictions));
+            }
+        }
+        let (mut index, evictions) = match indexed_evictions {
+            Some(indexed_evictions) => indexed_evictions,
+            None => {
+                let index = self.rebuild_index(now)?;
+                let evictions = plan(&index, now, &pinned_keys)?;
+                (index, evictions)
+            }
+        };
+
+        let mut report = CleanReport::default();
+        for (blob_id, eviction) in evictions {
+            if !dry_run {
+                for key in &eviction.keys {
+                    Self::ignore_not_found(self.storage.delete(&Self::meta_path(key)))?;
+                }
+                Self::ignore_not_found(self.storage.delete(&Self::blob_path(&blob_id)))?;
+                index.remove_blob(&blob_id);
+            }
+            report.evictions.push(eviction);
         }
 
-        let mut blob_sizes = HashMap::new();
-        for blob in Self::iter_subdir_files(&self.storage, "/blob")? {
-            let blob = blob?;
-            if let Ok(blob_id) = ICASE_NOPAD_ALPHANUMERIC_ENCODING
-                .decode(format!("{}{}", blob.subdir, blob.name).as_bytes())
-            {
-                let blob_id: BlobId = blob_id.try_into().unwrap();
-                blob_sizes.insert(blob_id, blob.size);
+        if !dry_run {
+            self.write_index(&index)?;
+            for path in journal.paths {
+                Self::ignore_not_found(self.storage.delete(&path))?;
             }
         }
+        Ok(report)
+    }
+
+    /// Determines the blobs to evict in the order of eviction.
+    pub(crate) fn plan_evictions(
+        index: &Index,
+        now: DateTime<Utc>,
+        pinned_keys: &HashSet<String>,
+        options: &CleanOptions,
+    ) -> Vec<(BlobId, Eviction)> {
+        let mut evictions = vec![];
 
-        #[derive(Debug, PartialOrd, Ord, PartialEq, Eq)]
+        #[derive(Debug)]
         struct Blob {
-            latest_access: Reverse<DateTime<Utc>>,
+            latest_access: DateTime<Utc>,
+            access_count: u64,
             size: u64,
             blob_id: BlobId,
             keys: Vec<String>,
+            expired: bool,
         }
         let mut blobs: HashMap<BlobId, Blob> = HashMap::new();
 
-        for key_file in Self::iter_subdir_files(&self.storage, "/meta")? {
+        for (key, indexed_key) in &index.keys {
+            let Some(indexed_blob) = index.blobs.get(&indexed_key.blob_id) else {
+                continue;
+            };
+            let latest_access = indexed_key.latest_access.into();
+            let entry = blobs.entry(indexed_key.blob_id).or_insert_with(|| Blob {
+                latest_access,
+                access_count: 0,
+                size: indexed_blob.size,
+                blob_id: indexed_key.blob_id,
+                keys: vec![],
+                expired: indexed_blob
+                    .expires_at
+                    .is_some_and(|expires_at| DateTime::<Utc>::from(expires_at) <= now),
+            });
+            entry.keys.push(key.clone());
+            entry.latest_access = std::cmp::max(entry.latest_access, latest_access);
+            entry.access_count = entry.access_count.saturating_add(indexed_key.access_count);
+        }
+
+        let mut blob_size_sum: u64 = blobs.values().map(|blob| blob.size).sum();
+        let orphan_cutoff = now - ORPHAN_BLOB_GRACE_PERIOD;
+        let mut orphans = vec![];
+        for (blob_id, indexed_blob) in &index.blobs {
+            if blobs.contains_key(blob_id) {
+                continue;
+            }
+            match indexed_blob.created.map(DateTime::<Utc>::from) {
+                Some(created) if created < orphan_cutoff => {
+                    orphans.push((created, *blob_id, indexed_blob.size))
+                }
+                _ => blob_size_sum += indexed_blob.size,
+            }
+        }
+        orphans.sort_unstable();
+        for (created, blob_id, size) in orphans {
+            evictions.push((
+                blob_id,
+                Eviction {
+                    keys: vec![],
+                    blob_size: size,
+                    latest_access: created,
+                    reason: EvictionReason::Orphan,
+                },
+            ));
+        }
+
+        let mut evict = |blob: Blob, reason: EvictionReason| -> u64 {
+            let Blob {
+                latest_access,
+                mut keys,
+                blob_id,
+                size,
+                ..
+            } = blob;
+            keys.sort_unstable();
+            evictions.push((
+                blob_id,
+                Eviction {
+                    keys,
+                    blob_size: size,
+                    latest_access,
+                    reason,
+                },
+            ));
+            size
+        };
+
+        let lru_order = |a: &Blob, b: &Blob| {
+            a.latest_access
+                .cmp(&b.latest_access)
+                .then_with(|| b.size.cmp(&a.size))
+                .then_with(|| b.blob_id.cmp(&a.blob_id))
+        };
+        let policy_order = |a: &Blob, b: &Blob| match options.policy {
+            EvictionPolicy::Lru => lru_order(a, b),
+            EvictionPolicy::Lfu => a
+                .access_count
+                .cmp(&b.access_count)
+                .then_with(|| lru_order(a, b)),
+            EvictionPolicy::SizeWeighted => {
+                let weight = |blob: &Blob| {
+                    let unused_secs = (now - blob.latest_access).num_seconds().max(0);
+                    u128::from(blob.size) * unused_secs as u128
+                };
+                weight(b).cmp(&weight(a)).then_with(|| lru_order(a, b))
+            }
+        };
+
+        let (mut expired, unexpired): (Vec<_>, Vec<_>) =
+            blobs.into_values().partition(|blob| blob.expired);
+        expired.sort_unstable_by(lru_order);
+        for blob in expired {
+            blob_size_sum -= evict(blob, EvictionReason::Expired);
+        }
+
+        let (pinned, mut candidates): (Vec<_>, Vec<_>) = unexpired
+            .into_iter()
+            .partition(|blob| blob.keys.iter().any(|key| pinned_keys.contains(key)));
+        candidates.sort_unstable_by(policy_order);
+
+        let mut candidates: Vec<Option<Blob>> = candidates.into_iter().map(Some).collect();
+        for quota in &options.prefix_quotas {
+            let in_namespace =
+                |blob: &Blob| blob.keys.iter().any(|key| key.starts_with(&quota.prefix));
+            let mut namespace_size: u64 = pinned
+                .iter()
+                .chain(candidates.iter().flatten())
+                .filter(|blob| in_namespace(blob))
+                .map(|blob| blob.size)
+                .sum();
+            for slot in &mut candidates {
+                if namespace_size <= quota.max_blob_size_sum {
+                    break;
+                }
+                if let Some(blob) = slot.take_if(|blob| in_namespace(blob)) {
+                    let size = evict(blob, EvictionReason::Quota);
+                    namespace_size -= size;
+                    blob_size_sum -= size;
+                }
+            }
+        }
+        let mut candidates: Vec<Blob> = candidates.into_iter().flatten().collect();
+
+        if let Some(max_unused_age) = options.max_unused_age {
+            let cutoff = now - max_unused_age;
+            let (mut unused, used): (Vec<_>, Vec<_>) = candidates
+                .into_iter()
+                .partition(|blob| blob.latest_access < cutoff);
+            unused.sort_unstable_by(lru_order);
+            for blob in unused {
+                blob_size_sum -= evict(blob, EvictionReason::Age);
+            }
+            candidates = used;
+        }
+
+        if let Some(max_blob_size_sum) = options.max_blob_size_sum {
+            for blob in candidates {
+                if blob_size_sum <= max_blob_size_sum {
+                    break;
+                }
+                blob_size_sum -= evict(blob, EvictionReason::Size);
+            }
+        }
+
+        evictions
+    }
+
+    /// Returns whether the keys referring to the blobs to evict, according to the meta files,
+    /// match the index.
+    ///
+    /// Orphaned blobs whose keys are unknown to the index cannot be verified without a full scan
+    /// of the meta files.
+    fn verify_evictions(
+        &self,
+        index: &Index,
+        evictions: &[(BlobId, Eviction)],
+    ) -> IoPathResult<bool> {
+        for (blob_id, eviction) in evictions {
+            let Some(keys) = index.blobs.get(blob_id).and_then(|blob| blob.keys.as_ref()) else {
+                return Ok(false);
+            };
+            for key in keys {
+                let meta = match self.read_meta(&Self::meta_path(key)) {
+                    Ok(meta) => meta,
+                    Err(err) if err.io_error().kind() == ErrorKind::NotFound => {
+                        if eviction.keys.contains(key) {
+                            return Ok(false);
+                        }
+                        continue;
+                    }
+                    Err(err) => return Err(err),
+                };
+                if (meta.blob_id() == blob_id) != eviction.keys.contains(key) {
+                    return Ok(false);
+                }
+                let latest_access = meta.latest_access().ok();
+                let indexed_access = index.keys.get(key).map(|key| key.latest_access.into());
+                if meta.blob_id() == blob_id && latest_access > indexed_access {
+                    return Ok(false);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Creates the index from the meta files and blobs in the storage.
+    fn rebuild_index(&self, now: DateTime<Utc>) -> IoPathResult<Index> {
+        let mut index = Index::new(now);
+
+        let mut blob_files = HashMap::new();
+        for blob in Self::iter_subdir_files_if_exists(&self.storage, "/blob")? {
+            let blob = blob?;
+            if let Some(blob_id) = Self::decode_blob_id(&blob) {
+                blob_files.insert(blob_id, blob);
+            }
+        }
+
+        for key_file in Self::iter_subdir_files_if_exists(&self.storage, "/meta")? {
             let key_file = key_file?;
             let meta = self.read_meta(&key_file.path)?;
             let latest_access = meta.latest_access().map_err(|err| {
                 IoPathError::new_no_path(io::Error::new(ErrorKind::InvalidData, format!("{err:?}")))
             })?;
-            if let Some(&size) = blob_sizes.get(meta.blob_id()) {
-                let entry = blobs.entry(*meta.blob_id()).or_insert_with(|| Blob {
-                    latest_access: Reverse(latest_access),
-                    size,
-                    blob_id: *meta.blob_id(),
-                    keys: vec![],
+            if let Some(blob_file) = blob_files.get(meta.blob_id()) {
+                let indexed_blob = index.blobs.entry(*meta.blob_id()).or_insert(IndexedBlob {
+                    size: blob_file.size,
+                    created: blob_file.modified.map(Into::into),
+                    expires_at: meta.expires_at().map(Into::into),
+                    keys: Some(vec![]),
                 });
-                entry.keys.push(key_file.name.to_string());
-                entry.latest_access = Reverse(std::cmp::max(entry.latest_access.0, latest_access));
+                indexed_blob.extend_expiry(meta.expires_at().map(Into::into));
+                if let Some(keys) = &mut indexed_blob.keys {
+                    keys.push(key_file.name.clone());
+                }
+                index.keys.insert(
+                    key_file.name,
+                    IndexedKey {
+                        blob_id: *meta.blob_id(),
+                        latest_access: latest_access.into(),
+                        access_count: meta.access_count(),
+                    },
+                );
             }
         }
 
-        let mut blob_size_sum: u64 = blobs.values().map(|blob| blob.size).sum();
-        let mut heap: BinaryHeap<Blob> = blobs.into_values().collect();
+        for (blob_id, blob_file) in blob_files {
+            index.blobs.entry(blob_id).or_insert(IndexedBlob {
+                size: blob_file.size,
+                created: blob_file.modified.map(Into::into),
+                expires_at: None,
+                keys: None,
+            });
+        }
 
-        let cutoff = max_unused_age.map(|max_unused_age| self.clock.now() - max_unused_age);
-        while !heap.is_empty() {
-            if let Some(Blob {
-                latest_access: Reverse(latest_access),
-                ..
-            }) = heap.peek()
-                && latest_access >= &cutoff.unwrap_or(DateTime::<Utc>::MIN_UTC)
-                && blob_size_sum <= max_blob_size_sum.unwrap_or(u64::MAX)
-            {
-                break;
+        Ok(index)
+    }
+
+    fn is_indexed(&self) -> IoPathResult<bool> {
+        if self.indexed.load(Ordering::Relaxed) {
+            return Ok(true);
+        }
+        let exists = self.storage.exists_file(INDEX_PATH)?;
+        self.set_indexed(exists);
+        Ok(exists)
+    }
+
+    fn set_indexed(&self, indexed: bool) {
+        self.indexed.store(indexed, Ordering::Relaxed);
+    }
+
+    fn read_index(&self) -> IoPathResult<Option<Index>> {
+        let mut reader = match self.storage.get(INDEX_PATH) {
+            Ok(file_handle) => file_handle.reader,
+            Err(err) if err.io_error().kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let mut data = vec![];
+        reader.read_to_end(&mut data).with_path(INDEX_PATH)?;
+        Ok(Index::from_bytes(&data))
+    }
+
+    fn write_index(&self, index: &Index) -> IoPathResult<()> {
+        let mut writer = self.storage.put(INDEX_PATH)?;
+        writer.write_all(&index.to_bytes()).with_path(INDEX_PATH)?;
+        writer.close().with_path(INDEX_PATH)?;
+        self.set_indexed(true);
+        Ok(())
+    }
+
+    /// Records a change in the journal, if the cache is indexed.
+    fn append_to_journal(&self, record: JournalRecord) -> IoPathResult<()> {
+        if !self.is_indexed()? {
+            return Ok(());
+        }
+        let path = self.new_journal_path();
+        let mut writer = self.storage.put(&path)?;
+        writer.write_all(&record.to_bytes()).with_path(&path)?;
+        writer.close().with_path(&path)
+    }
+
+    /// Reads the records in the journal.
+    ///
+    /// Records that cannot be decoded might still be written and are skipped, unless older than
+    /// the [ORPHAN_BLOB_GRACE_PERIOD]. In that case, the record is considered lost and the journal
+    /// is incomplete.
+    fn read_journal(&self, now: DateTime<Utc>) -> IoPathResult<Journal> {
+        let mut journal = Journal {
+            records: vec![],
+            paths: vec![],
+            is_complete: true,
+        };
+        let entries = match self.storage.list(INDEX_JOURNAL_PATH) {
+            Ok(entries) => entries.collect::<IoPathResult<Vec<_>>>()?,
+            Err(err) if err.io_error().kind() == ErrorKind::NotFound => return Ok(journal),
+            Err(err) => return Err(err),
+        };
+        let cutoff = now - ORPHAN_BLOB_GRACE_PERIOD;
+        for entry in entries {
+            if entry.entry_type != EntryType::File {
+                continue;
             }
-            let Blob {
-                keys,
-                blob_id,
-                size,
-                ..
-            } = heap.pop().unwrap();
-            for key in keys {
-                self.storage.delete(&Self::meta_path(&key))?;
+            let path = format!("{INDEX_JOURNAL_PATH}/{}", entry.name);
+            let mut reader = match self.storage.get(&path) {
+                Ok(file_handle) => file_handle.reader,
+                Err(err) if err.io_error().kind() == ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+            let mut data = vec![];
+            reader.read_to_end(&mut data).with_path(&path)?;
+            match JournalRecord::from_bytes(&data) {
+                Some(record) => journal.records.push(record),
+                None if entry.modified.is_some_and(|modified| modified >= cutoff) => continue,
+                None => journal.is_complete = false,
             }
-            self.storage.delete(&Self::blob_path(&blob_id))?;
-            blob_size_sum -= size;
+            journal.paths.push(path);
         }
+        Ok(journal)
+    }
+
+    /// Pins the given keys, exempting the blobs they refer to from eviction by
+    /// [LocalCache::clean].
+    ///
+    /// Pins apply to keys and not to the stored data. Thus, keys may be pinned before data is
+    /// stored under them, and the pin remains if data is stored again under a pinned key.
+    pub fn pin(&self, keys: &[&str]) -> IoPathResult<()> {
+        for key in keys {
+            let pin_path = Self::pin_path(key);
+            self.storage.put(&pin_path)?.close().with_path(&pin_path)?;
+        }
+        Ok(())
+    }
 
+    /// Removes the pins of the given keys. Keys that are not pinned are ignored.
+    pub fn unpin(&self, keys: &[&str]) -> IoPathResult<()> {
+        for key in keys {
+            Self::ignore_not_found(self.storage.delete(&Self::pin_path(key)))?;
+        }
         Ok(())
     }
 
-    fn read_meta(&self, path: &str) -> IoPathResult<Pin<Box<Meta<[u8; META_MAX_SIZE]>>>> {
-        let mut reader = self.storage.get(path)?.reader;
-        let mut meta_data = [0u8; META_MAX_SIZE];
-        reader.read_exact(meta_data.as_mut()).no_path()?;
+    /// Returns the pinned keys.
+    pub fn pinned_keys(&self) -> IoPathResult<HashSet<String>> {
+        Self::iter_subdir_files_if_exists(&self.storage, "/pin")?
+            .map(|pin_file| pin_file.map(|pin_file| pin_file.name))
+            .collect()
+    }
+
+    /// Checks the cache for inconsistencies and returns them sorted by path.
+    ///
+    /// This detects meta files that cannot be decoded, keys referring to missing blobs, blobs not
+    /// referred to by any key, and blobs not matching the checksum recorded when they were stored.
+    /// If `repair` is `true`, the inconsistencies are repaired as described for each
+    /// [Inconsistency].
+    ///
+    /// Blobs are written before the keys referring to them. Thus, blobs of entries being stored
+    /// concurrently may be reported as orphans, and the cache should not be in use while
+    /// repairing it. Repairing discards the index used by [LocalCache::clean], so that it gets
+    /// rebuilt.
+    pub fn fsck(&self, repair: bool) -> IoPathResult<Vec<Inconsistency>> {
+        let mut inconsistencies = vec![];
+
+        let mut blob_paths = HashMap::new();
+        for blob in Self::iter_subdir_files_if_exists(&self.storage, "/blob")? {
+            let blob = blob?;
+            match Self::decode_blob_id(&blob) {
+                Some(blob_id) => {
+                    blob_paths.insert(blob_id, blob.path);
+                }
+                None => inconsistencies.push(Inconsistency::OrphanBlob { path: blob.path }),
+            }
+        }
+
+        let mut blob_refs: HashMap<BlobId, (Vec<String>, Option<Checksum>)> = HashMap::new();
+        for key_file in Self::iter_subdir_files_if_exists(&self.storage, "/meta")? {
+            let key_file = key_file?;
+            let meta = match self.read_meta(&key_file.path) {
+                Ok(meta) => meta,
+                Err(err) if err.io_error().kind() == ErrorKind::NotFound => continue,
+                Err(err) if err.io_error().kind() == ErrorKind::InvalidData => {
+                    inconsistencies.push(Inconsistency::UndecodableMeta {
+                        path: key_file.path,
+                    });
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            if !blob_paths.contains_key(meta.blob_id()) {
+                inconsistencies.push(Inconsistency::DanglingKey {
+                    key: key_file.name,
+                    blob_path: Self::blob_path(meta.blob_id()),
+                });
+                continue;
+            }
+            let (keys, checksum) = blob_refs.entry(*meta.blob_id()).or_default();
+            keys.push(key_file.name);
+            *checksum = checksum.or(meta.checksum());
+        }
+
+        for (blob_id, path) in blob_paths {
+            match blob_refs.remove(&blob_id) {
+                None => inconsistencies.push(Inconsistency::OrphanBlob { path }),
+                Some((mut keys, Some(checksum))) => {
+                    if !self.verify_blob(&path, checksum)? {
+                        keys.sort_unstable();
+                        inconsistencies.push(Inconsistency::CorruptedBlob { path, keys });
+                    }
+                }
+                Some((_, None)) => {}
+            }
+        }
+        inconsistencies.sort_unstable_by(|a, b| a.path().cmp(b.path()));
+
+        if repair && !inconsistencies.is_empty() {
+            for inconsistency in &inconsistencies {
+                self.repair(inconsistency)?;
+            }
+            Self::ignore_not_found(self.storage.delete(INDEX_PATH))?;
+            self.set_indexed(false);
+        }
+        Ok(inconsistencies)
+    }
+
+    /// Returns the entry stored under `key` along with the ID of its blob, without accessing it.
+    pub(crate) fn entry_with_blob_id(
+        &self,
+        key: &str,
+    ) -> IoPathResult<Option<(CacheEntry, BlobId)>> {
+        self.read_entry(key.to_string(), &Self::meta_path(key))
+    }
+
+    /// Lists the entries with a key starting with `prefix` like [Cache::list], along with the IDs
+    /// of their blobs.
+    pub(crate) fn list_with_blob_ids(
+        &self,
+        prefix: &str,
+    ) -> IoPathResult<Vec<(CacheEntry, BlobId)>> {
+        let key_files = match Self::iter_subdir_files(&self.storage, "/meta") {
+            Ok(key_files) => key_files,
+            Err(err) if err.io_error().kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err),
+        };
+        let mut entries = vec![];
+        for key_file in key_files {
+            let key_file = key_file?;
+            if !key_file.name.starts_with(prefix) {
+                continue;
+            }
+            if let Some(entry) = self.read_entry(key_file.name, &key_file.path)? {
+                entries.push(entry);
+            }
+        }
+        entries.sort_unstable_by(|(a, _), (b, _)| a.key.cmp(&b.key));
+        Ok(entries)
+    }
+
+    fn read_entry(
+        &self,
+        key: String,
+        meta_path: &str,
+    ) -> IoPathResult<Option<(CacheEntry, BlobId)>> {
+        let meta = match self.read_meta(meta_path) {
+            Ok(meta) => meta,
+            Err(err) if err.io_error().kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        if meta.is_expired_at(self.clock.now()) {
+            return Ok(None);
+        }
+        let latest_access = meta.latest_access().map_err(|err| {
+            IoPathError::new(
+                io::Error::new(ErrorKind::InvalidData, format!("{err:?}")),
+                meta_path,
+            )
+        })?;
+        let size = match self.storage.get(&Self::blob_path(meta.blob_id())) {
+            Ok(file_handle) => file_handle.size_hint,
+            Err(err) if err.io_error().kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let entry = CacheEntry {
+            key,
+            size,
+            latest_access,
+            tree_hash: meta.tree_hash(),
+        };
+        Ok(Some((entry, *meta.blob_id())))
+    }
+
+    /// Opens the blob with the given ID without updating the latest access of any key.
+    pub(crate) fn read_blob(&self, blob_id: &BlobId) -> IoPathResult<S::Reader> {
+        Ok(self.storage.get(&Self::blob_path(blob_id))?.reader)
+    }
+
+    /// Returns whether the blob at `path` matches the `checksum`.
+    fn verify_blob(&self, path: &str, checksum: Checksum) -> IoPathResult<bool> {
+        let reader = match self.storage.get(path) {
+            Ok(file_handle) => file_handle.reader,
+            // deleted concurrently, thus not corrupted
+            Err(err) if err.io_error().kind() == ErrorKind::NotFound => return Ok(true),
+            Err(err) => return Err(err),
+        };
+        match io::copy(&mut VerifyingReader::new(reader, checksum), &mut io::sink()) {
+            Ok(_) => Ok(true),
+            Err(err)
+                if err
+                    .get_ref()
+                    .is_some_and(|err| err.is::<ChecksumMismatch>()) =>
+            {
+                Ok(false)
+            }
+            Err(err) => Err(err).with_path(path),
+        }
+    }
+
+    fn repair(&self, inconsistency: &Inconsistency) -> IoPathResult<()> {
+        match inconsistency {
+            Inconsistency::UndecodableMeta { path } => self.quarantine(path),
+            Inconsistency::DanglingKey { key, .. } => {
+                Self::ignore_not_found(self.storage.delete(&Self::meta_path(key)))
+            }
+            Inconsistency::OrphanBlob { path } => Self::ignore_not_found(self.storage.delete(path)),
+            Inconsistency::CorruptedBlob { path, keys } => {
+                for key in keys {
+                    Self::ignore_not_found(self.storage.delete(&Self::meta_path(key)))?;
+                }
+                self.quarantine(path)
+            }
+        }
+    }
+
+    /// Moves the file at `path` to the same path below `/quarantine`.
+    fn quarantine(&self, path: &str) -> IoPathResult<()> {
+        let quarantine_path = format!("/quarantine{path}");
+        let mut reader = match self.storage.get(path) {
+            Ok(file_handle) => file_handle.reader,
+            Err(err) if err.io_error().kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        let mut writer = self.storage.put(&quarantine_path)?;
+        io::copy(&mut reader, &mut writer).with_path(&quarantine_path)?;
+        writer.close().with_path(&quarantine_path)?;
+        Self::ignore_not_found(self.storage.delete(path))
+    }
+
+    /// Opens the blob stored under `key` and updates the latest access time of the key.
+    ///
+    /// Returns `Ok(None)` if the key or its blob do not exist.
+    fn open_entry<'a>(&self, key: Cow<'a, str>) -> IoPathResult<Option<CacheHit<'a, S::Reader>>> {
+        let meta_path = Self::meta_path(&key);
+        let mut meta = match self.read_meta(&meta_path) {
+            Ok(meta) => meta,
+            Err(err) if err.io_error().kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let now = self.clock.now();
+        if meta.is_expired_at(now) {
+            return Ok(None);
+        }
+
+        meta.set_latest_access(now);
+        meta.increment_access_count();
+        let mut writer = self.storage.put(&meta_path)?;
+        writer
+            .write_all(meta.deref().as_ref())
+            .with_path(&meta_path)?;
+        writer.close().with_path(&meta_path)?;
+        self.append_to_journal(JournalRecord::Access {
+            time: now.into(),
+            key: key.to_string(),
+            blob_id: *meta.blob_id(),
+            access_count: meta.access_count(),
+        })?;
+
+        match self.storage.get(&Self::blob_path(meta.blob_id())) {
+            Ok(file_handle) => Ok(Some(CacheHit {
+                key,
+                reader: file_handle.reader,
+                size_hint: Some(file_handle.size_hint),
+                checksum: meta.checksum(),
+            })),
+            Err(err) if err.io_error().kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn ignore_not_found(result: IoPathResult<()>) -> IoPathResult<()> {
+        match result {
+            Err(err) if err.io_error().kind() == ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    fn read_meta(&self, path: &str) -> IoPathResult<Pin<Box<Meta<AlignedVec>>>> {
+        let reader = self.storage.get(path)?.reader;
+        let mut meta_data = AlignedVec::with_capacity(META_MAX_SIZE);
+        meta_data
+            .extend_from_reader(&mut reader.take(META_MAX_SIZE as u64))
+            .no_path()?;
         Meta::from_bytes(meta_data).map_err(|err| {
             IoPathError::new_no_path(io::Error::new(ErrorKind::InvalidData, format!("{err:?}")))
         })
     }
 
+    fn decode_blob_id(blob: &SubdirFile) -> Option<BlobId> {
+        ICASE_NOPAD_ALPHANUMERIC_ENCODING
+            .decode(format!("{}{}", blob.subdir, blob.name).as_bytes())
+            .ok()?
+            .try_into()
+            .ok()
+    }
+
+    /// Like [Self::iter_subdir_files], but returns an empty iterator if `path` does not exist.
+    pub(crate) fn iter_subdir_files_if_exists<'a>(
+        storage: &'a S,
+        path: &'a str,
+    ) -> IoPathResult<impl Iterator<Item = IoPathResult<SubdirFile>> + use<'a, S, C, R>> {
+        match Self::iter_subdir_files(storage, path) {
+            Ok(files) => Ok(Some(files).into_iter().flatten()),
+            Err(err) if err.io_error().kind() == ErrorKind::NotFound => {
+                Ok(None.into_iter().flatten())
+            }
+            Err(err) => Err(err),
+        }
+    }
+
     fn iter_subdir_files<'a>(
         storage: &'a S,
         path: &'a str,
@@ -267,6 +1070,7 @@ impl<S: Storage, C: Clock, R: RngBytes> LocalCache<S, C, R> {
                                 subdir: path_entry.name.to_string(),
                                 name: subdir_entry.name.to_string(),
                                 size: subdir_entry.size,
+                                modified: subdir_entry.modified,
                             }))
                         }
                         Err(err) => Some(Err(err)),
@@ -279,25 +1083,52 @@ impl<S: Storage, C: Clock, R: RngBytes> LocalCache<S, C, R> {
     }
 }
 
-struct SubdirFile {
-    path: String,
+pub(crate) struct SubdirFile {
+    pub(crate) path: String,
     subdir: String,
     name: String,
-    size: u64,
+    pub(crate) size: u64,
+    pub(crate) modified: Option<DateTime<Utc>>,
+}
+
+struct Journal {
+    records: Vec<JournalRecord>,
+    /// Paths of the files containing the records, or lost records.
+    paths: Vec<String>,
+    is_complete: bool,
 }
 
 /// A writer for a cache entry.
+///
+/// The meta files and the journal record are only written when the writer is closed. Thus, if the
+/// writer is dropped without closing it, the keys are left unchanged and only an orphaned blob
+/// remains.
 pub struct CacheWriter<S: Storage, M: AsRef<[u8]>> {
-    blob_writer: S::Writer,
-    meta_writers: Vec<S::Writer>,
+    storage: Arc<S>,
+    blob_writer: ChecksumWriter<S::Writer>,
+    /// Path of a blob to move to its content-addressed path once written.
+    staged_blob_path: Option<String>,
+    meta_paths: Vec<String>,
+    /// Path of the journal file and the keys to record in it.
+    journal: Option<(String, Vec<String>)>,
     meta: Pin<Box<Meta<M>>>,
 }
 
 impl<S: Storage, M: AsRef<[u8]>> CacheWriter<S, M> {
-    fn new(blob_writer: S::Writer, meta_writers: Vec<S::Writer>, meta: Pin<Box<Meta<M>>>) -> Self {
+    fn new(
+        storage: Arc<S>,
+        blob_writer: S::Writer,
+        staged_blob_path: Option<String>,
+        meta_paths: Vec<String>,
+        journal: Option<(String, Vec<String>)>,
+        meta: Pin<Box<Meta<M>>>,
+    ) -> Self {
         CacheWriter {
-            blob_writer,
-            meta_writers,
+            storage,
+            blob_writer: ChecksumWriter::new(blob_writer),
+            staged_blob_path,
+            meta_paths,
+            journal,
             meta,
         }
     }
@@ -313,13 +1144,49 @@ impl<S: Storage, M: AsRef<[u8]>> Write for CacheWriter<S, M> {
     }
 }
 
-impl<S: Storage, M: AsRef<[u8]>> Close for CacheWriter<S, M> {
-    fn close(self) -> io::Result<()> {
-        self.blob_writer.close()?;
-        for mut writer in self.meta_writers {
+impl<S: Storage, M: AsRef<[u8]> + AsMut<[u8]>> Close for CacheWriter<S, M> {
+    fn abort(self) {
+        let (blob_writer, _) = self.blob_writer.finalize();
+        blob_writer.abort();
+    }
+
+    fn close(mut self) -> io::Result<()> {
+        let (blob_writer, checksum) = self.blob_writer.finalize();
+        blob_writer.close()?;
+        if self.staged_blob_path.is_some() {
+            self.meta.set_blob_id(content_blob_id(&checksum.hash));
+        }
+        self.meta.set_checksum(&checksum);
+        for meta_path in &self.meta_paths {
+            let mut writer = self.storage.put(meta_path)?;
             writer.write_all(self.meta.deref().as_ref())?;
             writer.close()?;
         }
+        if let Some((journal_path, keys)) = self.journal {
+            let mut writer = self.storage.put(&journal_path)?;
+            let time = self
+                .meta
+                .latest_access()
+                .map_err(|err| io::Error::new(ErrorKind::InvalidData, format!("{err:?}")))?;
+            let record = JournalRecord::Set {
+                time: time.into(),
+                blob_id: *self.meta.blob_id(),
+                size: checksum.size,
+                expires_at: self.meta.expires_at().map(Into::into),
+                keys,
+            };
+            writer.write_all(&record.to_bytes())?;
+            writer.close()?;
+        }
+        if let Some(staged_path) = self.staged_blob_path {
+            // The keys are recorded first, so that a concurrent clean does not delete an existing
+            // blob with the same content as unused once it is shared with the keys. Replacing the
+            // blob renews the modification time relevant for the grace period of orphaned blobs.
+            self.storage.rename(
+                &staged_path,
+                &LocalCache::<S>::blob_path(self.meta.blob_id()),
+            )?;
+        }
         Ok(())
     }
 }
@@ -327,9 +1194,12 @@ impl<S: Storage, M: AsRef<[u8]>> Close for CacheWriter<S, M> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cache::clean::PrefixQuota;
     use crate::storage::in_memory::InMemoryStorage;
+    use crate::storage::{FileHandle, StorageEntry};
     use crate::util::clock::test_fakes::ControlledClock;
     use chrono::TimeDelta;
+    use std::sync::Mutex;
 
     #[test]
     fn test_returns_none_for_non_existent_keys() {
@@ -359,6 +1229,20 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_dropping_writer_without_closing_leaves_keys_unchanged() {
+        let storage = InMemoryStorage::new();
+        let mut cache = LocalCache::new(storage);
+        cache_entry_with_content(&mut cache, &["existing-key"], "Hello, world!").unwrap();
+
+        let mut writer = cache.set(&["existing-key", "new-key"]).unwrap();
+        writer.write_all(b"Goodbye, world!").unwrap();
+        drop(writer);
+
+        assert_cache_entry_with_content(&cache, &["existing-key"], "existing-key", "Hello, world!");
+        assert_no_cache_entry(&cache, &["new-key"]);
+    }
+
     #[test]
     fn test_get_falls_back_to_first_available_key() {
         let storage = InMemoryStorage::new();
@@ -376,20 +1260,63 @@ mod tests {
     }
 
     #[test]
-    fn test_get_updates_last_access_time() {
+    fn test_get_by_prefix_returns_most_recently_accessed_matching_key() {
         let mut clock = ControlledClock::default();
         let storage = InMemoryStorage::new();
         let mut cache = LocalCache::with_clock(storage, clock.clone());
 
-        cache_entry_with_content(&mut cache, &["key"], "Hello, world!").unwrap();
+        cache_entry_with_content(&mut cache, &["prefix-old"], "old").unwrap();
+        clock.advance_by(TimeDelta::days(1));
+        cache_entry_with_content(&mut cache, &["prefix-new"], "new").unwrap();
+        clock.advance_by(TimeDelta::days(1));
+        cache_entry_with_content(&mut cache, &["other-newest"], "newest").unwrap();
+
+        assert_cache_entry_by_prefix_with_content(&cache, &["prefix-"], "prefix-new", "new");
 
         clock.advance_by(TimeDelta::days(1));
-        let mut reader = cache.get(&["key"]).unwrap().unwrap().reader;
-        reader.read_to_string(&mut String::new()).unwrap();
+        cache.get(&["prefix-old"]).unwrap().unwrap();
+        assert_cache_entry_by_prefix_with_content(&cache, &["prefix-"], "prefix-old", "old");
+    }
 
-        let storage = cache.into_storage();
-        let mut meta_reader = storage
-            .get(&LocalCache::<InMemoryStorage>::meta_path("key"))
+    #[test]
+    fn test_get_by_prefix_prefers_earlier_prefixes() {
+        let mut clock = ControlledClock::default();
+        let storage = InMemoryStorage::new();
+        let mut cache = LocalCache::with_clock(storage, clock.clone());
+
+        cache_entry_with_content(&mut cache, &["a-key"], "a").unwrap();
+        clock.advance_by(TimeDelta::days(1));
+        cache_entry_with_content(&mut cache, &["b-key"], "b").unwrap();
+
+        assert_cache_entry_by_prefix_with_content(&cache, &["c-", "a-", "b-"], "a-key", "a");
+    }
+
+    #[test]
+    fn test_get_by_prefix_returns_none_if_no_key_matches() {
+        let storage = InMemoryStorage::new();
+        let mut cache = LocalCache::new(storage);
+        assert!(cache.get_by_prefix(&["prefix-"]).unwrap().is_none());
+
+        cache_entry_with_content(&mut cache, &["key"], "Hello, world!").unwrap();
+        assert!(cache.get_by_prefix(&[]).unwrap().is_none());
+        assert!(cache.get_by_prefix(&["prefix-"]).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_get_updates_last_access_time() {
+        let mut clock = ControlledClock::default();
+        let storage = InMemoryStorage::new();
+        let mut cache = LocalCache::with_clock(storage, clock.clone());
+
+        cache_entry_with_content(&mut cache, &["key"], "Hello, world!").unwrap();
+
+        clock.advance_by(TimeDelta::days(1));
+        let mut reader = cache.get(&["key"]).unwrap().unwrap().reader;
+        reader.read_to_string(&mut String::new()).unwrap();
+
+        let storage = cache.into_storage().unwrap();
+        let mut meta_reader = storage
+            .get(&LocalCache::<InMemoryStorage>::meta_path("key"))
             .unwrap();
         let mut buf = Vec::with_capacity(META_MAX_SIZE);
         meta_reader.reader.read_to_end(&mut buf).unwrap();
@@ -397,6 +1324,64 @@ mod tests {
         assert_eq!(meta.deref().latest_access().unwrap(), clock.now());
     }
 
+    #[test]
+    fn test_expired_entries_are_treated_as_missing() {
+        let mut clock = ControlledClock::default();
+        let storage = InMemoryStorage::new();
+        let cache = LocalCache::with_clock(storage, clock.clone());
+
+        let mut writer = cache
+            .set_with_ttl(
+                &["expiring", "expiring-alternate-key"],
+                Some(TimeDelta::days(1)),
+            )
+            .unwrap();
+        writer.write_all(b"Hello, world!").unwrap();
+        writer.close().unwrap();
+        clock.advance_by(TimeDelta::hours(23));
+        assert_cache_entry_with_content(&cache, &["expiring"], "expiring", "Hello, world!");
+
+        clock.advance_by(TimeDelta::hours(1));
+        assert_no_cache_entry(&cache, &["expiring", "expiring-alternate-key"]);
+        assert!(cache.get_by_prefix(&["expiring"]).unwrap().is_none());
+        assert_eq!(cache.list("").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_clean_removes_expired_entries_independent_of_limits() {
+        let mut clock = ControlledClock::default();
+        let storage = InMemoryStorage::new();
+        let mut cache = LocalCache::with_clock(storage, clock.clone());
+
+        let mut writer = cache
+            .set_with_ttl(&["expiring"], Some(TimeDelta::days(1)))
+            .unwrap();
+        writer.write_all(b"Hello, world!").unwrap();
+        writer.close().unwrap();
+        cache_entry_with_content(&mut cache, &["non-expiring"], "Goodbye, world!").unwrap();
+        cache.pin(&["expiring"]).unwrap();
+        clock.advance_by(TimeDelta::days(2));
+
+        let report = cache.clean(&CleanOptions::default()).unwrap();
+
+        assert_eq!(
+            report
+                .evictions
+                .iter()
+                .map(|eviction| (eviction.keys.clone(), eviction.reason))
+                .collect::<Vec<_>>(),
+            vec![(vec!["expiring".to_string()], EvictionReason::Expired)]
+        );
+        assert_cache_entry_with_content(
+            &cache,
+            &["non-expiring"],
+            "non-expiring",
+            "Goodbye, world!",
+        );
+        let storage = cache.into_storage().unwrap();
+        assert_blob_count(&storage, 1);
+    }
+
     #[test]
     fn test_clean_does_not_do_anything_if_no_limits_are_given() {
         let storage = InMemoryStorage::new();
@@ -404,7 +1389,7 @@ mod tests {
 
         cache_entry_with_content(&mut cache, &["key"], "Hello, world!").unwrap();
 
-        cache.clean(None, None).unwrap();
+        cache.clean(&CleanOptions::default()).unwrap();
 
         assert_cache_entry_with_content(&cache, &["key"], "key", "Hello, world!");
     }
@@ -420,12 +1405,17 @@ mod tests {
         cache_entry_with_content(&mut cache, &["new"], "Goodbye, world!").unwrap();
         clock.advance_by(TimeDelta::days(1));
 
-        cache.clean(Some(TimeDelta::days(2)), None).unwrap();
+        cache
+            .clean(&CleanOptions {
+                max_unused_age: Some(TimeDelta::days(2)),
+                ..CleanOptions::default()
+            })
+            .unwrap();
 
         assert_no_cache_entry(&cache, &["old"]);
         assert_cache_entry_with_content(&cache, &["new"], "new", "Goodbye, world!");
 
-        let storage = cache.into_storage();
+        let storage = cache.into_storage().unwrap();
         assert_blob_count(&storage, 1);
     }
 
@@ -439,7 +1429,12 @@ mod tests {
         clock.advance_by(TimeDelta::days(2));
 
         cache.get(&["new"]).unwrap().unwrap();
-        cache.clean(Some(TimeDelta::days(1)), None).unwrap();
+        cache
+            .clean(&CleanOptions {
+                max_unused_age: Some(TimeDelta::days(1)),
+                ..CleanOptions::default()
+            })
+            .unwrap();
 
         assert_cache_entry_with_content(&cache, &["old"], "old", "Hello, world!");
         assert_cache_entry_with_content(&cache, &["new"], "new", "Hello, world!");
@@ -464,7 +1459,12 @@ mod tests {
         clock.advance_by(TimeDelta::days(1));
         cache_entry_with_content(&mut cache, &["0-days-old"], "0123456789").unwrap();
 
-        cache.clean(None, Some(21)).unwrap();
+        cache
+            .clean(&CleanOptions {
+                max_blob_size_sum: Some(21),
+                ..CleanOptions::default()
+            })
+            .unwrap();
 
         assert_no_cache_entry(
             &cache,
@@ -473,17 +1473,435 @@ mod tests {
         assert_cache_entry_with_content(&cache, &["1-day-old"], "1-day-old", "0123456789");
         assert_cache_entry_with_content(&cache, &["0-days-old"], "0-days-old", "0123456789");
 
-        let storage = cache.into_storage();
+        let storage = cache.into_storage().unwrap();
         assert_blob_count(&storage, 2);
     }
 
+    #[test]
+    fn test_clean_applies_prefix_quotas_before_space_limit() {
+        let mut clock = ControlledClock::default();
+        let storage = InMemoryStorage::new();
+        let mut cache = LocalCache::with_clock(storage, clock.clone());
+
+        cache_entry_with_content(&mut cache, &["npm-old"], "0123456789").unwrap();
+        clock.advance_by(TimeDelta::days(1));
+        cache_entry_with_content(&mut cache, &["gradle-old"], "0123456789").unwrap();
+        clock.advance_by(TimeDelta::days(1));
+        cache_entry_with_content(&mut cache, &["gradle-new"], "0123456789").unwrap();
+
+        let quotas = vec![PrefixQuota {
+            prefix: "gradle-".to_string(),
+            max_blob_size_sum: 15,
+        }];
+        let report = cache
+            .clean(&CleanOptions {
+                max_blob_size_sum: Some(25),
+                prefix_quotas: quotas,
+                ..CleanOptions::default()
+            })
+            .unwrap();
+
+        assert_eq!(
+            report
+                .evictions
+                .iter()
+                .map(|eviction| (eviction.keys.clone(), eviction.reason))
+                .collect::<Vec<_>>(),
+            vec![(vec!["gradle-old".to_string()], EvictionReason::Quota)]
+        );
+        assert_cache_entry_with_content(&cache, &["npm-old"], "npm-old", "0123456789");
+        assert_cache_entry_with_content(&cache, &["gradle-new"], "gradle-new", "0123456789");
+    }
+
+    #[test]
+    fn test_clean_with_lfu_policy_deletes_least_frequently_used_entries() {
+        let mut clock = ControlledClock::default();
+        let storage = InMemoryStorage::new();
+        let mut cache = LocalCache::with_clock(storage, clock.clone());
+
+        cache_entry_with_content(&mut cache, &["frequent"], "0123456789").unwrap();
+        cache
+            .clean(&CleanOptions {
+                policy: EvictionPolicy::Lfu,
+                ..CleanOptions::default()
+            })
+            .unwrap();
+        for _ in 0..2 {
+            assert_cache_entry_with_content(&cache, &["frequent"], "frequent", "0123456789");
+        }
+        clock.advance_by(TimeDelta::days(1));
+        cache_entry_with_content(&mut cache, &["rare"], "0123456789").unwrap();
+
+        cache
+            .clean(&CleanOptions {
+                max_blob_size_sum: Some(15),
+                policy: EvictionPolicy::Lfu,
+                ..CleanOptions::default()
+            })
+            .unwrap();
+
+        assert_cache_entry_with_content(&cache, &["frequent"], "frequent", "0123456789");
+        assert_no_cache_entry(&cache, &["rare"]);
+    }
+
+    #[test]
+    fn test_clean_with_size_weighted_policy_prefers_large_entries() {
+        let mut clock = ControlledClock::default();
+        let storage = InMemoryStorage::new();
+        let mut cache = LocalCache::with_clock(storage, clock.clone());
+
+        cache_entry_with_content(&mut cache, &["small"], "01234").unwrap();
+        clock.advance_by(TimeDelta::days(1));
+        cache_entry_with_content(&mut cache, &["large"], "01234567890123456789").unwrap();
+        clock.advance_by(TimeDelta::days(1));
+
+        cache
+            .clean(&CleanOptions {
+                max_blob_size_sum: Some(20),
+                policy: EvictionPolicy::SizeWeighted,
+                ..CleanOptions::default()
+            })
+            .unwrap();
+
+        assert_cache_entry_with_content(&cache, &["small"], "small", "01234");
+        assert_no_cache_entry(&cache, &["large"]);
+    }
+
+    #[test]
+    fn test_clean_reports_evicted_blobs() {
+        let mut clock = ControlledClock::new(Utc::now());
+        let storage = InMemoryStorage::new();
+        let mut cache = LocalCache::with_clock(storage, clock.clone());
+
+        let start = clock.now();
+        cache_entry_with_content(&mut cache, &["old", "old-alternate-key"], "0123456789").unwrap();
+        clock.advance_by(TimeDelta::days(2));
+        cache_entry_with_content(&mut cache, &["large"], "0123456789").unwrap();
+        clock.advance_by(TimeDelta::hours(1));
+        cache_entry_with_content(&mut cache, &["new"], "01234").unwrap();
+
+        let report = cache
+            .clean(&CleanOptions {
+                max_unused_age: Some(TimeDelta::days(1)),
+                max_blob_size_sum: Some(5),
+                ..CleanOptions::default()
+            })
+            .unwrap();
+
+        assert_eq!(
+            report.evictions,
+            vec![
+                Eviction {
+                    keys: vec!["old".to_string(), "old-alternate-key".to_string()],
+                    blob_size: 10,
+                    latest_access: start,
+                    reason: EvictionReason::Age,
+                },
+                Eviction {
+                    keys: vec!["large".to_string()],
+                    blob_size: 10,
+                    latest_access: start + TimeDelta::days(2),
+                    reason: EvictionReason::Size,
+                },
+            ]
+        );
+        assert_eq!(report.freed_size(), 20);
+    }
+
+    #[test]
+    fn test_clean_dry_run_reports_without_deleting() {
+        let mut clock = ControlledClock::new(Utc::now());
+        let storage = InMemoryStorage::new();
+        let mut cache = LocalCache::with_clock(storage.clone(), clock.clone());
+
+        cache_entry_with_content(&mut cache, &["key"], "Hello, world!").unwrap();
+        cache_entry_with_content(&mut cache, &["key"], "Goodbye, world!").unwrap();
+        clock.advance_by(TimeDelta::days(2));
+
+        let dry_run_report = cache
+            .clean_dry_run(&CleanOptions {
+                max_unused_age: Some(TimeDelta::days(1)),
+                ..CleanOptions::default()
+            })
+            .unwrap();
+        assert_eq!(
+            dry_run_report
+                .evictions
+                .iter()
+                .map(|eviction| eviction.reason)
+                .collect::<Vec<_>>(),
+            vec![EvictionReason::Orphan, EvictionReason::Age]
+        );
+        assert_blob_count(&storage, 2);
+
+        let report = cache
+            .clean(&CleanOptions {
+                max_unused_age: Some(TimeDelta::days(1)),
+                ..CleanOptions::default()
+            })
+            .unwrap();
+        assert_eq!(report, dry_run_report);
+        assert_blob_count(&storage, 0);
+    }
+
+    #[test]
+    fn test_clean_does_not_remove_pinned_entries() {
+        let mut clock = ControlledClock::default();
+        let storage = InMemoryStorage::new();
+        let mut cache = LocalCache::with_clock(storage, clock.clone());
+
+        cache_entry_with_content(&mut cache, &["pinned", "alternate-key"], "0123456789").unwrap();
+        cache_entry_with_content(&mut cache, &["old"], "0123456789").unwrap();
+        clock.advance_by(TimeDelta::days(2));
+        cache_entry_with_content(&mut cache, &["new"], "0123456789").unwrap();
+        cache.pin(&["pinned"]).unwrap();
+
+        let report = cache
+            .clean(&CleanOptions {
+                max_unused_age: Some(TimeDelta::days(1)),
+                max_blob_size_sum: Some(15),
+                ..CleanOptions::default()
+            })
+            .unwrap();
+
+        assert_eq!(
+            report
+                .evictions
+                .iter()
+                .map(|eviction| (eviction.keys.clone(), eviction.reason))
+                .collect::<Vec<_>>(),
+            vec![
+                (vec!["old".to_string()], EvictionReason::Age),
+                (vec!["new".to_string()], EvictionReason::Size),
+            ]
+        );
+        assert_cache_entry_with_content(&cache, &["pinned"], "pinned", "0123456789");
+        assert_cache_entry_with_content(&cache, &["alternate-key"], "alternate-key", "0123456789");
+    }
+
+    #[test]
+    fn test_pins_apply_to_keys() {
+        let storage = InMemoryStorage::new();
+        let cache = LocalCache::new(storage);
+
+        cache.pin(&["key-a", "key-b"]).unwrap();
+        cache.pin(&["key-a"]).unwrap();
+        cache.unpin(&["key-b", "not-pinned"]).unwrap();
+
+        assert_eq!(
+            cache.pinned_keys().unwrap(),
+            HashSet::from(["key-a".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_clean_removes_orphaned_blobs_after_grace_period() {
+        let mut clock = ControlledClock::new(Utc::now());
+        let storage = InMemoryStorage::new();
+        let mut cache = LocalCache::with_clock(storage.clone(), clock.clone());
+
+        cache_entry_with_content(&mut cache, &["key"], "Hello, world!").unwrap();
+        cache_entry_with_content(&mut cache, &["key"], "Goodbye, world!").unwrap();
+
+        cache
+            .clean(&CleanOptions {
+                max_unused_age: Some(TimeDelta::days(1)),
+                ..CleanOptions::default()
+            })
+            .unwrap();
+        assert_blob_count(&storage, 2);
+
+        clock.advance_by(ORPHAN_BLOB_GRACE_PERIOD + TimeDelta::minutes(1));
+        cache
+            .clean(&CleanOptions {
+                max_unused_age: Some(TimeDelta::days(1)),
+                ..CleanOptions::default()
+            })
+            .unwrap();
+
+        assert_cache_entry_with_content(&cache, &["key"], "key", "Goodbye, world!");
+        assert_blob_count(&storage, 1);
+    }
+
+    #[test]
+    fn test_clean_counts_orphaned_blobs_towards_space_limit() {
+        let mut clock = ControlledClock::new(Utc::now());
+        let storage = InMemoryStorage::new();
+        let mut cache = LocalCache::with_clock(storage, clock.clone());
+
+        cache_entry_with_content(&mut cache, &["old"], "0123456789").unwrap();
+        cache_entry_with_content(&mut cache, &["old"], "0123456789").unwrap();
+        clock.advance_by(TimeDelta::minutes(1));
+        cache_entry_with_content(&mut cache, &["new"], "0123456789").unwrap();
+
+        cache
+            .clean(&CleanOptions {
+                max_blob_size_sum: Some(25),
+                ..CleanOptions::default()
+            })
+            .unwrap();
+
+        assert_no_cache_entry(&cache, &["old"]);
+        assert_cache_entry_with_content(&cache, &["new"], "new", "0123456789");
+        let storage = cache.into_storage().unwrap();
+        assert_blob_count(&storage, 2);
+    }
+
+    #[test]
+    fn test_clean_uses_index_instead_of_meta_files() {
+        type Cache = LocalCache<InMemoryStorage, ControlledClock>;
+        let mut clock = ControlledClock::new(Utc::now());
+        let storage = InMemoryStorage::new();
+        let mut cache = LocalCache::with_clock(storage.clone(), clock.clone());
+
+        cache_entry_with_content(&mut cache, &["old"], "old").unwrap();
+        cache_entry_with_content(&mut cache, &["accessed"], "accessed").unwrap();
+        cache.clean(&CleanOptions::default()).unwrap();
+        assert!(storage.exists_file(INDEX_PATH).unwrap());
+
+        clock.advance_by(TimeDelta::hours(2));
+        cache_entry_with_content(&mut cache, &["new"], "new").unwrap();
+        assert_cache_entry_with_content(&cache, &["accessed"], "accessed", "accessed");
+        // would fail a scan of the meta files
+        let mut writer = storage.put(&Cache::meta_path("undecodable")).unwrap();
+        writer.write_all(b"garbage").unwrap();
+        writer.close().unwrap();
+
+        let report = cache
+            .clean(&CleanOptions {
+                max_unused_age: Some(TimeDelta::hours(1)),
+                ..CleanOptions::default()
+            })
+            .unwrap();
+
+        assert_eq!(
+            report
+                .evictions
+                .iter()
+                .map(|eviction| eviction.keys.clone())
+                .collect::<Vec<_>>(),
+            vec![vec!["old".to_string()]]
+        );
+        assert_eq!(storage.list(INDEX_JOURNAL_PATH).unwrap().count(), 0);
+        assert_no_cache_entry(&cache, &["old"]);
+        assert_cache_entry_with_content(&cache, &["new"], "new", "new");
+    }
+
+    #[test]
+    fn test_clean_rebuilds_index_if_inconsistent() {
+        let mut clock = ControlledClock::new(Utc::now());
+        let storage = InMemoryStorage::new();
+        let mut cache = LocalCache::with_clock(storage.clone(), clock.clone());
+
+        cache_entry_with_content(&mut cache, &["key"], "Hello, world!").unwrap();
+        cache.clean(&CleanOptions::default()).unwrap();
+
+        clock.advance_by(TimeDelta::hours(2));
+        cache_entry_with_content(&mut cache, &["key"], "Goodbye, world!").unwrap();
+        let journal_files = storage
+            .list(INDEX_JOURNAL_PATH)
+            .unwrap()
+            .map(|entry| entry.unwrap().name.to_string())
+            .collect::<Vec<_>>();
+        for journal_file in journal_files {
+            storage
+                .delete(&format!("{INDEX_JOURNAL_PATH}/{journal_file}"))
+                .unwrap();
+        }
+
+        cache
+            .clean(&CleanOptions {
+                max_unused_age: Some(TimeDelta::hours(1)),
+                ..CleanOptions::default()
+            })
+            .unwrap();
+
+        assert_cache_entry_with_content(&cache, &["key"], "key", "Goodbye, world!");
+    }
+
+    #[test]
+    fn test_clean_rebuilds_undecodable_index() {
+        let storage = InMemoryStorage::new();
+        let mut cache = LocalCache::with_clock(storage.clone(), ControlledClock::default());
+        cache_entry_with_content(&mut cache, &["key"], "Hello, world!").unwrap();
+        let mut writer = storage.put(INDEX_PATH).unwrap();
+        writer.write_all(b"garbage").unwrap();
+        writer.close().unwrap();
+
+        let report = cache
+            .clean(&CleanOptions {
+                max_blob_size_sum: Some(0),
+                ..CleanOptions::default()
+            })
+            .unwrap();
+
+        assert_eq!(report.evictions.len(), 1);
+        assert_no_cache_entry(&cache, &["key"]);
+        let index = cache.read_index().unwrap().unwrap();
+        assert!(index.blobs.is_empty());
+        assert!(index.keys.is_empty());
+    }
+
+    #[test]
+    fn test_journals_changes_only_while_indexed() {
+        let storage = InMemoryStorage::new();
+        let mut cache = LocalCache::new(storage.clone());
+        let journal_len = || {
+            storage
+                .list(INDEX_JOURNAL_PATH)
+                .map_or(0, |list| list.count())
+        };
+
+        cache_entry_with_content(&mut cache, &["unindexed"], "Hello, world!").unwrap();
+        assert_eq!(journal_len(), 0);
+
+        cache.clean(&CleanOptions::default()).unwrap();
+        cache_entry_with_content(&mut cache, &["indexed"], "Hello, world!").unwrap();
+        assert_eq!(journal_len(), 1);
+
+        let mut writer = storage
+            .put(&LocalCache::<InMemoryStorage>::meta_path("broken"))
+            .unwrap();
+        writer.write_all(b"garbage").unwrap();
+        writer.close().unwrap();
+        cache.fsck(true).unwrap();
+        cache_entry_with_content(&mut cache, &["repaired"], "Hello, world!").unwrap();
+        assert!(!storage.exists_file(INDEX_PATH).unwrap());
+        assert_eq!(journal_len(), 1);
+    }
+
+    #[test]
+    fn test_journals_changes_once_another_instance_created_the_index() {
+        let storage = InMemoryStorage::new();
+        let mut cache = LocalCache::new(storage.clone());
+        let mut cleanup_cache = LocalCache::new(storage.clone());
+
+        cache_entry_with_content(&mut cache, &["unindexed"], "Hello, world!").unwrap();
+        cleanup_cache.clean(&CleanOptions::default()).unwrap();
+        cache_entry_with_content(&mut cache, &["indexed"], "Hello, world!").unwrap();
+
+        assert_eq!(storage.list(INDEX_JOURNAL_PATH).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_into_storage_returns_none_while_writer_is_alive() {
+        let storage = InMemoryStorage::new();
+        let cache = LocalCache::new(storage.clone());
+        let writer = cache.set(&["key"]).unwrap();
+        assert!(cache.into_storage().is_none());
+        drop(writer);
+
+        let cache = LocalCache::new(storage);
+        assert!(cache.into_storage().is_some());
+    }
+
     #[test]
     fn test_key_without_blob_is_handled_gracefully() {
         let storage = InMemoryStorage::new();
         let mut cache = LocalCache::new(storage);
         cache_entry_with_content(&mut cache, &["key0"], "cached content").unwrap();
 
-        let storage = cache.into_storage();
+        let storage = cache.into_storage().unwrap();
         let mut to_delete = Vec::new();
         for subdir in storage.list("/blob").unwrap() {
             let subdir = subdir.unwrap();
@@ -503,6 +1921,333 @@ mod tests {
         assert_cache_entry_with_content(&cache, &["key0", "key1"], "key1", "fallback");
     }
 
+    #[test]
+    fn test_list_returns_entries_matching_prefix() {
+        let mut clock = ControlledClock::default();
+        let storage = InMemoryStorage::new();
+        let mut cache = LocalCache::with_clock(storage, clock.clone());
+        assert_eq!(cache.list("").unwrap(), vec![]);
+
+        let first_access = clock.now();
+        cache_entry_with_content(&mut cache, &["prefix-b", "prefix-a"], "Hello, world!").unwrap();
+        clock.advance_by(TimeDelta::days(1));
+        cache_entry_with_content(&mut cache, &["other"], "Goodbye").unwrap();
+
+        assert_eq!(
+            cache.list("prefix-").unwrap(),
+            vec![
+                CacheEntry {
+                    key: "prefix-a".to_string(),
+                    size: 13,
+                    latest_access: first_access,
+                    tree_hash: None,
+                },
+                CacheEntry {
+                    key: "prefix-b".to_string(),
+                    size: 13,
+                    latest_access: first_access,
+                    tree_hash: None,
+                },
+            ]
+        );
+        assert_eq!(
+            cache
+                .list("")
+                .unwrap()
+                .into_iter()
+                .map(|entry| entry.key)
+                .collect::<Vec<_>>(),
+            vec!["other", "prefix-a", "prefix-b"]
+        );
+    }
+
+    #[test]
+    fn test_list_does_not_update_last_access_time() {
+        let mut clock = ControlledClock::default();
+        let storage = InMemoryStorage::new();
+        let mut cache = LocalCache::with_clock(storage, clock.clone());
+
+        let first_access = clock.now();
+        cache_entry_with_content(&mut cache, &["key"], "Hello, world!").unwrap();
+        clock.advance_by(TimeDelta::days(1));
+        cache.list("").unwrap();
+
+        assert_eq!(cache.list("").unwrap()[0].latest_access, first_access);
+    }
+
+    #[test]
+    fn test_entry_returns_entry_without_accessing_it() {
+        let mut clock = ControlledClock::default();
+        let storage = InMemoryStorage::new();
+        let mut cache = LocalCache::with_clock(storage.clone(), clock.clone());
+        assert_eq!(cache.entry("key").unwrap(), None);
+
+        let first_access = clock.now();
+        cache_entry_with_content(&mut cache, &["key"], "Hello, world!").unwrap();
+        cache.clean(&CleanOptions::default()).unwrap();
+        clock.advance_by(TimeDelta::days(1));
+        cache.entry("key").unwrap();
+
+        assert_eq!(
+            cache.entry("key").unwrap(),
+            Some(CacheEntry {
+                key: "key".to_string(),
+                size: 13,
+                latest_access: first_access,
+                tree_hash: None,
+            })
+        );
+        let meta = cache
+            .read_meta(&LocalCache::<InMemoryStorage>::meta_path("key"))
+            .unwrap();
+        assert_eq!(meta.access_count(), 0);
+        assert_eq!(
+            storage
+                .list(INDEX_JOURNAL_PATH)
+                .map_or(0, |list| list.count()),
+            0
+        );
+    }
+
+    #[test]
+    fn test_entry_returns_tree_hash() {
+        let cache = LocalCache::new(InMemoryStorage::new());
+        let tree_hash = blake3::hash(b"tree");
+        let options = SetOptions {
+            tree_hash: Some(tree_hash),
+            ..SetOptions::default()
+        };
+        let mut writer = cache.set_with_options(&["key"], &options).unwrap();
+        writer.write_all(b"Hello, world!").unwrap();
+        writer.close().unwrap();
+
+        assert_eq!(
+            cache.entry("key").unwrap().unwrap().tree_hash,
+            Some(tree_hash)
+        );
+        assert_eq!(cache.list("").unwrap()[0].tree_hash, Some(tree_hash));
+    }
+
+    #[test]
+    fn test_delete_removes_keys_and_unreferenced_blobs() {
+        let storage = InMemoryStorage::new();
+        let mut cache = LocalCache::new(storage);
+
+        cache_entry_with_content(&mut cache, &["key0", "key1"], "Hello, world!").unwrap();
+        cache_entry_with_content(&mut cache, &["other"], "Goodbye, world!").unwrap();
+
+        cache.delete(&["key0", "non-existent-key"]).unwrap();
+        assert_no_cache_entry(&cache, &["key0"]);
+        assert_cache_entry_with_content(&cache, &["key1"], "key1", "Hello, world!");
+
+        cache.delete(&["key1"]).unwrap();
+        assert_no_cache_entry(&cache, &["key1"]);
+        assert_cache_entry_with_content(&cache, &["other"], "other", "Goodbye, world!");
+
+        let storage = cache.into_storage().unwrap();
+        assert_blob_count(&storage, 1);
+    }
+
+    #[test]
+    fn test_deduplication_shares_blobs_with_identical_content() {
+        let storage = InMemoryStorage::new();
+        let mut cache = LocalCache::new(storage.clone()).with_deduplication(true);
+
+        cache_entry_with_content(&mut cache, &["key0"], "Hello, world!").unwrap();
+        cache_entry_with_content(&mut cache, &["key1"], "Hello, world!").unwrap();
+        cache_entry_with_content(&mut cache, &["other"], "Goodbye, world!").unwrap();
+        assert_blob_count(&storage, 2);
+
+        cache.delete(&["key0"]).unwrap();
+        assert_no_cache_entry(&cache, &["key0"]);
+        assert_cache_entry_with_content(&cache, &["key1"], "key1", "Hello, world!");
+        assert_blob_count(&storage, 2);
+    }
+
+    /// Storage running a hook after the next rename, to interleave operations with closing a
+    /// deduplicating writer.
+    struct RenameHookStorage {
+        storage: InMemoryStorage,
+        after_rename: Mutex<Option<Box<dyn FnOnce()>>>,
+    }
+
+    impl Storage for RenameHookStorage {
+        type Reader = <InMemoryStorage as Storage>::Reader;
+        type Writer = <InMemoryStorage as Storage>::Writer;
+
+        fn delete(&self, path: &str) -> IoPathResult<()> {
+            self.storage.delete(path)
+        }
+
+        fn exists_file(&self, path: &str) -> IoPathResult<bool> {
+            self.storage.exists_file(path)
+        }
+
+        fn get(&self, path: &str) -> IoPathResult<FileHandle<Self::Reader>> {
+            self.storage.get(path)
+        }
+
+        fn list(
+            &self,
+            path: &str,
+        ) -> IoPathResult<impl Iterator<Item = IoPathResult<StorageEntry<'_>>>> {
+            self.storage.list(path)
+        }
+
+        fn put(&self, path: &str) -> IoPathResult<Self::Writer> {
+            self.storage.put(path)
+        }
+
+        fn rename(&self, from: &str, to: &str) -> IoPathResult<()> {
+            self.storage.rename(from, to)?;
+            if let Some(hook) = self.after_rename.lock().unwrap().take() {
+                hook();
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_clean_during_store_keeps_shared_blob() {
+        let mut clock = ControlledClock::default();
+        let storage = InMemoryStorage::new();
+        let mut cleanup_cache = LocalCache::with_clock(storage.clone(), clock.clone());
+        let mut cache = LocalCache::with_clock(
+            RenameHookStorage {
+                storage: storage.clone(),
+                after_rename: Mutex::new(None),
+            },
+            clock.clone(),
+        )
+        .with_deduplication(true);
+        cache_entry_with_content(&mut cache, &["old"], "Hello, world!").unwrap();
+        cleanup_cache.clean(&CleanOptions::default()).unwrap();
+        clock.advance_by(TimeDelta::days(2));
+
+        *cache.storage().after_rename.lock().unwrap() = Some(Box::new(move || {
+            cleanup_cache
+                .clean(&CleanOptions {
+                    max_unused_age: Some(TimeDelta::days(1)),
+                    ..CleanOptions::default()
+                })
+                .unwrap();
+        }));
+        cache_entry_with_content(&mut cache, &["new"], "Hello, world!").unwrap();
+
+        assert!(cache.storage().after_rename.lock().unwrap().is_none());
+        assert_cache_entry_with_content(&cache, &["new"], "new", "Hello, world!");
+    }
+
+    #[test]
+    fn test_clean_keeps_shared_blob_while_another_key_is_used() {
+        let mut clock = ControlledClock::default();
+        let storage = InMemoryStorage::new();
+        let mut cache =
+            LocalCache::with_clock(storage.clone(), clock.clone()).with_deduplication(true);
+        cache.clean(&CleanOptions::default()).unwrap();
+
+        cache_entry_with_content(&mut cache, &["old"], "Hello, world!").unwrap();
+        let mut writer = cache
+            .set_with_ttl(&["expiring"], Some(TimeDelta::hours(1)))
+            .unwrap();
+        writer.write_all(b"Hello, world!").unwrap();
+        writer.close().unwrap();
+        clock.advance_by(TimeDelta::days(2));
+        cache_entry_with_content(&mut cache, &["new"], "Hello, world!").unwrap();
+        cache_entry_with_content(&mut cache, &["other"], "Goodbye, world!").unwrap();
+
+        let report = cache
+            .clean(&CleanOptions {
+                max_unused_age: Some(TimeDelta::days(1)),
+                max_blob_size_sum: Some(30),
+                ..CleanOptions::default()
+            })
+            .unwrap();
+
+        assert!(report.evictions.is_empty());
+        assert_cache_entry_with_content(&cache, &["old"], "old", "Hello, world!");
+        assert_cache_entry_with_content(&cache, &["new"], "new", "Hello, world!");
+        assert_no_cache_entry(&cache, &["expiring"]);
+        assert_blob_count(&storage, 2);
+    }
+
+    #[test]
+    fn test_delete_on_empty_cache_succeeds() {
+        let storage = InMemoryStorage::new();
+        let cache = LocalCache::new(storage);
+        cache.delete(&["key"]).unwrap();
+    }
+
+    #[test]
+    fn test_fsck_reports_no_inconsistencies_for_consistent_cache() {
+        let mut cache = LocalCache::new(InMemoryStorage::new());
+        assert_eq!(cache.fsck(false).unwrap(), vec![]);
+
+        cache_entry_with_content(&mut cache, &["key0", "key1"], "Hello, world!").unwrap();
+        cache_entry_with_content(&mut cache, &["key2"], "Goodbye, world!").unwrap();
+        assert_eq!(cache.fsck(false).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_fsck_detects_and_repairs_inconsistencies() {
+        type Cache = LocalCache<InMemoryStorage>;
+        let mut cache = LocalCache::new(InMemoryStorage::new());
+        for key in ["intact", "dangling", "orphan", "corrupted"] {
+            cache_entry_with_content(&mut cache, &[key], "Hello, world!").unwrap();
+        }
+        let blob_path = |cache: &Cache, key: &str| {
+            Cache::blob_path(cache.read_meta(&Cache::meta_path(key)).unwrap().blob_id())
+        };
+        let dangling_blob_path = blob_path(&cache, "dangling");
+        let orphan_blob_path = blob_path(&cache, "orphan");
+        let corrupted_blob_path = blob_path(&cache, "corrupted");
+        let broken_meta_path = Cache::meta_path("broken");
+
+        let storage = cache.into_storage().unwrap();
+        storage.delete(&dangling_blob_path).unwrap();
+        storage.delete(&Cache::meta_path("orphan")).unwrap();
+        for (path, content) in [
+            (&corrupted_blob_path, &b"Hello, World!"[..]),
+            (&broken_meta_path, b"garbage"),
+        ] {
+            let mut writer = storage.put(path).unwrap();
+            writer.write_all(content).unwrap();
+            writer.close().unwrap();
+        }
+        let cache = LocalCache::new(storage);
+
+        let mut expected = vec![
+            Inconsistency::DanglingKey {
+                key: "dangling".to_string(),
+                blob_path: dangling_blob_path,
+            },
+            Inconsistency::OrphanBlob {
+                path: orphan_blob_path,
+            },
+            Inconsistency::CorruptedBlob {
+                path: corrupted_blob_path.clone(),
+                keys: vec!["corrupted".to_string()],
+            },
+            Inconsistency::UndecodableMeta {
+                path: broken_meta_path.clone(),
+            },
+        ];
+        expected.sort_unstable_by(|a, b| a.path().cmp(b.path()));
+        assert_eq!(cache.fsck(false).unwrap(), expected);
+        assert_eq!(cache.fsck(true).unwrap(), expected);
+        assert_eq!(cache.fsck(false).unwrap(), vec![]);
+
+        assert_cache_entry_with_content(&cache, &["intact"], "intact", "Hello, world!");
+        assert_no_cache_entry(&cache, &["dangling"]);
+        assert_no_cache_entry(&cache, &["corrupted"]);
+        let storage = cache.into_storage().unwrap();
+        assert_blob_count(&storage, 1);
+        for path in [corrupted_blob_path, broken_meta_path] {
+            assert!(storage.exists_file(&format!("/quarantine{path}")).unwrap());
+            assert!(!storage.exists_file(&path).unwrap());
+        }
+    }
+
     fn cache_entry_with_content<C: Cache>(
         cache: &mut C,
         keys: &[&str],
@@ -526,6 +2271,7 @@ mod tests {
             key,
             mut reader,
             size_hint,
+            checksum,
         } = cache
             .get(keys)
             .expect("IO failure getting cache entry")
@@ -541,6 +2287,33 @@ mod tests {
             .expect("failed to read cache entry");
         assert_eq!(buf, content, "cache entry content mismatch");
         assert_eq!(size_hint, Some(content.len() as u64));
+        assert_eq!(
+            checksum,
+            Some(Checksum {
+                size: content.len() as u64,
+                hash: blake3::hash(content.as_bytes()),
+            })
+        );
+    }
+
+    fn assert_cache_entry_by_prefix_with_content<C: Cache>(
+        cache: &C,
+        prefixes: &[&str],
+        matched_key: &str,
+        content: &str,
+    ) {
+        let CacheHit {
+            key, mut reader, ..
+        } = cache
+            .get_by_prefix(prefixes)
+            .expect("IO failure getting cache entry")
+            .expect("cache entry not found");
+        assert_eq!(key, matched_key);
+        let mut buf = String::new();
+        reader
+            .read_to_string(&mut buf)
+            .expect("failed to read cache entry");
+        assert_eq!(buf, content, "cache entry content mismatch");
     }
 
     fn assert_no_cache_entry<C: Cache>(cache: &C, keys: &[&str]) {
@@ -1,7 +1,9 @@
 //! Serialization and deserialization of metadata for cache entries.
 
 use crate::cache::blob_id::BlobId;
+use crate::cache::checksum::Checksum;
 use chrono::{DateTime, Utc};
+use rkyv::rancor::Source;
 use rkyv::util::AlignedVec;
 use rkyv::{Archive, Serialize, rancor};
 use std::error::Error;
@@ -22,6 +24,7 @@ struct MetaV1 {
 
 const _META_V1_SCRATCH_SIZE: usize = 0;
 
+#[cfg(test)]
 impl MetaV1 {
     pub fn new(blob_id: BlobId, latest_access: DateTime<Utc>) -> Self {
         Self {
@@ -33,33 +36,86 @@ impl MetaV1 {
     }
 }
 
-pub const META_MAX_SIZE: usize = 40;
+/// Extends [MetaV1] with the size and hash of the blob to verify its integrity, an expiry time,
+/// the number of accesses of the cache entry, and the hash of the directory tree stored in it.
+#[derive(Archive, Clone, Debug, Serialize, PartialEq)]
+#[rkyv(compare(PartialEq), attr(derive(Debug)))]
+#[repr(C)]
+struct MetaV2 {
+    version: u16,
+    blob_id: BlobId,
+    latest_access: i64,
+    latest_access_nsecs: u32,
+    blob_size: u64,
+    blob_hash: [u8; blake3::OUT_LEN],
+    /// Expiry time as seconds since the Unix epoch, or [NO_EXPIRY] if the entry does not expire.
+    expires_at: i64,
+    access_count: u64,
+    /// Hash of the stored directory tree, or [NO_TREE_HASH] if not recorded.
+    tree_hash: [u8; blake3::OUT_LEN],
+}
+
+const _META_V2_SCRATCH_SIZE: usize = 0;
+
+const NO_EXPIRY: i64 = i64::MAX;
+
+const NO_TREE_HASH: [u8; blake3::OUT_LEN] = [0; blake3::OUT_LEN];
+
+impl MetaV2 {
+    pub fn new(blob_id: BlobId, latest_access: DateTime<Utc>) -> Self {
+        Self {
+            version: 2,
+            blob_id,
+            latest_access: latest_access.timestamp(),
+            latest_access_nsecs: latest_access.timestamp_subsec_nanos(),
+            blob_size: 0,
+            blob_hash: [0; blake3::OUT_LEN],
+            expires_at: NO_EXPIRY,
+            access_count: 0,
+            tree_hash: NO_TREE_HASH,
+        }
+    }
+}
+
+pub const META_MAX_SIZE: usize = 128;
+
+#[derive(Debug)]
+enum ArchiveView {
+    V1(NonNull<ArchivedMetaV1>),
+    V2(NonNull<ArchivedMetaV2>),
+}
 
 #[derive(Debug)]
 pub struct Meta<T> {
     data: T,
-    archive_view: NonNull<ArchivedMetaV1>,
+    archive_view: ArchiveView,
     _pin: PhantomPinned,
 }
 
+#[derive(Debug)]
+struct UnsupportedFormatError;
+
+impl Display for UnsupportedFormatError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unsupported meta version or size")
+    }
+}
+
+impl Error for UnsupportedFormatError {}
+
 // safety: Send is not implemented automatically for Meta because of the NonNull field.
 // However, the data is owned by the Meta struct, and thus the NonNull field cannot be aliased.
 // Thus, send is safe.
 unsafe impl<T: Send> Send for Meta<T> {}
 
 impl Meta<AlignedVec> {
+    /// Creates metadata in the latest version. The checksum is initialized to zero and must be set
+    /// with [Meta::set_checksum] once the blob has been written. The entry does not expire unless
+    /// set with [Meta::set_expires_at].
     pub fn new(blob_id: BlobId, latest_access: DateTime<Utc>) -> Pin<Box<Self>> {
-        let meta = MetaV1::new(blob_id, latest_access);
+        let meta = MetaV2::new(blob_id, latest_access);
         let data = rkyv::to_bytes::<rancor::Error>(&meta).expect("failed to serialize meta");
-        let mut boxed_meta = Box::new(Self {
-            data,
-            archive_view: NonNull::dangling(),
-            _pin: PhantomPinned,
-        });
-        // TODO access_mut?
-        boxed_meta.archive_view =
-            NonNull::from(rkyv::access::<ArchivedMetaV1, rancor::Error>(&boxed_meta.data).unwrap());
-        Box::into_pin(boxed_meta)
+        Self::from_bytes(data).expect("failed to access serialized meta")
     }
 }
 
@@ -67,39 +123,207 @@ impl<T: AsMut<[u8]>> Meta<T> {
     pub fn from_bytes(data: T) -> Result<Pin<Box<Self>>, DeserializationError<impl Debug>> {
         let mut boxed_meta = Box::new(Self {
             data,
-            archive_view: NonNull::dangling(),
+            archive_view: ArchiveView::V1(NonNull::dangling()),
             _pin: PhantomPinned,
         });
-        let meta = rkyv::access::<ArchivedMetaV1, rancor::Error>(boxed_meta.data.as_mut())?;
-        boxed_meta.archive_view = NonNull::from(meta);
+        let bytes = boxed_meta.data.as_mut();
+        boxed_meta.archive_view = match bytes.first_chunk().copied().map(u16::from_le_bytes) {
+            Some(1) if bytes.len() == size_of::<ArchivedMetaV1>() => ArchiveView::V1(
+                NonNull::from(rkyv::access::<ArchivedMetaV1, rancor::Error>(bytes)?),
+            ),
+            Some(2) if bytes.len() == size_of::<ArchivedMetaV2>() => ArchiveView::V2(
+                NonNull::from(rkyv::access::<ArchivedMetaV2, rancor::Error>(bytes)?),
+            ),
+            _ => return Err(rancor::Error::new(UnsupportedFormatError).into()),
+        };
         Ok(Box::into_pin(boxed_meta))
     }
 
     pub fn set_latest_access(self: &mut Pin<Box<Self>>, latest_access: DateTime<Utc>) {
         // Safety: we're not moving the data out of the pin.
         let x = unsafe { self.as_mut().get_unchecked_mut() };
-        // Safety: self.archive_view is always a valid pointer after initialization
-        let archive_view = unsafe { x.archive_view.as_mut() };
-        archive_view.latest_access = latest_access.timestamp().into();
-        archive_view.latest_access_nsecs = latest_access.timestamp_subsec_nanos().into();
+        match &mut x.archive_view {
+            ArchiveView::V1(archive_view) => {
+                // Safety: self.archive_view is always a valid pointer after initialization
+                let archive_view = unsafe { archive_view.as_mut() };
+                archive_view.latest_access = latest_access.timestamp().into();
+                archive_view.latest_access_nsecs = latest_access.timestamp_subsec_nanos().into();
+            }
+            ArchiveView::V2(archive_view) => {
+                // Safety: self.archive_view is always a valid pointer after initialization
+                let archive_view = unsafe { archive_view.as_mut() };
+                archive_view.latest_access = latest_access.timestamp().into();
+                archive_view.latest_access_nsecs = latest_access.timestamp_subsec_nanos().into();
+            }
+        }
+    }
+
+    /// Increments the number of accesses of the entry.
+    ///
+    /// Metadata in a version not supporting access counts is left unchanged.
+    pub fn increment_access_count(self: &mut Pin<Box<Self>>) {
+        // Safety: we're not moving the data out of the pin.
+        let x = unsafe { self.as_mut().get_unchecked_mut() };
+        if let ArchiveView::V2(archive_view) = &mut x.archive_view {
+            // Safety: self.archive_view is always a valid pointer after initialization
+            let archive_view = unsafe { archive_view.as_mut() };
+            archive_view.access_count = archive_view
+                .access_count
+                .to_native()
+                .saturating_add(1)
+                .into();
+        }
+    }
+
+    /// Sets the checksum of the blob.
+    ///
+    /// Metadata in a version not supporting checksums is left unchanged.
+    pub fn set_checksum(self: &mut Pin<Box<Self>>, checksum: &Checksum) {
+        // Safety: we're not moving the data out of the pin.
+        let x = unsafe { self.as_mut().get_unchecked_mut() };
+        if let ArchiveView::V2(archive_view) = &mut x.archive_view {
+            // Safety: self.archive_view is always a valid pointer after initialization
+            let archive_view = unsafe { archive_view.as_mut() };
+            archive_view.blob_size = checksum.size.into();
+            archive_view.blob_hash = *checksum.hash.as_bytes();
+        }
+    }
+
+    /// Sets the ID of the blob the entry refers to.
+    pub fn set_blob_id(self: &mut Pin<Box<Self>>, blob_id: BlobId) {
+        // Safety: we're not moving the data out of the pin.
+        let x = unsafe { self.as_mut().get_unchecked_mut() };
+        match &mut x.archive_view {
+            ArchiveView::V1(archive_view) => {
+                // Safety: self.archive_view is always a valid pointer after initialization
+                let archive_view = unsafe { archive_view.as_mut() };
+                archive_view.blob_id = blob_id;
+            }
+            ArchiveView::V2(archive_view) => {
+                // Safety: self.archive_view is always a valid pointer after initialization
+                let archive_view = unsafe { archive_view.as_mut() };
+                archive_view.blob_id = blob_id;
+            }
+        }
+    }
+
+    /// Sets the time when the entry expires (with a precision of seconds), or `None` if it does
+    /// not expire.
+    ///
+    /// Metadata in a version not supporting expiry is left unchanged.
+    pub fn set_expires_at(self: &mut Pin<Box<Self>>, expires_at: Option<DateTime<Utc>>) {
+        // Safety: we're not moving the data out of the pin.
+        let x = unsafe { self.as_mut().get_unchecked_mut() };
+        if let ArchiveView::V2(archive_view) = &mut x.archive_view {
+            // Safety: self.archive_view is always a valid pointer after initialization
+            let archive_view = unsafe { archive_view.as_mut() };
+            archive_view.expires_at = expires_at
+                .map_or(NO_EXPIRY, |expires_at| expires_at.timestamp())
+                .into();
+        }
+    }
+
+    /// Sets the hash of the directory tree stored in the entry, or `None` if not known.
+    ///
+    /// Metadata in a version not supporting tree hashes is left unchanged.
+    pub fn set_tree_hash(self: &mut Pin<Box<Self>>, tree_hash: Option<blake3::Hash>) {
+        // Safety: we're not moving the data out of the pin.
+        let x = unsafe { self.as_mut().get_unchecked_mut() };
+        if let ArchiveView::V2(archive_view) = &mut x.archive_view {
+            // Safety: self.archive_view is always a valid pointer after initialization
+            let archive_view = unsafe { archive_view.as_mut() };
+            archive_view.tree_hash =
+                tree_hash.map_or(NO_TREE_HASH, |tree_hash| *tree_hash.as_bytes());
+        }
     }
 }
 
 impl<T: AsRef<[u8]>> Meta<T> {
     pub fn blob_id(&self) -> &BlobId {
-        // Safety: self.archive_view is always a valid pointer after initialization
-        let archive_view = unsafe { self.archive_view.as_ref() };
-        &archive_view.blob_id
+        match self.archive_view {
+            // Safety: self.archive_view is always a valid pointer after initialization
+            ArchiveView::V1(archive_view) => unsafe { &archive_view.as_ref().blob_id },
+            // Safety: self.archive_view is always a valid pointer after initialization
+            ArchiveView::V2(archive_view) => unsafe { &archive_view.as_ref().blob_id },
+        }
     }
 
     pub fn latest_access(&self) -> Result<DateTime<Utc>, DeserializationError<()>> {
-        // Safety: self.archive_view is always a valid pointer after initialization
-        let archive_view = unsafe { self.archive_view.as_ref() };
-        DateTime::from_timestamp(
-            archive_view.latest_access.to_native(),
-            archive_view.latest_access_nsecs.to_native(),
-        )
-        .ok_or(DeserializationError::from(()))
+        let (secs, nsecs) = match self.archive_view {
+            ArchiveView::V1(archive_view) => {
+                // Safety: self.archive_view is always a valid pointer after initialization
+                let archive_view = unsafe { archive_view.as_ref() };
+                (archive_view.latest_access, archive_view.latest_access_nsecs)
+            }
+            ArchiveView::V2(archive_view) => {
+                // Safety: self.archive_view is always a valid pointer after initialization
+                let archive_view = unsafe { archive_view.as_ref() };
+                (archive_view.latest_access, archive_view.latest_access_nsecs)
+            }
+        };
+        DateTime::from_timestamp(secs.to_native(), nsecs.to_native())
+            .ok_or(DeserializationError::from(()))
+    }
+
+    /// Returns the checksum of the blob, if recorded in this version of the metadata.
+    pub fn checksum(&self) -> Option<Checksum> {
+        match self.archive_view {
+            ArchiveView::V1(_) => None,
+            ArchiveView::V2(archive_view) => {
+                // Safety: self.archive_view is always a valid pointer after initialization
+                let archive_view = unsafe { archive_view.as_ref() };
+                Some(Checksum {
+                    size: archive_view.blob_size.to_native(),
+                    hash: blake3::Hash::from_bytes(archive_view.blob_hash),
+                })
+            }
+        }
+    }
+
+    /// Returns the time when the entry expires, or `None` if it does not expire.
+    pub fn expires_at(&self) -> Option<DateTime<Utc>> {
+        match self.archive_view {
+            ArchiveView::V1(_) => None,
+            ArchiveView::V2(archive_view) => {
+                // Safety: self.archive_view is always a valid pointer after initialization
+                let archive_view = unsafe { archive_view.as_ref() };
+                match archive_view.expires_at.to_native() {
+                    NO_EXPIRY => None,
+                    expires_at => DateTime::from_timestamp(expires_at, 0),
+                }
+            }
+        }
+    }
+
+    /// Returns the number of accesses of the entry, or `0` if not recorded in this version of the
+    /// metadata.
+    pub fn access_count(&self) -> u64 {
+        match self.archive_view {
+            ArchiveView::V1(_) => 0,
+            ArchiveView::V2(archive_view) => {
+                // Safety: self.archive_view is always a valid pointer after initialization
+                unsafe { archive_view.as_ref() }.access_count.to_native()
+            }
+        }
+    }
+
+    /// Returns the hash of the directory tree stored in the entry, if recorded.
+    pub fn tree_hash(&self) -> Option<blake3::Hash> {
+        match self.archive_view {
+            ArchiveView::V1(_) => None,
+            ArchiveView::V2(archive_view) => {
+                // Safety: self.archive_view is always a valid pointer after initialization
+                let archive_view = unsafe { archive_view.as_ref() };
+                (archive_view.tree_hash != NO_TREE_HASH)
+                    .then(|| blake3::Hash::from_bytes(archive_view.tree_hash))
+            }
+        }
+    }
+
+    /// Returns whether the entry has expired at the given time.
+    pub fn is_expired_at(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at()
+            .is_some_and(|expires_at| expires_at <= now)
     }
 }
 
@@ -158,12 +382,17 @@ mod tests {
 
     #[test]
     fn test_meta_roundtrip() {
-        let meta_in = Meta::new(
+        let mut meta_in = Meta::new(
             [0; BLOB_ID_SIZE],
             DateTime::parse_from_rfc3339("2025-01-24T20:47:33.123Z")
                 .unwrap()
                 .to_utc(),
         );
+        let checksum = Checksum {
+            size: 42,
+            hash: blake3::hash(b"blob"),
+        };
+        meta_in.set_checksum(&checksum);
         let data = Vec::from(meta_in.deref().as_ref());
         let meta_out = Meta::from_bytes(data).unwrap();
         assert_eq!(meta_in.blob_id(), meta_out.blob_id());
@@ -171,6 +400,80 @@ mod tests {
             meta_in.latest_access().unwrap(),
             meta_out.latest_access().unwrap()
         );
+        assert_eq!(meta_out.checksum(), Some(checksum));
+    }
+
+    #[test]
+    fn test_reads_v1_meta_without_checksum() {
+        let date = DateTime::parse_from_rfc3339("2025-01-24T20:47:33.123Z")
+            .unwrap()
+            .to_utc();
+        let data = rkyv::to_bytes::<rancor::Error>(&MetaV1::new([1; BLOB_ID_SIZE], date)).unwrap();
+        let mut meta = Meta::from_bytes(data).unwrap();
+        assert_eq!(meta.blob_id(), &[1; BLOB_ID_SIZE]);
+        assert_eq!(meta.latest_access().unwrap(), date);
+        assert_eq!(meta.checksum(), None);
+        assert_eq!(meta.expires_at(), None);
+        assert_eq!(meta.access_count(), 0);
+        assert_eq!(meta.tree_hash(), None);
+
+        let date = date.add(chrono::Duration::days(1));
+        meta.set_latest_access(date);
+        assert_eq!(meta.latest_access().unwrap(), date);
+    }
+
+    #[test]
+    fn test_can_set_expiry() {
+        let date = DateTime::parse_from_rfc3339("2025-01-24T20:47:33Z")
+            .unwrap()
+            .to_utc();
+        let mut meta = Meta::new([0; BLOB_ID_SIZE], date);
+        assert_eq!(meta.expires_at(), None);
+        assert!(!meta.is_expired_at(DateTime::<Utc>::MAX_UTC));
+
+        meta.set_expires_at(Some(date));
+        let meta = Meta::from_bytes(Vec::from(meta.deref().as_ref())).unwrap();
+        assert_eq!(meta.expires_at(), Some(date));
+        assert!(!meta.is_expired_at(date - chrono::Duration::seconds(1)));
+        assert!(meta.is_expired_at(date));
+    }
+
+    #[test]
+    fn test_can_count_accesses() {
+        let mut meta = Meta::new([0; BLOB_ID_SIZE], Utc::now());
+        assert_eq!(meta.access_count(), 0);
+
+        meta.increment_access_count();
+        meta.increment_access_count();
+        let meta = Meta::from_bytes(Vec::from(meta.deref().as_ref())).unwrap();
+        assert_eq!(meta.access_count(), 2);
+    }
+
+    #[test]
+    fn test_can_set_tree_hash() {
+        let mut meta = Meta::new([0; BLOB_ID_SIZE], Utc::now());
+        assert_eq!(meta.tree_hash(), None);
+
+        meta.set_tree_hash(Some(blake3::hash(b"tree")));
+        let mut meta = Meta::from_bytes(Vec::from(meta.deref().as_ref())).unwrap();
+        assert_eq!(meta.tree_hash(), Some(blake3::hash(b"tree")));
+
+        meta.set_tree_hash(None);
+        assert_eq!(meta.tree_hash(), None);
+    }
+
+    #[test]
+    fn test_can_set_blob_id() {
+        let mut meta = Meta::new([1; 16], Utc::now());
+        meta.set_blob_id([2; 16]);
+        assert_eq!(meta.blob_id(), &[2; 16]);
+    }
+
+    #[test]
+    fn test_rejects_unsupported_versions() {
+        assert!(Meta::from_bytes(vec![]).is_err());
+        assert!(Meta::from_bytes(vec![3, 0, 0, 0]).is_err());
+        assert!(Meta::from_bytes(vec![1, 0, 0, 0]).is_err());
     }
 
     #[test]
@@ -2,15 +2,24 @@
 //!
 //! This module defines the `Cache` trait and provides implementations of it in its submodules.
 
+use crate::cache::checksum::Checksum;
 use crate::error::IoPathResult;
 use crate::util::close::Close;
+use chrono::{DateTime, TimeDelta, Utc};
+use std::borrow::Cow;
 use std::io::{Read, Write};
 
 pub mod blob_id;
 pub mod cache_dispatcher;
+pub mod checksum;
+pub mod chunked;
+pub mod clean;
+pub mod fsck;
+mod index;
 pub mod local;
 mod meta;
 pub mod remote;
+pub mod tiered;
 
 /// A cache manages keys and associated data.
 ///
@@ -32,23 +41,97 @@ pub trait Cache {
     /// of the keys is found, `Ok(None)` is returned.
     fn get<'a>(&self, keys: &[&'a str]) -> IoPathResult<Option<CacheHit<'a, Self::Reader>>>;
 
+    /// Returns a reader for the data stored under the most recently accessed key starting with the
+    /// first given prefix that matches any key in the cache. If none of the prefixes matches a
+    /// key, `Ok(None)` is returned.
+    fn get_by_prefix(
+        &self,
+        prefixes: &[&str],
+    ) -> IoPathResult<Option<CacheHit<'static, Self::Reader>>>;
+
     /// Returns a writer for the data to be stored under all the given keys.
     ///
     /// If a key already exists, its data will be overwritten.
     ///
     /// The writer must be finalized by calling [Close::close] to make the data available
     /// atomically.
-    fn set(&self, keys: &[&str]) -> IoPathResult<Self::Writer>;
+    fn set(&self, keys: &[&str]) -> IoPathResult<Self::Writer> {
+        self.set_with_options(keys, &SetOptions::default())
+    }
+
+    /// Like [Cache::set], but if `ttl` is given, the entries expire after this duration.
+    ///
+    /// Expired entries are treated as if they did not exist and are deleted when cleaning the
+    /// cache.
+    fn set_with_ttl(&self, keys: &[&str], ttl: Option<TimeDelta>) -> IoPathResult<Self::Writer> {
+        self.set_with_options(
+            keys,
+            &SetOptions {
+                ttl,
+                ..SetOptions::default()
+            },
+        )
+    }
+
+    /// Like [Cache::set], but with the given [SetOptions] applied to the entries.
+    fn set_with_options(&self, keys: &[&str], options: &SetOptions) -> IoPathResult<Self::Writer>;
+
+    /// Returns the entries in the cache whose key starts with `prefix`, sorted by key.
+    ///
+    /// Listing the entries does not update their latest access time.
+    fn list(&self, prefix: &str) -> IoPathResult<Vec<CacheEntry>>;
+
+    /// Returns the entry stored under `key`, or `Ok(None)` if the key is not found in the cache.
+    ///
+    /// Like [Cache::list], this does not update the latest access time of the entry, and its data
+    /// is not retrieved.
+    fn entry(&self, key: &str) -> IoPathResult<Option<CacheEntry>>;
+
+    /// Deletes the given keys from the cache.
+    ///
+    /// Keys not found in the cache are ignored. The data stored under a key is deleted once no
+    /// other key refers to it anymore.
+    fn delete(&self, keys: &[&str]) -> IoPathResult<()>;
+}
+
+/// Options for storing data with [Cache::set_with_options].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SetOptions {
+    /// Duration after which the entries expire, if any. See [Cache::set_with_ttl].
+    pub ttl: Option<TimeDelta>,
+
+    /// Hash of the directory tree stored in the entries, to be returned in
+    /// [CacheEntry::tree_hash].
+    pub tree_hash: Option<blake3::Hash>,
+}
+
+/// Information about an entry in a cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheEntry {
+    /// Key of the entry.
+    pub key: String,
+
+    /// Size of the cached data in bytes.
+    pub size: u64,
+
+    /// Time of the latest access of the entry via its key.
+    pub latest_access: DateTime<Utc>,
+
+    /// Hash of the directory tree stored in the entry, if given when storing it.
+    pub tree_hash: Option<blake3::Hash>,
 }
 
 /// Returned on a successful cache get operation.
 pub struct CacheHit<'a, Reader: Read> {
     /// Cache key that was hit.
-    pub key: &'a str,
+    pub key: Cow<'a, str>,
 
     /// Reader for the cached data.
     pub reader: Reader,
 
     /// (Approximate) size of the cached data in bytes.
     pub size_hint: Option<u64>,
+
+    /// Checksum to verify the integrity of the cached data, if available.
+    pub checksum: Option<Checksum>,
 }
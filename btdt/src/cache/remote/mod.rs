@@ -0,0 +1,1120 @@
+//! Provides a remote cache implementation using HTTP.
+
+mod retry;
+
+pub use retry::RetryPolicy;
+
+use crate::cache::checksum::Checksum;
+use crate::cache::remote::RemoteCacheError::MissingCacheId;
+use crate::cache::remote::retry::{is_retryable_error, is_retryable_status, read_retry_after};
+use crate::cache::{Cache, CacheEntry, CacheHit, SetOptions};
+use crate::error::{IoPathError, IoPathResult, WithPath};
+use crate::util::close::Close;
+pub use crate::util::http;
+use crate::util::http::error::HttpClientError;
+use crate::util::http::{
+    AwaitingRequestBody, AwaitingRequestHeaders, ChunkedTransferEncoding, HttpClient, HttpRequest,
+    HttpResponse, HttpStatus, OptionTransferEncoding, ReadResponseBody, ReadResponseHeaders,
+};
+use biscuit_auth::UnverifiedBiscuit;
+use biscuit_auth::macros::block;
+use chrono::DateTime;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io;
+use std::io::{ErrorKind, Read, Seek, Write};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use url::Url;
+
+/// A remote cache that stores data via the btdt HTTP API.
+///
+/// Failed requests are retried according to the [RetryPolicy] set with
+/// [RemoteCache::with_retry_policy]. To be able to retry uploads, the uploaded data is spooled to
+/// a temporary file while it is sent, unless retries are disabled.
+#[derive(Clone)]
+pub struct RemoteCache {
+    base_url: Url,
+    cache_id: String,
+    client: HttpClient,
+    token: UnverifiedBiscuit,
+    retry_policy: RetryPolicy,
+}
+
+impl RemoteCache {
+    /// Creates a new remote cache with the given base URL, HTTP client, and authentication token.
+    pub fn new(
+        base_url: Url,
+        client: HttpClient,
+        token: UnverifiedBiscuit,
+    ) -> Result<Self, RemoteCacheError> {
+        let cache_id = base_url
+            .path_segments()
+            .ok_or(MissingCacheId)?
+            .next_back()
+            .ok_or(MissingCacheId)?
+            .to_string();
+        Ok(RemoteCache {
+            base_url,
+            cache_id,
+            client,
+            token,
+            retry_policy: RetryPolicy::default(),
+        })
+    }
+
+    /// Sets the policy for retrying failed requests.
+    ///
+    /// By default, [RetryPolicy::default] is used.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+}
+
+/// An error that can occur when using the remote cache.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum RemoteCacheError {
+    /// The provided URL does not contain a cache ID.
+    MissingCacheId,
+    /// An HTTP error occurred.
+    HttpError {
+        /// The HTTP status code.
+        status: u16,
+    },
+}
+
+impl Display for RemoteCacheError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingCacheId => write!(f, "missing cache ID in URL"),
+            Self::HttpError { status } => write!(f, "http error: {status}"),
+        }
+    }
+}
+
+impl Error for RemoteCacheError {}
+
+/// A cache writer writing to the remote cache.
+///
+/// The data is streamed to the server while it is written. If retries are enabled, it is also
+/// spooled to a temporary file, so that it can be sent again if the initial request fails.
+pub struct RemoteWriter {
+    cache: RemoteCache,
+    url: Url,
+    /// Request of the initial attempt, or `None` if it failed with a retryable error.
+    request: Option<HttpRequest<AwaitingRequestBody<ChunkedTransferEncoding>>>,
+    spool: Option<File>,
+}
+
+impl RemoteWriter {
+    /// Handles an error of the initial request. Returns the error if it cannot be retried.
+    fn handle_request_error<T>(&mut self, result: http::Result<T>) -> io::Result<Option<T>> {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(err) if self.spool.is_some() && is_retryable_error(&err) => {
+                self.request = None;
+                Ok(None)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Sends the spooled data in a new request.
+    fn resend(&self) -> http::Result<(HttpStatus, HttpResponse<ReadResponseHeaders>)> {
+        let mut spool = self.spool.as_ref().expect("retries require a spool");
+        spool.rewind()?;
+        let size = spool.metadata()?.len();
+        let mut request = self.cache.client.put(&self.url)?;
+        self.cache
+            .add_auth_header(&mut request, Operation::Put, &self.cache.cache_id)?;
+        let mut request = request.body_with_size(size as usize)?;
+        io::copy(&mut spool, &mut request)?;
+        request.response()?.read_status()
+    }
+}
+
+impl Write for RemoteWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(spool) = &mut self.spool else {
+            return match &mut self.request {
+                Some(request) => request.write(buf),
+                None => unreachable!("request can only fail if retries are enabled"),
+            };
+        };
+        spool.write_all(buf)?;
+        if let Some(request) = &mut self.request {
+            let result = request.write_all(buf).map_err(HttpClientError::from);
+            self.handle_request_error(result)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(request) = &mut self.request {
+            let result = request.flush().map_err(HttpClientError::from);
+            self.handle_request_error(result)?;
+        }
+        Ok(())
+    }
+}
+
+impl Close for RemoteWriter {
+    fn close(mut self) -> io::Result<()> {
+        let mut retry_after = None;
+        if let Some(request) = self.request.take() {
+            let result = request
+                .response()
+                .and_then(|response| response.read_status());
+            match self.handle_request_error(result)? {
+                Some((status, _)) if status.is_success() => return Ok(()),
+                Some((status, mut response))
+                    if self.spool.is_some() && is_retryable_status(status.code_u16()) =>
+                {
+                    retry_after = read_retry_after(&mut response);
+                }
+                Some((status, _)) => {
+                    return Err(io::Error::other(RemoteCacheError::HttpError {
+                        status: status.code_u16(),
+                    }));
+                }
+                None => {}
+            }
+        }
+
+        thread::sleep(self.cache.retry_policy.backoff(0, retry_after));
+        let (status, _) = self
+            .cache
+            .send_with_retries(1, || self.resend())
+            .map_err(Into::<io::Error>::into)?;
+        if !status.is_success() {
+            return Err(io::Error::other(RemoteCacheError::HttpError {
+                status: status.code_u16(),
+            }));
+        }
+
+        Ok(())
+    }
+}
+
+impl Cache for RemoteCache {
+    type Reader = HttpResponse<ReadResponseBody>;
+    type Writer = RemoteWriter;
+
+    fn get<'a>(&self, keys: &[&'a str]) -> IoPathResult<Option<CacheHit<'a, Self::Reader>>> {
+        if keys.is_empty() {
+            return Ok(None);
+        }
+        let mut url = self.base_url.clone();
+        for key in keys {
+            url.query_pairs_mut().append_pair("key", key);
+        }
+        self.get_from_url(&url, |hit_key| {
+            keys.iter()
+                .find(|&&key| key == hit_key)
+                .map(|&key| Cow::Borrowed(key))
+        })
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefixes: &[&str],
+    ) -> IoPathResult<Option<CacheHit<'static, Self::Reader>>> {
+        if prefixes.is_empty() {
+            return Ok(None);
+        }
+        let mut url = self.base_url.clone();
+        for prefix in prefixes {
+            url.query_pairs_mut().append_pair("prefix", prefix);
+        }
+        self.get_from_url(&url, |hit_key| {
+            prefixes
+                .iter()
+                .any(|prefix| hit_key.starts_with(prefix))
+                .then(|| Cow::Owned(hit_key.to_string()))
+        })
+    }
+
+    fn set_with_options(&self, keys: &[&str], options: &SetOptions) -> IoPathResult<Self::Writer> {
+        let mut url = self.base_url.clone();
+        for key in keys {
+            url.query_pairs_mut().append_pair("key", key);
+        }
+        if let Some(ttl) = options.ttl {
+            url.query_pairs_mut()
+                .append_pair("ttl", &ttl.num_seconds().to_string());
+        }
+        if let Some(tree_hash) = options.tree_hash {
+            url.query_pairs_mut()
+                .append_pair("tree_hash", &tree_hash.to_hex());
+        }
+
+        let try_request = || {
+            let mut request = self.client.put(&url)?;
+            self.add_auth_header(&mut request, Operation::Put, &self.cache_id)?;
+            request.body()
+        };
+        let spool = if self.retry_policy.max_retries > 0 {
+            Some(tempfile::tempfile().with_path(url.as_str())?)
+        } else {
+            None
+        };
+        let mut writer = RemoteWriter {
+            cache: self.clone(),
+            url: url.clone(),
+            request: None,
+            spool,
+        };
+        writer.request = writer
+            .handle_request_error(try_request())
+            .with_path(url.as_str())?;
+        Ok(writer)
+    }
+
+    fn list(&self, prefix: &str) -> IoPathResult<Vec<CacheEntry>> {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .expect("base URL was checked to have path segments")
+            .pop_if_empty()
+            .push("keys");
+        if !prefix.is_empty() {
+            url.query_pairs_mut().append_pair("prefix", prefix);
+        }
+
+        let try_request = || {
+            let mut request = self.client.get(&url)?;
+            self.add_auth_header(&mut request, Operation::List, &self.cache_id)?;
+            request.no_body()?.read_status()
+        };
+        let (status, response) = self
+            .send_with_retries(0, try_request)
+            .map_err(HttpClientError::into)
+            .with_path(url.as_str())?;
+
+        if !status.is_success() {
+            return Err(IoPathError::new_no_path(io::Error::other(
+                RemoteCacheError::HttpError {
+                    status: status.code_u16(),
+                },
+            )));
+        }
+
+        let entries: Vec<ListedEntry> = read_json_body(response, &url)?;
+        entries
+            .into_iter()
+            .map(|entry| entry.into_cache_entry(&url))
+            .collect()
+    }
+
+    fn entry(&self, key: &str) -> IoPathResult<Option<CacheEntry>> {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .expect("base URL was checked to have path segments")
+            .pop_if_empty()
+            .push("entry");
+        url.query_pairs_mut().append_pair("key", key);
+
+        let try_request = || {
+            let mut request = self.client.get(&url)?;
+            self.add_auth_header(&mut request, Operation::Get, &self.cache_id)?;
+            request.no_body()?.read_status()
+        };
+        let (status, response) = self
+            .send_with_retries(0, try_request)
+            .map_err(HttpClientError::into)
+            .with_path(url.as_str())?;
+
+        if !status.is_success() {
+            return Err(IoPathError::new_no_path(io::Error::other(
+                RemoteCacheError::HttpError {
+                    status: status.code_u16(),
+                },
+            )));
+        }
+
+        if status.code() == "204" {
+            return Ok(None);
+        }
+
+        let entry: ListedEntry = read_json_body(response, &url)?;
+        entry.into_cache_entry(&url).map(Some)
+    }
+
+    fn delete(&self, keys: &[&str]) -> IoPathResult<()> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+
+        let mut url = self.base_url.clone();
+        for key in keys {
+            url.query_pairs_mut().append_pair("key", key);
+        }
+
+        let try_request = || {
+            let mut request = self.client.delete(&url)?;
+            self.add_auth_header(&mut request, Operation::Delete, &self.cache_id)?;
+            request.no_body()?.read_status()
+        };
+        let (status, _) = self
+            .send_with_retries(0, try_request)
+            .map_err(HttpClientError::into)
+            .with_path(url.as_str())?;
+
+        if !status.is_success() {
+            return Err(IoPathError::new_no_path(io::Error::other(
+                RemoteCacheError::HttpError {
+                    status: status.code_u16(),
+                },
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// A cache entry as returned by the list and entry endpoints of the btdt HTTP API.
+#[derive(Deserialize)]
+struct ListedEntry {
+    key: String,
+    size: u64,
+    latest_access: String,
+    /// Hex encoded, not returned by older versions of the server.
+    #[serde(default)]
+    tree_hash: Option<String>,
+}
+
+impl ListedEntry {
+    fn into_cache_entry(self, url: &Url) -> IoPathResult<CacheEntry> {
+        Ok(CacheEntry {
+            latest_access: DateTime::parse_from_rfc3339(&self.latest_access)
+                .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))
+                .with_path(url.as_str())?
+                .to_utc(),
+            tree_hash: self
+                .tree_hash
+                .map(blake3::Hash::from_hex)
+                .transpose()
+                .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))
+                .with_path(url.as_str())?,
+            key: self.key,
+            size: self.size,
+        })
+    }
+}
+
+/// Reads the body of `response` and deserializes it from JSON.
+fn read_json_body<T: DeserializeOwned>(
+    response: HttpResponse<ReadResponseHeaders>,
+    url: &Url,
+) -> IoPathResult<T> {
+    let mut body = String::new();
+    response
+        .read_body()
+        .map_err(HttpClientError::into)
+        .with_path(url.as_str())?
+        .read_to_string(&mut body)
+        .with_path(url.as_str())?;
+    serde_json::from_str(&body)
+        .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))
+        .with_path(url.as_str())
+}
+
+enum Operation {
+    Get,
+    Put,
+    List,
+    Delete,
+}
+
+impl AsRef<str> for Operation {
+    fn as_ref(&self) -> &str {
+        match self {
+            Operation::Get => "get",
+            Operation::Put => "put",
+            Operation::List => "list",
+            Operation::Delete => "delete",
+        }
+    }
+}
+
+impl RemoteCache {
+    /// Sends a get request to `url` and returns the resulting cache hit, if any.
+    ///
+    /// The key returned by the server is validated with `match_key` which returns `None` if the
+    /// key does not correspond to the request.
+    fn get_from_url<'a>(
+        &self,
+        url: &Url,
+        match_key: impl Fn(&str) -> Option<Cow<'a, str>>,
+    ) -> IoPathResult<Option<CacheHit<'a, <Self as Cache>::Reader>>> {
+        let try_request = || {
+            let mut request = self.client.get(url)?;
+            self.add_auth_header(&mut request, Operation::Get, &self.cache_id)?;
+            request.no_body()?.read_status()
+        };
+        let (status, mut response) = self
+            .send_with_retries(0, try_request)
+            .map_err(HttpClientError::into)
+            .with_path(url.as_str())?;
+
+        if !status.is_success() {
+            return Err(IoPathError::new_no_path(io::Error::other(
+                RemoteCacheError::HttpError {
+                    status: status.code_u16(),
+                },
+            )));
+        }
+
+        if status.code() == "204" {
+            return Ok(None);
+        }
+
+        let mut size_hint = None;
+        let mut hit_key = None;
+        let mut content_size = None;
+        let mut content_hash = None;
+        while let Some(header) = response
+            .read_next_header()
+            .map_err(HttpClientError::into)
+            .with_path(url.as_str())?
+        {
+            if size_hint.is_none() && header.key().eq_ignore_ascii_case("content-length") {
+                size_hint = header.value().parse::<u64>().ok();
+            }
+            if hit_key.is_none() && header.key().eq_ignore_ascii_case("btdt-cache-key") {
+                hit_key = match_key(header.value());
+            }
+            if content_size.is_none() && header.key().eq_ignore_ascii_case("btdt-content-size") {
+                content_size = Some(
+                    header
+                        .value()
+                        .parse::<u64>()
+                        .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))
+                        .with_path(url.as_str())?,
+                );
+            }
+            if content_hash.is_none() && header.key().eq_ignore_ascii_case("btdt-content-blake3") {
+                content_hash = Some(
+                    blake3::Hash::from_hex(header.value())
+                        .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))
+                        .with_path(url.as_str())?,
+                );
+            }
+        }
+        let reader = response
+            .read_body()
+            .map_err(HttpClientError::into)
+            .with_path(url.as_str())?;
+
+        let checksum = content_size
+            .zip(content_hash)
+            .map(|(size, hash)| Checksum { size, hash });
+        Ok(Some(CacheHit {
+            checksum,
+            key: hit_key
+                .ok_or_else(|| {
+                    io::Error::new(
+                        ErrorKind::InvalidData,
+                        "missing Btdt-Cache-Key header in response",
+                    )
+                })
+                .with_path(url.as_str())?,
+            size_hint,
+            reader,
+        }))
+    }
+
+    /// Sends a request with `send`, retrying it according to the retry policy.
+    ///
+    /// `retries` is the number of retries that already happened. The response of the last attempt
+    /// is returned, which might have a retryable status if all retries failed.
+    fn send_with_retries(
+        &self,
+        mut retries: u32,
+        send: impl Fn() -> http::Result<(HttpStatus, HttpResponse<ReadResponseHeaders>)>,
+    ) -> http::Result<(HttpStatus, HttpResponse<ReadResponseHeaders>)> {
+        loop {
+            let retry_after = match send() {
+                Ok((status, mut response))
+                    if retries < self.retry_policy.max_retries
+                        && is_retryable_status(status.code_u16()) =>
+                {
+                    read_retry_after(&mut response)
+                }
+                Err(err) if retries < self.retry_policy.max_retries && is_retryable_error(&err) => {
+                    None
+                }
+                result => return result,
+            };
+            thread::sleep(self.retry_policy.backoff(retries, retry_after));
+            retries += 1;
+        }
+    }
+
+    fn add_auth_header<T: OptionTransferEncoding>(
+        &self,
+        request: &mut HttpRequest<AwaitingRequestHeaders<T>>,
+        operation: Operation,
+        cache_id: &str,
+    ) -> http::Result<()> {
+        let expiration = SystemTime::now()
+            .checked_add(Duration::from_secs(5 * 60))
+            .expect("time overflow");
+        request.header(
+            "Authorization",
+            &format!(
+                "Bearer {}",
+                self.token
+                    .append(block!(
+                        "\
+                            check if operation({operation});\
+                            check if cache({cache});\
+                            check if time($time), $time < {expiration};\
+                        ",
+                        operation = operation.as_ref(),
+                        cache = cache_id,
+                        expiration = expiration,
+                    ))
+                    .unwrap()
+                    .to_base64()
+                    .unwrap()
+            ),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::http::tests::{EMPTY_RESPONSE, TestServer};
+    use super::*;
+    use biscuit_auth::KeyPair;
+    use biscuit_auth::macros::biscuit;
+    use chrono::TimeDelta;
+    use std::io;
+    use std::io::Read;
+
+    fn auth_token() -> UnverifiedBiscuit {
+        UnverifiedBiscuit::from(
+            biscuit!("")
+                .build(&KeyPair::new())
+                .unwrap()
+                .to_vec()
+                .unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_get_returns_none_for_empty_keys() {
+        let test_server = TestServer::start(EMPTY_RESPONSE.into()).unwrap();
+        let cache = RemoteCache::new(
+            test_server.base_url().join("api/caches/cache-id").unwrap(),
+            HttpClient::default().unwrap(),
+            auth_token(),
+        )
+        .unwrap();
+        assert!(cache.get(&[]).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_get_returns_non_for_cache_miss() -> io::Result<()> {
+        let test_server = TestServer::start(EMPTY_RESPONSE.into()).unwrap();
+        let addr = test_server.addr();
+        let cache = RemoteCache::new(
+            test_server.base_url().join("api/caches/cache-id").unwrap(),
+            HttpClient::default().unwrap(),
+            auth_token(),
+        )
+        .unwrap();
+        assert!(cache.get(&["non-existent"])?.is_none());
+
+        assert_eq!(
+            test_server.request()?,
+            format!(
+                "\
+                GET /api/caches/cache-id?key=non-existent HTTP/1.1\r\n\
+                Host: {}\r\n\
+                Connection: close\r\n\
+                User-Agent: btdt/{}\r\n\
+                Authorization: <auth-header-value>\r\n\r\n\
+            ",
+                addr,
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_returns_checksum_from_headers() -> io::Result<()> {
+        let hash = blake3::hash(b"Hello!\r\n");
+        let test_server = TestServer::start(format!(
+            "HTTP/1.1 200 Ok\r\nBtdt-Cache-Key: existent\r\nBtdt-Content-Size: 8\r\n\
+            Btdt-Content-Blake3: {hash}\r\nContent-Length: 8\r\n\r\nHello!\r\n"
+        ))
+        .unwrap();
+        let cache = RemoteCache::new(
+            test_server.base_url().join("api/caches/cache-id").unwrap(),
+            HttpClient::default().unwrap(),
+            auth_token(),
+        )
+        .unwrap();
+        let cache_hit = cache.get(&["existent"])?.unwrap();
+        assert_eq!(cache_hit.checksum, Some(Checksum { size: 8, hash }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_returns_data_for_cache_hit() -> io::Result<()> {
+        let test_server = TestServer::start(
+            "HTTP/1.1 200 Ok\r\nBtdt-Cache-Key: existent\r\nContent-Length: 8\r\n\r\nHello!\r\n"
+                .into(),
+        )
+        .unwrap();
+        let addr = test_server.addr();
+        let cache = RemoteCache::new(
+            test_server.base_url().join("api/caches/cache-id").unwrap(),
+            HttpClient::default().unwrap(),
+            auth_token(),
+        )
+        .unwrap();
+        let CacheHit {
+            key,
+            size_hint,
+            mut reader,
+            checksum,
+        } = cache.get(&["non-existent", "existent"])?.unwrap();
+        assert_eq!(key, "existent");
+        assert_eq!(size_hint, Some(8));
+        assert_eq!(checksum, None);
+
+        let mut buf = String::new();
+        reader.read_to_string(&mut buf)?;
+        assert_eq!(buf, "Hello!\r\n");
+
+        assert_eq!(
+            test_server.request()?,
+            format!(
+                "\
+                GET /api/caches/cache-id?key=non-existent&key=existent HTTP/1.1\r\n\
+                Host: {}\r\n\
+                Connection: close\r\n\
+                User-Agent: btdt/{}\r\n\
+                Authorization: <auth-header-value>\r\n\r\n\
+            ",
+                addr,
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_by_prefix_returns_data_for_cache_hit() -> io::Result<()> {
+        let test_server = TestServer::start(
+            "HTTP/1.1 200 Ok\r\nBtdt-Cache-Key: prefix-key\r\nContent-Length: 8\r\n\r\nHello!\r\n"
+                .into(),
+        )
+        .unwrap();
+        let addr = test_server.addr();
+        let cache = RemoteCache::new(
+            test_server.base_url().join("api/caches/cache-id").unwrap(),
+            HttpClient::default().unwrap(),
+            auth_token(),
+        )
+        .unwrap();
+        let CacheHit {
+            key,
+            size_hint,
+            mut reader,
+            ..
+        } = cache.get_by_prefix(&["other-", "prefix-"])?.unwrap();
+        assert_eq!(key, "prefix-key");
+        assert_eq!(size_hint, Some(8));
+
+        let mut buf = String::new();
+        reader.read_to_string(&mut buf)?;
+        assert_eq!(buf, "Hello!\r\n");
+
+        assert_eq!(
+            test_server.request()?,
+            format!(
+                "\
+                GET /api/caches/cache-id?prefix=other-&prefix=prefix- HTTP/1.1\r\n\
+                Host: {}\r\n\
+                Connection: close\r\n\
+                User-Agent: btdt/{}\r\n\
+                Authorization: <auth-header-value>\r\n\r\n\
+            ",
+                addr,
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_by_prefix_rejects_key_not_matching_prefix() {
+        let test_server = TestServer::start(
+            "HTTP/1.1 200 Ok\r\nBtdt-Cache-Key: other-key\r\nContent-Length: 0\r\n\r\n".into(),
+        )
+        .unwrap();
+        let cache = RemoteCache::new(
+            test_server.base_url().join("api/caches/cache-id").unwrap(),
+            HttpClient::default().unwrap(),
+            auth_token(),
+        )
+        .unwrap();
+        let error = cache.get_by_prefix(&["prefix-"]).err().unwrap();
+        assert_eq!(error.io_error().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_get_returns_error_for_non_success_http_status() -> io::Result<()> {
+        let test_server =
+            TestServer::start("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".into())
+                .unwrap();
+        let cache = RemoteCache::new(
+            test_server.base_url().join("api/caches/cache-id").unwrap(),
+            HttpClient::default().unwrap(),
+            auth_token(),
+        )
+        .unwrap();
+        let error = cache.get(&["non-existent"]).err().unwrap().into_io_error();
+        match *error
+            .into_inner()
+            .unwrap()
+            .downcast::<RemoteCacheError>()
+            .unwrap()
+        {
+            RemoteCacheError::HttpError { status } => {
+                assert_eq!(status, 404);
+            }
+            _ => panic!("unexpected error type"),
+        }
+        Ok(())
+    }
+
+    const SERVICE_UNAVAILABLE_RESPONSE: &str =
+        "HTTP/1.1 503 Service Unavailable\r\nRetry-After: 0\r\nContent-Length: 0\r\n\r\n";
+
+    fn fast_retry_policy(max_retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_retries,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(10),
+            jitter: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_get_retries_retryable_http_status() -> io::Result<()> {
+        let test_server = TestServer::start_with_responses(vec![
+            SERVICE_UNAVAILABLE_RESPONSE.into(),
+            "HTTP/1.1 200 Ok\r\nBtdt-Cache-Key: key\r\nContent-Length: 9\r\n\r\nTest data".into(),
+        ])
+        .unwrap();
+        let cache = RemoteCache::new(
+            test_server.base_url().join("api/caches/cache-id").unwrap(),
+            HttpClient::default().unwrap(),
+            auth_token(),
+        )
+        .unwrap()
+        .with_retry_policy(fast_retry_policy(1));
+
+        let mut buf = String::new();
+        cache
+            .get(&["key"])?
+            .unwrap()
+            .reader
+            .read_to_string(&mut buf)?;
+        assert_eq!(buf, "Test data");
+        assert_eq!(test_server.requests()?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_returns_error_once_retries_are_exhausted() {
+        let test_server = TestServer::start_with_responses(vec![
+            SERVICE_UNAVAILABLE_RESPONSE.into(),
+            SERVICE_UNAVAILABLE_RESPONSE.into(),
+        ])
+        .unwrap();
+        let cache = RemoteCache::new(
+            test_server.base_url().join("api/caches/cache-id").unwrap(),
+            HttpClient::default().unwrap(),
+            auth_token(),
+        )
+        .unwrap()
+        .with_retry_policy(fast_retry_policy(1));
+
+        let error = cache.get(&["key"]).err().unwrap().into_io_error();
+        assert!(matches!(
+            *error
+                .into_inner()
+                .unwrap()
+                .downcast::<RemoteCacheError>()
+                .unwrap(),
+            RemoteCacheError::HttpError { status: 503 }
+        ));
+    }
+
+    #[test]
+    fn test_set_resends_data_after_retryable_http_status() -> io::Result<()> {
+        let test_server = TestServer::start_with_responses(vec![
+            SERVICE_UNAVAILABLE_RESPONSE.into(),
+            EMPTY_RESPONSE.into(),
+        ])
+        .unwrap();
+        let cache = RemoteCache::new(
+            test_server.base_url().join("api/caches/cache-id").unwrap(),
+            HttpClient::default().unwrap(),
+            auth_token(),
+        )
+        .unwrap()
+        .with_retry_policy(fast_retry_policy(1));
+
+        let mut writer = cache.set(&["key"])?;
+        writer.write_all(b"Test data")?;
+        writer.close()?;
+
+        let requests = test_server.requests()?;
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].ends_with("Transfer-Encoding: chunked\r\n\r\nTest data"));
+        assert!(requests[1].starts_with("PUT /api/caches/cache-id?key=key HTTP/1.1\r\n"));
+        assert!(requests[1].ends_with("Content-Length: 9\r\n\r\nTest data"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_returns_entries() -> io::Result<()> {
+        let body = r#"[{"key":"prefix-key","size":42,"latest_access":"2025-01-02T03:04:05Z"}]"#;
+        let test_server = TestServer::start(format!(
+            "HTTP/1.1 200 Ok\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        ))
+        .unwrap();
+        let addr = test_server.addr();
+        let cache = RemoteCache::new(
+            test_server.base_url().join("api/caches/cache-id").unwrap(),
+            HttpClient::default().unwrap(),
+            auth_token(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            cache.list("prefix-")?,
+            vec![CacheEntry {
+                key: "prefix-key".to_string(),
+                size: 42,
+                latest_access: DateTime::parse_from_rfc3339("2025-01-02T03:04:05Z")
+                    .unwrap()
+                    .to_utc(),
+                tree_hash: None,
+            }]
+        );
+
+        assert_eq!(
+            test_server.request()?,
+            format!(
+                "\
+                GET /api/caches/cache-id/keys?prefix=prefix- HTTP/1.1\r\n\
+                Host: {}\r\n\
+                Connection: close\r\n\
+                User-Agent: btdt/{}\r\n\
+                Authorization: <auth-header-value>\r\n\r\n\
+            ",
+                addr,
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_entry_returns_entry() -> io::Result<()> {
+        let tree_hash = blake3::hash(b"tree");
+        let body = format!(
+            r#"{{"key":"some-key","size":42,"latest_access":"2025-01-02T03:04:05Z","tree_hash":"{}"}}"#,
+            tree_hash.to_hex()
+        );
+        let test_server = TestServer::start(format!(
+            "HTTP/1.1 200 Ok\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        ))
+        .unwrap();
+        let addr = test_server.addr();
+        let cache = RemoteCache::new(
+            test_server.base_url().join("api/caches/cache-id").unwrap(),
+            HttpClient::default().unwrap(),
+            auth_token(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            cache.entry("some-key")?,
+            Some(CacheEntry {
+                key: "some-key".to_string(),
+                size: 42,
+                latest_access: DateTime::parse_from_rfc3339("2025-01-02T03:04:05Z")
+                    .unwrap()
+                    .to_utc(),
+                tree_hash: Some(tree_hash),
+            })
+        );
+
+        assert_eq!(
+            test_server.request()?,
+            format!(
+                "\
+                GET /api/caches/cache-id/entry?key=some-key HTTP/1.1\r\n\
+                Host: {}\r\n\
+                Connection: close\r\n\
+                User-Agent: btdt/{}\r\n\
+                Authorization: <auth-header-value>\r\n\r\n\
+            ",
+                addr,
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_entry_returns_none_for_non_existent_key() -> io::Result<()> {
+        let test_server = TestServer::start(EMPTY_RESPONSE.into()).unwrap();
+        let cache = RemoteCache::new(
+            test_server.base_url().join("api/caches/cache-id").unwrap(),
+            HttpClient::default().unwrap(),
+            auth_token(),
+        )
+        .unwrap();
+
+        assert_eq!(cache.entry("non-existent")?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_sends_keys_to_remote_cache() -> io::Result<()> {
+        let test_server = TestServer::start(EMPTY_RESPONSE.into()).unwrap();
+        let addr = test_server.addr();
+        let cache = RemoteCache::new(
+            test_server.base_url().join("api/caches/cache-id").unwrap(),
+            HttpClient::default().unwrap(),
+            auth_token(),
+        )
+        .unwrap();
+
+        cache.delete(&["key1", "key2"])?;
+
+        assert_eq!(
+            test_server.request()?,
+            format!(
+                "\
+                DELETE /api/caches/cache-id?key=key1&key=key2 HTTP/1.1\r\n\
+                Host: {}\r\n\
+                Connection: close\r\n\
+                User-Agent: btdt/{}\r\n\
+                Authorization: <auth-header-value>\r\n\r\n\
+            ",
+                addr,
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_sends_data_to_remote_cache() -> io::Result<()> {
+        let test_server = TestServer::start(EMPTY_RESPONSE.into()).unwrap();
+        let addr = test_server.addr();
+        let cache = RemoteCache::new(
+            test_server.base_url().join("api/caches/cache-id").unwrap(),
+            HttpClient::default().unwrap(),
+            auth_token(),
+        )
+        .unwrap();
+        let mut writer = cache.set(&["key1", "key2"])?;
+
+        writer.write_all(b"Test data")?;
+        writer.close()?;
+
+        assert_eq!(
+            test_server.request()?,
+            format!(
+                "\
+                PUT /api/caches/cache-id?key=key1&key=key2 HTTP/1.1\r\n\
+                Host: {}\r\n\
+                Connection: close\r\n\
+                User-Agent: btdt/{}\r\n\
+                Authorization: <auth-header-value>\r\n\
+                Transfer-Encoding: chunked\r\n\
+                \r\n\
+                Test data",
+                addr,
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_with_ttl_sends_ttl_to_remote_cache() -> io::Result<()> {
+        let test_server = TestServer::start(EMPTY_RESPONSE.into()).unwrap();
+        let cache = RemoteCache::new(
+            test_server.base_url().join("api/caches/cache-id").unwrap(),
+            HttpClient::default().unwrap(),
+            auth_token(),
+        )
+        .unwrap();
+        let writer = cache.set_with_ttl(&["key"], Some(TimeDelta::days(1)))?;
+        writer.close()?;
+
+        assert!(
+            test_server
+                .request()?
+                .starts_with("PUT /api/caches/cache-id?key=key&ttl=86400 HTTP/1.1\r\n")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_with_options_sends_tree_hash_to_remote_cache() -> io::Result<()> {
+        let test_server = TestServer::start(EMPTY_RESPONSE.into()).unwrap();
+        let cache = RemoteCache::new(
+            test_server.base_url().join("api/caches/cache-id").unwrap(),
+            HttpClient::default().unwrap(),
+            auth_token(),
+        )
+        .unwrap();
+        let tree_hash = blake3::hash(b"tree");
+        let writer = cache.set_with_options(
+            &["key"],
+            &SetOptions {
+                tree_hash: Some(tree_hash),
+                ..SetOptions::default()
+            },
+        )?;
+        writer.close()?;
+
+        assert!(test_server.request()?.starts_with(&format!(
+            "PUT /api/caches/cache-id?key=key&tree_hash={} HTTP/1.1\r\n",
+            tree_hash.to_hex()
+        )));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,193 @@
+//! Retrying of failed requests to the remote cache.
+
+use crate::util::http::HttpResponse;
+use crate::util::http::ReadResponseHeaders;
+use crate::util::http::error::HttpClientError;
+use chrono::{DateTime, Utc};
+use std::io::ErrorKind;
+use std::time::Duration;
+
+/// Defines how often and when failed requests to a remote cache are retried.
+///
+/// Requests are retried on connection errors and on the HTTP status codes 408, 429, 502, 503, and
+/// 504. The delay before a retry doubles with each retry, starting at `initial_backoff` and capped
+/// at `max_backoff`. If the server responds with a `Retry-After` header, its value is used instead,
+/// but it is also capped at `max_backoff`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Maximum number of retries after the initial attempt.
+    pub max_retries: u32,
+
+    /// Delay before the first retry.
+    pub initial_backoff: Duration,
+
+    /// Maximum delay before a retry.
+    pub max_backoff: Duration,
+
+    /// Fraction of the delay (between 0 and 1) that is randomized to avoid many clients retrying
+    /// at the same time. With a jitter of 0.5, the delay is chosen randomly between half and the
+    /// full backoff.
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            jitter: 0.5,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries.
+    pub const NONE: Self = Self {
+        max_retries: 0,
+        initial_backoff: Duration::ZERO,
+        max_backoff: Duration::ZERO,
+        jitter: 0.0,
+    };
+
+    /// Returns the delay before the retry following `retries` previous retries.
+    pub(crate) fn backoff(&self, retries: u32, retry_after: Option<Duration>) -> Duration {
+        if let Some(retry_after) = retry_after {
+            return retry_after.min(self.max_backoff);
+        }
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(retries))
+            .min(self.max_backoff);
+        let jitter = self.jitter.clamp(0.0, 1.0) * rand::random::<f64>();
+        backoff.mul_f64(1.0 - jitter)
+    }
+}
+
+/// Returns whether a response with the given status code should be retried.
+pub(crate) fn is_retryable_status(status: u16) -> bool {
+    matches!(status, 408 | 429 | 502 | 503 | 504)
+}
+
+/// Returns whether a request failing with `err` should be retried.
+pub(crate) fn is_retryable_error(err: &HttpClientError) -> bool {
+    match err {
+        HttpClientError::IoError(err) => matches!(
+            err.kind(),
+            ErrorKind::ConnectionRefused
+                | ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::NotConnected
+                | ErrorKind::BrokenPipe
+                | ErrorKind::TimedOut
+                | ErrorKind::UnexpectedEof
+        ),
+        HttpClientError::Timeout(_) => true,
+        _ => false,
+    }
+}
+
+/// Reads the headers of `response` and returns the delay requested by a `Retry-After` header.
+pub(crate) fn read_retry_after(
+    response: &mut HttpResponse<ReadResponseHeaders>,
+) -> Option<Duration> {
+    let mut retry_after = None;
+    while let Ok(Some(header)) = response.read_next_header() {
+        if header.key().eq_ignore_ascii_case("retry-after") {
+            retry_after = parse_retry_after(header.value(), Utc::now());
+        }
+    }
+    retry_after
+}
+
+/// Parses the value of a `Retry-After` header, which is either a number of seconds or an HTTP date.
+fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some((date.to_utc() - now).to_std().unwrap_or(Duration::ZERO))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::http::error::TimeoutKind;
+    use std::io;
+
+    #[test]
+    fn test_backoff_grows_exponentially_up_to_max_backoff() {
+        let policy = RetryPolicy {
+            max_retries: 10,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5),
+            jitter: 0.0,
+        };
+        let backoffs: Vec<_> = (0..5)
+            .map(|retries| policy.backoff(retries, None))
+            .collect();
+        assert_eq!(backoffs, [1, 2, 4, 5, 5].map(Duration::from_secs).to_vec());
+    }
+
+    #[test]
+    fn test_backoff_applies_jitter() {
+        let policy = RetryPolicy {
+            jitter: 0.5,
+            ..RetryPolicy::default()
+        };
+        for _ in 0..100 {
+            let backoff = policy.backoff(1, None);
+            assert!(
+                (Duration::from_millis(500)..=Duration::from_secs(1)).contains(&backoff),
+                "backoff {backoff:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_backoff_uses_capped_retry_after() {
+        let policy = RetryPolicy::default();
+        assert_eq!(
+            policy.backoff(0, Some(Duration::from_secs(7))),
+            Duration::from_secs(7)
+        );
+        assert_eq!(
+            policy.backoff(0, Some(Duration::from_secs(3600))),
+            policy.max_backoff
+        );
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = DateTime::parse_from_rfc3339("2015-10-21T07:28:00Z")
+            .unwrap()
+            .to_utc();
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:30 GMT", now),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:27:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn test_is_retryable_error() {
+        assert!(is_retryable_error(&HttpClientError::IoError(
+            ErrorKind::ConnectionRefused.into()
+        )));
+        assert!(!is_retryable_error(&HttpClientError::IoError(
+            io::Error::new(ErrorKind::InvalidData, "malformed response")
+        )));
+        assert!(is_retryable_error(&HttpClientError::Timeout(
+            TimeoutKind::Read
+        )));
+        assert!(!is_retryable_error(&HttpClientError::MissingHost));
+    }
+}
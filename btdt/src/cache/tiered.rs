@@ -0,0 +1,448 @@
+//! Provides a cache combining a fast local tier with a slower remote tier.
+
+use super::checksum::VerifyingReader;
+use super::{Cache, CacheEntry, CacheHit, SetOptions};
+use crate::error::IoPathResult;
+use crate::util::close::Close;
+use std::collections::BTreeMap;
+use std::io;
+use std::io::{Read, Write};
+
+/// A cache that checks a local tier before falling back to a remote tier.
+///
+/// Data retrieved from the remote tier is stored in the local tier while it is read, so that
+/// subsequent retrievals are served locally. Populating the local tier is best effort: if it fails,
+/// the data is still read from the remote tier. The local tier is only populated once the data
+/// has been read completely and was verified against its checksum.
+///
+/// Data is written through to both tiers, and keys are deleted from both tiers.
+///
+/// # Examples
+///
+/// ```rust
+/// # use std::io;
+/// use std::io::{Read, Write};
+/// use btdt::cache::Cache;
+/// use btdt::cache::local::LocalCache;
+/// use btdt::cache::tiered::TieredCache;
+/// use btdt::storage::in_memory::InMemoryStorage;
+/// use btdt::util::close::Close;
+///
+/// # fn main() -> io::Result<()> {
+/// let remote = LocalCache::new(InMemoryStorage::new());
+/// let mut writer = remote.set(&["cache-key"])?;
+/// writer.write_all(b"Hello, world!")?;
+/// writer.close()?;
+///
+/// let cache = TieredCache::new(LocalCache::new(InMemoryStorage::new()), remote);
+/// let mut buf = String::new();
+/// cache.get(&["cache-key"])?.unwrap().reader.read_to_string(&mut buf)?;
+/// assert_eq!(buf, "Hello, world!");
+/// assert!(cache.local().get(&["cache-key"])?.is_some());
+/// # Ok(())
+/// # }
+/// ```
+pub struct TieredCache<L: Cache, R: Cache> {
+    local: L,
+    remote: R,
+}
+
+impl<L: Cache, R: Cache> TieredCache<L, R> {
+    /// Creates a new tiered cache checking `local` before `remote`.
+    pub fn new(local: L, remote: R) -> Self {
+        Self { local, remote }
+    }
+
+    /// Returns the local tier.
+    pub fn local(&self) -> &L {
+        &self.local
+    }
+
+    /// Returns the local tier mutably, e.g. to clean it.
+    pub fn local_mut(&mut self) -> &mut L {
+        &mut self.local
+    }
+
+    /// Returns the remote tier.
+    pub fn remote(&self) -> &R {
+        &self.remote
+    }
+
+    /// Consumes the tiered cache and returns the local and remote tier.
+    pub fn into_inner(self) -> (L, R) {
+        (self.local, self.remote)
+    }
+
+    fn populate<'a>(&self, cache_hit: CacheHit<'a, R::Reader>) -> CacheHit<'a, TieredReader<L, R>> {
+        let local_writer = self.local.set(&[&cache_hit.key]).ok();
+        let reader = match cache_hit.checksum {
+            Some(checksum) => {
+                RemoteReader::Verifying(Box::new(VerifyingReader::new(cache_hit.reader, checksum)))
+            }
+            None => RemoteReader::Unverified(cache_hit.reader),
+        };
+        CacheHit {
+            key: cache_hit.key,
+            reader: TieredReader::Remote(PopulatingReader {
+                reader,
+                local_writer,
+            }),
+            size_hint: cache_hit.size_hint,
+            // The data is already verified by the reader before the local tier is populated.
+            checksum: None,
+        }
+    }
+}
+
+impl<L: Cache, R: Cache> Cache for TieredCache<L, R> {
+    type Reader = TieredReader<L, R>;
+    type Writer = TieredWriter<L, R>;
+
+    fn get<'a>(&self, keys: &[&'a str]) -> IoPathResult<Option<CacheHit<'a, Self::Reader>>> {
+        let local_hit = self.local.get(keys)?;
+        // Keys listed before the key found locally take precedence and may exist remotely.
+        let preceding_keys = match &local_hit {
+            Some(cache_hit) => {
+                let index = keys
+                    .iter()
+                    .position(|key| *key == cache_hit.key)
+                    .unwrap_or(keys.len());
+                &keys[..index]
+            }
+            None => keys,
+        };
+        if !preceding_keys.is_empty()
+            && let Some(cache_hit) = self.remote.get(preceding_keys)?
+        {
+            return Ok(Some(self.populate(cache_hit)));
+        }
+        Ok(local_hit.map(local_hit_to_tiered))
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefixes: &[&str],
+    ) -> IoPathResult<Option<CacheHit<'static, Self::Reader>>> {
+        if let Some(cache_hit) = self.local.get_by_prefix(prefixes)? {
+            return Ok(Some(local_hit_to_tiered(cache_hit)));
+        }
+        Ok(self
+            .remote
+            .get_by_prefix(prefixes)?
+            .map(|cache_hit| self.populate(cache_hit)))
+    }
+
+    fn set_with_options(&self, keys: &[&str], options: &SetOptions) -> IoPathResult<Self::Writer> {
+        Ok(TieredWriter {
+            local: self.local.set_with_options(keys, options)?,
+            remote: self.remote.set_with_options(keys, options)?,
+        })
+    }
+
+    /// Returns the entries of both tiers. For keys existing in both tiers, the entry with the
+    /// latest access is returned.
+    fn list(&self, prefix: &str) -> IoPathResult<Vec<CacheEntry>> {
+        let mut entries = BTreeMap::new();
+        for entry in self
+            .remote
+            .list(prefix)?
+            .into_iter()
+            .chain(self.local.list(prefix)?)
+        {
+            match entries.get(&entry.key) {
+                Some(CacheEntry { latest_access, .. }) if *latest_access >= entry.latest_access => {
+                }
+                _ => {
+                    entries.insert(entry.key.clone(), entry);
+                }
+            }
+        }
+        Ok(entries.into_values().collect())
+    }
+
+    /// Returns the entry of the tier in which it was accessed most recently.
+    fn entry(&self, key: &str) -> IoPathResult<Option<CacheEntry>> {
+        let remote_entry = self.remote.entry(key)?;
+        Ok(match (self.local.entry(key)?, remote_entry) {
+            (Some(local), Some(remote)) if remote.latest_access > local.latest_access => {
+                Some(remote)
+            }
+            (local, remote) => local.or(remote),
+        })
+    }
+
+    fn delete(&self, keys: &[&str]) -> IoPathResult<()> {
+        self.remote.delete(keys)?;
+        self.local.delete(keys)
+    }
+}
+
+fn local_hit_to_tiered<L: Cache, R: Cache>(
+    CacheHit {
+        key,
+        reader,
+        size_hint,
+        checksum,
+    }: CacheHit<L::Reader>,
+) -> CacheHit<TieredReader<L, R>> {
+    CacheHit {
+        key,
+        reader: TieredReader::Local(reader),
+        size_hint,
+        checksum,
+    }
+}
+
+/// Reader returned by the [TieredCache].
+pub enum TieredReader<L: Cache, R: Cache> {
+    /// Reads data found in the local tier.
+    Local(L::Reader),
+    /// Reads data found in the remote tier, storing it in the local tier.
+    Remote(PopulatingReader<L::Writer, R::Reader>),
+}
+
+impl<L: Cache, R: Cache> Read for TieredReader<L, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Local(reader) => reader.read(buf),
+            Self::Remote(reader) => reader.read(buf),
+        }
+    }
+}
+
+enum RemoteReader<R: Read> {
+    Verifying(Box<VerifyingReader<R>>),
+    Unverified(R),
+}
+
+impl<R: Read> Read for RemoteReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Verifying(reader) => reader.read(buf),
+            Self::Unverified(reader) => reader.read(buf),
+        }
+    }
+}
+
+/// A reader that writes the data read through it to the local tier of a [TieredCache].
+///
+/// The data is only made available in the local tier once the end of the data is reached. If
+/// writing to the local tier fails, the local tier is no longer populated, but reading continues.
+pub struct PopulatingReader<W: Write + Close, R: Read> {
+    reader: RemoteReader<R>,
+    local_writer: Option<W>,
+}
+
+impl<W: Write + Close, R: Read> Read for PopulatingReader<W, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        if read == 0 && !buf.is_empty() {
+            if let Some(local_writer) = self.local_writer.take() {
+                let _ = local_writer.close();
+            }
+        } else if let Some(local_writer) = &mut self.local_writer
+            && local_writer.write_all(&buf[..read]).is_err()
+            && let Some(local_writer) = self.local_writer.take()
+        {
+            local_writer.abort();
+        }
+        Ok(read)
+    }
+}
+
+impl<W: Write + Close, R: Read> Drop for PopulatingReader<W, R> {
+    fn drop(&mut self) {
+        if let Some(local_writer) = self.local_writer.take() {
+            local_writer.abort();
+        }
+    }
+}
+
+/// Writer returned by the [TieredCache], writing to both tiers.
+pub struct TieredWriter<L: Cache, R: Cache> {
+    local: L::Writer,
+    remote: R::Writer,
+}
+
+impl<L: Cache, R: Cache> Write for TieredWriter<L, R> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.remote.write(buf)?;
+        self.local.write_all(&buf[..written])?;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.remote.flush()?;
+        self.local.flush()
+    }
+}
+
+impl<L: Cache, R: Cache> Close for TieredWriter<L, R> {
+    /// Closes the writer of the remote tier first, so that the data only becomes available in the
+    /// local tier if it was stored successfully in the remote tier.
+    fn close(self) -> io::Result<()> {
+        if let Err(err) = self.remote.close() {
+            self.local.abort();
+            return Err(err);
+        }
+        self.local.close()
+    }
+
+    fn abort(self) {
+        self.remote.abort();
+        self.local.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::local::LocalCache;
+    use crate::storage::in_memory::InMemoryStorage;
+
+    type TestCache = TieredCache<LocalCache<InMemoryStorage>, LocalCache<InMemoryStorage>>;
+
+    fn new_cache() -> TestCache {
+        TieredCache::new(
+            LocalCache::new(InMemoryStorage::new()),
+            LocalCache::new(InMemoryStorage::new()),
+        )
+    }
+
+    fn set(cache: &impl Cache, keys: &[&str], data: &[u8]) {
+        let mut writer = cache.set(keys).unwrap();
+        writer.write_all(data).unwrap();
+        writer.close().unwrap();
+    }
+
+    fn read(cache_hit: Option<CacheHit<impl Read>>) -> Option<(String, String)> {
+        cache_hit.map(|mut cache_hit| {
+            let mut buf = String::new();
+            cache_hit.reader.read_to_string(&mut buf).unwrap();
+            (cache_hit.key.into_owned(), buf)
+        })
+    }
+
+    fn hit(key: &str, data: &str) -> Option<(String, String)> {
+        Some((key.to_string(), data.to_string()))
+    }
+
+    #[test]
+    fn test_get_prefers_local_tier() {
+        let cache = new_cache();
+        set(cache.local(), &["key"], b"local");
+        set(cache.remote(), &["key"], b"remote");
+
+        assert_eq!(read(cache.get(&["key"]).unwrap()), hit("key", "local"));
+    }
+
+    #[test]
+    fn test_get_populates_local_tier_from_remote_tier() {
+        let cache = new_cache();
+        set(cache.remote(), &["key"], b"remote");
+
+        assert_eq!(read(cache.local().get(&["key"]).unwrap()), None);
+        assert_eq!(read(cache.get(&["key"]).unwrap()), hit("key", "remote"));
+        assert_eq!(
+            read(cache.local().get(&["key"]).unwrap()),
+            hit("key", "remote")
+        );
+    }
+
+    #[test]
+    fn test_get_does_not_populate_local_tier_with_partially_read_data() {
+        let cache = new_cache();
+        set(cache.remote(), &["key"], b"remote");
+
+        let mut cache_hit = cache.get(&["key"]).unwrap().unwrap();
+        let mut buf = [0; 3];
+        cache_hit.reader.read_exact(&mut buf).unwrap();
+        drop(cache_hit);
+
+        assert_eq!(read(cache.local().get(&["key"]).unwrap()), None);
+    }
+
+    #[test]
+    fn test_get_respects_key_order_across_tiers() {
+        let cache = new_cache();
+        set(cache.local(), &["secondary"], b"local");
+        set(cache.remote(), &["primary"], b"remote");
+
+        assert_eq!(
+            read(cache.get(&["primary", "secondary"]).unwrap()),
+            hit("primary", "remote")
+        );
+        assert_eq!(
+            read(cache.get(&["non-existent", "secondary"]).unwrap()),
+            hit("secondary", "local")
+        );
+        assert_eq!(read(cache.get(&["non-existent"]).unwrap()), None);
+    }
+
+    #[test]
+    fn test_get_by_prefix_falls_back_to_remote_tier() {
+        let cache = new_cache();
+        set(cache.remote(), &["prefix-remote"], b"remote");
+
+        assert_eq!(
+            read(cache.get_by_prefix(&["prefix-"]).unwrap()),
+            hit("prefix-remote", "remote")
+        );
+        set(cache.local(), &["prefix-local"], b"local");
+        assert_eq!(
+            read(cache.get_by_prefix(&["prefix-"]).unwrap()),
+            hit("prefix-local", "local")
+        );
+        assert_eq!(read(cache.get_by_prefix(&["other-"]).unwrap()), None);
+    }
+
+    #[test]
+    fn test_set_writes_to_both_tiers() {
+        let cache = new_cache();
+        set(&cache, &["key"], b"data");
+
+        assert_eq!(
+            read(cache.local().get(&["key"]).unwrap()),
+            hit("key", "data")
+        );
+        assert_eq!(
+            read(cache.remote().get(&["key"]).unwrap()),
+            hit("key", "data")
+        );
+    }
+
+    #[test]
+    fn test_list_merges_both_tiers() {
+        let cache = new_cache();
+        set(cache.local(), &["local", "both"], b"local");
+        set(cache.remote(), &["remote", "both"], b"remote data");
+
+        let entries = cache.list("").unwrap();
+        let keys: Vec<_> = entries.iter().map(|entry| entry.key.as_str()).collect();
+        assert_eq!(keys, vec!["both", "local", "remote"]);
+        assert_eq!(entries[0].size, 11);
+    }
+
+    #[test]
+    fn test_entry_returns_most_recently_accessed_entry_of_both_tiers() {
+        let cache = new_cache();
+        set(cache.local(), &["local", "both"], b"local");
+        set(cache.remote(), &["remote", "both"], b"remote data");
+
+        assert_eq!(cache.entry("local").unwrap().unwrap().size, 5);
+        assert_eq!(cache.entry("remote").unwrap().unwrap().size, 11);
+        assert_eq!(cache.entry("both").unwrap().unwrap().size, 11);
+        assert_eq!(cache.entry("non-existent").unwrap(), None);
+    }
+
+    #[test]
+    fn test_delete_deletes_from_both_tiers() {
+        let cache = new_cache();
+        set(&cache, &["key"], b"data");
+
+        cache.delete(&["key"]).unwrap();
+
+        assert_eq!(read(cache.local().get(&["key"]).unwrap()), None);
+        assert_eq!(read(cache.remote().get(&["key"]).unwrap()), None);
+    }
+}
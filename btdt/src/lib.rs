@@ -35,4 +35,5 @@ pub mod test_util {
 
     pub mod fs;
     pub mod fs_spec;
+    pub mod s3;
 }
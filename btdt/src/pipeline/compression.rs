@@ -0,0 +1,192 @@
+//! Compression of the archived data before it is stored in the cache.
+
+use std::io;
+use std::io::{BufReader, Read, Write};
+use zstd::stream::read::Decoder;
+use zstd::stream::write::Encoder;
+
+/// Compression to apply to the archived data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    /// Store the archive without compression.
+    #[default]
+    None,
+    /// Compress the archive with [Zstandard](https://facebook.github.io/zstd/).
+    Zstd {
+        /// Compression level from 1 (fastest) to 22 (strongest compression). Negative values
+        /// select even faster compression levels and `0` selects the default level.
+        level: i32,
+        /// Number of worker threads used for compression. `0` disables multithreading.
+        workers: u32,
+    },
+}
+
+impl Compression {
+    /// Default compression level used for Zstandard.
+    pub const DEFAULT_ZSTD_LEVEL: i32 = zstd::DEFAULT_COMPRESSION_LEVEL;
+
+    /// Returns Zstandard compression with the default level and without multithreading.
+    pub fn zstd() -> Self {
+        Self::Zstd {
+            level: Self::DEFAULT_ZSTD_LEVEL,
+            workers: 0,
+        }
+    }
+
+    /// Returns the codec used by this compression.
+    pub(crate) fn codec(&self) -> Codec {
+        match self {
+            Self::None => Codec::None,
+            Self::Zstd { .. } => Codec::Zstd,
+        }
+    }
+}
+
+/// Compression codec identifying how to decompress data.
+///
+/// The discriminant is the identifier used in the archive envelope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum Codec {
+    None = 0,
+    Zstd = 1,
+}
+
+impl TryFrom<u8> for Codec {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::None),
+            1 => Ok(Self::Zstd),
+            unknown => Err(unknown),
+        }
+    }
+}
+
+/// A writer compressing the written data according to a [Compression].
+pub(crate) enum CompressingWriter<W: Write> {
+    None(W),
+    Zstd(Encoder<'static, W>),
+}
+
+impl<W: Write> CompressingWriter<W> {
+    /// Creates a new compressing writer writing the compressed data to `writer`.
+    pub fn new(writer: W, compression: Compression) -> io::Result<Self> {
+        Ok(match compression {
+            Compression::None => Self::None(writer),
+            Compression::Zstd { level, workers } => {
+                let mut encoder = Encoder::new(writer, level)?;
+                if workers > 0 {
+                    encoder.multithread(workers)?;
+                }
+                Self::Zstd(encoder)
+            }
+        })
+    }
+
+    /// Writes any remaining compressed data and returns the underlying writer.
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Self::None(writer) => Ok(writer),
+            Self::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for CompressingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::None(writer) => writer.write(buf),
+            Self::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::None(writer) => writer.flush(),
+            Self::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// A reader decompressing data written with a [CompressingWriter].
+pub(crate) enum DecompressingReader<R: Read> {
+    None(R),
+    Zstd(Decoder<'static, BufReader<R>>),
+}
+
+impl<R: Read> DecompressingReader<R> {
+    /// Creates a new decompressing reader reading data compressed with `codec` from `reader`.
+    pub fn new(reader: R, codec: Codec) -> io::Result<Self> {
+        Ok(match codec {
+            Codec::None => Self::None(reader),
+            Codec::Zstd => Self::Zstd(Decoder::new(reader)?),
+        })
+    }
+}
+
+impl<R: Read> Read for DecompressingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::None(reader) => reader.read(buf),
+            Self::Zstd(decoder) => decoder.read(buf),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compress(data: &[u8], compression: Compression) -> Vec<u8> {
+        let mut writer = CompressingWriter::new(Vec::new(), compression).unwrap();
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap()
+    }
+
+    fn decompress(data: &[u8], codec: Codec) -> Vec<u8> {
+        let mut buf = Vec::new();
+        DecompressingReader::new(data, codec)
+            .unwrap()
+            .read_to_end(&mut buf)
+            .unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_no_compression_passes_data_through() {
+        let data = b"Hello, world!";
+        assert_eq!(compress(data, Compression::None), data);
+        assert_eq!(decompress(data, Codec::None), data);
+    }
+
+    #[test]
+    fn test_zstd_roundtrip() {
+        let data = b"Hello, world! Hello, world! Hello, world!".repeat(100);
+        let compressed = compress(&data, Compression::zstd());
+        assert!(compressed.len() < data.len());
+        assert_eq!(decompress(&compressed, Codec::Zstd), data);
+    }
+
+    #[test]
+    fn test_zstd_roundtrip_multithreaded() {
+        let data = b"Hello, world! Hello, world! Hello, world!".repeat(100);
+        let compressed = compress(
+            &data,
+            Compression::Zstd {
+                level: 19,
+                workers: 2,
+            },
+        );
+        assert_eq!(decompress(&compressed, Codec::Zstd), data);
+    }
+
+    #[test]
+    fn test_codec_roundtrips_through_identifier() {
+        for codec in [Codec::None, Codec::Zstd] {
+            assert_eq!(Codec::try_from(codec as u8), Ok(codec));
+        }
+        assert_eq!(Codec::try_from(255), Err(255));
+    }
+}
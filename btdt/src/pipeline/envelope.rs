@@ -0,0 +1,190 @@
+//! Versioned envelope describing how an archive stored in the cache is encoded.
+//!
+//! Each archive stored by a [Pipeline](super::Pipeline) is prefixed with an 8 byte header:
+//!
+//! | Offset | Size | Content                                      |
+//! |--------|------|----------------------------------------------|
+//! | 0      | 5    | Magic bytes `\0BTDT`                         |
+//! | 5      | 1    | Envelope format version (currently `1`)      |
+//! | 6      | 1    | Compression codec (`0`: none, `1`: zstd)     |
+//! | 7      | 1    | Archive format (`0`: tar)                    |
+//!
+//! A TAR archive never starts with the magic bytes (a leading null byte only occurs in the
+//! all-zero end-of-archive marker). Thus, data without the header is treated as a legacy plain
+//! TAR archive as written by older versions of btdt.
+
+use crate::pipeline::compression::Codec;
+use std::io;
+use std::io::{Chain, Cursor, ErrorKind, Read, Write};
+
+/// Magic bytes identifying the envelope header.
+const MAGIC: &[u8; 5] = b"\0BTDT";
+/// Current version of the envelope format.
+const VERSION: u8 = 1;
+/// Total length of the envelope header in bytes.
+const HEADER_LEN: usize = MAGIC.len() + 3;
+
+/// Reader providing the data following the envelope header.
+pub(crate) type PayloadReader<R> = Chain<Cursor<Vec<u8>>, R>;
+
+/// Format of the archive inside the envelope.
+///
+/// The discriminant is the identifier used in the envelope header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum ArchiveFormat {
+    Tar = 0,
+}
+
+impl TryFrom<u8> for ArchiveFormat {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Tar),
+            unknown => Err(unknown),
+        }
+    }
+}
+
+/// Description of how an archive is encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Envelope {
+    pub codec: Codec,
+    pub archive_format: ArchiveFormat,
+}
+
+impl Envelope {
+    /// Envelope assumed for data without an envelope header.
+    pub const LEGACY: Self = Self {
+        codec: Codec::None,
+        archive_format: ArchiveFormat::Tar,
+    };
+
+    /// Writes the envelope header to `writer`.
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        let mut header = [0u8; HEADER_LEN];
+        header[..MAGIC.len()].copy_from_slice(MAGIC);
+        header[MAGIC.len()] = VERSION;
+        header[MAGIC.len() + 1] = self.codec as u8;
+        header[MAGIC.len() + 2] = self.archive_format as u8;
+        writer.write_all(&header)
+    }
+
+    /// Reads the envelope header from `reader`.
+    ///
+    /// Returns the parsed envelope and a reader providing the data following the header. If the
+    /// data does not start with an envelope header, [Envelope::LEGACY] is returned together with a
+    /// reader providing all of the data.
+    pub fn read_from<R: Read>(mut reader: R) -> io::Result<(Self, PayloadReader<R>)> {
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        (&mut reader)
+            .take(HEADER_LEN as u64)
+            .read_to_end(&mut header)?;
+
+        if !header.starts_with(MAGIC) {
+            return Ok((Self::LEGACY, Cursor::new(header).chain(reader)));
+        }
+        if header.len() < HEADER_LEN {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "truncated archive envelope header",
+            ));
+        }
+
+        let version = header[MAGIC.len()];
+        if version != VERSION {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported archive envelope version: {version}"),
+            ));
+        }
+        let codec = Codec::try_from(header[MAGIC.len() + 1]).map_err(|codec| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported compression codec: {codec}"),
+            )
+        })?;
+        let archive_format =
+            ArchiveFormat::try_from(header[MAGIC.len() + 2]).map_err(|format| {
+                io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("unsupported archive format: {format}"),
+                )
+            })?;
+
+        Ok((
+            Self {
+                codec,
+                archive_format,
+            },
+            Cursor::new(Vec::new()).chain(reader),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_envelope(data: &[u8]) -> io::Result<(Envelope, Vec<u8>)> {
+        let (envelope, mut reader) = Envelope::read_from(data)?;
+        let mut remainder = Vec::new();
+        reader.read_to_end(&mut remainder)?;
+        Ok((envelope, remainder))
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let envelope = Envelope {
+            codec: Codec::Zstd,
+            archive_format: ArchiveFormat::Tar,
+        };
+        let mut data = Vec::new();
+        envelope.write_to(&mut data).unwrap();
+        assert_eq!(data, b"\0BTDT\x01\x01\x00");
+        data.extend_from_slice(b"payload");
+
+        assert_eq!(
+            read_envelope(&data).unwrap(),
+            (envelope, b"payload".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_data_without_header_is_treated_as_legacy() {
+        for data in [
+            &b""[..],
+            b"\0",
+            b"\0\0\0\0\0\0\0\0\0\0",
+            b"some-file.txt\0\0\0",
+        ] {
+            assert_eq!(
+                read_envelope(data).unwrap(),
+                (Envelope::LEGACY, data.to_vec())
+            );
+        }
+    }
+
+    #[test]
+    fn test_rejects_truncated_header() {
+        assert_eq!(
+            read_envelope(b"\0BTDT\x01").unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn test_rejects_unknown_values() {
+        for data in [
+            &b"\0BTDT\x02\x00\x00"[..],
+            b"\0BTDT\x01\xff\x00",
+            b"\0BTDT\x01\x00\xff",
+        ] {
+            assert_eq!(
+                read_envelope(data).unwrap_err().kind(),
+                ErrorKind::InvalidData
+            );
+        }
+    }
+}
@@ -0,0 +1,844 @@
+//! A pipeline defines how multiple files a processed to be stored in the cache, e.g. by archiving
+//! them in TAR format and potentially compressing them.
+
+mod compression;
+mod envelope;
+
+pub use compression::Compression;
+
+use crate::cache::checksum::VerifyingReader;
+use crate::cache::{Cache, SetOptions};
+use crate::error::{IoPathError, IoPathResult, WithPath};
+use crate::pipeline::compression::{CompressingWriter, DecompressingReader};
+use crate::pipeline::envelope::{ArchiveFormat, Envelope};
+use crate::util::close::Close;
+use chrono::TimeDelta;
+use ignore::overrides::Override;
+use ignore::{Error, WalkBuilder};
+use std::borrow::Cow;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use tar::{Builder, EntryType, Header};
+
+/// A pipeline defines how multiple files a processed to be stored in the cache.
+///
+/// # Examples
+///
+/// ```rust
+/// # use std::fs;
+/// # use std::io;
+/// use btdt::cache::local::LocalCache;
+/// # use btdt::error::IoPathResult;
+/// use btdt::pipeline::Pipeline;
+/// use btdt::storage::in_memory::InMemoryStorage;
+///
+/// # fn main() -> IoPathResult<()> {
+/// # const CACHEABLE_PATH: &str = "/tmp/btdt-cacheable";
+/// # struct CacheableDir;
+/// # impl CacheableDir {
+/// #     pub fn new() -> Self {
+/// #         fs::create_dir(CACHEABLE_PATH).expect(format!("Failed to create directory at {}", CACHEABLE_PATH).as_str());
+/// #         Self
+/// #     }
+/// # }
+/// # impl Drop for CacheableDir {
+/// #    fn drop(&mut self) {
+/// #        fs::remove_dir_all(CACHEABLE_PATH).expect(format!("Failed to remove directory at {}", CACHEABLE_PATH).as_str());
+/// #    }
+/// # }
+/// # let _cacheable_dir = CacheableDir::new();
+/// let mut pipeline = Pipeline::new(LocalCache::new(InMemoryStorage::new()));
+/// pipeline.store(&["cache-key"], CACHEABLE_PATH)?;
+/// pipeline.restore(&["cache-key"], CACHEABLE_PATH)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Pipeline<C: Cache> {
+    cache: C,
+    compression: Compression,
+    ttl: Option<TimeDelta>,
+    tree_hash: Option<blake3::Hash>,
+}
+
+impl<C: Cache> Pipeline<C> {
+    /// Creates a new pipeline with the given cache.
+    ///
+    /// Stored archives are not compressed by default. Use [Pipeline::with_compression] to change
+    /// this.
+    pub fn new(cache: C) -> Self {
+        Pipeline {
+            cache,
+            compression: Compression::default(),
+            ttl: None,
+            tree_hash: None,
+        }
+    }
+
+    /// Sets the compression to apply to archives when storing files.
+    ///
+    /// Restoring files detects the compression automatically, independent of this setting.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets the duration after which stored entries expire.
+    ///
+    /// By default, stored entries do not expire. See [Cache::set_with_ttl].
+    pub fn with_ttl(mut self, ttl: Option<TimeDelta>) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Sets the hash of the source directory tree to record in stored entries.
+    ///
+    /// The recorded hash can be retrieved with [Pipeline::stored_tree_hash] to determine whether
+    /// an entry needs to be stored again. The pipeline does not compute the hash itself.
+    pub fn with_tree_hash(mut self, tree_hash: Option<blake3::Hash>) -> Self {
+        self.tree_hash = tree_hash;
+        self
+    }
+
+    /// Returns the tree hash recorded in the entry stored under `key`.
+    ///
+    /// Returns `Ok(None)` if there is no entry for `key` or no tree hash was recorded for it. The
+    /// entry is looked up with [Cache::entry], so it is not accessed.
+    pub fn stored_tree_hash(&self, key: &str) -> IoPathResult<Option<blake3::Hash>> {
+        Ok(self.cache.entry(key)?.and_then(|entry| entry.tree_hash))
+    }
+
+    /// Restores the files stored in the cache.
+    ///
+    /// The first key found in the cache is used to restore the files. If no key is found, nothing
+    /// is restored. Restored files are written into the directory specified by `destination`.
+    /// The compression and archive format are detected from the stored data, such that archives
+    /// are decompressed transparently.
+    ///
+    /// Returns `Ok(Some(key))` if files were restored where `key` is the cache key used, `Ok(None)`
+    /// otherwise.
+    pub fn restore<'a>(
+        &self,
+        keys: &[&'a str],
+        destination: impl AsRef<Path>,
+    ) -> IoPathResult<Option<Cow<'a, str>>> {
+        self.restore_with_prefixes(keys, &[], destination)
+    }
+
+    /// Restores the files stored in the cache, falling back to keys matching a prefix.
+    ///
+    /// Works like [Pipeline::restore], but if none of the `keys` is found, the most recently
+    /// accessed key starting with the first matching prefix in `prefixes` is used.
+    ///
+    /// Returns `Ok(Some(key))` if files were restored where `key` is the cache key used, `Ok(None)`
+    /// otherwise.
+    pub fn restore_with_prefixes<'a>(
+        &self,
+        keys: &[&'a str],
+        prefixes: &[&str],
+        destination: impl AsRef<Path>,
+    ) -> IoPathResult<Option<Cow<'a, str>>> {
+        let cache_hit = match self.cache.get(keys)? {
+            Some(cache_hit) => Some(cache_hit),
+            None => self.cache.get_by_prefix(prefixes)?,
+        };
+        if let Some(cache_hit) = cache_hit {
+            match cache_hit.checksum {
+                Some(checksum) => Self::unpack(
+                    VerifyingReader::new(cache_hit.reader, checksum),
+                    destination.as_ref(),
+                )?,
+                None => Self::unpack(cache_hit.reader, destination.as_ref())?,
+            }
+            Ok(Some(cache_hit.key))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Unpacks the archive into `destination`.
+    ///
+    /// The archive is first unpacked into a staging directory within `destination` and only moved
+    /// into place once the complete data has been read (and thus been verified if read with a
+    /// [VerifyingReader]). This ensures that no partially restored files are left behind if the
+    /// data turns out to be corrupted.
+    fn unpack(mut reader: impl Read, destination: &Path) -> IoPathResult<()> {
+        let created_destination = !destination.exists();
+        fs::create_dir_all(destination).with_path(destination)?;
+        let staging_dir = tempfile::Builder::new()
+            .prefix(".btdt-restore-")
+            .tempdir_in(destination)
+            .with_path(destination)?;
+
+        let result = Self::unpack_archive(&mut reader, staging_dir.path())
+            .and_then(|()| {
+                io::copy(&mut reader, &mut io::sink()).with_path(destination)?;
+                Ok(())
+            })
+            .and_then(|()| move_entries(staging_dir.path(), destination));
+        if result.is_err() {
+            drop(staging_dir);
+            if created_destination {
+                let _ = fs::remove_dir(destination);
+            }
+        }
+        result
+    }
+
+    fn unpack_archive(reader: impl Read, destination: &Path) -> IoPathResult<()> {
+        let (envelope, reader) = Envelope::read_from(reader).with_path(destination)?;
+        let reader = DecompressingReader::new(reader, envelope.codec).with_path(destination)?;
+        match envelope.archive_format {
+            ArchiveFormat::Tar => tar::Archive::new(reader)
+                .unpack(destination)
+                .with_path(destination),
+        }
+    }
+
+    /// Stores the files in the cache.
+    ///
+    /// The files in the directory specified by `source` are archived, compressed according to the
+    /// configured [Compression], and stored in the cache under the given keys.
+    ///
+    /// Files named `.btdtignore` can be used to exclude files from the cache. The syntax follows
+    /// the [`.gitignore` specification](https://git-scm.com/docs/gitignore).
+    pub fn store(&mut self, keys: &[&str], source: impl AsRef<Path>) -> IoPathResult<()> {
+        self.store_with_overrides(keys, source, Override::empty())
+    }
+
+    /// Stores the files in the cache.
+    ///
+    /// The files in the directory specified by `source` are archived, compressed according to the
+    /// configured [Compression], and stored in the cache under the given keys.
+    ///
+    /// Files named `.btdtignore` can be used to exclude files from the cache. The syntax follows
+    /// the [`.gitignore` specification](https://git-scm.com/docs/gitignore).
+    pub fn store_with_overrides(
+        &mut self,
+        keys: &[&str],
+        source: impl AsRef<Path>,
+        overrides: Override,
+    ) -> IoPathResult<()> {
+        let options = SetOptions {
+            ttl: self.ttl,
+            tree_hash: self.tree_hash,
+        };
+        let mut writer = BufWriter::new(self.cache.set_with_options(keys, &options)?);
+        Envelope {
+            codec: self.compression.codec(),
+            archive_format: ArchiveFormat::Tar,
+        }
+        .write_to(&mut writer)
+        .with_path(source.as_ref())?;
+        let mut writer =
+            CompressingWriter::new(writer, self.compression).with_path(source.as_ref())?;
+        {
+            let mut archive = tar::Builder::new(&mut writer);
+            archive.follow_symlinks(false);
+            Self::add_dir_to_archive(&mut archive, source.as_ref(), overrides)?;
+            archive.finish().with_path(source.as_ref())?;
+        }
+        writer
+            .finish()
+            .with_path(source.as_ref())?
+            .into_inner()
+            .map_err(|e| e.into())
+            .and_then(Close::close)
+            .with_path(source.as_ref())?;
+        Ok(())
+    }
+
+    fn add_dir_to_archive(
+        archive_builder: &mut Builder<impl Write>,
+        root: &Path,
+        overrides: Override,
+    ) -> IoPathResult<()> {
+        let walker = WalkBuilder::new(root)
+            .follow_links(false)
+            .standard_filters(false)
+            .add_custom_ignore_filename(".btdtignore")
+            .overrides(overrides)
+            // A deterministic order allows to deduplicate archives of identical directories.
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .build();
+
+        for entry in walker {
+            let entry = entry.map_err(|err| match err {
+                Error::WithPath { path, err } => IoPathError::new(io::Error::other(err), path),
+                err => IoPathError::new_no_path(io::Error::other(err)),
+            })?;
+
+            let source_path = entry.path();
+            if source_path == root {
+                continue;
+            }
+            let archived_path = source_path
+                .strip_prefix(root)
+                .expect("root not a prefix of parth");
+
+            let file_type = entry.file_type().expect("file type should be available");
+            if file_type.is_dir() {
+                archive_builder
+                    .append_dir(archived_path, source_path)
+                    .with_path(source_path)?;
+            } else if file_type.is_symlink() {
+                let link_target = std::fs::read_link(source_path).with_path(source_path)?;
+                let mut header = Header::new_old();
+                header.set_entry_type(EntryType::Symlink);
+                header.set_size(0);
+                archive_builder
+                    .append_link(&mut header, archived_path, link_target)
+                    .with_path(source_path)?;
+            } else if file_type.is_file() {
+                let mut file = File::open(source_path).with_path(source_path)?;
+                archive_builder
+                    .append_file(archived_path, &mut file)
+                    .with_path(entry.path())?;
+            } else {
+                return Err(io::Error::other("Unsupported file type")).with_path(entry.path());
+            }
+        }
+        Ok(())
+    }
+
+    /// Consumes the pipeline and returns the cache.
+    pub fn into_cache(self) -> C {
+        self.cache
+    }
+}
+
+/// Moves all entries in `source` into `destination`, merging directories existing in both and
+/// replacing any other existing entries.
+fn move_entries(source: &Path, destination: &Path) -> IoPathResult<()> {
+    for entry in fs::read_dir(source).with_path(source)? {
+        let entry = entry.with_path(source)?;
+        let source_path = entry.path();
+        let destination_path = destination.join(entry.file_name());
+        let source_type = entry.file_type().with_path(&source_path)?;
+        match fs::symlink_metadata(&destination_path) {
+            Ok(destination_meta) if source_type.is_dir() && destination_meta.is_dir() => {
+                move_entries(&source_path, &destination_path)?;
+                fs::set_permissions(
+                    &destination_path,
+                    entry.metadata().with_path(&source_path)?.permissions(),
+                )
+                .with_path(&destination_path)?;
+                continue;
+            }
+            Ok(destination_meta) if destination_meta.is_dir() => {
+                fs::remove_dir_all(&destination_path).with_path(&destination_path)?
+            }
+            Ok(_) => fs::remove_file(&destination_path).with_path(&destination_path)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err).with_path(&destination_path),
+        }
+        fs::rename(&source_path, &destination_path).with_path(&destination_path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::local::LocalCache;
+    use crate::storage::filesystem::FilesystemStorage;
+    use crate::storage::in_memory::InMemoryStorage;
+    use crate::test_util::fs_spec::{DirSpec, FileSpec, Node};
+    use ignore::overrides::OverrideBuilder;
+    use std::fs;
+    use std::fs::Permissions;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::tempdir;
+
+    fn file_with_name(name: &str) -> (String, Box<dyn Node>) {
+        (
+            name.to_string(),
+            Box::new(FileSpec {
+                permissions: Permissions::from_mode(0o644),
+                content: vec![],
+            }) as Box<dyn Node>,
+        )
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let cache = LocalCache::new(InMemoryStorage::new());
+        let mut pipeline = Pipeline::new(cache);
+
+        let spec = DirSpec::create_unix_fixture();
+
+        let tempdir = tempdir().unwrap();
+        let source_path = tempdir.path().join("source-root");
+        spec.create(source_path.as_ref()).unwrap();
+        pipeline.store(&["cache-key"], &source_path).unwrap();
+
+        let destination_path = tempdir.path().join("destination-root");
+        pipeline.restore(&["cache-key"], &destination_path).unwrap();
+
+        assert_eq!(spec.compare_with(&destination_path).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_roundtrip_with_zstd_compression() {
+        let cache = LocalCache::new(InMemoryStorage::new());
+        let mut pipeline = Pipeline::new(cache).with_compression(Compression::Zstd {
+            level: 3,
+            workers: 2,
+        });
+
+        let spec = DirSpec::create_unix_fixture();
+
+        let tempdir = tempdir().unwrap();
+        let source_path = tempdir.path().join("source-root");
+        spec.create(source_path.as_ref()).unwrap();
+        pipeline.store(&["cache-key"], &source_path).unwrap();
+
+        let destination_path = tempdir.path().join("destination-root");
+        pipeline.restore(&["cache-key"], &destination_path).unwrap();
+
+        assert_eq!(spec.compare_with(&destination_path).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_restores_uncompressed_archive_with_compression_enabled() {
+        let cache = LocalCache::new(InMemoryStorage::new());
+        let mut pipeline = Pipeline::new(cache);
+
+        let spec = DirSpec::create_unix_fixture();
+
+        let tempdir = tempdir().unwrap();
+        let source_path = tempdir.path().join("source-root");
+        spec.create(source_path.as_ref()).unwrap();
+        pipeline.store(&["cache-key"], &source_path).unwrap();
+
+        let pipeline = pipeline.with_compression(Compression::zstd());
+        let destination_path = tempdir.path().join("destination-root");
+        pipeline.restore(&["cache-key"], &destination_path).unwrap();
+
+        assert_eq!(spec.compare_with(&destination_path).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_restores_legacy_archive_without_envelope() {
+        let cache = LocalCache::new(InMemoryStorage::new());
+
+        let spec = DirSpec::create_unix_fixture();
+        let tempdir = tempdir().unwrap();
+        let source_path = tempdir.path().join("source-root");
+        spec.create(source_path.as_ref()).unwrap();
+
+        let mut writer = cache.set(&["cache-key"]).unwrap();
+        {
+            let mut archive = tar::Builder::new(&mut writer);
+            archive.follow_symlinks(false);
+            Pipeline::<LocalCache<InMemoryStorage>>::add_dir_to_archive(
+                &mut archive,
+                &source_path,
+                Override::empty(),
+            )
+            .unwrap();
+            archive.finish().unwrap();
+        }
+        writer.close().unwrap();
+
+        let pipeline = Pipeline::new(cache);
+        let destination_path = tempdir.path().join("destination-root");
+        pipeline.restore(&["cache-key"], &destination_path).unwrap();
+
+        assert_eq!(spec.compare_with(&destination_path).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_restore_fails_on_corrupted_data_without_leaving_files_behind() {
+        let tempdir = tempdir().unwrap();
+        let cache_path = tempdir.path().join("cache");
+        fs::create_dir(&cache_path).unwrap();
+        let mut pipeline =
+            Pipeline::new(LocalCache::new(FilesystemStorage::new(cache_path.clone())));
+
+        let spec = DirSpec::create_unix_fixture();
+        let source_path = tempdir.path().join("source-root");
+        spec.create(source_path.as_ref()).unwrap();
+        pipeline.store(&["cache-key"], &source_path).unwrap();
+
+        for blob_dir in fs::read_dir(cache_path.join("blob")).unwrap() {
+            for blob in fs::read_dir(blob_dir.unwrap().path()).unwrap() {
+                let blob_path = blob.unwrap().path();
+                let mut data = fs::read(&blob_path).unwrap();
+                let last = data.len() - 1;
+                data[last] ^= 0xff;
+                fs::write(&blob_path, data).unwrap();
+            }
+        }
+
+        let destination_path = tempdir.path().join("destination-root");
+        let err = pipeline
+            .restore(&["cache-key"], &destination_path)
+            .unwrap_err();
+        assert_eq!(err.io_error().kind(), io::ErrorKind::InvalidData);
+        assert!(!destination_path.exists());
+
+        let existing_destination_path = tempdir.path().join("existing-destination-root");
+        fs::create_dir(&existing_destination_path).unwrap();
+        fs::write(existing_destination_path.join("file.txt"), "existing").unwrap();
+        pipeline
+            .restore(&["cache-key"], &existing_destination_path)
+            .unwrap_err();
+        assert_eq!(
+            fs::read_dir(&existing_destination_path)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name())
+                .collect::<Vec<_>>(),
+            vec!["file.txt"]
+        );
+    }
+
+    #[test]
+    fn test_restore_merges_into_existing_destination() {
+        let cache = LocalCache::new(InMemoryStorage::new());
+        let mut pipeline = Pipeline::new(cache);
+
+        let tempdir = tempdir().unwrap();
+        let source_path = tempdir.path().join("source-root");
+        fs::create_dir_all(source_path.join("dir")).unwrap();
+        fs::write(source_path.join("dir/cached.txt"), "cached").unwrap();
+        fs::write(source_path.join("replaced.txt"), "cached").unwrap();
+        pipeline.store(&["cache-key"], &source_path).unwrap();
+
+        let destination_path = tempdir.path().join("destination-root");
+        fs::create_dir_all(destination_path.join("dir")).unwrap();
+        fs::write(destination_path.join("dir/existing.txt"), "existing").unwrap();
+        fs::write(destination_path.join("replaced.txt"), "existing").unwrap();
+        pipeline.restore(&["cache-key"], &destination_path).unwrap();
+
+        for (path, content) in [
+            ("dir/cached.txt", "cached"),
+            ("dir/existing.txt", "existing"),
+            ("replaced.txt", "cached"),
+        ] {
+            assert_eq!(
+                fs::read_to_string(destination_path.join(path)).unwrap(),
+                content
+            );
+        }
+        assert_eq!(fs::read_dir(&destination_path).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_respects_btdtignore_files() {
+        let cache = LocalCache::new(InMemoryStorage::new());
+        let mut pipeline = Pipeline::new(cache);
+
+        let spec = DirSpec {
+            permissions: Permissions::from_mode(0o755),
+            children: [
+                (
+                    ".btdtignore".to_string(),
+                    Box::new(FileSpec {
+                        permissions: Permissions::from_mode(0o644),
+                        content: b"
+# comment
+/ignore-root-only
+ignore-everywhere
+ignore-only-dir/
+ignore-*-wildcard
+subpath/**/ignore
+!**/include
+                "
+                        .to_vec(),
+                    }) as Box<dyn Node>,
+                ),
+                file_with_name("some-file"),
+                file_with_name(".hidden-file"),
+                file_with_name("include"),
+                file_with_name("ignore-root-only"),
+                file_with_name("ignore-everywhere"),
+                file_with_name("ignore-foo-wildcard"),
+                file_with_name("ignore-with-local-ignore-file"),
+                (
+                    "ignore-only-dir".to_string(),
+                    Box::new(DirSpec {
+                        permissions: Permissions::from_mode(0o755),
+                        children: [file_with_name("foo"), file_with_name("include")]
+                            .into_iter()
+                            .collect(),
+                    }) as Box<dyn Node>,
+                ),
+                (
+                    "subpath".to_string(),
+                    Box::new(DirSpec {
+                        permissions: Permissions::from_mode(0o755),
+                        children: [
+                            file_with_name("ignore-root-only"),
+                            file_with_name("ignore-everywhere"),
+                            file_with_name("ignore-only-dir"),
+                            file_with_name("ignore-with-local-ignore-file"),
+                            (
+                                ".btdtignore".to_string(),
+                                Box::new(FileSpec {
+                                    permissions: Permissions::from_mode(0o644),
+                                    content: b"ignore-with-local-ignore-file".to_vec(),
+                                }) as Box<dyn Node>,
+                            ),
+                            (
+                                "subsubpath".to_string(),
+                                Box::new(DirSpec {
+                                    permissions: Permissions::from_mode(0o755),
+                                    children: [file_with_name("foo"), file_with_name("ignore")]
+                                        .into_iter()
+                                        .collect(),
+                                }) as Box<dyn Node>,
+                            ),
+                        ]
+                        .into_iter()
+                        .collect(),
+                    }) as Box<dyn Node>,
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let expected = DirSpec {
+            permissions: Permissions::from_mode(0o755),
+            children: [
+                (
+                    ".btdtignore".to_string(),
+                    Box::new(FileSpec {
+                        permissions: Permissions::from_mode(0o644),
+                        content: b"
+# comment
+/ignore-root-only
+ignore-everywhere
+ignore-only-dir/
+ignore-*-wildcard
+subpath/**/ignore
+!**/include
+                "
+                        .to_vec(),
+                    }) as Box<dyn Node>,
+                ),
+                file_with_name("some-file"),
+                file_with_name(".hidden-file"),
+                file_with_name("include"),
+                file_with_name("ignore-with-local-ignore-file"),
+                (
+                    "subpath".to_string(),
+                    Box::new(DirSpec {
+                        permissions: Permissions::from_mode(0o755),
+                        children: [
+                            file_with_name("ignore-root-only"),
+                            file_with_name("ignore-only-dir"),
+                            (
+                                ".btdtignore".to_string(),
+                                Box::new(FileSpec {
+                                    permissions: Permissions::from_mode(0o644),
+                                    content: b"ignore-with-local-ignore-file".to_vec(),
+                                }) as Box<dyn Node>,
+                            ),
+                            (
+                                "subsubpath".to_string(),
+                                Box::new(DirSpec {
+                                    permissions: Permissions::from_mode(0o755),
+                                    children: [file_with_name("foo")].into_iter().collect(),
+                                }) as Box<dyn Node>,
+                            ),
+                        ]
+                        .into_iter()
+                        .collect(),
+                    }) as Box<dyn Node>,
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        };
+
+        let tempdir = tempdir().unwrap();
+        let source_path = tempdir.path().join("source-root");
+        spec.create(source_path.as_ref()).unwrap();
+        pipeline.store(&["cache-key"], &source_path).unwrap();
+
+        let destination_path = tempdir.path().join("destination-root");
+        pipeline.restore(&["cache-key"], &destination_path).unwrap();
+
+        assert_eq!(expected.compare_with(&destination_path).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_does_not_use_gitignore_files() {
+        let cache = LocalCache::new(InMemoryStorage::new());
+        let mut pipeline = Pipeline::new(cache);
+
+        let spec = DirSpec {
+            permissions: Permissions::from_mode(0o755),
+            children: [
+                (
+                    ".gitignore".to_string(),
+                    Box::new(FileSpec {
+                        permissions: Permissions::from_mode(0o644),
+                        content: b"do-not-ignore".to_vec(),
+                    }) as Box<dyn Node>,
+                ),
+                (
+                    "do-not-ignore".to_string(),
+                    Box::new(FileSpec {
+                        permissions: Permissions::from_mode(0o644),
+                        content: vec![],
+                    }) as Box<dyn Node>,
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        };
+
+        let tempdir = tempdir().unwrap();
+        let source_path = tempdir.path().join("source-root");
+        spec.create(source_path.as_ref()).unwrap();
+        pipeline.store(&["cache-key"], &source_path).unwrap();
+
+        let destination_path = tempdir.path().join("destination-root");
+        pipeline.restore(&["cache-key"], &destination_path).unwrap();
+
+        assert_eq!(spec.compare_with(&destination_path).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_store_with_overrides() {
+        let cache = LocalCache::new(InMemoryStorage::new());
+        let mut pipeline = Pipeline::new(cache);
+
+        let spec = DirSpec {
+            permissions: Permissions::from_mode(0o755),
+            children: [file_with_name("some-file"), file_with_name("ignore")]
+                .into_iter()
+                .collect(),
+        };
+        let expected = DirSpec {
+            permissions: Permissions::from_mode(0o755),
+            children: [file_with_name("some-file")].into_iter().collect(),
+        };
+
+        let tempdir = tempdir().unwrap();
+        let source_path = tempdir.path().join("source-root");
+        spec.create(source_path.as_ref()).unwrap();
+
+        let overrides = OverrideBuilder::new(&source_path)
+            .add("!ignore")
+            .unwrap()
+            .build()
+            .unwrap();
+        pipeline
+            .store_with_overrides(&["cache-key"], &source_path, overrides)
+            .unwrap();
+
+        let destination_path = tempdir.path().join("destination-root");
+        pipeline.restore(&["cache-key"], &destination_path).unwrap();
+
+        assert_eq!(expected.compare_with(&destination_path).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_restore_returns_restored_cache_key() {
+        let cache = LocalCache::new(InMemoryStorage::new());
+        let mut pipeline = Pipeline::new(cache);
+
+        let tempdir = tempdir().unwrap();
+        let source_path = tempdir.path().join("source-root");
+        fs::create_dir(&source_path).unwrap();
+        pipeline.store(&["cache-key-0"], tempdir.path()).unwrap();
+        pipeline.store(&["cache-key-1"], tempdir.path()).unwrap();
+
+        let destination_path = tempdir.path().join("destination-root");
+
+        assert!(
+            pipeline
+                .restore(&["non-existent"], &destination_path)
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(
+            pipeline
+                .restore(
+                    &["non-existent", "cache-key-1", "cache-key-0"],
+                    &destination_path
+                )
+                .unwrap(),
+            Some(Cow::Borrowed("cache-key-1"))
+        );
+    }
+
+    #[test]
+    fn test_restore_with_prefixes_falls_back_to_prefix_match() {
+        let cache = LocalCache::new(InMemoryStorage::new());
+        let mut pipeline = Pipeline::new(cache);
+
+        let tempdir = tempdir().unwrap();
+        let source_path = tempdir.path().join("source-root");
+        fs::create_dir(&source_path).unwrap();
+        fs::write(source_path.join("file.txt"), "prefixed").unwrap();
+        pipeline.store(&["prefix-abc"], &source_path).unwrap();
+        fs::write(source_path.join("file.txt"), "exact").unwrap();
+        pipeline.store(&["exact"], &source_path).unwrap();
+
+        let destination_path = tempdir.path().join("destination-root");
+        assert_eq!(
+            pipeline
+                .restore_with_prefixes(&["exact", "non-existent"], &["prefix-"], &destination_path)
+                .unwrap(),
+            Some(Cow::Borrowed("exact"))
+        );
+        assert_eq!(
+            fs::read_to_string(destination_path.join("file.txt")).unwrap(),
+            "exact"
+        );
+
+        let destination_path = tempdir.path().join("destination-root-prefix");
+        assert_eq!(
+            pipeline
+                .restore_with_prefixes(&["prefix-def"], &["prefix-"], &destination_path)
+                .unwrap(),
+            Some(Cow::Owned("prefix-abc".to_string()))
+        );
+        assert_eq!(
+            fs::read_to_string(destination_path.join("file.txt")).unwrap(),
+            "prefixed"
+        );
+
+        assert!(
+            pipeline
+                .restore_with_prefixes(&["prefix-def"], &["other-"], &destination_path)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_stored_tree_hash() {
+        let cache = LocalCache::new(InMemoryStorage::new());
+        let mut pipeline = Pipeline::new(cache).with_compression(Compression::zstd());
+
+        let tempdir = tempdir().unwrap();
+        let source_path = tempdir.path().join("source-root");
+        fs::create_dir(&source_path).unwrap();
+        fs::write(source_path.join("file.txt"), "content").unwrap();
+        pipeline.store(&["without-hash"], &source_path).unwrap();
+        let mut pipeline = pipeline.with_tree_hash(Some(blake3::hash(b"tree")));
+        pipeline.store(&["with-hash"], &source_path).unwrap();
+
+        assert_eq!(pipeline.stored_tree_hash("non-existent").unwrap(), None);
+        assert_eq!(pipeline.stored_tree_hash("without-hash").unwrap(), None);
+        assert_eq!(
+            pipeline.stored_tree_hash("with-hash").unwrap(),
+            Some(blake3::hash(b"tree"))
+        );
+
+        let destination_path = tempdir.path().join("destination-root");
+        pipeline.restore(&["with-hash"], &destination_path).unwrap();
+        assert_eq!(
+            fs::read_to_string(destination_path.join("file.txt")).unwrap(),
+            "content"
+        );
+    }
+}
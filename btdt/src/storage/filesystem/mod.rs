@@ -5,12 +5,13 @@ mod staged_file;
 use crate::error::{IoPathError, IoPathResult, WithPath};
 use crate::storage::filesystem::staged_file::{StagedFile, clean_leftover_tmp_files};
 use crate::storage::{EntryType, FileHandle, Storage, StorageEntry};
+use chrono::DateTime;
 use fs2::FileExt;
 use rand::rngs::ThreadRng;
 use std::borrow::Cow;
 use std::fs::File;
 use std::io::ErrorKind;
-use std::path::{Component, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::{fs, io};
 
 /// Storage implementation using the local filesystem.
@@ -96,6 +97,11 @@ impl Storage for FilesystemStorage {
         let file = File::open(&canonical_path).with_path(&canonical_path)?;
         Ok(FileHandle {
             size_hint: file.allocated_size().with_path(&canonical_path)?,
+            modified: file
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .map(DateTime::from),
             reader: file,
         })
     }
@@ -119,6 +125,7 @@ impl Storage for FilesystemStorage {
                     file_type if file_type.is_dir() => Some(EntryType::Directory),
                     _ => None,
                 } {
+                    let metadata = entry.metadata().with_path(entry.path())?;
                     Ok(Some(StorageEntry {
                         name: Cow::Owned(
                             entry
@@ -133,11 +140,16 @@ impl Storage for FilesystemStorage {
                                 .with_path(entry.path())?,
                         ),
                         entry_type,
-                        size: if entry.file_type().with_path(entry.path())?.is_file() {
-                            entry.metadata().with_path(entry.path())?.len()
+                        size: if entry_type == EntryType::File {
+                            metadata.len()
                         } else {
                             0
                         },
+                        modified: if entry_type == EntryType::File {
+                            metadata.modified().ok().map(DateTime::from)
+                        } else {
+                            None
+                        },
                     }))
                 } else {
                     Ok(None)
@@ -148,6 +160,26 @@ impl Storage for FilesystemStorage {
 
     fn put(&self, path: &str) -> IoPathResult<Self::Writer> {
         let canonical_path = self.canonical_path(path)?;
+        self.create_parent_dirs(&canonical_path)?;
+        StagedFile::new(canonical_path, &mut ThreadRng::default())
+    }
+
+    fn rename(&self, from: &str, to: &str) -> IoPathResult<()> {
+        let canonical_from = self.canonical_path(from)?;
+        if canonical_from.is_dir() {
+            return Err(IoPathError::new(
+                io::Error::new(ErrorKind::IsADirectory, "Is a directory"),
+                canonical_from,
+            ));
+        }
+        let canonical_to = self.canonical_path(to)?;
+        self.create_parent_dirs(&canonical_to)?;
+        fs::rename(&canonical_from, &canonical_to).with_path(&canonical_from)
+    }
+}
+
+impl FilesystemStorage {
+    fn create_parent_dirs(&self, canonical_path: &Path) -> IoPathResult<()> {
         if self.root.exists()
             && let Some(parent_dir) = canonical_path.parent()
         {
@@ -168,11 +200,9 @@ impl Storage for FilesystemStorage {
                 }
             }
         }
-        StagedFile::new(canonical_path, &mut ThreadRng::default())
+        Ok(())
     }
-}
 
-impl FilesystemStorage {
     fn canonical_path(&self, path: &str) -> IoPathResult<PathBuf> {
         if !path.starts_with('/') {
             return Err(IoPathError::new(
@@ -195,7 +225,6 @@ mod tests {
     use crate::storage::tests::{read_file_from_storage_to_string, write_file_to_storage};
     use crate::test_storage;
     use std::fs::create_dir_all;
-    use std::path::Path;
     use tempfile::{TempDir, tempdir};
 
     struct FilesystemStorageTestFixture {
@@ -239,6 +268,10 @@ mod tests {
         fn put(&self, path: &str) -> IoPathResult<Self::Writer> {
             self.storage.put(path)
         }
+
+        fn rename(&self, from: &str, to: &str) -> IoPathResult<()> {
+            self.storage.rename(from, to)
+        }
     }
 
     test_storage!(filesystem_tests, FilesystemStorageTestFixture::new());
@@ -255,9 +288,9 @@ mod tests {
     fn test_does_not_create_non_existent_root() {
         let tempdir = tempdir().unwrap();
         let storage_path = tempdir.path().join("non-existent");
-        let mut storage = FilesystemStorage::new(storage_path.clone());
+        let storage = FilesystemStorage::new(storage_path.clone());
         assert_eq!(
-            write_file_to_storage(&mut storage, "/file.txt", "Hello, world!")
+            write_file_to_storage(&storage, "/file.txt", "Hello, world!")
                 .unwrap_err()
                 .io_error()
                 .kind(),
@@ -271,8 +304,8 @@ mod tests {
         let tempdir = tempdir().unwrap();
         let storage_root = tempdir.path().join("storage-root");
         fs::create_dir(&storage_root).unwrap();
-        let mut storage = FilesystemStorage::new(storage_root.clone());
-        assert!(write_file_to_storage(&mut storage, "/../file.txt", "file-content").is_err());
+        let storage = FilesystemStorage::new(storage_root.clone());
+        assert!(write_file_to_storage(&storage, "/../file.txt", "file-content").is_err());
         assert!(!storage_root.join("file.txt").exists());
     }
 
@@ -300,8 +333,8 @@ mod tests {
         let _push_cwd = PushCwd::new(tempdir.path()).unwrap();
         let storage_path = PathBuf::from("dir/storage-root");
         create_dir_all(&storage_path).unwrap();
-        let mut storage = FilesystemStorage::new(storage_path.clone());
-        write_file_to_storage(&mut storage, "/some/subdir/file.txt", "Hello, world!").unwrap();
-        read_file_from_storage_to_string(&mut storage, "/some/subdir/file.txt").unwrap();
+        let storage = FilesystemStorage::new(storage_path.clone());
+        write_file_to_storage(&storage, "/some/subdir/file.txt", "Hello, world!").unwrap();
+        read_file_from_storage_to_string(&storage, "/some/subdir/file.txt").unwrap();
     }
 }
@@ -18,6 +18,7 @@ const TMP_FILE_SUFFIX_ENCODED_LEN: usize = 7;
 ///
 /// The file is created with a temporary name in the same directory as the target path.
 /// Once [Close::close] is called or the instance is dropped, the file is moved to the target path.
+/// With [Close::abort], the temporary file is deleted instead.
 pub struct StagedFile<P: AsRef<Path>> {
     file: File,
     tmp_path: PathBuf,
@@ -87,6 +88,12 @@ impl<P: AsRef<Path>> Close for StagedFile<P> {
     fn close(mut self) -> io::Result<()> {
         self.finalize()
     }
+
+    fn abort(mut self) {
+        self.finalized = true;
+        // Left over temporary files are deleted when cleaning the storage.
+        let _ = fs::remove_file(&self.tmp_path);
+    }
 }
 
 impl<P: AsRef<Path>> Drop for StagedFile<P> {
@@ -185,6 +192,17 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_abort_removes_tmp_file() {
+        let tempdir = tempdir().unwrap();
+        let path = tempdir.path().join("test.txt");
+        let mut file = StagedFile::new(&path, &mut StdRng::seed_from_u64(0)).unwrap();
+        file.write_all("Hello, world!".as_bytes()).unwrap();
+        file.abort();
+        assert!(!path.exists());
+        assert_eq!(fs::read_dir(tempdir.path()).unwrap().count(), 0);
+    }
+
     #[test]
     fn test_clean_leftover_tmp_files_removes_leftover_tmp_files() {
         let tempdir = tempdir().unwrap();
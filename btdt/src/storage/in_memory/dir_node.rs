@@ -72,6 +72,37 @@ impl DirNode {
         }
     }
 
+    pub fn remove_file(&mut self, name: &str) -> IoPathResult<Arc<FileNode>> {
+        match self.0.get(name) {
+            Some(Node::File(_)) => match self.0.remove(name) {
+                Some(Node::File(file)) => Ok(file),
+                _ => unreachable!(),
+            },
+            Some(Node::Dir(_)) => Err(IoPathError::new(
+                io::Error::new(ErrorKind::IsADirectory, "Is a directory"),
+                name,
+            )),
+            None => Err(IoPathError::new(
+                io::Error::new(ErrorKind::NotFound, "No such file or directory"),
+                name,
+            )),
+        }
+    }
+
+    pub fn insert_file(&mut self, name: &str, file: Arc<FileNode>) -> IoPathResult<()> {
+        if let Some(Node::Dir(_)) = self.0.get(name) {
+            return Err(IoPathError::new(
+                io::Error::new(
+                    ErrorKind::IsADirectory,
+                    "A directory with the same name already exists",
+                ),
+                name,
+            ));
+        }
+        self.0.insert(name.to_string(), Node::File(file));
+        Ok(())
+    }
+
     pub fn create_file(&mut self, name: &str) -> IoPathResult<FileWriter> {
         let node = self
             .0
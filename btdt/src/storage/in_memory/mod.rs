@@ -7,7 +7,7 @@ mod path_iter;
 use super::in_memory::dir_node::{DirNode, Node};
 use super::in_memory::path_iter::PathIterExt;
 use crate::error::{IoPathResult, WithPath};
-use crate::storage::in_memory::file_node::{FileReader, FileWriter};
+use crate::storage::in_memory::file_node::{FileNode, FileReader, FileWriter};
 use crate::storage::{EntryType, FileHandle, Storage, StorageEntry};
 use crate::util::close::SelfClosing;
 use std::borrow::Cow;
@@ -112,6 +112,7 @@ impl Storage for InMemoryStorage {
                 return match dir.get(component.name) {
                     Some(Node::File(file)) => Ok(FileHandle {
                         size_hint: file.size() as u64,
+                        modified: Some(file.modified()),
                         reader: file.reader(),
                     }),
                     Some(Node::Dir(_)) => {
@@ -180,6 +181,10 @@ impl Storage for InMemoryStorage {
                         Node::Dir(dir) => dir.size() as u64,
                         Node::File(file) => file.size() as u64,
                     },
+                    modified: match node {
+                        Node::Dir(_) => None,
+                        Node::File(file) => Some(file.modified()),
+                    },
                 })
             })
             .collect::<Vec<_>>()
@@ -203,6 +208,59 @@ impl Storage for InMemoryStorage {
         ))
         .with_path(path)
     }
+
+    fn rename(&self, from: &str, to: &str) -> IoPathResult<()> {
+        let root = &mut *self.root.write().unwrap();
+        let file = remove_file(root, from)?;
+        if let Err(err) = insert_file(root, to, file.clone()) {
+            insert_file(root, from, file)?;
+            return Err(err);
+        }
+        Ok(())
+    }
+}
+
+fn remove_file(root: &mut DirNode, path: &str) -> IoPathResult<Arc<FileNode>> {
+    let mut dir = root;
+    for (i, component) in path.path_components().with_path(path)?.enumerate() {
+        if component.is_last {
+            return dir.remove_file(component.name);
+        }
+
+        dir = match dir.get_mut(component.name) {
+            Some(Node::Dir(dir)) => dir,
+            _ => {
+                return Err(io::Error::new(
+                    ErrorKind::NotFound,
+                    "No such file or directory",
+                ))
+                .with_path(first_n_path_components(path, i + 1)?);
+            }
+        };
+    }
+
+    Err(io::Error::new(
+        ErrorKind::InvalidInput,
+        "Path must contain at least one component",
+    ))
+    .with_path(path)
+}
+
+fn insert_file(root: &mut DirNode, path: &str, file: Arc<FileNode>) -> IoPathResult<()> {
+    let mut dir = root;
+    for component in path.path_components().with_path(path)? {
+        if component.is_last {
+            return dir.insert_file(component.name, file);
+        }
+
+        dir = dir.get_or_insert_dir(component.name)?;
+    }
+
+    Err(io::Error::new(
+        ErrorKind::InvalidInput,
+        "Path must contain at least one component",
+    ))
+    .with_path(path)
 }
 
 fn first_n_path_components(path: &str, n: usize) -> IoPathResult<String> {
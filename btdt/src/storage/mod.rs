@@ -4,11 +4,13 @@
 
 pub mod filesystem;
 pub mod in_memory;
+pub mod s3;
 #[cfg(test)]
 pub mod tests;
 
 use super::util::close::Close;
 use crate::error::IoPathResult;
+use chrono::{DateTime, Utc};
 use std::borrow::Cow;
 use std::io::{Read, Write};
 
@@ -57,6 +59,14 @@ pub trait Storage {
     /// The implementation must ensure that the file becomes available atomically when
     /// [Close::close] is called. It also must create intermediate directories if necessary.
     fn put(&self, path: &str) -> IoPathResult<Self::Writer>;
+
+    /// Moves the file at path `from` to path `to`.
+    ///
+    /// An existing file at `to` is replaced. Intermediate directories are created if necessary.
+    ///
+    /// The implementation must ensure that the file becomes available at `to` atomically. It is
+    /// not required that the file disappears from `from` at the same time.
+    fn rename(&self, from: &str, to: &str) -> IoPathResult<()>;
 }
 
 /// A handle to a file in the storage, containing its size and a reader for its content.
@@ -64,6 +74,9 @@ pub struct FileHandle<Reader: Read> {
     /// The (approximate) size of the file in bytes.
     pub size_hint: u64,
 
+    /// The time of the last modification of the file, if provided by the storage.
+    pub modified: Option<DateTime<Utc>>,
+
     /// A reader for the file content.
     pub reader: Reader,
 }
@@ -88,4 +101,8 @@ pub struct StorageEntry<'a> {
     ///
     /// This is `0` for directories.
     pub size: u64,
+    /// The time of the last modification of the entry, if provided by the storage.
+    ///
+    /// This is `None` for directories.
+    pub modified: Option<DateTime<Utc>>,
 }
@@ -0,0 +1,1409 @@
+//! Implementation of the `Storage` trait for S3-compatible object storage.
+//!
+//! Object storage does not have real directories. Instead, keys are grouped by the `/` separator
+//! into implicit directories. Consequently, a directory exists exactly as long as there is a file
+//! within it, and deleting the last file in a directory also removes the directory.
+
+pub(crate) mod sigv4;
+pub(crate) mod xml;
+
+use crate::error::{IoPathError, IoPathResult, WithPath};
+use crate::storage::{EntryType, FileHandle, Storage, StorageEntry};
+use crate::util::close::Close;
+use crate::util::http::{
+    Header, HttpClient, HttpResponse, HttpStatus, ReadResponseBody, ReadResponseHeaders,
+};
+use chrono::{DateTime, Utc};
+use sigv4::{CanonicalRequest, Signer, UNSIGNED_PAYLOAD};
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::io::{ErrorKind, Read, Write};
+use std::sync::Arc;
+use std::{io, thread};
+use url::Url;
+
+/// Default size of the parts of a multipart upload (16 MiB).
+pub const DEFAULT_PART_SIZE: usize = 16 * 1024 * 1024;
+
+/// Size of the byte ranges requested when reading an object (16 MiB).
+const RANGE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Maximum size of an object copied with a single request, and of the parts when copying larger
+/// objects with a multipart upload (5 GiB).
+const MAX_COPY_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+
+/// Credentials to authenticate with the S3 API.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials {
+    /// The access key ID.
+    pub access_key_id: String,
+    /// The secret access key.
+    pub secret_access_key: String,
+    /// The session token when using temporary credentials.
+    pub session_token: Option<String>,
+}
+
+impl Debug for Credentials {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("access_key_id", &self.access_key_id)
+            .field("secret_access_key", &"<redacted>")
+            .field(
+                "session_token",
+                &self.session_token.as_ref().map(|_| "<redacted>"),
+            )
+            .finish()
+    }
+}
+
+/// Configuration of an [S3Storage].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct S3Config {
+    /// Base URL of the S3 API, e.g. `https://s3.eu-central-1.amazonaws.com`.
+    pub endpoint: Url,
+    /// The region used to sign requests.
+    pub region: String,
+    /// The bucket to store files in.
+    pub bucket: String,
+    /// Prefix prepended to all object keys.
+    ///
+    /// This allows to store multiple caches in the same bucket.
+    pub prefix: String,
+    /// Whether to address the bucket as part of the path (`https://endpoint/bucket/key`) instead of
+    /// as part of the host name (`https://bucket.endpoint/key`).
+    ///
+    /// Most S3-compatible services other than AWS S3 require path-style addressing.
+    pub path_style: bool,
+    /// The credentials to authenticate with.
+    pub credentials: Credentials,
+}
+
+impl S3Config {
+    /// Creates a configuration for the given bucket and key prefix from the standard AWS
+    /// environment variables.
+    ///
+    /// The following environment variables are used:
+    ///
+    /// - `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY` (required),
+    /// - `AWS_SESSION_TOKEN` (optional),
+    /// - `AWS_REGION` or `AWS_DEFAULT_REGION` (defaults to `us-east-1`),
+    /// - `AWS_ENDPOINT_URL_S3` or `AWS_ENDPOINT_URL` to use an S3-compatible service other than
+    ///   AWS S3. If set, path-style addressing is used.
+    pub fn from_env(
+        bucket: impl Into<String>,
+        prefix: impl Into<String>,
+    ) -> Result<Self, S3ConfigError> {
+        Self::from_lookup(bucket, prefix, |name| std::env::var(name).ok())
+    }
+
+    /// Like [S3Config::from_env], but looks up the variables with the given function instead of
+    /// reading the environment.
+    ///
+    /// This allows to override some of the environment variables with other configuration sources.
+    pub fn from_lookup(
+        bucket: impl Into<String>,
+        prefix: impl Into<String>,
+        var: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, S3ConfigError> {
+        let require = |name: &'static str| var(name).ok_or(S3ConfigError::MissingEnvVar(name));
+        let region = var("AWS_REGION")
+            .or_else(|| var("AWS_DEFAULT_REGION"))
+            .unwrap_or_else(|| "us-east-1".into());
+        let (endpoint, path_style) =
+            match var("AWS_ENDPOINT_URL_S3").or_else(|| var("AWS_ENDPOINT_URL")) {
+                Some(endpoint) => (endpoint, true),
+                None => (format!("https://s3.{region}.amazonaws.com"), false),
+            };
+        Ok(Self {
+            endpoint: Url::parse(&endpoint)
+                .map_err(|err| S3ConfigError::InvalidEndpoint(err.to_string()))?,
+            region,
+            bucket: bucket.into(),
+            prefix: prefix.into(),
+            path_style,
+            credentials: Credentials {
+                access_key_id: require("AWS_ACCESS_KEY_ID")?,
+                secret_access_key: require("AWS_SECRET_ACCESS_KEY")?,
+                session_token: var("AWS_SESSION_TOKEN"),
+            },
+        })
+    }
+}
+
+/// An error in the configuration of an [S3Storage].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum S3ConfigError {
+    /// A required environment variable is not set.
+    MissingEnvVar(&'static str),
+    /// The endpoint is not a valid URL.
+    InvalidEndpoint(String),
+}
+
+impl Display for S3ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingEnvVar(name) => write!(f, "missing environment variable: {name}"),
+            Self::InvalidEndpoint(err) => write!(f, "invalid S3 endpoint URL: {err}"),
+        }
+    }
+}
+
+impl Error for S3ConfigError {}
+
+/// An error response of the S3 API.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct S3Error {
+    /// The HTTP status code.
+    pub status: u16,
+    /// The S3 error code, e.g. `NoSuchKey`. Empty if the response did not include one.
+    pub code: String,
+    /// The human-readable error message. Empty if the response did not include one.
+    pub message: String,
+}
+
+impl S3Error {
+    fn into_io_error(self) -> io::Error {
+        let kind = match self.status {
+            403 => ErrorKind::PermissionDenied,
+            404 => ErrorKind::NotFound,
+            _ => ErrorKind::Other,
+        };
+        io::Error::new(kind, self)
+    }
+}
+
+impl Display for S3Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "S3 error: {}", self.status)?;
+        if !self.code.is_empty() {
+            write!(f, " {}", self.code)?;
+        }
+        if !self.message.is_empty() {
+            write!(f, ": {}", self.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for S3Error {}
+
+/// Storage implementation using an S3-compatible object storage.
+///
+/// Files are stored as objects in the configured bucket with the storage path (without the leading
+/// `/`) appended to the configured prefix as key. Large files are uploaded with multipart uploads,
+/// and files are read in chunks with ranged requests, so that neither requires to hold the whole
+/// file in memory.
+///
+/// See the [module documentation](self) for differences in the handling of directories compared
+/// to other storage implementations.
+#[derive(Clone)]
+pub struct S3Storage {
+    client: S3Client,
+    part_size: usize,
+    copy_part_size: u64,
+}
+
+impl S3Storage {
+    /// Creates a new S3 storage using the given HTTP client and configuration.
+    pub fn new(http_client: HttpClient, config: S3Config) -> Self {
+        Self {
+            client: S3Client {
+                http: Arc::new(http_client),
+                config: Arc::new(config),
+            },
+            part_size: DEFAULT_PART_SIZE,
+            copy_part_size: MAX_COPY_SIZE,
+        }
+    }
+
+    /// Sets the size of the parts of multipart uploads.
+    ///
+    /// Files smaller than this are uploaded with a single request. Note that AWS S3 requires parts
+    /// to be at least 5 MiB.
+    pub fn with_part_size(mut self, part_size: usize) -> Self {
+        self.part_size = part_size.max(1);
+        self
+    }
+
+    #[cfg(test)]
+    fn with_copy_part_size(mut self, copy_part_size: u64) -> Self {
+        self.copy_part_size = copy_part_size.max(1);
+        self
+    }
+
+    /// Returns the configuration of the storage.
+    pub fn config(&self) -> &S3Config {
+        &self.client.config
+    }
+
+    fn key(&self, path: &str) -> IoPathResult<String> {
+        let Some(relative_path) = path.strip_prefix('/') else {
+            return Err(IoPathError::new(
+                io::Error::new(
+                    ErrorKind::InvalidInput,
+                    "Path must be absolute, i.e. start with a slash '/'",
+                ),
+                path,
+            ));
+        };
+        Ok(format!("{}{relative_path}", self.client.key_prefix()))
+    }
+
+    fn dir_prefix(&self, path: &str) -> IoPathResult<String> {
+        let key = self.key(path)?;
+        if key.len() == self.client.key_prefix().len() {
+            Ok(key)
+        } else {
+            Ok(format!("{}/", key.trim_end_matches('/')))
+        }
+    }
+
+    fn has_entries(&self, dir_prefix: &str) -> io::Result<bool> {
+        let page = self.client.list_objects(dir_prefix, None, Some(1))?;
+        Ok(!page.objects.is_empty() || !page.common_prefixes.is_empty())
+    }
+}
+
+impl Storage for S3Storage {
+    type Reader = S3Reader;
+    type Writer = S3Writer;
+
+    fn delete(&self, path: &str) -> IoPathResult<()> {
+        let key = self.key(path)?;
+        match self.client.head_object(&key) {
+            Ok(_) => self.client.delete_object(&key).with_path(path),
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                if self.has_entries(&self.dir_prefix(path)?).with_path(path)? {
+                    Err(IoPathError::new(
+                        io::Error::new(ErrorKind::DirectoryNotEmpty, "directory not empty"),
+                        path,
+                    ))
+                } else {
+                    Err(IoPathError::new(err, path))
+                }
+            }
+            Err(err) => Err(IoPathError::new(err, path)),
+        }
+    }
+
+    fn exists_file(&self, path: &str) -> IoPathResult<bool> {
+        match self.client.head_object(&self.key(path)?) {
+            Ok(_) => Ok(true),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(IoPathError::new(err, path)),
+        }
+    }
+
+    fn get(&self, path: &str) -> IoPathResult<FileHandle<Self::Reader>> {
+        let key = self.key(path)?;
+        let object = self.client.head_object(&key).with_path(path)?;
+        Ok(FileHandle {
+            size_hint: object.size,
+            modified: object.last_modified,
+            reader: S3Reader {
+                client: self.client.clone(),
+                key,
+                etag: object.etag,
+                size: object.size,
+                position: 0,
+                range_end: 0,
+                response: None,
+            },
+        })
+    }
+
+    fn list(
+        &self,
+        path: &str,
+    ) -> IoPathResult<impl Iterator<Item = IoPathResult<StorageEntry<'_>>>> {
+        let dir_prefix = self.dir_prefix(path)?;
+        let mut entries = vec![];
+        let mut continuation_token = None;
+        loop {
+            let page = self
+                .client
+                .list_objects(&dir_prefix, continuation_token.as_deref(), None)
+                .with_path(path)?;
+            entries.extend(page.common_prefixes.iter().filter_map(|prefix| {
+                let name = prefix.strip_prefix(&dir_prefix)?.trim_end_matches('/');
+                (!name.is_empty()).then(|| StorageEntry {
+                    entry_type: EntryType::Directory,
+                    name: Cow::Owned(name.to_string()),
+                    size: 0,
+                    modified: None,
+                })
+            }));
+            entries.extend(page.objects.iter().filter_map(|object| {
+                let name = object.key.strip_prefix(&dir_prefix)?;
+                (!name.is_empty()).then(|| StorageEntry {
+                    entry_type: EntryType::File,
+                    name: Cow::Owned(name.to_string()),
+                    size: object.size,
+                    modified: object.last_modified,
+                })
+            }));
+            continuation_token = page.next_continuation_token;
+            if continuation_token.is_none() {
+                break;
+            }
+        }
+
+        if entries.is_empty() && dir_prefix.len() > self.client.key_prefix().len() {
+            let kind = if self.exists_file(path)? {
+                ErrorKind::NotADirectory
+            } else {
+                ErrorKind::NotFound
+            };
+            return Err(IoPathError::new(
+                io::Error::new(kind, "no such directory"),
+                path,
+            ));
+        }
+
+        Ok(entries.into_iter().map(Ok))
+    }
+
+    fn put(&self, path: &str) -> IoPathResult<Self::Writer> {
+        Ok(S3Writer {
+            client: self.client.clone(),
+            key: self.key(path)?,
+            part_size: self.part_size,
+            buffer: Vec::new(),
+            upload: None,
+            finalized: false,
+        })
+    }
+
+    /// Copies the object to the new key and deletes the original object afterward, as S3 does
+    /// not support renaming objects.
+    fn rename(&self, from: &str, to: &str) -> IoPathResult<()> {
+        let source_key = self.key(from)?;
+        let key = self.key(to)?;
+        let object = self.client.head_object(&source_key).with_path(from)?;
+        self.client
+            .copy_object(&source_key, &key, object.size, self.copy_part_size)
+            .with_path(to)?;
+        self.client.delete_object(&source_key).with_path(from)
+    }
+}
+
+/// Metadata of an object.
+struct ObjectInfo {
+    size: u64,
+    etag: Option<String>,
+    last_modified: Option<DateTime<Utc>>,
+}
+
+/// A response of the S3 API with successful status code.
+struct S3Response {
+    status: HttpStatus,
+    headers: Vec<Header>,
+    response: HttpResponse<ReadResponseHeaders>,
+}
+
+impl S3Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|header| header.key().eq_ignore_ascii_case(name))
+            .map(Header::value)
+    }
+
+    fn into_body(self) -> io::Result<HttpResponse<ReadResponseBody>> {
+        Ok(self.response.read_body()?)
+    }
+
+    fn into_string(self) -> io::Result<String> {
+        let mut body = String::new();
+        self.into_body()?.read_to_string(&mut body)?;
+        Ok(body)
+    }
+}
+
+/// Low-level client for the S3 API operations used by the storage.
+#[derive(Clone)]
+struct S3Client {
+    http: Arc<HttpClient>,
+    config: Arc<S3Config>,
+}
+
+impl S3Client {
+    fn key_prefix(&self) -> Cow<'_, str> {
+        let prefix = self.config.prefix.trim_matches('/');
+        if prefix.is_empty() {
+            Cow::Borrowed("")
+        } else {
+            Cow::Owned(format!("{prefix}/"))
+        }
+    }
+
+    fn url(&self, key: &str, query: &[(&str, &str)]) -> Url {
+        let mut url = self.config.endpoint.clone();
+        let base_path = url.path().trim_end_matches('/').to_string();
+        if self.config.path_style {
+            url.set_path(&format!(
+                "{base_path}{}",
+                sigv4::encode_path(&format!("/{}/{key}", self.config.bucket))
+            ));
+        } else {
+            let host = format!(
+                "{}.{}",
+                self.config.bucket,
+                url.host_str().unwrap_or_default()
+            );
+            let _ = url.set_host(Some(&host));
+            url.set_path(&format!(
+                "{base_path}{}",
+                sigv4::encode_path(&format!("/{key}"))
+            ));
+        }
+        if query.is_empty() {
+            url.set_query(None);
+        } else {
+            url.set_query(Some(
+                &query
+                    .iter()
+                    .map(|(name, value)| {
+                        format!(
+                            "{}={}",
+                            sigv4::encode_component(name),
+                            sigv4::encode_component(value)
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("&"),
+            ));
+        }
+        url
+    }
+
+    /// Sends a signed request and returns the response if it has a successful status code.
+    fn send(
+        &self,
+        method: &str,
+        url: &Url,
+        headers: &[(&str, &str)],
+        body: Option<&[u8]>,
+    ) -> io::Result<S3Response> {
+        let host = match url.port() {
+            Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+        let time = Utc::now();
+        let amz_date = sigv4::amz_date(time);
+        let mut signed_headers = vec![
+            ("host", host.as_str()),
+            ("x-amz-content-sha256", UNSIGNED_PAYLOAD),
+            ("x-amz-date", amz_date.as_str()),
+        ];
+        if let Some(session_token) = &self.config.credentials.session_token {
+            signed_headers.push(("x-amz-security-token", session_token));
+        }
+        signed_headers.extend_from_slice(headers);
+        let authorization = Signer::new(&self.config.credentials, &self.config.region)
+            .authorization(
+                &CanonicalRequest {
+                    method,
+                    path: url.path(),
+                    query: url.query().unwrap_or_default(),
+                    headers: &signed_headers,
+                    payload_hash: UNSIGNED_PAYLOAD,
+                },
+                time,
+            );
+
+        let try_request = || {
+            let mut request = self.http.method(method, url)?;
+            for (name, value) in &signed_headers[1..] {
+                request.header(name, value)?;
+            }
+            request.header("Authorization", &authorization)?;
+            match body {
+                Some(body) => {
+                    let mut request = request.body_with_size(body.len())?;
+                    request.write_all(body)?;
+                    request.response()?.read_status()
+                }
+                None => request.no_body()?.read_status(),
+            }
+        };
+        let (status, mut response) = try_request().map_err(io::Error::from)?;
+        let mut response_headers = vec![];
+        while let Some(header) = response.read_next_header().map_err(io::Error::from)? {
+            response_headers.push(header);
+        }
+        let response = S3Response {
+            status,
+            headers: response_headers,
+            response,
+        };
+
+        if !response.status.is_success() {
+            let status = response.status.code_u16();
+            let error = if method == "HEAD" {
+                None
+            } else {
+                response
+                    .into_string()
+                    .ok()
+                    .and_then(|body| xml::parse_error(&body))
+            }
+            .unwrap_or_default();
+            return Err(S3Error {
+                status,
+                code: error.code,
+                message: error.message,
+            }
+            .into_io_error());
+        }
+        Ok(response)
+    }
+
+    fn head_object(&self, key: &str) -> io::Result<ObjectInfo> {
+        let response = self.send("HEAD", &self.url(key, &[]), &[], None)?;
+        let size = response
+            .header("Content-Length")
+            .and_then(|size| size.parse().ok())
+            .ok_or_else(|| {
+                io::Error::new(
+                    ErrorKind::InvalidData,
+                    "missing Content-Length header in S3 response",
+                )
+            })?;
+        Ok(ObjectInfo {
+            size,
+            etag: response.header("ETag").map(str::to_string),
+            last_modified: response
+                .header("Last-Modified")
+                .and_then(|last_modified| DateTime::parse_from_rfc2822(last_modified).ok())
+                .map(|last_modified| last_modified.to_utc()),
+        })
+    }
+
+    fn delete_object(&self, key: &str) -> io::Result<()> {
+        self.send("DELETE", &self.url(key, &[]), &[], None)?;
+        Ok(())
+    }
+
+    fn list_objects(
+        &self,
+        prefix: &str,
+        continuation_token: Option<&str>,
+        max_keys: Option<usize>,
+    ) -> io::Result<xml::ListObjectsPage> {
+        let max_keys = max_keys.map(|max_keys| max_keys.to_string());
+        let mut query = vec![("list-type", "2"), ("delimiter", "/"), ("prefix", prefix)];
+        if let Some(continuation_token) = continuation_token {
+            query.push(("continuation-token", continuation_token));
+        }
+        if let Some(max_keys) = &max_keys {
+            query.push(("max-keys", max_keys));
+        }
+        let body = self
+            .send("GET", &self.url("", &query), &[], None)?
+            .into_string()?;
+        xml::parse_list_objects(&body)
+    }
+
+    fn get_range(
+        &self,
+        key: &str,
+        etag: Option<&str>,
+        start: u64,
+        end: u64,
+    ) -> io::Result<HttpResponse<ReadResponseBody>> {
+        let range = format!("bytes={start}-{}", end - 1);
+        let mut headers = vec![("range", range.as_str())];
+        if let Some(etag) = etag {
+            headers.push(("if-match", etag));
+        }
+        let response = self
+            .send("GET", &self.url(key, &[]), &headers, None)
+            .map_err(
+                |err| match err.get_ref().and_then(|err| err.downcast_ref()) {
+                    Some(S3Error { status: 412, .. }) => {
+                        io::Error::other(format!("object {key} was modified while reading it"))
+                    }
+                    _ => err,
+                },
+            )?;
+        if response.status.code() != "206" {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "unexpected response to range request: {}",
+                    response.status.as_str()
+                ),
+            ));
+        }
+        response.into_body()
+    }
+
+    fn put_object(&self, key: &str, data: &[u8]) -> io::Result<()> {
+        self.send("PUT", &self.url(key, &[]), &[], Some(data))?;
+        Ok(())
+    }
+
+    /// Copies an object within the bucket, using a multipart upload if the object is larger than
+    /// `part_size`.
+    fn copy_object(
+        &self,
+        source_key: &str,
+        key: &str,
+        size: u64,
+        part_size: u64,
+    ) -> io::Result<()> {
+        let copy_source = sigv4::encode_path(&format!("/{}/{source_key}", self.config.bucket));
+        if size <= part_size {
+            let body = self
+                .send(
+                    "PUT",
+                    &self.url(key, &[]),
+                    &[("x-amz-copy-source", &copy_source)],
+                    Some(&[]),
+                )?
+                .into_string()?;
+            return error_in_body(&body);
+        }
+
+        let upload_id = self.create_multipart_upload(key)?;
+        let copy_parts = || {
+            let mut etags = vec![];
+            for (i, start) in (0..size).step_by(part_size as usize).enumerate() {
+                let end = (start + part_size).min(size);
+                etags.push(self.upload_part_copy(
+                    key,
+                    &upload_id,
+                    i + 1,
+                    &copy_source,
+                    start,
+                    end,
+                )?);
+            }
+            self.complete_multipart_upload(key, &upload_id, &etags)
+        };
+        copy_parts().inspect_err(|_| {
+            let _ = self.abort_multipart_upload(key, &upload_id);
+        })
+    }
+
+    fn upload_part_copy(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: usize,
+        copy_source: &str,
+        start: u64,
+        end: u64,
+    ) -> io::Result<String> {
+        let part_number = part_number.to_string();
+        let range = format!("bytes={start}-{}", end - 1);
+        let body = self
+            .send(
+                "PUT",
+                &self.url(
+                    key,
+                    &[("partNumber", &part_number), ("uploadId", upload_id)],
+                ),
+                &[
+                    ("x-amz-copy-source", copy_source),
+                    ("x-amz-copy-source-range", &range),
+                ],
+                Some(&[]),
+            )?
+            .into_string()?;
+        error_in_body(&body)?;
+        xml::parse_etag(&body)
+    }
+
+    fn create_multipart_upload(&self, key: &str) -> io::Result<String> {
+        let body = self
+            .send("POST", &self.url(key, &[("uploads", "")]), &[], Some(&[]))?
+            .into_string()?;
+        xml::parse_upload_id(&body)
+    }
+
+    fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: usize,
+        data: &[u8],
+    ) -> io::Result<String> {
+        let part_number = part_number.to_string();
+        let response = self.send(
+            "PUT",
+            &self.url(
+                key,
+                &[("partNumber", &part_number), ("uploadId", upload_id)],
+            ),
+            &[],
+            Some(data),
+        )?;
+        response.header("ETag").map(str::to_string).ok_or_else(|| {
+            io::Error::new(ErrorKind::InvalidData, "missing ETag header in S3 response")
+        })
+    }
+
+    fn complete_multipart_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        etags: &[String],
+    ) -> io::Result<()> {
+        let body = self
+            .send(
+                "POST",
+                &self.url(key, &[("uploadId", upload_id)]),
+                &[],
+                Some(xml::complete_multipart_upload(etags).as_bytes()),
+            )?
+            .into_string()?;
+        error_in_body(&body)
+    }
+
+    fn abort_multipart_upload(&self, key: &str, upload_id: &str) -> io::Result<()> {
+        self.send(
+            "DELETE",
+            &self.url(key, &[("uploadId", upload_id)]),
+            &[],
+            None,
+        )?;
+        Ok(())
+    }
+}
+
+/// Returns an error if the body of a successful response is an error response.
+///
+/// Some requests may fail after the status code has been sent.
+fn error_in_body(body: &str) -> io::Result<()> {
+    match xml::parse_error(body) {
+        Some(error) => Err(S3Error {
+            status: 200,
+            code: error.code,
+            message: error.message,
+        }
+        .into_io_error()),
+        None => Ok(()),
+    }
+}
+
+/// Reader for an object in an [S3Storage].
+///
+/// The object is requested in chunks with ranged requests. If the object is modified while reading
+/// it, an error is returned.
+pub struct S3Reader {
+    client: S3Client,
+    key: String,
+    etag: Option<String>,
+    size: u64,
+    position: u64,
+    range_end: u64,
+    response: Option<HttpResponse<ReadResponseBody>>,
+}
+
+impl Read for S3Reader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.position >= self.size {
+            return Ok(0);
+        }
+        let response = match &mut self.response {
+            Some(response) => response,
+            None => {
+                self.range_end = self.size.min(self.position + RANGE_SIZE);
+                self.response.insert(self.client.get_range(
+                    &self.key,
+                    self.etag.as_deref(),
+                    self.position,
+                    self.range_end,
+                )?)
+            }
+        };
+        let n = response.read(buf)?;
+        if n == 0 {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "S3 response ended before the end of the requested range",
+            ));
+        }
+        self.position += n as u64;
+        if self.position >= self.range_end {
+            self.response = None;
+        }
+        Ok(n)
+    }
+}
+
+struct MultipartUpload {
+    upload_id: String,
+    etags: Vec<String>,
+}
+
+/// Writer for an object in an [S3Storage].
+///
+/// Data is buffered in memory up to the part size. Smaller objects are uploaded with a single
+/// request when the writer is closed, larger objects with a multipart upload. The object becomes
+/// available once the writer is closed. If dropped without closing, the writer will be closed
+/// implicitly, unless the thread is panicking. In that case the upload is aborted, as with
+/// [Close::abort].
+pub struct S3Writer {
+    client: S3Client,
+    key: String,
+    part_size: usize,
+    buffer: Vec<u8>,
+    upload: Option<MultipartUpload>,
+    finalized: bool,
+}
+
+impl S3Writer {
+    fn upload_buffer_as_part(&mut self) -> io::Result<()> {
+        let upload = match &mut self.upload {
+            Some(upload) => upload,
+            None => self.upload.insert(MultipartUpload {
+                upload_id: self.client.create_multipart_upload(&self.key)?,
+                etags: vec![],
+            }),
+        };
+        let etag = self.client.upload_part(
+            &self.key,
+            &upload.upload_id,
+            upload.etags.len() + 1,
+            &self.buffer,
+        )?;
+        upload.etags.push(etag);
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Aborts a started multipart upload, so that the object is not stored.
+    fn abort_upload(&mut self) {
+        self.finalized = true;
+        if let Some(upload) = self.upload.take() {
+            let _ = self
+                .client
+                .abort_multipart_upload(&self.key, &upload.upload_id);
+        }
+    }
+
+    fn finalize(&mut self) -> io::Result<()> {
+        self.finalized = true;
+        if self.upload.is_none() {
+            return self.client.put_object(&self.key, &self.buffer);
+        }
+        let result = if self.buffer.is_empty() {
+            Ok(())
+        } else {
+            self.upload_buffer_as_part()
+        };
+        let upload = self.upload.take().expect("upload was started");
+        result
+            .and_then(|_| {
+                self.client
+                    .complete_multipart_upload(&self.key, &upload.upload_id, &upload.etags)
+            })
+            .inspect_err(|_| {
+                let _ = self
+                    .client
+                    .abort_multipart_upload(&self.key, &upload.upload_id);
+            })
+    }
+}
+
+impl Write for S3Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.part_size - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..n]);
+        if self.buffer.len() >= self.part_size {
+            self.upload_buffer_as_part()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // Parts must have a minimum size, so buffered data can only be uploaded once the part is
+        // full or the writer is closed.
+        Ok(())
+    }
+}
+
+impl Close for S3Writer {
+    fn close(mut self) -> io::Result<()> {
+        self.finalize()
+    }
+
+    fn abort(mut self) {
+        self.abort_upload();
+    }
+}
+
+impl Drop for S3Writer {
+    fn drop(&mut self) {
+        if self.finalized {
+            return;
+        }
+        if thread::panicking() {
+            self.abort_upload();
+        } else {
+            self.finalize().expect("Failed to upload object to S3");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::{
+        assert_modified_between, read_file_from_storage_to_string, write_file_to_storage,
+    };
+    use crate::test_util::s3::FakeS3Server;
+    use std::sync::LazyLock;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static SERVER: LazyLock<FakeS3Server> = LazyLock::new(|| FakeS3Server::start().unwrap());
+
+    fn storage() -> S3Storage {
+        storage_with_prefix("")
+    }
+
+    fn storage_with_prefix(prefix: &str) -> S3Storage {
+        static BUCKET_COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let bucket = format!("bucket-{}", BUCKET_COUNTER.fetch_add(1, Ordering::Relaxed));
+        S3Storage::new(
+            HttpClient::default().unwrap(),
+            SERVER.config(bucket, prefix),
+        )
+    }
+
+    fn list_sorted(storage: &S3Storage, path: &str) -> IoPathResult<Vec<StorageEntry<'static>>> {
+        let mut entries: Vec<_> = storage
+            .list(path)?
+            .map(|entry| {
+                entry.map(|entry| StorageEntry {
+                    name: Cow::Owned(entry.name.into_owned()),
+                    modified: None,
+                    ..entry
+                })
+            })
+            .collect::<IoPathResult<_>>()?;
+        entries.sort_unstable_by_key(|entry| entry.name.to_string());
+        Ok(entries)
+    }
+
+    fn file(name: &str, size: u64) -> StorageEntry<'static> {
+        StorageEntry {
+            entry_type: EntryType::File,
+            name: Cow::Owned(name.to_string()),
+            size,
+            modified: None,
+        }
+    }
+
+    fn dir(name: &str) -> StorageEntry<'static> {
+        StorageEntry {
+            entry_type: EntryType::Directory,
+            name: Cow::Owned(name.to_string()),
+            size: 0,
+            modified: None,
+        }
+    }
+
+    #[test]
+    fn test_get_returns_error_for_non_existent_file() {
+        let storage = storage();
+        assert_eq!(
+            storage
+                .get("/non-existent.txt")
+                .err()
+                .unwrap()
+                .io_error()
+                .kind(),
+            ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn test_can_get_file_that_was_previously_put_into_storage() {
+        let storage = storage();
+        write_file_to_storage(&storage, "/dir/file.txt", "Hello, world!").unwrap();
+        let handle = storage.get("/dir/file.txt").unwrap();
+        assert_eq!(handle.size_hint, 13);
+        assert_eq!(
+            read_file_from_storage_to_string(&storage, "/dir/file.txt").unwrap(),
+            "Hello, world!"
+        );
+    }
+
+    #[test]
+    fn test_can_store_empty_file() {
+        let storage = storage();
+        write_file_to_storage(&storage, "/empty", "").unwrap();
+        assert_eq!(
+            read_file_from_storage_to_string(&storage, "/empty").unwrap(),
+            ""
+        );
+    }
+
+    #[test]
+    fn test_can_overwrite_existing_file() {
+        let storage = storage();
+        write_file_to_storage(&storage, "/file.txt", "Hello, world!").unwrap();
+        write_file_to_storage(&storage, "/file.txt", "Bye, world!").unwrap();
+        assert_eq!(
+            read_file_from_storage_to_string(&storage, "/file.txt").unwrap(),
+            "Bye, world!"
+        );
+    }
+
+    #[test]
+    fn test_uses_multipart_upload_for_large_files() {
+        let storage = storage().with_part_size(4);
+        let mut writer = storage.put("/file.txt").unwrap();
+        writer.write_all(b"Hello, world!").unwrap();
+        writer.close().unwrap();
+        assert_eq!(
+            read_file_from_storage_to_string(&storage, "/file.txt").unwrap(),
+            "Hello, world!"
+        );
+        assert_eq!(
+            SERVER.completed_multipart_uploads(&storage.config().bucket),
+            1
+        );
+    }
+
+    #[test]
+    fn test_can_rename_file() {
+        let storage = storage();
+        write_file_to_storage(&storage, "/file.txt", "Hello, world!").unwrap();
+        write_file_to_storage(&storage, "/dir/renamed.txt", "Goodbye, world!").unwrap();
+        storage.rename("/file.txt", "/dir/renamed.txt").unwrap();
+        assert!(!storage.exists_file("/file.txt").unwrap());
+        assert_eq!(
+            read_file_from_storage_to_string(&storage, "/dir/renamed.txt").unwrap(),
+            "Hello, world!"
+        );
+    }
+
+    #[test]
+    fn test_uses_multipart_copy_for_large_files() {
+        let storage = storage().with_copy_part_size(4);
+        write_file_to_storage(&storage, "/file.txt", "Hello, world!").unwrap();
+        storage.rename("/file.txt", "/renamed.txt").unwrap();
+        assert_eq!(
+            read_file_from_storage_to_string(&storage, "/renamed.txt").unwrap(),
+            "Hello, world!"
+        );
+        assert_eq!(
+            SERVER.completed_multipart_uploads(&storage.config().bucket),
+            1
+        );
+    }
+
+    #[test]
+    fn test_rename_returns_error_for_non_existent_file() {
+        let storage = storage();
+        let result = storage.rename("/non-existent.txt", "/renamed.txt");
+        assert_eq!(result.err().unwrap().io_error().kind(), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_reads_large_files_in_ranges() {
+        let storage = storage();
+        let content: Vec<u8> = (0..RANGE_SIZE + 42).map(|i| (i % 251) as u8).collect();
+        let mut writer = storage.put("/large").unwrap();
+        writer.write_all(&content).unwrap();
+        writer.close().unwrap();
+
+        let mut buf = Vec::new();
+        storage
+            .get("/large")
+            .unwrap()
+            .reader
+            .read_to_end(&mut buf)
+            .unwrap();
+        assert!(buf == content);
+    }
+
+    #[test]
+    fn test_read_fails_if_object_is_modified() {
+        let storage = storage();
+        write_file_to_storage(&storage, "/file.txt", "Hello, world!").unwrap();
+        let mut reader = storage.get("/file.txt").unwrap().reader;
+        write_file_to_storage(&storage, "/file.txt", "Bye, world!").unwrap();
+        assert!(reader.read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_aborts_upload_when_panicking() {
+        let storage = storage().with_part_size(4);
+        let result = thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let mut writer = storage.put("/file.txt").unwrap();
+                    writer.write_all(b"Hello, world!").unwrap();
+                    panic!("abort upload");
+                })
+                .join()
+        });
+        assert!(result.is_err());
+        assert!(!storage.exists_file("/file.txt").unwrap());
+        assert_eq!(
+            SERVER.pending_multipart_uploads(&storage.config().bucket),
+            0
+        );
+    }
+
+    #[test]
+    fn test_abort_discards_upload() {
+        let storage = storage().with_part_size(4);
+        let mut writer = storage.put("/file.txt").unwrap();
+        writer.write_all(b"Hello, world!").unwrap();
+        writer.abort();
+        assert!(!storage.exists_file("/file.txt").unwrap());
+        assert_eq!(
+            SERVER.pending_multipart_uploads(&storage.config().bucket),
+            0
+        );
+    }
+
+    #[test]
+    fn test_list_returns_direct_children_of_directory() {
+        let storage = storage();
+        write_file_to_storage(&storage, "/rootfile.txt", "rootfile-content").unwrap();
+        write_file_to_storage(&storage, "/dir/file1.txt", "file1-content").unwrap();
+        write_file_to_storage(&storage, "/dir/file2.txt", "file2-content").unwrap();
+        write_file_to_storage(&storage, "/dir/subdir/subfile.txt", "subfile-content").unwrap();
+
+        assert_eq!(
+            list_sorted(&storage, "/").unwrap(),
+            vec![dir("dir"), file("rootfile.txt", 16)]
+        );
+        assert_eq!(
+            list_sorted(&storage, "/dir").unwrap(),
+            vec![file("file1.txt", 13), file("file2.txt", 13), dir("subdir")]
+        );
+        assert_eq!(
+            list_sorted(&storage, "/dir/").unwrap(),
+            list_sorted(&storage, "/dir").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_list_provides_modification_time_of_files() {
+        let storage = storage();
+        let before = chrono::Utc::now();
+        write_file_to_storage(&storage, "/dir/file.txt", "content").unwrap();
+        let after = chrono::Utc::now();
+
+        let entries: Vec<_> = storage.list("/").unwrap().map(Result::unwrap).collect();
+        assert_eq!(entries[0].modified, None);
+        let entries: Vec<_> = storage.list("/dir").unwrap().map(Result::unwrap).collect();
+        assert_modified_between(&entries[0], before, after);
+        let file_handle = storage.get("/dir/file.txt").unwrap();
+        let entry = StorageEntry {
+            modified: file_handle.modified,
+            ..entries[0].clone()
+        };
+        assert_modified_between(&entry, before, after);
+    }
+
+    #[test]
+    fn test_list_follows_pagination() {
+        let storage = storage();
+        for i in 0..5 {
+            write_file_to_storage(&storage, &format!("/dir/{i}"), "content").unwrap();
+        }
+        SERVER.set_max_keys(&storage.config().bucket, 2);
+        assert_eq!(
+            list_sorted(&storage, "/dir").unwrap(),
+            (0..5).map(|i| file(&i.to_string(), 7)).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_list_of_empty_root_is_empty() {
+        let storage = storage();
+        assert_eq!(list_sorted(&storage, "/").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_list_returns_error_for_non_existent_dir() {
+        let storage = storage();
+        write_file_to_storage(&storage, "/file.txt", "content").unwrap();
+        assert_eq!(
+            storage
+                .list("/non-existent")
+                .err()
+                .unwrap()
+                .io_error()
+                .kind(),
+            ErrorKind::NotFound
+        );
+        assert_eq!(
+            storage.list("/file.txt").err().unwrap().io_error().kind(),
+            ErrorKind::NotADirectory
+        );
+    }
+
+    #[test]
+    fn test_can_delete_file() {
+        let storage = storage();
+        write_file_to_storage(&storage, "/dir/file.txt", "file-content").unwrap();
+        storage.delete("/dir/file.txt").unwrap();
+        assert!(!storage.exists_file("/dir/file.txt").unwrap());
+        assert_eq!(list_sorted(&storage, "/").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_delete_returns_errors() {
+        let storage = storage();
+        write_file_to_storage(&storage, "/dir/file.txt", "file-content").unwrap();
+        assert_eq!(
+            storage.delete("/dir").err().unwrap().io_error().kind(),
+            ErrorKind::DirectoryNotEmpty
+        );
+        assert_eq!(
+            storage
+                .delete("/non-existent")
+                .err()
+                .unwrap()
+                .io_error()
+                .kind(),
+            ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn test_exists_returns_true_for_existing_file() {
+        let storage = storage();
+        assert!(!storage.exists_file("/dir/file.txt").unwrap());
+        write_file_to_storage(&storage, "/dir/file.txt", "file-content").unwrap();
+        assert!(!storage.exists_file("/dir").unwrap());
+        assert!(storage.exists_file("/dir/file.txt").unwrap());
+    }
+
+    #[test]
+    fn test_prefix_separates_storages() {
+        let storage_a = storage_with_prefix("a");
+        let storage_b = S3Storage::new(
+            HttpClient::default().unwrap(),
+            S3Config {
+                prefix: "/b/".into(),
+                ..storage_a.config().clone()
+            },
+        );
+        write_file_to_storage(&storage_a, "/file.txt", "a").unwrap();
+        write_file_to_storage(&storage_b, "/file.txt", "b").unwrap();
+        assert_eq!(
+            read_file_from_storage_to_string(&storage_a, "/file.txt").unwrap(),
+            "a"
+        );
+        assert_eq!(
+            read_file_from_storage_to_string(&storage_b, "/file.txt").unwrap(),
+            "b"
+        );
+        assert_eq!(
+            list_sorted(&storage_a, "/").unwrap(),
+            vec![file("file.txt", 1)]
+        );
+    }
+
+    #[test]
+    fn test_keys_are_encoded() {
+        let storage = storage();
+        write_file_to_storage(&storage, "/dir/key with spaces+&=?.txt", "content").unwrap();
+        assert_eq!(
+            list_sorted(&storage, "/dir").unwrap(),
+            vec![file("key with spaces+&=?.txt", 7)]
+        );
+        assert_eq!(
+            read_file_from_storage_to_string(&storage, "/dir/key with spaces+&=?.txt").unwrap(),
+            "content"
+        );
+    }
+
+    #[test]
+    fn test_rejects_invalid_credentials() {
+        let storage = storage();
+        let storage = S3Storage::new(
+            HttpClient::default().unwrap(),
+            S3Config {
+                credentials: Credentials {
+                    secret_access_key: "wrong".into(),
+                    ..storage.config().credentials.clone()
+                },
+                ..storage.config().clone()
+            },
+        );
+        assert_eq!(
+            storage
+                .exists_file("/file.txt")
+                .unwrap_err()
+                .io_error()
+                .kind(),
+            ErrorKind::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn test_config_from_lookup() {
+        let vars = |name: &str| match name {
+            "AWS_ACCESS_KEY_ID" => Some("access-key".to_string()),
+            "AWS_SECRET_ACCESS_KEY" => Some("secret".to_string()),
+            "AWS_DEFAULT_REGION" => Some("eu-central-1".to_string()),
+            _ => None,
+        };
+        assert_eq!(
+            S3Config::from_lookup("bucket", "prefix", vars).unwrap(),
+            S3Config {
+                endpoint: Url::parse("https://s3.eu-central-1.amazonaws.com").unwrap(),
+                region: "eu-central-1".into(),
+                bucket: "bucket".into(),
+                prefix: "prefix".into(),
+                path_style: false,
+                credentials: Credentials {
+                    access_key_id: "access-key".into(),
+                    secret_access_key: "secret".into(),
+                    session_token: None,
+                },
+            }
+        );
+
+        let config = S3Config::from_lookup("bucket", "", |name| match name {
+            "AWS_ENDPOINT_URL" => Some("http://localhost:9000".to_string()),
+            "AWS_REGION" => Some("local".to_string()),
+            "AWS_SESSION_TOKEN" => Some("token".to_string()),
+            _ => vars(name),
+        })
+        .unwrap();
+        assert_eq!(config.endpoint.as_str(), "http://localhost:9000/");
+        assert_eq!(config.region, "local");
+        assert!(config.path_style);
+        assert_eq!(config.credentials.session_token, Some("token".into()));
+
+        assert_eq!(
+            S3Config::from_lookup("bucket", "", |_| None).unwrap_err(),
+            S3ConfigError::MissingEnvVar("AWS_ACCESS_KEY_ID")
+        );
+    }
+
+    #[test]
+    fn test_url() {
+        let client = S3Client {
+            http: Arc::new(HttpClient::default().unwrap()),
+            config: Arc::new(
+                S3Config::from_lookup("bucket", "", |name| match name {
+                    "AWS_ACCESS_KEY_ID" | "AWS_SECRET_ACCESS_KEY" => Some("x".into()),
+                    _ => None,
+                })
+                .unwrap(),
+            ),
+        };
+        assert_eq!(
+            client.url("dir/a b", &[("uploadId", "1/2")]).as_str(),
+            "https://bucket.s3.us-east-1.amazonaws.com/dir/a%20b?uploadId=1%2F2"
+        );
+    }
+}
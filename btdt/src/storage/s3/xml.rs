@@ -0,0 +1,231 @@
+//! Parsing and generation of the XML documents used by the S3 API.
+
+use chrono::{DateTime, Utc};
+use roxmltree::{Document, Node};
+use std::io;
+use std::io::ErrorKind;
+
+/// A page of results of a `ListObjectsV2` request.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub(crate) struct ListObjectsPage {
+    /// The objects of the page.
+    pub objects: Vec<Object>,
+    /// Common prefixes of keys (i.e. "directories") if a delimiter was given.
+    pub common_prefixes: Vec<String>,
+    /// Token to request the next page, if the result was truncated.
+    pub next_continuation_token: Option<String>,
+}
+
+/// An object listed in the result of a `ListObjectsV2` request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Object {
+    pub key: String,
+    pub size: u64,
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+/// An error response returned by the S3 API.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub(crate) struct ErrorResponse {
+    pub code: String,
+    pub message: String,
+}
+
+fn parse(xml: &str) -> io::Result<Document<'_>> {
+    Document::parse(xml).map_err(|err| io::Error::new(ErrorKind::InvalidData, err))
+}
+
+fn child_text<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.children()
+        .find(|child| child.has_tag_name(name))
+        .map(|child| child.text().unwrap_or(""))
+}
+
+fn required_child_text<'a>(node: Node<'a, '_>, name: &str) -> io::Result<&'a str> {
+    child_text(node, name).ok_or_else(|| {
+        io::Error::new(
+            ErrorKind::InvalidData,
+            format!("missing <{name}> element in S3 response"),
+        )
+    })
+}
+
+/// Parses the response body of a `ListObjectsV2` request.
+pub(crate) fn parse_list_objects(xml: &str) -> io::Result<ListObjectsPage> {
+    let document = parse(xml)?;
+    let root = document.root_element();
+    let mut page = ListObjectsPage::default();
+    for node in root.children() {
+        if node.has_tag_name("Contents") {
+            let key = required_child_text(node, "Key")?;
+            let size = required_child_text(node, "Size")?.parse().map_err(|_| {
+                io::Error::new(ErrorKind::InvalidData, "invalid object size in S3 response")
+            })?;
+            let last_modified = child_text(node, "LastModified")
+                .and_then(|text| DateTime::parse_from_rfc3339(text).ok())
+                .map(|last_modified| last_modified.to_utc());
+            page.objects.push(Object {
+                key: key.to_string(),
+                size,
+                last_modified,
+            });
+        } else if node.has_tag_name("CommonPrefixes") {
+            page.common_prefixes
+                .push(required_child_text(node, "Prefix")?.to_string());
+        }
+    }
+    if child_text(root, "IsTruncated") == Some("true") {
+        page.next_continuation_token =
+            Some(required_child_text(root, "NextContinuationToken")?.to_string());
+    }
+    Ok(page)
+}
+
+/// Parses the upload ID from the response body of a `CreateMultipartUpload` request.
+pub(crate) fn parse_upload_id(xml: &str) -> io::Result<String> {
+    let document = parse(xml)?;
+    Ok(required_child_text(document.root_element(), "UploadId")?.to_string())
+}
+
+/// Parses the ETag from the response body of an `UploadPartCopy` request.
+pub(crate) fn parse_etag(xml: &str) -> io::Result<String> {
+    let document = parse(xml)?;
+    Ok(required_child_text(document.root_element(), "ETag")?.to_string())
+}
+
+/// Parses an error response.
+///
+/// Returns `None` if the document is not an error response.
+pub(crate) fn parse_error(xml: &str) -> Option<ErrorResponse> {
+    let document = Document::parse(xml).ok()?;
+    let root = document.root_element();
+    if !root.has_tag_name("Error") {
+        return None;
+    }
+    Some(ErrorResponse {
+        code: child_text(root, "Code").unwrap_or_default().to_string(),
+        message: child_text(root, "Message").unwrap_or_default().to_string(),
+    })
+}
+
+/// Creates the request body of a `CompleteMultipartUpload` request from the ETags of the parts.
+pub(crate) fn complete_multipart_upload(etags: &[String]) -> String {
+    let mut xml = String::from("<CompleteMultipartUpload>");
+    for (i, etag) in etags.iter().enumerate() {
+        xml.push_str(&format!(
+            "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+            i + 1,
+            escape(etag)
+        ));
+    }
+    xml.push_str("</CompleteMultipartUpload>");
+    xml
+}
+
+/// Escapes special characters in XML text content.
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_list_objects() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+                <Name>bucket</Name>
+                <Prefix>dir/</Prefix>
+                <KeyCount>3</KeyCount>
+                <IsTruncated>true</IsTruncated>
+                <NextContinuationToken>token&amp;1</NextContinuationToken>
+                <Contents>
+                    <Key>dir/a.txt</Key>
+                    <LastModified>2025-01-02T03:04:05.000Z</LastModified>
+                    <Size>42</Size>
+                    <ETag>"etag"</ETag>
+                </Contents>
+                <Contents>
+                    <Key>dir/b.txt</Key>
+                    <Size>0</Size>
+                </Contents>
+                <CommonPrefixes>
+                    <Prefix>dir/sub/</Prefix>
+                </CommonPrefixes>
+            </ListBucketResult>"#;
+        assert_eq!(
+            parse_list_objects(xml).unwrap(),
+            ListObjectsPage {
+                objects: vec![
+                    Object {
+                        key: "dir/a.txt".into(),
+                        size: 42,
+                        last_modified: Some(
+                            DateTime::parse_from_rfc3339("2025-01-02T03:04:05Z")
+                                .unwrap()
+                                .to_utc()
+                        ),
+                    },
+                    Object {
+                        key: "dir/b.txt".into(),
+                        size: 0,
+                        last_modified: None,
+                    },
+                ],
+                common_prefixes: vec!["dir/sub/".into()],
+                next_continuation_token: Some("token&1".into()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_list_objects_without_continuation() {
+        let xml = "<ListBucketResult><IsTruncated>false</IsTruncated></ListBucketResult>";
+        assert_eq!(parse_list_objects(xml).unwrap(), ListObjectsPage::default());
+    }
+
+    #[test]
+    fn test_parse_upload_id() {
+        let xml = "<InitiateMultipartUploadResult>\
+            <Bucket>bucket</Bucket><Key>key</Key><UploadId>upload-id</UploadId>\
+            </InitiateMultipartUploadResult>";
+        assert_eq!(parse_upload_id(xml).unwrap(), "upload-id");
+    }
+
+    #[test]
+    fn test_parse_etag() {
+        let xml = "<CopyPartResult>\
+            <LastModified>2025-01-02T03:04:05.000Z</LastModified><ETag>\"etag\"</ETag>\
+            </CopyPartResult>";
+        assert_eq!(parse_etag(xml).unwrap(), "\"etag\"");
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let xml = "<Error><Code>NoSuchKey</Code><Message>The key does not exist.</Message></Error>";
+        assert_eq!(
+            parse_error(xml),
+            Some(ErrorResponse {
+                code: "NoSuchKey".into(),
+                message: "The key does not exist.".into(),
+            })
+        );
+        assert_eq!(parse_error("<ListBucketResult/>"), None);
+        assert_eq!(parse_error("not xml"), None);
+    }
+
+    #[test]
+    fn test_complete_multipart_upload() {
+        assert_eq!(
+            complete_multipart_upload(&["\"a\"".into(), "\"b\"".into()]),
+            "<CompleteMultipartUpload>\
+            <Part><PartNumber>1</PartNumber><ETag>&quot;a&quot;</ETag></Part>\
+            <Part><PartNumber>2</PartNumber><ETag>&quot;b&quot;</ETag></Part>\
+            </CompleteMultipartUpload>"
+        );
+    }
+}
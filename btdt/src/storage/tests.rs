@@ -1,5 +1,6 @@
 use crate::error::{IoPathResult, WithPath};
-use crate::storage::Storage;
+use crate::storage::{Storage, StorageEntry};
+use chrono::{DateTime, TimeDelta, Utc};
 use std::io::{Read, Write};
 
 #[macro_export]
@@ -7,9 +8,12 @@ macro_rules! test_storage {
     ($mod_name:ident, $constructor:expr) => {
         mod $mod_name {
             use super::*;
-            use crate::storage::tests::{read_file_from_storage_to_string, write_file_to_storage};
             #[allow(unused_imports)] // false positive
             use std::io::{Read, Write};
+            use $crate::storage::tests::{
+                assert_modified_between, read_file_from_storage_to_string,
+                without_modification_time, write_file_to_storage,
+            };
 
             #[test]
             fn test_get_returns_error_for_non_existent_file() {
@@ -86,7 +90,12 @@ macro_rules! test_storage {
                 write_file_to_storage(&storage, "/dir/subdir/subfile.txt", "subfile-content")
                     .unwrap();
 
-                let mut entries: Vec<_> = storage.list("/").unwrap().map(Result::unwrap).collect();
+                let mut entries: Vec<_> = storage
+                    .list("/")
+                    .unwrap()
+                    .map(Result::unwrap)
+                    .map(without_modification_time)
+                    .collect();
                 entries.sort_unstable_by_key(|entry| entry.name.to_string());
                 assert_eq!(
                     entries,
@@ -95,17 +104,23 @@ macro_rules! test_storage {
                             entry_type: EntryType::Directory,
                             name: Cow::Owned("dir".to_string()),
                             size: 0,
+                            modified: None,
                         },
                         StorageEntry {
                             entry_type: EntryType::File,
                             name: Cow::Owned("rootfile.txt".to_string()),
                             size: 16,
+                            modified: None,
                         }
                     ]
                 );
 
-                let mut entries: Vec<_> =
-                    storage.list("/dir").unwrap().map(Result::unwrap).collect();
+                let mut entries: Vec<_> = storage
+                    .list("/dir")
+                    .unwrap()
+                    .map(Result::unwrap)
+                    .map(without_modification_time)
+                    .collect();
                 entries.sort_unstable_by_key(|entry| entry.name.to_string());
                 assert_eq!(
                     entries,
@@ -114,21 +129,39 @@ macro_rules! test_storage {
                             entry_type: EntryType::File,
                             name: Cow::Owned("file1.txt".to_string()),
                             size: 13,
+                            modified: None,
                         },
                         StorageEntry {
                             entry_type: EntryType::File,
                             name: Cow::Owned("file2.txt".to_string()),
                             size: 13,
+                            modified: None,
                         },
                         StorageEntry {
                             entry_type: EntryType::Directory,
                             name: Cow::Owned("subdir".to_string()),
                             size: 0,
+                            modified: None,
                         },
                     ]
                 );
             }
 
+            #[test]
+            fn test_list_provides_modification_time_of_files() {
+                let storage = $constructor;
+                let before = chrono::Utc::now();
+                write_file_to_storage(&storage, "/dir/file.txt", "file-content").unwrap();
+                let after = chrono::Utc::now();
+
+                let entries: Vec<_> = storage.list("/").unwrap().map(Result::unwrap).collect();
+                assert_eq!(entries[0].modified, None);
+                let entries: Vec<_> = storage.list("/dir").unwrap().map(Result::unwrap).collect();
+                assert_modified_between(&entries[0], before, after);
+                let file_handle = storage.get("/dir/file.txt").unwrap();
+                assert_eq!(file_handle.modified, entries[0].modified);
+            }
+
             #[test]
             fn test_can_delete_file() {
                 let storage = $constructor;
@@ -170,6 +203,30 @@ macro_rules! test_storage {
                 assert!(storage.exists_file("/dir/file.txt").unwrap());
             }
 
+            #[test]
+            fn test_can_rename_file() {
+                let storage = $constructor;
+                write_file_to_storage(&storage, "/file.txt", "Hello, world!").unwrap();
+                write_file_to_storage(&storage, "/dir/renamed.txt", "Goodbye, world!").unwrap();
+                storage.rename("/file.txt", "/dir/renamed.txt").unwrap();
+                storage
+                    .rename("/dir/renamed.txt", "/new-dir/renamed.txt")
+                    .unwrap();
+                assert!(!storage.exists_file("/file.txt").unwrap());
+                assert!(!storage.exists_file("/dir/renamed.txt").unwrap());
+                assert_eq!(
+                    &read_file_from_storage_to_string(&storage, "/new-dir/renamed.txt").unwrap(),
+                    "Hello, world!"
+                );
+            }
+
+            #[test]
+            fn test_rename_returns_error_for_non_existent_file() {
+                let storage = $constructor;
+                let result = storage.rename("/non-existent.txt", "/renamed.txt");
+                assert_eq!(result.err().unwrap().io_error().kind(), ErrorKind::NotFound);
+            }
+
             #[test]
             fn test_put_is_atomic() {
                 let storage_a = $constructor;
@@ -213,3 +270,25 @@ pub fn read_file_from_storage_to_string(
     handle.reader.read_to_string(&mut buf).with_path(path)?;
     Ok(buf)
 }
+
+pub fn without_modification_time(entry: StorageEntry<'_>) -> StorageEntry<'_> {
+    StorageEntry {
+        modified: None,
+        ..entry
+    }
+}
+
+/// Asserts that the entry was modified in the given time range, allowing for the coarser
+/// resolution of filesystem timestamps.
+pub fn assert_modified_between(
+    entry: &StorageEntry<'_>,
+    before: DateTime<Utc>,
+    after: DateTime<Utc>,
+) {
+    let modified = entry.modified.expect("modification time missing");
+    let tolerance = TimeDelta::seconds(1);
+    assert!(
+        before - tolerance <= modified && modified <= after + tolerance,
+        "{modified} not between {before} and {after}"
+    );
+}
@@ -0,0 +1,574 @@
+//! A minimal in-memory stand-in for an S3-compatible object storage service.
+
+use crate::storage::s3::sigv4::{CanonicalRequest, Signer};
+use crate::storage::s3::xml::escape;
+use crate::storage::s3::{Credentials, S3Config};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::ops::Bound;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use url::Url;
+
+const ACCESS_KEY_ID: &str = "fake-access-key-id";
+const SECRET_ACCESS_KEY: &str = "fake-secret-access-key";
+const REGION: &str = "us-east-1";
+
+/// A fake S3 server for tests.
+///
+/// The server keeps all data in memory and supports the subset of the S3 API used by
+/// [S3Storage](crate::storage::s3::S3Storage) with path-style addressing. Buckets are created
+/// implicitly on first use. Requests must be signed with the credentials returned by
+/// [FakeS3Server::credentials].
+///
+/// The server is shut down when dropped.
+pub struct FakeS3Server {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    shutdown: Arc<AtomicBool>,
+    join_handle: Option<JoinHandle<()>>,
+}
+
+#[derive(Default)]
+struct State {
+    buckets: HashMap<String, Bucket>,
+    next_id: u64,
+}
+
+impl State {
+    fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+}
+
+#[derive(Default)]
+struct Bucket {
+    objects: BTreeMap<String, Object>,
+    uploads: HashMap<String, BTreeMap<usize, Vec<u8>>>,
+    completed_uploads: usize,
+    max_keys: Option<usize>,
+}
+
+struct Object {
+    data: Arc<Vec<u8>>,
+    etag: String,
+    last_modified: DateTime<Utc>,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn read_from(reader: &mut impl BufRead) -> io::Result<Self> {
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        let mut parts = request_line.split_whitespace();
+        let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "malformed request line",
+            ));
+        };
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+        let mut headers = vec![];
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            if line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+            }
+        }
+
+        let mut request = Self {
+            method: method.to_string(),
+            path: path.to_string(),
+            query: query.to_string(),
+            headers,
+            body: vec![],
+        };
+        let content_length = request
+            .header("content-length")
+            .and_then(|length| length.parse().ok())
+            .unwrap_or(0);
+        request.body = vec![0; content_length];
+        reader.read_exact(&mut request.body)?;
+        Ok(request)
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn query_param(&self, name: &str) -> Option<String> {
+        url::form_urlencoded::parse(self.query.as_bytes())
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    }
+
+    fn is_authorized(&self) -> bool {
+        let Some(authorization) = self.header("authorization") else {
+            return false;
+        };
+        let Some(signed_headers) = authorization
+            .split(", ")
+            .find_map(|part| part.strip_prefix("SignedHeaders="))
+        else {
+            return false;
+        };
+        let Some(time) = self
+            .header("x-amz-date")
+            .and_then(|date| NaiveDateTime::parse_from_str(date, "%Y%m%dT%H%M%SZ").ok())
+        else {
+            return false;
+        };
+        let headers: Vec<_> = signed_headers
+            .split(';')
+            .map(|name| (name, self.header(name).unwrap_or_default()))
+            .collect();
+        let expected = Signer::new(&FakeS3Server::credentials(), REGION).authorization(
+            &CanonicalRequest {
+                method: &self.method,
+                path: &self.path,
+                query: &self.query,
+                headers: &headers,
+                payload_hash: self.header("x-amz-content-sha256").unwrap_or_default(),
+            },
+            DateTime::<Utc>::from_naive_utc_and_offset(time, Utc),
+        );
+        expected == authorization
+    }
+}
+
+struct Response {
+    status: &'static str,
+    headers: Vec<(&'static str, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn new(status: &'static str) -> Self {
+        Self {
+            status,
+            headers: vec![],
+            body: vec![],
+        }
+    }
+
+    fn xml(status: &'static str, body: String) -> Self {
+        Self {
+            status,
+            headers: vec![("Content-Type", "application/xml".into())],
+            body: body.into_bytes(),
+        }
+    }
+
+    fn error(status: &'static str, code: &str) -> Self {
+        Self::xml(
+            status,
+            format!("<Error><Code>{code}</Code><Message>{code}</Message></Error>"),
+        )
+    }
+
+    fn header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+
+    fn write_to(&self, writer: &mut impl Write, include_body: bool) -> io::Result<()> {
+        write!(writer, "HTTP/1.1 {}\r\n", self.status)?;
+        if !self
+            .headers
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case("Content-Length"))
+        {
+            write!(writer, "Content-Length: {}\r\n", self.body.len())?;
+        }
+        for (name, value) in &self.headers {
+            write!(writer, "{name}: {value}\r\n")?;
+        }
+        writer.write_all(b"Connection: close\r\n\r\n")?;
+        if include_body {
+            writer.write_all(&self.body)?;
+        }
+        writer.flush()
+    }
+}
+
+impl FakeS3Server {
+    /// Starts the server on a random local port.
+    pub fn start() -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(State::default()));
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let join_handle = {
+            let state = state.clone();
+            let shutdown = shutdown.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if shutdown.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let Ok(stream) = stream else { continue };
+                    let state = state.clone();
+                    thread::spawn(move || {
+                        let _ = Self::handle_connection(stream, &state);
+                    });
+                }
+            })
+        };
+        Ok(Self {
+            addr,
+            state,
+            shutdown,
+            join_handle: Some(join_handle),
+        })
+    }
+
+    /// Returns the endpoint URL of the server.
+    pub fn endpoint(&self) -> Url {
+        Url::parse(&format!("http://{}", self.addr)).expect("valid URL")
+    }
+
+    /// Returns the credentials accepted by the server.
+    pub fn credentials() -> Credentials {
+        Credentials {
+            access_key_id: ACCESS_KEY_ID.into(),
+            secret_access_key: SECRET_ACCESS_KEY.into(),
+            session_token: None,
+        }
+    }
+
+    /// Returns a storage configuration to use the given bucket and prefix on this server.
+    pub fn config(&self, bucket: impl Into<String>, prefix: impl Into<String>) -> S3Config {
+        S3Config {
+            endpoint: self.endpoint(),
+            region: REGION.into(),
+            bucket: bucket.into(),
+            prefix: prefix.into(),
+            path_style: true,
+            credentials: Self::credentials(),
+        }
+    }
+
+    /// Limits the number of keys returned per page when listing objects in the bucket.
+    pub fn set_max_keys(&self, bucket: &str, max_keys: usize) {
+        self.with_bucket(bucket, |bucket| bucket.max_keys = Some(max_keys));
+    }
+
+    /// Returns the number of completed multipart uploads in the bucket.
+    pub fn completed_multipart_uploads(&self, bucket: &str) -> usize {
+        self.with_bucket(bucket, |bucket| bucket.completed_uploads)
+    }
+
+    /// Returns the number of multipart uploads in the bucket that have been neither completed nor
+    /// aborted.
+    pub fn pending_multipart_uploads(&self, bucket: &str) -> usize {
+        self.with_bucket(bucket, |bucket| bucket.uploads.len())
+    }
+
+    fn with_bucket<T>(&self, bucket: &str, f: impl FnOnce(&mut Bucket) -> T) -> T {
+        let mut state = self.state.lock().unwrap();
+        f(state.buckets.entry(bucket.to_string()).or_default())
+    }
+
+    fn handle_connection(stream: TcpStream, state: &Mutex<State>) -> io::Result<()> {
+        let mut reader = BufReader::new(stream);
+        let request = Request::read_from(&mut reader)?;
+        let response = if request.is_authorized() {
+            Self::handle_request(&request, &mut state.lock().unwrap())
+        } else {
+            Response::error("403 Forbidden", "SignatureDoesNotMatch")
+        };
+        response.write_to(reader.get_mut(), request.method != "HEAD")
+    }
+
+    fn handle_request(request: &Request, state: &mut State) -> Response {
+        let path = percent_encoding::percent_decode_str(&request.path).decode_utf8_lossy();
+        let (bucket_name, key) = path
+            .trim_start_matches('/')
+            .split_once('/')
+            .unwrap_or((path.trim_start_matches('/'), ""));
+        let id = state.next_id();
+        let copy_source = match request.header("x-amz-copy-source") {
+            Some(copy_source) => match Self::copy_source(request, state, copy_source) {
+                Some(data) => Some(data),
+                None => return Response::error("404 Not Found", "NoSuchKey"),
+            },
+            None => None,
+        };
+        let bucket = state.buckets.entry(bucket_name.to_string()).or_default();
+        let upload_id = request.query_param("uploadId");
+
+        match (request.method.as_str(), key, upload_id) {
+            ("GET", "", _) => Self::list_objects(request, bucket),
+            ("GET" | "HEAD", key, _) => Self::get_object(request, bucket, key),
+            ("PUT", key, None) => {
+                let etag = format!("\"{id}\"");
+                bucket.objects.insert(
+                    key.to_string(),
+                    Object {
+                        data: Arc::new(copy_source.as_ref().unwrap_or(&request.body).clone()),
+                        etag: etag.clone(),
+                        last_modified: Utc::now(),
+                    },
+                );
+                if copy_source.is_some() {
+                    return Response::xml(
+                        "200 OK",
+                        format!(
+                            "<CopyObjectResult><ETag>{}</ETag></CopyObjectResult>",
+                            escape(&etag)
+                        ),
+                    );
+                }
+                Response::new("200 OK").header("ETag", etag)
+            }
+            ("PUT", _, Some(upload_id)) => {
+                let Some(part_number) = request
+                    .query_param("partNumber")
+                    .and_then(|part_number| part_number.parse().ok())
+                else {
+                    return Response::error("400 Bad Request", "InvalidArgument");
+                };
+                let Some(parts) = bucket.uploads.get_mut(&upload_id) else {
+                    return Response::error("404 Not Found", "NoSuchUpload");
+                };
+                let etag = format!("\"part-{id}\"");
+                if let Some(data) = copy_source {
+                    parts.insert(part_number, data);
+                    return Response::xml(
+                        "200 OK",
+                        format!(
+                            "<CopyPartResult><ETag>{}</ETag></CopyPartResult>",
+                            escape(&etag)
+                        ),
+                    );
+                }
+                parts.insert(part_number, request.body.clone());
+                Response::new("200 OK").header("ETag", etag)
+            }
+            ("POST", key, None) if request.query_param("uploads").is_some() => {
+                let upload_id = format!("upload-{id}");
+                bucket.uploads.insert(upload_id.clone(), BTreeMap::new());
+                Response::xml(
+                    "200 OK",
+                    format!(
+                        "<InitiateMultipartUploadResult>\
+                        <Bucket>{}</Bucket><Key>{}</Key><UploadId>{upload_id}</UploadId>\
+                        </InitiateMultipartUploadResult>",
+                        escape(bucket_name),
+                        escape(key)
+                    ),
+                )
+            }
+            ("POST", key, Some(upload_id)) => {
+                let Some(parts) = bucket.uploads.remove(&upload_id) else {
+                    return Response::error("404 Not Found", "NoSuchUpload");
+                };
+                let etag = format!("\"{id}-{}\"", parts.len());
+                bucket.objects.insert(
+                    key.to_string(),
+                    Object {
+                        data: Arc::new(parts.into_values().flatten().collect()),
+                        etag: etag.clone(),
+                        last_modified: Utc::now(),
+                    },
+                );
+                bucket.completed_uploads += 1;
+                Response::xml(
+                    "200 OK",
+                    format!(
+                        "<CompleteMultipartUploadResult><ETag>{}</ETag>\
+                        </CompleteMultipartUploadResult>",
+                        escape(&etag)
+                    ),
+                )
+            }
+            ("DELETE", _, Some(upload_id)) => {
+                bucket.uploads.remove(&upload_id);
+                Response::new("204 No Content")
+            }
+            ("DELETE", key, None) => {
+                bucket.objects.remove(key);
+                Response::new("204 No Content")
+            }
+            _ => Response::error("501 Not Implemented", "NotImplemented"),
+        }
+    }
+
+    /// Returns the data of the object given by the `x-amz-copy-source` header, restricted to the
+    /// `x-amz-copy-source-range` header if present.
+    fn copy_source(request: &Request, state: &State, copy_source: &str) -> Option<Vec<u8>> {
+        let copy_source = percent_encoding::percent_decode_str(copy_source).decode_utf8_lossy();
+        let (bucket, key) = copy_source.trim_start_matches('/').split_once('/')?;
+        let data = &state.buckets.get(bucket)?.objects.get(key)?.data;
+        match request
+            .header("x-amz-copy-source-range")
+            .and_then(|range| range.strip_prefix("bytes="))
+        {
+            Some(range) => {
+                let (start, end) = range.split_once('-')?;
+                let (start, end): (usize, usize) = (start.parse().ok()?, end.parse().ok()?);
+                data.get(start..=end).map(<[u8]>::to_vec)
+            }
+            None => Some(data.to_vec()),
+        }
+    }
+
+    fn get_object(request: &Request, bucket: &Bucket, key: &str) -> Response {
+        let Some(object) = bucket.objects.get(key) else {
+            return Response::error("404 Not Found", "NoSuchKey");
+        };
+        if let Some(etag) = request.header("if-match")
+            && etag != object.etag
+        {
+            return Response::error("412 Precondition Failed", "PreconditionFailed");
+        }
+        if request.method == "HEAD" {
+            return Response::new("200 OK")
+                .header("Content-Length", object.data.len().to_string())
+                .header("ETag", object.etag.clone())
+                .header(
+                    "Last-Modified",
+                    object
+                        .last_modified
+                        .format("%a, %d %b %Y %H:%M:%S GMT")
+                        .to_string(),
+                );
+        }
+
+        let size = object.data.len();
+        let range = request
+            .header("range")
+            .and_then(|range| range.strip_prefix("bytes="))
+            .and_then(|range| range.split_once('-'))
+            .and_then(|(start, end)| {
+                Some((start.parse::<usize>().ok()?, end.parse::<usize>().ok()?))
+            });
+        match range {
+            Some((start, end)) if start < size => {
+                let end = end.min(size - 1);
+                Response {
+                    status: "206 Partial Content",
+                    headers: vec![
+                        ("Content-Range", format!("bytes {start}-{end}/{size}")),
+                        ("ETag", object.etag.clone()),
+                    ],
+                    body: object.data[start..=end].to_vec(),
+                }
+            }
+            Some(_) => Response::error("416 Range Not Satisfiable", "InvalidRange"),
+            None => Response {
+                status: "200 OK",
+                headers: vec![("ETag", object.etag.clone())],
+                body: object.data.to_vec(),
+            },
+        }
+    }
+
+    fn list_objects(request: &Request, bucket: &Bucket) -> Response {
+        let prefix = request.query_param("prefix").unwrap_or_default();
+        let delimiter = request.query_param("delimiter").filter(|d| !d.is_empty());
+        let start = request
+            .query_param("continuation-token")
+            .unwrap_or_else(|| prefix.clone());
+        let max_keys = request
+            .query_param("max-keys")
+            .and_then(|max_keys| max_keys.parse().ok())
+            .unwrap_or(1000)
+            .min(bucket.max_keys.unwrap_or(usize::MAX));
+
+        let mut contents = String::new();
+        let mut common_prefixes = String::new();
+        let mut last_common_prefix: Option<String> = None;
+        let mut count = 0;
+        let mut next_token = None;
+        for (key, object) in bucket
+            .objects
+            .range::<str, _>((Bound::Included(start.as_str()), Bound::Unbounded))
+        {
+            let Some(rest) = key.strip_prefix(&prefix) else {
+                break;
+            };
+            let common_prefix = delimiter.as_ref().and_then(|delimiter| {
+                rest.find(delimiter.as_str())
+                    .map(|i| format!("{prefix}{}", &rest[..i + delimiter.len()]))
+            });
+            if common_prefix.is_some() && common_prefix == last_common_prefix {
+                continue;
+            }
+            if count == max_keys {
+                next_token = Some(key.clone());
+                break;
+            }
+            count += 1;
+            match common_prefix {
+                Some(common_prefix) => {
+                    common_prefixes.push_str(&format!(
+                        "<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>",
+                        escape(&common_prefix)
+                    ));
+                    last_common_prefix = Some(common_prefix);
+                }
+                None => contents.push_str(&format!(
+                    "<Contents><Key>{}</Key><LastModified>{}</LastModified><Size>{}</Size>\
+                    <ETag>{}</ETag></Contents>",
+                    escape(key),
+                    object
+                        .last_modified
+                        .to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+                    object.data.len(),
+                    escape(&object.etag)
+                )),
+            }
+        }
+
+        Response::xml(
+            "200 OK",
+            format!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+                <ListBucketResult xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\">\
+                <Prefix>{}</Prefix><KeyCount>{count}</KeyCount><MaxKeys>{max_keys}</MaxKeys>\
+                <IsTruncated>{}</IsTruncated>{}{contents}{common_prefixes}</ListBucketResult>",
+                escape(&prefix),
+                next_token.is_some(),
+                next_token
+                    .map(|token| format!(
+                        "<NextContinuationToken>{}</NextContinuationToken>",
+                        escape(&token)
+                    ))
+                    .unwrap_or_default(),
+            ),
+        )
+    }
+}
+
+impl Drop for FakeS3Server {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        // Wake up the accept loop.
+        let _ = TcpStream::connect(self.addr);
+        if let Some(join_handle) = self.join_handle.take() {
+            let _ = join_handle.join();
+        }
+    }
+}
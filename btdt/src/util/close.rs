@@ -10,9 +10,19 @@ use std::ops::{Deref, DerefMut};
 /// [Close::close] instead of just dropping allows to retrieve potential errors and handle them.
 ///
 /// For a type implementing [Close], it should be considered to also implement [Drop] and panic in
-/// case of an error.
+/// case of an error. Such types should also override [Close::abort].
 pub trait Close {
     fn close(self) -> io::Result<()>;
+
+    /// Discards the operation instead of finalizing it, e.g. after an error occurred.
+    ///
+    /// The default implementation drops `self`. Types finalizing the operation when dropped, and
+    /// types wrapping other [Close] implementations, must override this method.
+    fn abort(self)
+    where
+        Self: Sized,
+    {
+    }
 }
 
 /// A wrapper type to provide a [Close] implementation that does nothing.
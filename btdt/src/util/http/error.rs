@@ -6,6 +6,7 @@ use std::io;
 
 /// An error that can occur during HTTP client operations.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum HttpClientError {
     /// An invalid URL scheme was encountered.
     InvalidScheme(String),
@@ -19,6 +20,43 @@ pub enum HttpClientError {
     IoError(io::Error),
     /// A TLS error occurred.
     TlsError(rustls::Error),
+    /// An operation exceeded its timeout.
+    Timeout(TimeoutKind),
+    /// The proxy failed to forward the request.
+    ProxyError(String),
+}
+
+/// The kind of operation that exceeded its timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TimeoutKind {
+    /// Establishing the connection.
+    Connect,
+    /// Reading from the connection.
+    Read,
+    /// Writing to the connection.
+    Write,
+    /// Completing the whole request.
+    Total,
+}
+
+impl Display for TimeoutKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Connect => write!(f, "connect timed out"),
+            Self::Read => write!(f, "read timed out"),
+            Self::Write => write!(f, "write timed out"),
+            Self::Total => write!(f, "request timed out"),
+        }
+    }
+}
+
+impl std::error::Error for TimeoutKind {}
+
+impl From<TimeoutKind> for io::Error {
+    fn from(value: TimeoutKind) -> Self {
+        io::Error::new(io::ErrorKind::TimedOut, value)
+    }
 }
 
 impl HttpClientError {
@@ -36,6 +74,8 @@ impl Display for HttpClientError {
             Self::UnsupportedFeature(feature) => write!(f, "unsupported feature: {feature}"),
             Self::IoError(err) => write!(f, "I/O error: {err}"),
             Self::TlsError(err) => write!(f, "TLS error: {err}"),
+            Self::Timeout(kind) => write!(f, "{kind}"),
+            Self::ProxyError(msg) => write!(f, "proxy error: {msg}"),
         }
     }
 }
@@ -44,7 +84,13 @@ impl std::error::Error for HttpClientError {}
 
 impl From<io::Error> for HttpClientError {
     fn from(err: io::Error) -> Self {
-        HttpClientError::IoError(err)
+        match err
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<TimeoutKind>())
+        {
+            Some(kind) => HttpClientError::Timeout(*kind),
+            None => HttpClientError::IoError(err),
+        }
     }
 }
 
@@ -61,6 +107,8 @@ impl From<HttpClientError> for io::Error {
                 io::Error::new(io::ErrorKind::InvalidInput, value)
             }
             HttpClientError::TlsError(_) => io::Error::other(value),
+            HttpClientError::Timeout(kind) => kind.into(),
+            HttpClientError::ProxyError(_) => io::Error::other(value),
         }
     }
 }
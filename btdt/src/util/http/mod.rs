@@ -24,7 +24,9 @@
 //! # }
 //! ```
 
-use crate::util::http::error::HttpClientError;
+use crate::util::http::error::{HttpClientError, TimeoutKind};
+use crate::util::http::proxy::proxy_authorization;
+pub use crate::util::http::proxy::{ProxyConfig, ProxyConfigError};
 pub use rustls::RootCertStore;
 use rustls::pki_types::{CertificateDer, ServerName, TrustAnchor};
 use rustls::{ClientConfig, ClientConnection, StreamOwned, crypto};
@@ -34,13 +36,15 @@ use std::error::Error;
 use std::io;
 use std::io::{BufRead, BufReader, BufWriter, Read, Write};
 use std::marker::PhantomData;
-use std::net::TcpStream;
+use std::net::{TcpStream, ToSocketAddrs};
 use std::path::Path;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 pub use url::Url;
 use webpki::anchor_from_trusted_cert;
 
 pub mod error;
+mod proxy;
 
 const CRLF: &[u8] = b"\r\n";
 const HTTP_VERSION: &str = "HTTP/1.1";
@@ -98,17 +102,72 @@ impl<T: TransferEncoding> OptionTransferEncoding for TSome<T> {}
 
 pub(crate) type Result<T> = std::result::Result<T, HttpClientError>;
 
+/// Timeouts of the [HttpClient].
+///
+/// A timeout of `None` or zero disables the respective timeout. Exceeding a timeout results in a
+/// [HttpClientError::Timeout].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Maximum duration to establish the TCP connection to a server.
+    pub connect: Option<Duration>,
+
+    /// Maximum duration to wait for data from the server in a single read.
+    pub read: Option<Duration>,
+
+    /// Maximum duration to wait for a single write to the server to be accepted.
+    pub write: Option<Duration>,
+
+    /// Maximum duration of a request, from establishing the connection until the last read of the
+    /// response.
+    pub total: Option<Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            connect: Some(Duration::from_secs(30)),
+            read: Some(Duration::from_secs(60)),
+            write: Some(Duration::from_secs(60)),
+            total: None,
+        }
+    }
+}
+
 /// A simple HTTP/1.1 client with TLS support using rustls.
 ///
+/// The client accesses all URLs directly unless proxies are configured with
+/// [HttpClient::with_proxy_config], e.g. the ones of the environment (see [ProxyConfig::from_env]).
+///
 /// See the module documentation for usage examples.
+#[derive(Clone)]
 pub struct HttpClient {
     tls_client_config: Arc<ClientConfig>,
+    timeouts: Timeouts,
+    proxy_config: ProxyConfig,
 }
 
 impl HttpClient {
     /// Creates a new HTTP client with the given TLS client configuration.
     pub fn new(tls_client_config: Arc<ClientConfig>) -> Self {
-        Self { tls_client_config }
+        Self {
+            tls_client_config,
+            timeouts: Timeouts::default(),
+            proxy_config: ProxyConfig::none(),
+        }
+    }
+
+    /// Sets the proxies used by the client.
+    pub fn with_proxy_config(mut self, proxy_config: ProxyConfig) -> Self {
+        self.proxy_config = proxy_config;
+        self
+    }
+
+    /// Sets the timeouts of the client.
+    ///
+    /// By default, [Timeouts::default] is used.
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
     }
 
     /// Creates a new HTTP client with the default TLS configuration.
@@ -163,9 +222,15 @@ impl HttpClient {
         method: &str,
         url: &Url,
     ) -> Result<HttpRequest<AwaitingRequestHeaders<TNone>>> {
-        let mut stream = self.connect(url)?;
+        let proxy = self.proxy_config.proxy_for(url);
+        let mut stream = self.connect(url, proxy)?;
         stream.write_all(method.as_bytes())?;
         stream.write_all(b" ")?;
+        // Plain HTTP requests to a proxy use the absolute URL, HTTPS requests are tunneled.
+        let forward_proxy = proxy.filter(|_| url.scheme() == "http");
+        if forward_proxy.is_some() {
+            stream.write_all(url.origin().ascii_serialization().as_bytes())?;
+        }
         stream.write_all(url.path().as_bytes())?;
         if let Some(query) = url.query() {
             stream.write_all(b"?")?;
@@ -180,9 +245,16 @@ impl HttpClient {
             _state: PhantomData,
         };
 
-        client.header("Host", url.host_str().ok_or(HttpClientError::MissingHost)?)?;
+        let host = url.host_str().ok_or(HttpClientError::MissingHost)?;
+        match url.port() {
+            Some(port) => client.header("Host", &format!("{host}:{port}"))?,
+            None => client.header("Host", host)?,
+        }
         client.header("Connection", "close")?;
         client.header("User-Agent", concat!("btdt/", env!("CARGO_PKG_VERSION")))?;
+        if let Some(authorization) = forward_proxy.and_then(proxy_authorization) {
+            client.header("Proxy-Authorization", &authorization)?;
+        }
 
         Ok(client)
     }
@@ -199,6 +271,18 @@ impl HttpClient {
         })
     }
 
+    /// Creates a new HTTP DELETE request for the given URL.
+    pub fn delete(
+        &self,
+        url: &Url,
+    ) -> Result<HttpRequest<AwaitingRequestHeaders<TSome<NoBodyTransferEncoding>>>> {
+        let client = self.method("DELETE", url)?;
+        Ok(HttpRequest {
+            stream: client.stream,
+            _state: PhantomData,
+        })
+    }
+
     /// Creates a new HTTP POST request for the given URL.
     #[allow(unused)]
     pub fn post(&self, url: &Url) -> Result<HttpRequest<AwaitingRequestHeaders<TNone>>> {
@@ -210,7 +294,7 @@ impl HttpClient {
         self.method("PUT", url)
     }
 
-    fn connect(&self, url: &Url) -> Result<Box<dyn WriteThenRead + Send>> {
+    fn connect(&self, url: &Url, proxy: Option<&Url>) -> Result<Box<dyn WriteThenRead + Send>> {
         let use_tls = match url.scheme() {
             "http" => false,
             "https" => true,
@@ -223,7 +307,21 @@ impl HttpClient {
         }
         let host = url.host_str().ok_or(HttpClientError::MissingHost)?;
         let port = url.port_or_known_default().expect("default port not known");
-        let stream = TcpStream::connect((host, port))?;
+        let stream = match proxy {
+            Some(proxy) => {
+                if proxy.scheme() != "http" {
+                    return Err(HttpClientError::UnsupportedFeature("non-http proxy"));
+                }
+                let proxy_host = proxy.host_str().ok_or(HttpClientError::MissingHost)?;
+                let proxy_port = proxy.port_or_known_default().unwrap_or(80);
+                let mut stream = self.connect_tcp(proxy_host, proxy_port)?;
+                if use_tls {
+                    Self::open_tunnel(&mut stream, &format!("{host}:{port}"), proxy)?;
+                }
+                stream
+            }
+            None => self.connect_tcp(host, port)?,
+        };
         if use_tls {
             let connection = ClientConnection::new(
                 self.tls_client_config.clone(),
@@ -235,6 +333,176 @@ impl HttpClient {
             Ok(Box::new(BufWriter::new(stream)))
         }
     }
+
+    fn connect_tcp(&self, host: &str, port: u16) -> Result<TimeoutStream> {
+        let deadline = enabled(self.timeouts.total).map(|total| Instant::now() + total);
+        let connect_timeout = shortest(enabled(self.timeouts.connect), remaining(deadline)?);
+        let stream = match connect_timeout {
+            Some(timeout) => {
+                Self::connect_with_timeout(host, port, timeout).map_err(|err| match err {
+                    HttpClientError::Timeout(TimeoutKind::Connect)
+                        if remaining(deadline).is_err() =>
+                    {
+                        HttpClientError::Timeout(TimeoutKind::Total)
+                    }
+                    err => err,
+                })?
+            }
+            None => TcpStream::connect((host, port))?,
+        };
+        let stream = TimeoutStream {
+            stream,
+            read_timeout: enabled(self.timeouts.read),
+            write_timeout: enabled(self.timeouts.write),
+            deadline,
+        };
+        stream.stream.set_read_timeout(stream.read_timeout)?;
+        stream.stream.set_write_timeout(stream.write_timeout)?;
+        Ok(stream)
+    }
+
+    /// Opens a tunnel to `authority` through the proxy connected via `stream`.
+    fn open_tunnel(stream: &mut TimeoutStream, authority: &str, proxy: &Url) -> Result<()> {
+        let mut request = format!("CONNECT {authority} {HTTP_VERSION}\r\nHost: {authority}\r\n");
+        if let Some(authorization) = proxy_authorization(proxy) {
+            request.push_str(&format!("Proxy-Authorization: {authorization}\r\n"));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes())?;
+
+        // Read byte-wise to not consume any data sent through the tunnel.
+        const MAX_RESPONSE_HEAD_SIZE: usize = 8192;
+        let mut response_head = Vec::new();
+        while !response_head.ends_with(b"\r\n\r\n") {
+            if response_head.len() >= MAX_RESPONSE_HEAD_SIZE {
+                return Err(HttpClientError::invalid_data("proxy response too large"));
+            }
+            let mut byte = [0u8];
+            stream.read_exact(&mut byte)?;
+            response_head.push(byte[0]);
+        }
+        let status_line_len = response_head
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .expect("response head ends with a newline")
+            + 1;
+        let status =
+            HttpStatus::new(String::from_utf8_lossy(&response_head[..status_line_len]).into())?;
+        if !status.is_success() {
+            return Err(HttpClientError::ProxyError(format!(
+                "CONNECT failed with status {} {}",
+                status.code(),
+                status.reason()
+            )));
+        }
+        Ok(())
+    }
+
+    fn connect_with_timeout(host: &str, port: u16, timeout: Duration) -> Result<TcpStream> {
+        let mut last_err = None;
+        for addr in (host, port).to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(err) if err.kind() == io::ErrorKind::TimedOut => {
+                    last_err = Some(HttpClientError::Timeout(TimeoutKind::Connect))
+                }
+                Err(err) => last_err = Some(err.into()),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            HttpClientError::IoError(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "could not resolve to any addresses",
+            ))
+        }))
+    }
+}
+
+/// Returns `timeout`, unless it is disabled by being zero.
+fn enabled(timeout: Option<Duration>) -> Option<Duration> {
+    timeout.filter(|timeout| !timeout.is_zero())
+}
+
+/// Returns the shorter of two optional timeouts.
+fn shortest(a: Option<Duration>, b: Option<Duration>) -> Option<Duration> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Returns the time remaining until `deadline`, or a [TimeoutKind::Total] error if it has passed.
+fn remaining(deadline: Option<Instant>) -> io::Result<Option<Duration>> {
+    match deadline {
+        Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+            Some(remaining) if !remaining.is_zero() => Ok(Some(remaining)),
+            _ => Err(TimeoutKind::Total.into()),
+        },
+        None => Ok(None),
+    }
+}
+
+/// A TCP stream reporting exceeded read and write timeouts, and an exceeded deadline of the
+/// request, as [TimeoutKind] errors.
+struct TimeoutStream {
+    stream: TcpStream,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    deadline: Option<Instant>,
+}
+
+impl TimeoutStream {
+    /// Performs an operation on the stream, with the socket timeout set by `set_timeout` limited
+    /// to the time remaining until the deadline.
+    fn with_timeout<T>(
+        &mut self,
+        kind: TimeoutKind,
+        set_timeout: fn(&TcpStream, Option<Duration>) -> io::Result<()>,
+        op: impl FnOnce(&mut TcpStream) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let timeout = match kind {
+            TimeoutKind::Write => self.write_timeout,
+            _ => self.read_timeout,
+        };
+        if self.deadline.is_some() {
+            set_timeout(&self.stream, shortest(timeout, remaining(self.deadline)?))?;
+        }
+        op(&mut self.stream).map_err(|err| {
+            // Depending on the platform, an exceeded timeout is reported as either of these kinds.
+            if !matches!(
+                err.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) {
+                err
+            } else if remaining(self.deadline).is_err() {
+                TimeoutKind::Total.into()
+            } else {
+                kind.into()
+            }
+        })
+    }
+}
+
+impl Read for TimeoutStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.with_timeout(TimeoutKind::Read, TcpStream::set_read_timeout, |stream| {
+            stream.read(buf)
+        })
+    }
+}
+
+impl Write for TimeoutStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.with_timeout(TimeoutKind::Write, TcpStream::set_write_timeout, |stream| {
+            stream.write(buf)
+        })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.with_timeout(TimeoutKind::Write, TcpStream::set_write_timeout, |stream| {
+            stream.flush()
+        })
+    }
 }
 
 trait WriteThenRead: Write {
@@ -243,7 +511,7 @@ trait WriteThenRead: Write {
     ) -> io::Result<HttpMessageReader<Box<dyn BufRead + Send>, ReadResponseStatus>>;
 }
 
-impl WriteThenRead for BufWriter<TcpStream> {
+impl WriteThenRead for BufWriter<TimeoutStream> {
     fn into_reader(
         self: Box<Self>,
     ) -> io::Result<HttpMessageReader<Box<dyn BufRead + Send>, ReadResponseStatus>> {
@@ -253,7 +521,7 @@ impl WriteThenRead for BufWriter<TcpStream> {
     }
 }
 
-impl WriteThenRead for BufWriter<StreamOwned<ClientConnection, TcpStream>> {
+impl WriteThenRead for BufWriter<StreamOwned<ClientConnection, TimeoutStream>> {
     fn into_reader(
         self: Box<Self>,
     ) -> io::Result<HttpMessageReader<Box<dyn BufRead + Send>, ReadResponseStatus>> {
@@ -339,7 +607,6 @@ impl<T: OptionTransferEncoding> HttpRequest<AwaitingRequestHeaders<T>> {
 impl HttpRequest<AwaitingRequestHeaders<TNone>> {
     /// Finalizes the HTTP request header section with fixed-size transfer encoding and awaits
     /// the request body.
-    #[allow(unused)]
     pub fn body_with_size(
         mut self,
         size: usize,
@@ -394,7 +661,6 @@ impl Write for HttpRequest<AwaitingRequestBody<ChunkedTransferEncoding>> {
 
 impl HttpRequest<AwaitingRequestBody<FixedSizeTransferEncoding>> {
     /// Finalizes the HTTP request and sends it, returning the HTTP response.
-    #[allow(unused)]
     pub fn response(self) -> Result<HttpResponse<ReadResponseStatus>> {
         Ok(HttpResponse {
             inner: self.stream.into_reader()?,
@@ -668,18 +934,28 @@ pub mod tests {
     pub const EMPTY_RESPONSE: &str = "HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n";
 
     pub struct TestServer {
-        join_handle: JoinHandle<io::Result<String>>,
+        join_handle: JoinHandle<io::Result<Vec<String>>>,
         addr: SocketAddr,
         base_url: Url,
     }
 
     impl TestServer {
         pub fn start(response: String) -> io::Result<Self> {
+            Self::start_with_responses(vec![response])
+        }
+
+        /// Starts a server answering one request per connection with each of the `responses` in
+        /// order.
+        pub fn start_with_responses(responses: Vec<String>) -> io::Result<Self> {
             let listener = TcpListener::bind("127.0.0.1:0")?;
             let addr = listener.local_addr()?;
-            let base_url =
-                Url::parse(&format!("http://{}:{}", addr.ip().to_string(), addr.port())).unwrap();
-            let join_handle = thread::spawn(move || Self::serve_once(listener, &response, None));
+            let base_url = Url::parse(&format!("http://{}:{}", addr.ip(), addr.port())).unwrap();
+            let join_handle = thread::spawn(move || {
+                responses
+                    .iter()
+                    .map(|response| Self::serve_once(&listener, response, None))
+                    .collect()
+            });
             Ok(Self {
                 join_handle,
                 addr,
@@ -688,9 +964,8 @@ pub mod tests {
         }
 
         pub fn start_with_tls(response: String) -> io::Result<Self> {
-            crypto::aws_lc_rs::default_provider()
-                .install_default()
-                .unwrap();
+            // Fails if another test already installed the provider.
+            let _ = crypto::aws_lc_rs::default_provider().install_default();
             let cert = CertificateDer::from_pem_slice(CERTIFICATE_PEM).unwrap();
             let private_key = PrivateKeyDer::from_pem_slice(CERTIFICATE_PRIVATE_KEY).unwrap();
             let server_conf = ServerConfig::builder()
@@ -700,14 +975,10 @@ pub mod tests {
 
             let listener = TcpListener::bind("127.0.0.1:0")?;
             let addr = listener.local_addr()?;
-            let base_url = Url::parse(&format!(
-                "https://{}:{}",
-                addr.ip().to_string(),
-                addr.port()
-            ))
-            .unwrap();
+            let base_url = Url::parse(&format!("https://{}:{}", addr.ip(), addr.port())).unwrap();
             let join_handle = thread::spawn(move || {
-                Self::serve_once(listener, &response, Some(Arc::new(server_conf)))
+                Self::serve_once(&listener, &response, Some(Arc::new(server_conf)))
+                    .map(|request| vec![request])
             });
             Ok(Self {
                 join_handle,
@@ -717,7 +988,7 @@ pub mod tests {
         }
 
         fn serve_once(
-            listener: TcpListener,
+            listener: &TcpListener,
             response: &str,
             tls_conf: Option<Arc<ServerConfig>>,
         ) -> io::Result<String> {
@@ -762,6 +1033,10 @@ pub mod tests {
         }
 
         pub fn request(self) -> io::Result<String> {
+            Ok(self.requests()?.remove(0))
+        }
+
+        pub fn requests(self) -> io::Result<Vec<String>> {
             self.join_handle.join().unwrap()
         }
 
@@ -774,13 +1049,58 @@ pub mod tests {
         }
     }
 
+    /// A stand-in for a proxy that answers a single `CONNECT` request with `response`. If the
+    /// response is successful, the connection is tunneled to the requested target afterward.
+    ///
+    /// Returns the URL of the proxy and a handle returning the received `CONNECT` request.
+    fn start_tunnel_proxy(
+        response: &'static str,
+    ) -> io::Result<(Url, JoinHandle<io::Result<String>>)> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = Url::parse(&format!("http://{}", listener.local_addr()?)).unwrap();
+        let join_handle = thread::spawn(move || {
+            let (mut client, _) = listener.accept()?;
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                let mut byte = [0u8];
+                client.read_exact(&mut byte)?;
+                request.push(byte[0]);
+            }
+            let request = String::from_utf8(request).unwrap();
+            client.write_all(response.as_bytes())?;
+            if response.starts_with("HTTP/1.1 200") {
+                let target = request.split(' ').nth(1).unwrap();
+                let mut target = TcpStream::connect(target)?;
+                let mut client_reader = client.try_clone()?;
+                let mut target_writer = target.try_clone()?;
+                thread::spawn(move || io::copy(&mut client_reader, &mut target_writer));
+                io::copy(&mut target, &mut client)?;
+            }
+            Ok(request)
+        });
+        Ok((url, join_handle))
+    }
+
+    fn tls_client_config() -> Result<Arc<ClientConfig>> {
+        let root_cert =
+            CertificateDer::from_pem_slice(include_bytes!("../../../../tls/ca.pem")).unwrap();
+        let mut cert_store = RootCertStore::empty();
+        cert_store.add(root_cert)?;
+        Ok(Arc::new(
+            ClientConfig::builder_with_provider(Arc::new(crypto::aws_lc_rs::default_provider()))
+                .with_safe_default_protocol_versions()?
+                .with_root_certificates(cert_store)
+                .with_no_client_auth(),
+        ))
+    }
+
     #[test]
     fn test_get_request_without_body() -> Result<()> {
         let test_server = TestServer::start(EMPTY_RESPONSE.into())?;
         let addr = test_server.addr();
         let url = Url::parse(&format!(
             "http://{}:{}/path?query=foo#fragment",
-            addr.ip().to_string(),
+            addr.ip(),
             addr.port()
         ))
         .unwrap();
@@ -793,7 +1113,7 @@ pub mod tests {
                 Host: {}\r\n\
                 Connection: close\r\n\
                 User-Agent: btdt/{}\r\n\r\n",
-                addr.ip(),
+                addr,
                 env!("CARGO_PKG_VERSION")
             )
         );
@@ -837,7 +1157,7 @@ pub mod tests {
                 User-Agent: btdt/{}\r\n\
                 Content-Length: {}\r\n\r\n\
                 {}",
-                addr.ip(),
+                addr,
                 env!("CARGO_PKG_VERSION"),
                 body.len(),
                 body
@@ -882,7 +1202,7 @@ pub mod tests {
                 User-Agent: btdt/{}\r\n\
                 Transfer-Encoding: chunked\r\n\r\n\
                 {}",
-                addr.ip(),
+                addr,
                 env!("CARGO_PKG_VERSION"),
                 body
             )
@@ -953,23 +1273,64 @@ pub mod tests {
     }
 
     #[test]
-    fn test_tls() -> Result<()> {
-        let root_cert =
-            CertificateDer::from_pem_slice(include_bytes!("../../../../tls/ca.pem")).unwrap();
-        let mut cert_store = RootCertStore::empty();
-        cert_store.add(root_cert)?;
-        let tls_client_config = Arc::new(
-            ClientConfig::builder_with_provider(Arc::new(crypto::aws_lc_rs::default_provider()))
-                .with_safe_default_protocol_versions()?
-                .with_root_certificates(cert_store)
-                .with_no_client_auth(),
-        );
+    fn test_read_timeout() -> Result<()> {
+        // The connection is accepted by the OS, but the server never responds.
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = Url::parse(&format!("http://{}/", listener.local_addr()?)).unwrap();
+        let client = HttpClient::default()?.with_timeouts(Timeouts {
+            read: Some(Duration::from_millis(50)),
+            ..Timeouts::default()
+        });
+
+        let result = client.get(&url)?.no_body()?.read_status();
+        assert!(matches!(
+            result,
+            Err(HttpClientError::Timeout(TimeoutKind::Read))
+        ));
+        Ok(())
+    }
 
+    #[test]
+    fn test_total_timeout() -> Result<()> {
+        // The server keeps sending data, but too slowly to finish the response.
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = Url::parse(&format!("http://{}/", listener.local_addr()?)).unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 1000000\r\n\r\n")
+                .unwrap();
+            while stream.write_all(b"x").is_ok() {
+                thread::sleep(Duration::from_millis(10));
+            }
+        });
+        let client = HttpClient::default()?.with_timeouts(Timeouts {
+            read: Some(Duration::from_secs(1)),
+            total: Some(Duration::from_millis(100)),
+            ..Timeouts::default()
+        });
+
+        let (status, response) = client.get(&url)?.no_body()?.read_status()?;
+        assert!(status.is_success());
+        let err = response
+            .read_body()?
+            .read_to_end(&mut Vec::new())
+            .unwrap_err();
+        assert!(matches!(
+            HttpClientError::from(err),
+            HttpClientError::Timeout(TimeoutKind::Total)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_tls() -> Result<()> {
+        let tls_client_config = tls_client_config()?;
         let test_server = TestServer::start_with_tls(EMPTY_RESPONSE.into())?;
         let addr = test_server.addr();
         let url = Url::parse(&format!(
             "https://{}:{}/path?query=foo#fragment",
-            addr.ip().to_string(),
+            addr.ip(),
             addr.port()
         ))
         .unwrap();
@@ -982,7 +1343,7 @@ pub mod tests {
                 Host: {}\r\n\
                 Connection: close\r\n\
                 User-Agent: btdt/{}\r\n\r\n",
-                addr.ip(),
+                addr,
                 env!("CARGO_PKG_VERSION")
             )
         );
@@ -1002,4 +1363,80 @@ pub mod tests {
         assert!(buf.is_empty());
         Ok(())
     }
+
+    #[test]
+    fn test_http_request_via_proxy() -> Result<()> {
+        let test_server = TestServer::start(EMPTY_RESPONSE.into())?;
+        let mut proxy_url = test_server.base_url().clone();
+        proxy_url.set_username("user").unwrap();
+        proxy_url.set_password(Some("pass")).unwrap();
+        let client = HttpClient::default()?
+            .with_proxy_config(ProxyConfig::none().with_http_proxy(proxy_url));
+        let url = Url::parse("http://btdt.example:8707/path?query=foo").unwrap();
+
+        let (status, _) = client.get(&url)?.no_body()?.read_status()?;
+
+        assert!(status.is_success());
+        assert_eq!(
+            test_server.request()?,
+            format!(
+                "GET http://btdt.example:8707/path?query=foo HTTP/1.1\r\n\
+                Host: btdt.example:8707\r\n\
+                Connection: close\r\n\
+                User-Agent: btdt/{}\r\n\
+                Proxy-Authorization: Basic dXNlcjpwYXNz\r\n\r\n",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_https_request_via_proxy_tunnel() -> Result<()> {
+        let test_server = TestServer::start_with_tls(EMPTY_RESPONSE.into())?;
+        let (mut proxy_url, proxy) =
+            start_tunnel_proxy("HTTP/1.1 200 Connection established\r\n\r\n")?;
+        proxy_url.set_username("user").unwrap();
+        proxy_url.set_password(Some("pass")).unwrap();
+        let client = HttpClient::new(tls_client_config()?)
+            .with_proxy_config(ProxyConfig::none().with_https_proxy(proxy_url));
+        let url = test_server.base_url().join("path").unwrap();
+
+        let (status, _) = client.get(&url)?.no_body()?.read_status()?;
+
+        assert!(status.is_success());
+        assert!(test_server.request()?.starts_with("GET /path HTTP/1.1\r\n"));
+        let authority = format!("127.0.0.1:{}", url.port().unwrap());
+        assert_eq!(
+            proxy.join().unwrap()?,
+            format!(
+                "CONNECT {authority} HTTP/1.1\r\n\
+                Host: {authority}\r\n\
+                Proxy-Authorization: Basic dXNlcjpwYXNz\r\n\r\n"
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_https_request_via_proxy_tunnel_fails_if_proxy_rejects_connect() -> Result<()> {
+        let (proxy_url, _) = start_tunnel_proxy(
+            "HTTP/1.1 407 Proxy Authentication Required\r\nContent-Length: 0\r\n\r\n",
+        )?;
+        let client = HttpClient::new(tls_client_config()?)
+            .with_proxy_config(ProxyConfig::none().with_https_proxy(proxy_url));
+        let url = Url::parse("https://btdt.example/").unwrap();
+
+        let result = client.get(&url);
+
+        match result {
+            Err(HttpClientError::ProxyError(msg)) => assert_eq!(
+                msg,
+                "CONNECT failed with status 407 Proxy Authentication Required"
+            ),
+            Err(err) => panic!("unexpected error: {err}"),
+            Ok(_) => panic!("request unexpectedly succeeded"),
+        }
+        Ok(())
+    }
 }